@@ -0,0 +1,182 @@
+//! Abstracts over the implementation of the MCD API, see [McdBackend]
+//!
+//! By default, all calls are forwarded to `mcdxdas.dll` of the DAS installation.
+//! A different implementation can be installed with [crate::library::set_backend]
+//! before the library is used for the first time, e.g. the
+//! [simulation](crate::simulation) that is available with the `simulation`
+//! feature.
+
+use std::os::raw::c_void;
+
+use crate::mcd_bindings::{
+    mcd_api_version_st, mcd_bool_t, mcd_char_t, mcd_core_con_info_st, mcd_core_st,
+    mcd_core_state_st, mcd_core_step_type_et, mcd_error_info_st, mcd_impl_version_info_st,
+    mcd_memblock_st, mcd_memspace_st, mcd_register_group_st, mcd_register_info_st, mcd_return_et,
+    mcd_rst_info_st, mcd_server_info_st, mcd_server_st, mcd_trig_set_state_st, mcd_trig_state_st,
+    mcd_txlist_st, DynamicMCDxDAS,
+};
+
+/// Declares [McdBackend] and implements it for [DynamicMCDxDAS] by forwarding
+/// each call to the function of the same name
+macro_rules! mcd_backend {
+    ($(fn $name:ident(&self $(, $argument:ident: $type:ty)*) -> $return:ty;)*) => {
+        /// The functions of the MCD API that are used by this library
+        ///
+        /// The signatures match the raw bindings of the MCD API, so implementors
+        /// must follow the semantics described in the MCD API header file
+        /// (`mcd_api.h`), including how output arrays and counts are handled.
+        pub trait McdBackend: Send + Sync {
+            $(
+                #[doc = concat!("See `", stringify!($name), "` in the MCD API")]
+                ///
+                /// # Safety
+                /// All pointers must be valid as specified by the MCD API.
+                unsafe fn $name(&self $(, $argument: $type)*) -> $return;
+            )*
+        }
+
+        impl McdBackend for DynamicMCDxDAS {
+            $(
+                unsafe fn $name(&self $(, $argument: $type)*) -> $return {
+                    DynamicMCDxDAS::$name(self $(, $argument)*)
+                }
+            )*
+        }
+    };
+}
+
+mcd_backend! {
+    fn mcd_initialize_f(
+        &self,
+        version_req: *const mcd_api_version_st,
+        impl_info: *mut mcd_impl_version_info_st
+    ) -> mcd_return_et;
+    fn mcd_exit_f(&self) -> ();
+    fn mcd_qry_servers_f(
+        &self,
+        host: *const mcd_char_t,
+        running: mcd_bool_t,
+        start_index: u32,
+        num_servers: *mut u32,
+        server_info: *mut mcd_server_info_st
+    ) -> mcd_return_et;
+    fn mcd_open_server_f(
+        &self,
+        system_key: *const mcd_char_t,
+        config_string: *const mcd_char_t,
+        server: *mut *mut mcd_server_st
+    ) -> mcd_return_et;
+    fn mcd_qry_systems_f(
+        &self,
+        start_index: u32,
+        num_systems: *mut u32,
+        system_con_info: *mut mcd_core_con_info_st
+    ) -> mcd_return_et;
+    fn mcd_qry_cores_f(
+        &self,
+        connection_info: *const mcd_core_con_info_st,
+        start_index: u32,
+        num_cores: *mut u32,
+        core_con_info: *mut mcd_core_con_info_st
+    ) -> mcd_return_et;
+    fn mcd_open_core_f(
+        &self,
+        core_con_info: *const mcd_core_con_info_st,
+        core: *mut *mut mcd_core_st
+    ) -> mcd_return_et;
+    fn mcd_qry_error_info_f(
+        &self,
+        core: *const mcd_core_st,
+        error_info: *mut mcd_error_info_st
+    ) -> ();
+    fn mcd_qry_mem_spaces_f(
+        &self,
+        core: *const mcd_core_st,
+        start_index: u32,
+        num_mem_spaces: *mut u32,
+        mem_spaces: *mut mcd_memspace_st
+    ) -> mcd_return_et;
+    fn mcd_qry_mem_blocks_f(
+        &self,
+        core: *const mcd_core_st,
+        mem_space_id: u32,
+        start_index: u32,
+        num_mem_blocks: *mut u32,
+        mem_blocks: *mut mcd_memblock_st
+    ) -> mcd_return_et;
+    fn mcd_qry_reg_groups_f(
+        &self,
+        core: *const mcd_core_st,
+        start_index: u32,
+        num_reg_groups: *mut u32,
+        reg_groups: *mut mcd_register_group_st
+    ) -> mcd_return_et;
+    fn mcd_qry_reg_map_f(
+        &self,
+        core: *const mcd_core_st,
+        reg_group_id: u32,
+        start_index: u32,
+        num_regs: *mut u32,
+        reg_info: *mut mcd_register_info_st
+    ) -> mcd_return_et;
+    fn mcd_qry_max_payload_size_f(
+        &self,
+        core: *const mcd_core_st,
+        max_payload: *mut u32
+    ) -> mcd_return_et;
+    fn mcd_execute_txlist_f(
+        &self,
+        core: *const mcd_core_st,
+        txlist: *mut mcd_txlist_st
+    ) -> mcd_return_et;
+    fn mcd_run_f(&self, core: *const mcd_core_st, global: mcd_bool_t) -> mcd_return_et;
+    fn mcd_stop_f(&self, core: *const mcd_core_st, global: mcd_bool_t) -> mcd_return_et;
+    fn mcd_step_f(
+        &self,
+        core: *const mcd_core_st,
+        global: mcd_bool_t,
+        step_type: mcd_core_step_type_et,
+        n_steps: u32
+    ) -> mcd_return_et;
+    fn mcd_qry_state_f(
+        &self,
+        core: *const mcd_core_st,
+        state: *mut mcd_core_state_st
+    ) -> mcd_return_et;
+    fn mcd_qry_rst_classes_f(
+        &self,
+        core: *const mcd_core_st,
+        rst_class_vector: *mut u32
+    ) -> mcd_return_et;
+    fn mcd_qry_rst_class_info_f(
+        &self,
+        core: *const mcd_core_st,
+        rst_class: u8,
+        rst_info: *mut mcd_rst_info_st
+    ) -> mcd_return_et;
+    fn mcd_rst_f(
+        &self,
+        core: *const mcd_core_st,
+        rst_class_vector: u32,
+        rst_and_halt: mcd_bool_t
+    ) -> mcd_return_et;
+    fn mcd_create_trig_f(
+        &self,
+        core: *const mcd_core_st,
+        trig: *mut c_void,
+        trig_id: *mut u32
+    ) -> mcd_return_et;
+    fn mcd_remove_trig_f(&self, core: *const mcd_core_st, trig_id: u32) -> mcd_return_et;
+    fn mcd_qry_trig_state_f(
+        &self,
+        core: *const mcd_core_st,
+        trig_id: u32,
+        trig_state: *mut mcd_trig_state_st
+    ) -> mcd_return_et;
+    fn mcd_activate_trig_set_f(&self, core: *const mcd_core_st) -> mcd_return_et;
+    fn mcd_qry_trig_set_state_f(
+        &self,
+        core: *const mcd_core_st,
+        trig_state: *mut mcd_trig_set_state_st
+    ) -> mcd_return_et;
+}
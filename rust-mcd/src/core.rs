@@ -3,13 +3,11 @@ use std::{
     ffi::{c_void, CStr},
 };
 
-use anyhow::Context;
-
 use super::{registers::RegisterGroups, reset::ResetClass, MCD_LIB};
 
 use crate::{
     breakpoint::TriggerType,
-    error::expect_error,
+    error::{check, ErrorContext, McdError, Result},
     mcd_bindings::{
         mcd_core_con_info_st, mcd_core_event_et, mcd_core_st, mcd_core_state_et, mcd_core_state_st,
         mcd_trig_set_state_st, mcd_trig_simple_core_st, mcd_trig_state_st, mcd_tx_st,
@@ -38,46 +36,74 @@ impl<'a> Core<'a> {
         }
     }
 
-    pub fn reset(&self, reset_type: ResetClass, halt_after_reset: bool) -> anyhow::Result<()> {
+    /// The name of the core as reported by the debug controller
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr(&self._core_connection.core[0] as *const i8) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The ID of the core as reported by the debug controller
+    pub fn id(&self) -> u32 {
+        self._core_connection.core_id
+    }
+
+    /// Describe an operation on this core for error reporting
+    pub(crate) fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).with_core(self)
+    }
+
+    pub(crate) fn check(&self, result: u32, context: impl FnOnce() -> ErrorContext) -> Result<()> {
+        check(result, Some(self), context)
+    }
+
+    pub fn reset(&self, reset_type: ResetClass, halt_after_reset: bool) -> Result<()> {
         let reset_vector = reset_type.as_vector();
         let rst_and_halt = if halt_after_reset { 1 } else { 0 };
         let result = unsafe { MCD_LIB.mcd_rst_f(self.core, reset_vector, rst_and_halt) };
-        if result != 0 {
-            Err(expect_error(Some(self))).with_context(|| "Library reported an error")
-        } else {
-            Ok(())
-        }
+        self.check(result, || self.context("reset core"))
     }
 
-    pub fn get_reset_classes(&self) -> anyhow::Result<impl Iterator<Item = ResetClass>> {
+    pub fn get_reset_classes(&self) -> Result<impl Iterator<Item = ResetClass<'_>>> {
         let mut reset_classes = 0;
         let result = unsafe { MCD_LIB.mcd_qry_rst_classes_f(self.core, &mut reset_classes) };
-        if result != 0 {
-            return Err(expect_error(Some(self)))
-                .with_context(|| "Could not obtain a list of available reset classes");
-        }
+        self.check(result, || self.context("query available reset classes"))?;
 
         Ok((0..32)
             .filter(move |bit| (reset_classes & (1 << *bit)) != 0)
             .map(|bit_set| ResetClass::construct_reset_class(self, bit_set)))
     }
+
+    /// Construct the given reset class, but only if the core reports it as available
+    ///
+    /// See also [Self::get_reset_classes] and [ResetClass::construct_reset_class].
+    pub fn get_reset_class(&self, class: u8) -> Result<ResetClass<'_>> {
+        self.get_reset_classes()?
+            .find(|reset_class| reset_class.class() == class)
+            .ok_or_else(|| McdError::ResetClassUnavailable {
+                core: self.name(),
+                class,
+            })
+    }
+
     /// Query the state of the core
-    pub fn query_state(&self) -> Result<CoreInfo, crate::error::Error> {
+    pub fn query_state(&self) -> Result<CoreInfo> {
         let mut output = mcd_core_state_st::default();
         let result = unsafe { MCD_LIB.mcd_qry_state_f(self.core, &mut output) };
+        self.check(result, || self.context("query core state"))?;
 
-        if result != 0 {
-            return Err(expect_error(Some(self)));
-        }
-
-        Ok(output.into())
+        CoreInfo::try_from(output).map_err(|state| McdError::UnknownCoreState {
+            core: self.name(),
+            state,
+        })
     }
 
-    /// Like [Self::query_state], but will never exit gracefully
+    /// Like [Self::query_state], but errors are ignored as long as the given
+    /// function returns true
     pub fn query_state_gracefully(
         &self,
-        mut should_query_again: impl FnMut(&crate::error::Error) -> bool,
-    ) -> anyhow::Result<CoreInfo> {
+        mut should_query_again: impl FnMut(&McdError) -> bool,
+    ) -> Result<CoreInfo> {
         loop {
             match self.query_state() {
                 Ok(info) => return Ok(info),
@@ -85,111 +111,100 @@ impl<'a> Core<'a> {
                     if should_query_again(&error) {
                         continue;
                     }
-                    return Err(error).context("Error is not tolerated");
+                    return Err(error);
                 }
             }
         }
     }
 
-    fn query_payload_size(&self) -> u32 {
+    /// The most bytes a single transaction transfers, larger transfers are split
+    /// by [Self::read_bytes] and [Self::write]
+    pub fn query_payload_size(&self) -> Result<u32> {
         if let Some(payload) = self.payload_size.get() {
-            return payload;
+            return Ok(payload);
         }
 
         let mut max_payload = 0;
         let result = unsafe { MCD_LIB.mcd_qry_max_payload_size_f(self.core, &mut max_payload) };
-        assert_eq!(result, 0);
+        self.check(result, || self.context("query maximum payload size"))?;
         self.payload_size.replace(Some(max_payload));
         log::trace!("Maximum payload is {}", max_payload);
-        max_payload
+        Ok(max_payload)
     }
 
-    pub fn read_bytes(&self, mut addr: u64, mut length: usize) -> anyhow::Result<Vec<u8>> {
-        let payload_length = self.query_payload_size();
-
-        // TODO split the request into multiple transactions
-        assert!(payload_length >= length as u32);
-
-        let mut buffer: Vec<u8> = (0..length).map(|_| 0).collect();
-
-        // TODO this logic might be wrong, check the [crate::transaction] module
-        while length > 0 {
-            let mut transaction = create_transaction(addr, Type::Read, &mut buffer);
-
-            let mut transaction_list = mcd_txlist_st {
-                tx: &mut transaction as *mut mcd_tx_st,
-                num_tx: 1,
-                num_tx_ok: 0,
-            };
-            let result = unsafe { MCD_LIB.mcd_execute_txlist_f(self.core, &mut transaction_list) };
-            if result != 0 {
-                return Err(expect_error(Some(self)))
-                    .with_context(|| "Internal MCD library eror while trying to read data");
-            }
-
-            if transaction_list.num_tx_ok == 1 {
-                break;
-            }
-
-            addr += transaction.num_bytes_ok as u64;
-            length -= transaction.num_bytes_ok as usize;
-        }
-
+    pub fn read_bytes(&self, addr: u64, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        self.transfer(addr, Type::Read, &mut buffer)?;
         Ok(buffer)
     }
 
-    pub fn write(&self, mut address: u64, mut data: Vec<u8>) -> anyhow::Result<()> {
-        let payload_length = self.query_payload_size();
-        assert!(payload_length >= data.len() as u32);
+    pub fn write(&self, address: u64, mut data: Vec<u8>) -> Result<()> {
+        self.transfer(address, Type::Write, &mut data)
+    }
 
-        while !data.is_empty() {
-            let mut transaction = create_transaction(address, Type::Write, &mut data);
+    /// Execute transactions for the buffer, each of them transfers at most the
+    /// maximum payload of the core
+    fn transfer(&self, address: u64, transaction_type: Type, buffer: &mut [u8]) -> Result<()> {
+        let operation = match transaction_type {
+            Type::Read => "read memory",
+            Type::Write => "write memory",
+        };
+        let max_payload = self.query_payload_size()?.max(1) as usize;
+
+        let mut transferred = 0;
+        while transferred < buffer.len() {
+            let chunk_address = address + transferred as u64;
+            let end = buffer.len().min(transferred + max_payload);
+            let mut transaction = create_transaction(
+                chunk_address,
+                &transaction_type,
+                &mut buffer[transferred..end],
+            );
 
             let mut transaction_list = mcd_txlist_st {
-                tx: (&mut transaction) as *mut mcd_tx_st,
+                tx: &mut transaction as *mut mcd_tx_st,
                 num_tx: 1,
                 num_tx_ok: 0,
             };
-
             let result = unsafe { MCD_LIB.mcd_execute_txlist_f(self.core, &mut transaction_list) };
-
-            if result != 0 {
-                return Err(expect_error(Some(self)))
-                    .with_context(|| "Internal MCD library eror while trying to write data");
-            }
-
-            if transaction_list.num_tx_ok == 1 {
-                break;
+            self.check(result, || {
+                self.context(operation).with_address(chunk_address)
+            })?;
+
+            // The library may complete a transaction partially, the rest is
+            // transferred by the next one
+            if transaction.num_bytes_ok == 0 {
+                return Err(McdError::IncompleteTransfer {
+                    context: self.context(operation).with_address(address),
+                    transferred,
+                    length: buffer.len(),
+                });
             }
-
-            address += transaction.num_bytes as u64;
-            data = data[(transaction.num_bytes as usize)..].into();
+            transferred += transaction.num_bytes_ok as usize;
         }
 
-        drop(data);
-
         Ok(())
     }
 
-    pub fn run(&self) -> anyhow::Result<()> {
+    pub fn run(&self) -> Result<()> {
         let result = unsafe { MCD_LIB.mcd_run_f(self.core, 0) };
-        if result != 0 {
-            Err(expect_error(Some(self))).with_context(|| "Internal library reported an error")
-        } else {
-            Ok(())
-        }
+        self.check(result, || self.context("run core"))
+    }
+
+    /// Stop the execution of the core
+    ///
+    /// The core is usually halted shortly after this call returns, use
+    /// [Self::query_state] to wait for the core to actually halt.
+    pub fn stop(&self) -> Result<()> {
+        let result = unsafe { MCD_LIB.mcd_stop_f(self.core, 0) };
+        self.check(result, || self.context("stop core"))
     }
 
-    pub fn step(&self) -> anyhow::Result<()> {
+    pub fn step(&self) -> Result<()> {
         let step_type = MCD_CORE_STEP_TYPE_INSTR as u32;
 
         let result = unsafe { MCD_LIB.mcd_step_f(self.core, 0, step_type, 1) };
-
-        if result != 0 {
-            Err(expect_error(Some(self))).with_context(|| "Internal library reported an error")
-        } else {
-            Ok(())
-        }
+        self.check(result, || self.context("step core"))
     }
 
     pub fn create_breakpoint(
@@ -197,7 +212,7 @@ impl<'a> Core<'a> {
         trigger_type: TriggerType,
         address: u64,
         size: u64,
-    ) -> anyhow::Result<Trigger> {
+    ) -> Result<Trigger<'_>> {
         let mut trigger = mcd_trig_simple_core_st::create_trigger(trigger_type, address, size);
         let mut trigger_id = 0;
 
@@ -209,9 +224,9 @@ impl<'a> Core<'a> {
             )
         };
 
-        if result != 0 {
-            return Err(expect_error(Some(self))).with_context(|| "Library reported an error");
-        }
+        self.check(result, || {
+            self.context("create breakpoint").with_address(address)
+        })?;
 
         log::trace!("trigger is modified: {:?}", trigger.modified == TRUE);
 
@@ -221,24 +236,23 @@ impl<'a> Core<'a> {
         })
     }
 
-    pub fn download_triggers(&self) {
-        let _state = self.sample_triggers();
+    pub fn download_triggers(&self) -> Result<()> {
+        let _state = self.sample_triggers()?;
 
         let result = unsafe { MCD_LIB.mcd_activate_trig_set_f(self.core) };
-
-        assert_eq!(result, 0);
+        self.check(result, || self.context("activate triggers"))
     }
 
-    pub fn sample_triggers(&self) -> TriggerSetState {
+    pub fn sample_triggers(&self) -> Result<TriggerSetState> {
         let mut state = mcd_trig_set_state_st::default();
 
         let result = unsafe { MCD_LIB.mcd_qry_trig_set_state_f(self.core, &mut state) };
-        assert_eq!(result, 0);
+        self.check(result, || self.context("query trigger set state"))?;
 
-        state.into()
+        Ok(state.into())
     }
 
-    pub fn register_groups(&self) -> anyhow::Result<RegisterGroups> {
+    pub fn register_groups(&self) -> Result<RegisterGroups<'_>> {
         RegisterGroups::from_core(self)
     }
 }
@@ -289,27 +303,21 @@ pub struct Trigger<'a> {
 }
 
 impl<'a> Trigger<'a> {
-    pub fn get_state(&self) -> anyhow::Result<TriggerState> {
+    pub fn get_state(&self) -> Result<TriggerState> {
         let mut state_output = mcd_trig_state_st::default();
         let result = unsafe {
             MCD_LIB.mcd_qry_trig_state_f(self.core.core, self.trigger_id, &mut state_output)
         };
-        if result != 0 {
-            return Err(expect_error(Some(self.core)))
-                .with_context(|| "Cannot query state for trigger");
-        }
-        assert_eq!(result, 0);
+        self.core
+            .check(result, || self.core.context("query trigger state"))?;
 
         Ok(state_output.into())
     }
 
-    pub fn remove(self) -> anyhow::Result<()> {
+    pub fn remove(self) -> Result<()> {
         let result = unsafe { MCD_LIB.mcd_remove_trig_f(self.core.core, self.trigger_id) };
-        if result != 0 {
-            return Err(expect_error(Some(self.core))).with_context(|| "Cannot remove trigger");
-        }
-
-        Ok(())
+        self.core
+            .check(result, || self.core.context("remove trigger"))
     }
 }
 
@@ -324,7 +332,7 @@ pub struct CoreInfo {
     info: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct CoreEvents {
     pub memory_change: bool,
@@ -349,7 +357,7 @@ impl From<mcd_core_event_et> for CoreEvents {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum CoreState {
     Unknown,
     Running,
@@ -358,39 +366,43 @@ pub enum CoreState {
     Custom,
 }
 
-impl From<mcd_core_state_et> for CoreState {
-    fn from(value: mcd_core_state_et) -> Self {
-        let value = value as i32;
-        match value {
-            _ if value == MCD_CORE_STATE_UNKNOWN => Self::Unknown,
-            _ if value == MCD_CORE_STATE_RUNNING => Self::Running,
-            _ if value == MCD_CORE_STATE_HALTED => Self::Halted,
-            _ if value == MCD_CORE_STATE_DEBUG => Self::Debug,
-            _ if (MCD_CORE_STATE_CUSTOM_LO..MCD_CORE_STATE_CUSTOM_HI).contains(&value) => {
+impl TryFrom<mcd_core_state_et> for CoreState {
+    /// The unknown state
+    type Error = mcd_core_state_et;
+
+    fn try_from(value: mcd_core_state_et) -> std::result::Result<Self, Self::Error> {
+        let state = value as i32;
+        Ok(match state {
+            _ if state == MCD_CORE_STATE_UNKNOWN => Self::Unknown,
+            _ if state == MCD_CORE_STATE_RUNNING => Self::Running,
+            _ if state == MCD_CORE_STATE_HALTED => Self::Halted,
+            _ if state == MCD_CORE_STATE_DEBUG => Self::Debug,
+            _ if (MCD_CORE_STATE_CUSTOM_LO..MCD_CORE_STATE_CUSTOM_HI).contains(&state) => {
                 Self::Custom
             }
-            _ => panic!("Invalid state {value}"),
-        }
+            _ => return Err(value),
+        })
     }
 }
 
-impl From<mcd_core_state_st> for CoreInfo {
-    fn from(value: mcd_core_state_st) -> Self {
+impl TryFrom<mcd_core_state_st> for CoreInfo {
+    /// The unknown state
+    type Error = mcd_core_state_et;
+
+    fn try_from(value: mcd_core_state_st) -> std::result::Result<Self, Self::Error> {
         let stop_reason = unsafe { CStr::from_ptr(&value.stop_str[0] as *const i8) }
-            .to_str()
-            .unwrap()
-            .to_owned();
+            .to_string_lossy()
+            .into_owned();
         let info = unsafe { CStr::from_ptr(&value.info_str[0] as *const i8) }
-            .to_str()
-            .unwrap()
-            .to_owned();
-        CoreInfo {
-            state: value.state.into(),
+            .to_string_lossy()
+            .into_owned();
+        Ok(CoreInfo {
+            state: value.state.try_into()?,
             events: value.event.into(),
             hw_thread_id: value.hw_thread_id,
             trigger_id: value.trig_id,
             stop_reason,
             info,
-        }
+        })
     }
 }
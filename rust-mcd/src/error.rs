@@ -1,9 +1,191 @@
+//! Errors reported by this library, see [McdError]
+
 use std::{ffi::CStr, fmt::Display};
 
 use crate::mcd_bindings::{mcd_core_st, mcd_error_info_st, MCD_ERR_NONE};
 
 use super::{core::Core, MCD_LIB};
 
+/// Result type of all fallible operations of this library
+pub type Result<T> = std::result::Result<T, McdError>;
+
+/// An error that occurred while interacting with the MCD library
+#[derive(Debug, thiserror::Error)]
+pub enum McdError {
+    /// The library reported an error while executing the operation
+    #[error("Cannot {context}")]
+    Library {
+        context: ErrorContext,
+        // Boxed since the error information of the library is large
+        #[source]
+        error: Box<Error>,
+    },
+    /// The library reported a failure, but did not provide any error information
+    #[error("Cannot {context}, library returned {return_code} without error information")]
+    Unreported {
+        context: ErrorContext,
+        return_code: u32,
+    },
+    /// The library reported a core state that is not known to this library
+    #[error("Core {core} reported the unknown state {state}")]
+    UnknownCoreState { core: String, state: u32 },
+    /// The requested reset class is not offered by the core
+    #[error("Reset class {class} is not available for core {core}")]
+    ResetClassUnavailable { core: String, class: u8 },
+    /// The library stopped transferring memory without reporting an error
+    #[error("Cannot {context}, only {transferred} of {length} bytes were transferred")]
+    IncompleteTransfer {
+        context: ErrorContext,
+        transferred: usize,
+        length: usize,
+    },
+    /// This library only supports a single server
+    #[error("Library only supports exactly one server, found {0}")]
+    UnsupportedServerCount(u32),
+    /// Opening a core did not succeed after retrying
+    #[error("Could not open core after {0} tries")]
+    OpenCoreRetriesExceeded(usize),
+    /// The core with the given index does not exist in the system
+    #[error("Core {index} does not exist, the system has {count} core(s)")]
+    NoSuchCore { index: usize, count: usize },
+    /// The register group with the given index does not exist
+    #[error("Register group {index} does not exist, core {core} has {count} group(s)")]
+    NoSuchRegisterGroup {
+        core: String,
+        index: usize,
+        count: usize,
+    },
+    /// The library returned a different number of registers than announced
+    #[error(
+        "Register group of core {core} announced {expected} registers, but {actual} were returned"
+    )]
+    RegisterCountMismatch {
+        core: String,
+        expected: u32,
+        actual: u32,
+    },
+    /// The MCD library could not be loaded
+    #[error("Unable to load mcdxdas.dll")]
+    LoadLibrary(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// A backend was set after the library was already initialized
+    #[error("MCD backend was already initialized")]
+    BackendAlreadyInitialized,
+}
+
+impl McdError {
+    /// The error information the library reported, if any
+    pub fn library_error(&self) -> Option<&Error> {
+        match self {
+            McdError::Library { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    /// What was attempted when the error occurred, if known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            McdError::Library { context, .. }
+            | McdError::Unreported { context, .. }
+            | McdError::IncompleteTransfer { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error code the library reported, if any
+    pub fn error_code(&self) -> Option<McdErrorCode> {
+        self.library_error().map(Error::error_code)
+    }
+
+    /// The event the library reported along with the error, if any
+    pub fn event(&self) -> Option<EventError> {
+        self.library_error().and_then(Error::event_error_code)
+    }
+
+    /// Whether the operation failed because the device was reset
+    pub fn is_reset(&self) -> bool {
+        self.event() == Some(EventError::Reset)
+    }
+
+    /// Whether the operation failed because the device cannot be accessed, e.g.
+    /// because it is locked, powered down or in reset
+    pub fn is_access_denied(&self) -> bool {
+        matches!(
+            self.error_code(),
+            Some(McdErrorCode::McdErrDeviceAccess | McdErrorCode::McdErrDeviceLocked)
+        )
+    }
+}
+
+/// Describes the operation that failed, see [McdError::context]
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    /// The operation, e.g. "read memory"
+    pub operation: &'static str,
+    /// The name of the core the operation was executed on
+    pub core: Option<String>,
+    /// The address the operation accessed
+    pub address: Option<u64>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        ErrorContext {
+            operation,
+            core: None,
+            address: None,
+        }
+    }
+
+    pub fn with_core(mut self, core: &Core) -> Self {
+        self.core = Some(core.name());
+        self
+    }
+
+    pub fn with_address(mut self, address: u64) -> Self {
+        self.address = Some(address);
+        self
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.operation)?;
+        if let Some(core) = &self.core {
+            write!(f, " on core {core}")?;
+        }
+        if let Some(address) = self.address {
+            write!(f, " at {address:#X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Turn the return value of a library call into a [Result]
+///
+/// If the call failed, the error information of the library is queried for
+/// the given core (or globally if no core is given).
+pub(crate) fn check(
+    return_code: u32,
+    core: Option<&Core<'_>>,
+    context: impl FnOnce() -> ErrorContext,
+) -> Result<()> {
+    if return_code == 0 {
+        return Ok(());
+    }
+
+    let context = context();
+    Err(match get_error(core) {
+        Some(error) => McdError::Library {
+            context,
+            error: Box::new(error),
+        },
+        None => McdError::Unreported {
+            context,
+            return_code,
+        },
+    })
+}
+
 /// Obtain a more specific error description of the latest error
 ///
 /// A core may be specified to get the last error that happened for the operation
@@ -21,11 +203,7 @@ pub fn get_error(core: Option<&'_ Core<'_>>) -> Option<Error> {
     }
 }
 
-/// Like [get_error], but it will panic if the library does not report an error
-pub fn expect_error(core: Option<&'_ Core<'_>>) -> Error {
-    get_error(core).expect("expected error, but library reported none")
-}
-
+/// The error information reported by the library
 #[derive(Debug)]
 pub struct Error {
     inner: mcd_error_info_st,
@@ -36,25 +214,29 @@ impl Error {
         McdErrorCode::from_code(self.inner.error_code)
     }
 
-    pub fn event_error_code(&self) -> EventError {
+    /// The event that caused the error, if the library reported any
+    pub fn event_error_code(&self) -> Option<EventError> {
         EventError::from_library_code(self.inner.error_events)
     }
+
+    /// The error description reported by the library
+    pub fn description(&self) -> String {
+        // SAFETY:
+        // u8 and i8 share the same memory layout, so slices can be transmuted safely.
+        let error_string = unsafe { std::mem::transmute::<&[i8], &[u8]>(&self.inner.error_str) };
+        CStr::from_bytes_until_nul(error_string)
+            .map(|info| info.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| String::from_utf8_lossy(error_string).into_owned())
+    }
 }
 
 impl std::error::Error for Error {}
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // SAFETY:
-        // u8 and i8 share the same memory layout, so slices can be transmuted safely.
-        let error_string = unsafe { std::mem::transmute::<&[i8], &[u8]>(&self.inner.error_str) };
-        let info = CStr::from_bytes_until_nul(error_string)
-            .unwrap()
-            .to_str()
-            .unwrap();
-
         f.write_fmt(format_args!(
-            "{info}, error_code = {:?}, event_code = {:?}",
+            "{}, error_code = {:?}, event_code = {:?}",
+            self.description(),
             self.error_code(),
             self.event_error_code()
         ))
@@ -79,18 +261,28 @@ pub enum EventError {
 }
 
 impl EventError {
-    fn from_library_code(code: u32) -> EventError {
-        match code as i32 {
-            crate::mcd_bindings::MCD_ERR_EVT_RESET => Self::Reset,
-            crate::mcd_bindings::MCD_ERR_EVT_PWRDN => Self::PowerDown,
-            crate::mcd_bindings::MCD_ERR_EVT_HWFAILURE => Self::HardwareFailure,
-            _ => panic!("Unsupported library event error code {:x}", code),
-        }
+    /// Interpret the event bitmask reported by the library
+    ///
+    /// If multiple events are reported, the most severe one is returned.
+    fn from_library_code(code: u32) -> Option<EventError> {
+        [Self::HardwareFailure, Self::PowerDown, Self::Reset]
+            .into_iter()
+            .find(|event| code & event.library_code() != 0)
+    }
+
+    /// The code the library uses for this event, see [Error::event_error_code]
+    pub fn library_code(&self) -> u32 {
+        let code = match self {
+            Self::Reset => crate::mcd_bindings::MCD_ERR_EVT_RESET,
+            Self::PowerDown => crate::mcd_bindings::MCD_ERR_EVT_PWRDN,
+            Self::HardwareFailure => crate::mcd_bindings::MCD_ERR_EVT_HWFAILURE,
+        };
+        code as u32
     }
 }
 
 /// See the original header files for [crate::mcd_bindings::mcd_error_code_et]
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum McdErrorCode {
     /// No error.
     McdErrNone,
@@ -185,4 +377,39 @@ impl McdErrorCode {
             code => Self::Unknown(code),
         }
     }
+
+    /// The code the library uses for this error, see [Self::from_code]
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::McdErrNone => 0,
+            Self::McdErrFnUnimplemented => 256,
+            Self::McdErrUsage => 257,
+            Self::McdErrParam => 258,
+            Self::McdErrConnection => 512,
+            Self::McdErrTimedOut => 513,
+            Self::McdErrGeneral => 3840,
+            Self::McdErrResultTooLong => 4096,
+            Self::McdErrCouldNotStartServer => 4352,
+            Self::McdErrServerLocked => 4353,
+            Self::McdErrNoMemSpaces => 5121,
+            Self::McdErrNoMemBlocks => 5122,
+            Self::McdErrMemSpaceId => 5136,
+            Self::McdErrNoRegGroups => 5184,
+            Self::McdErrRegGroupId => 5185,
+            Self::McdErrRegNotCompound => 5186,
+            Self::McdErrOverlays => 5376,
+            Self::McdErrDeviceAccess => 6400,
+            Self::McdErrDeviceLocked => 6401,
+            Self::McdErrTxlistRead => 8448,
+            Self::McdErrTxlistWrite => 8449,
+            Self::McdErrTxlistTx => 8450,
+            Self::McdErrChlTypeNotSupported => 12544,
+            Self::McdErrChlTargetNotSupported => 12545,
+            Self::McdErrChlSetup => 12546,
+            Self::McdErrChlMessageFailed => 12608,
+            Self::McdErrTrigCreate => 12800,
+            Self::McdErrTrigAccess => 12801,
+            Self::Unknown(code) => *code,
+        }
+    }
 }
@@ -2,8 +2,7 @@
 
 use std::path::PathBuf;
 
-use anyhow::Context;
-
+pub mod backend;
 pub mod breakpoint;
 pub mod core;
 pub mod error;
@@ -11,7 +10,10 @@ pub mod library;
 pub mod memory;
 pub mod registers;
 pub mod reset;
+#[cfg(feature = "simulation")]
+pub mod simulation;
 pub mod system;
+pub mod watcher;
 
 mod raw;
 mod transaction;
@@ -24,17 +26,15 @@ mod mcd_bindings {
 }
 
 lazy_static::lazy_static! {
-    static ref MCD_LIB: crate::mcd_bindings::DynamicMCDxDAS = {
-        load_library().unwrap()
-    };
+    static ref MCD_LIB: &'static dyn backend::McdBackend = library::backend();
 }
 
-fn load_library() -> anyhow::Result<crate::mcd_bindings::DynamicMCDxDAS> {
+fn load_library() -> error::Result<crate::mcd_bindings::DynamicMCDxDAS> {
     // The environment variable DAS_HOME is defined by the standard DAS installation
     let das_home = PathBuf::from(
-        std::env::var("DAS_HOME").with_context(|| "Unable to determine path to mcdxdas.dll")?,
+        std::env::var("DAS_HOME").map_err(|error| error::McdError::LoadLibrary(Box::new(error)))?,
     );
     let mcd_das_dll_path = das_home.join("bin/mcdxdas.dll");
     unsafe { crate::mcd_bindings::DynamicMCDxDAS::new(mcd_das_dll_path) }
-        .with_context(|| "Unable to load mcdxdas.dll")
+        .map_err(|error| error::McdError::LoadLibrary(Box::new(error)))
 }
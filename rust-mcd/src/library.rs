@@ -1,7 +1,9 @@
-use anyhow::Context;
+use std::sync::OnceLock;
 
 use crate::{
-    error::expect_error,
+    backend::McdBackend,
+    error::{check, ErrorContext, McdError, Result},
+    load_library,
     mcd_bindings::{
         mcd_api_version_st, mcd_impl_version_info_st, MCD_API_VER_AUTHOR, MCD_API_VER_MAJOR,
         MCD_API_VER_MINOR,
@@ -9,9 +11,42 @@ use crate::{
     MCD_LIB,
 };
 
+static BACKEND: OnceLock<Box<dyn McdBackend>> = OnceLock::new();
+
+/// Use the given backend instead of `mcdxdas.dll` for all calls to the MCD API
+///
+/// This must be called before any other function of this library, otherwise
+/// the default backend was already loaded and an error is returned.
+pub fn set_backend(backend: impl McdBackend + 'static) -> Result<()> {
+    BACKEND
+        .set(Box::new(backend))
+        .map_err(|_| McdError::BackendAlreadyInitialized)
+}
+
+/// The backend all calls to the MCD API are forwarded to
+///
+/// Loads `mcdxdas.dll` if no other backend was set with [set_backend].
+///
+/// # Panic
+/// Panics if `mcdxdas.dll` cannot be loaded, call [init] first to handle this
+/// case gracefully.
+pub(crate) fn backend() -> &'static dyn McdBackend {
+    BACKEND
+        .get_or_init(|| Box::new(load_library().unwrap()))
+        .as_ref()
+}
+
 /// Initialize the library
-pub fn init() {
+///
+/// Loads `mcdxdas.dll` if no other backend was set with [set_backend].
+pub fn init() -> Result<()> {
     log::debug!("Initializing MCD library");
+    if BACKEND.get().is_none() {
+        // Ignore the error, another thread might have initialized the backend
+        // in the meantime
+        let _ = BACKEND.set(Box::new(load_library()?));
+    }
+
     let mut author = [0i8; 32];
     let string = MCD_API_VER_AUTHOR.map(|c| c as i8);
     author[0..(string.len())].copy_from_slice(string.as_slice());
@@ -33,10 +68,10 @@ pub fn init() {
         date: [0; 16],
     };
     let result = unsafe { MCD_LIB.mcd_initialize_f(&version_requirement, &mut output) };
-    assert_eq!(result, 0);
+    check(result, None, || ErrorContext::new("initialize library"))
 }
 
-pub fn scan_open_servers() -> anyhow::Result<u32> {
+pub fn scan_open_servers() -> Result<u32> {
     log::trace!("Scanning for open servers");
     let host = b"localhost\0";
     let mut num_open_servers = 0u32;
@@ -50,9 +85,6 @@ pub fn scan_open_servers() -> anyhow::Result<u32> {
         )
     };
 
-    if result != 0 {
-        Err(expect_error(None)).with_context(|| "MCD Library reported an error")
-    } else {
-        Ok(num_open_servers)
-    }
+    check(result, None, || ErrorContext::new("scan for open servers"))?;
+    Ok(num_open_servers)
 }
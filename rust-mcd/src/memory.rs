@@ -4,13 +4,11 @@
 //! poor understanding of the concept) and mostly untested.
 //!
 #![allow(dead_code)]
-use std::{ffi::CStr, fmt::Debug, ptr};
-
-use anyhow::Context;
+use std::{borrow::Cow, ffi::CStr, fmt::Debug, ptr};
 
 use crate::{
     core::Core,
-    error::expect_error,
+    error::Result,
     mcd_bindings::{mcd_memblock_st, mcd_memspace_st, MCD_MEM_BLOCK_NOPARENT},
     MCD_LIB,
 };
@@ -21,12 +19,12 @@ pub struct MemorySpace<'a> {
 }
 
 impl<'a> MemorySpace<'a> {
-    pub fn get_all(core: &'a Core) -> Vec<MemorySpace<'a>> {
+    pub fn get_all(core: &'a Core) -> Result<Vec<MemorySpace<'a>>> {
         let mut query_spaces = 0;
         let result = unsafe {
             MCD_LIB.mcd_qry_mem_spaces_f(core.core, 0, &mut query_spaces, ptr::null_mut())
         };
-        assert_eq!(result, 0);
+        core.check(result, || core.context("query the number of memory spaces"))?;
 
         println!("Found {:?} spaces", &query_spaces);
         let mut reserved_spaces = Vec::new();
@@ -42,15 +40,15 @@ impl<'a> MemorySpace<'a> {
                 reserved_spaces.as_mut_ptr(),
             )
         };
-        assert_eq!(result, 0);
+        core.check(result, || core.context("query memory spaces"))?;
 
-        reserved_spaces
+        Ok(reserved_spaces
             .into_iter()
             .map(|inner| MemorySpace { inner, core })
-            .collect()
+            .collect())
     }
 
-    pub fn get_blocks(&self) -> anyhow::Result<Vec<MemoryBlock>> {
+    pub fn get_blocks(&self) -> Result<Vec<MemoryBlock<'_>>> {
         log::trace!("Querying for the number of memory spaces");
 
         let mut result_block_count = 0;
@@ -65,10 +63,9 @@ impl<'a> MemorySpace<'a> {
             )
         };
 
-        if result != 0 {
-            return Err(expect_error(Some(self.core)))
-                .with_context(|| "MCD library reported an error");
-        }
+        self.core.check(result, || {
+            self.core.context("query the number of memory blocks")
+        })?;
 
         log::trace!(
             "Querying additional information for {:?} blocks",
@@ -86,10 +83,8 @@ impl<'a> MemorySpace<'a> {
                 reserved_spaces.as_mut_ptr(),
             )
         };
-        if result != 0 {
-            return Err(expect_error(Some(self.core)))
-                .with_context(|| "MCD library reported an error");
-        }
+        self.core
+            .check(result, || self.core.context("query memory blocks"))?;
 
         Ok(reserved_spaces
             .into_iter()
@@ -101,10 +96,8 @@ impl<'a> MemorySpace<'a> {
     }
 
     /// The name of this space as reported from the debug controller
-    pub fn get_name(&self) -> &str {
-        unsafe { CStr::from_ptr(&self.inner.mem_space_name[0] as *const i8) }
-            .to_str()
-            .unwrap()
+    pub fn get_name(&self) -> Cow<'_, str> {
+        unsafe { CStr::from_ptr(&self.inner.mem_space_name[0] as *const i8) }.to_string_lossy()
     }
 }
 
@@ -135,10 +128,8 @@ impl<'a> MemoryBlock<'a> {
     }
 
     /// The name of this block as reported from the debug controller
-    pub fn name(&self) -> &str {
-        unsafe { CStr::from_ptr(&self.inner.mem_block_name[0] as *const i8) }
-            .to_str()
-            .unwrap()
+    pub fn name(&self) -> Cow<'_, str> {
+        unsafe { CStr::from_ptr(&self.inner.mem_block_name[0] as *const i8) }.to_string_lossy()
     }
 }
 
@@ -1,11 +1,10 @@
-use anyhow::{Context, Result};
-
 use crate::{
-    error::get_error,
-    mcd_bindings::{mcd_core_con_info_st, DynamicMCDxDAS, MCD_ERR_NONE},
+    backend::McdBackend,
+    error::{check, ErrorContext, Result},
+    mcd_bindings::mcd_core_con_info_st,
 };
 
-impl DynamicMCDxDAS {
+impl dyn McdBackend {
     /// See [Self::mcd_qry_cores_f]
     pub fn query_core_info(
         &self,
@@ -13,10 +12,10 @@ impl DynamicMCDxDAS {
         start_index: u32,
         core_query_count: u32,
     ) -> Result<Vec<mcd_core_con_info_st>> {
-        assert!(
-            core_query_count > 0,
-            "Can only query non-zero number of cores"
-        );
+        // Querying zero cores would return the number of cores instead
+        if core_query_count == 0 {
+            return Ok(Vec::new());
+        }
 
         let mut core_info = vec![mcd_core_con_info_st::default(); core_query_count as usize];
         let mut num_cores = core_query_count;
@@ -30,12 +29,10 @@ impl DynamicMCDxDAS {
             )
         };
 
-        if result != (MCD_ERR_NONE as u32) {
-            return Err(get_error(None).unwrap())
-                .with_context(|| "Cannot query cores of the system");
-        } else {
-            Ok(core_info)
-        }
+        check(result, None, || {
+            ErrorContext::new("query cores of the system")
+        })?;
+        Ok(core_info)
     }
 
     /// See [Self::mcd_qry_cores_f], with `num_devices` set to 0
@@ -51,11 +48,9 @@ impl DynamicMCDxDAS {
             )
         };
 
-        if result != (MCD_ERR_NONE as u32) {
-            return Err(get_error(None).unwrap())
-                .with_context(|| "Cannot query cores of the system");
-        } else {
-            Ok(num_cores)
-        }
+        check(result, None, || {
+            ErrorContext::new("query the number of cores of the system")
+        })?;
+        Ok(num_cores)
     }
 }
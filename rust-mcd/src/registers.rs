@@ -2,13 +2,10 @@
 //!
 //! Registers are grouped in multiple groups within a core.
 
-use std::{ffi::CStr, fmt::Debug, io::Cursor};
-
-use anyhow::Context;
-use byteorder::ReadBytesExt;
+use std::{ffi::CStr, fmt::Debug};
 
 use crate::{
-    error::expect_error,
+    error::{McdError, Result},
     mcd_bindings::{mcd_register_group_st, mcd_register_info_st},
     MCD_LIB,
 };
@@ -22,17 +19,16 @@ pub struct RegisterGroups<'a> {
 }
 
 impl<'a> RegisterGroups<'a> {
-    pub(crate) fn from_core(core: &'a Core<'a>) -> anyhow::Result<Self> {
+    pub(crate) fn from_core(core: &'a Core<'a>) -> Result<Self> {
         let mut number_of_groups = 0;
 
         let result = unsafe {
             MCD_LIB.mcd_qry_reg_groups_f(core.core, 0, &mut number_of_groups, core::ptr::null_mut())
         };
 
-        if result != 0 {
-            return Err(expect_error(Some(core)))
-                .with_context(|| "Library reported an internal error");
-        }
+        core.check(result, || {
+            core.context("query the number of register groups")
+        })?;
 
         let mut register_groups = vec![mcd_register_group_st::default(); number_of_groups as usize];
 
@@ -45,10 +41,7 @@ impl<'a> RegisterGroups<'a> {
             )
         };
 
-        if result != 0 {
-            return Err(expect_error(Some(core)))
-                .with_context(|| "Unable to query register groups");
-        }
+        core.check(result, || core.context("query register groups"))?;
 
         Ok(Self {
             core,
@@ -61,8 +54,15 @@ impl<'a> RegisterGroups<'a> {
     /// TODO Add/Reference documentation what the signifcance of index is. For
     /// a trivial setup with the Aurix Lite Kit v2 connected over MicroUSB, this
     /// must be 0.
-    pub fn get_group(&self, index: usize) -> anyhow::Result<RegisterGroup> {
-        let register_group = &self.register_groups[index];
+    pub fn get_group(&self, index: usize) -> Result<RegisterGroup<'_>> {
+        let register_group =
+            self.register_groups
+                .get(index)
+                .ok_or_else(|| McdError::NoSuchRegisterGroup {
+                    core: self.core.name(),
+                    index,
+                    count: self.register_groups.len(),
+                })?;
 
         let mut number_of_registers = Default::default();
 
@@ -75,13 +75,19 @@ impl<'a> RegisterGroups<'a> {
                 core::ptr::null_mut(),
             )
         };
-        if result != 0 {
-            return Err(expect_error(Some(self.core)))
-                .with_context(|| "Unable to query the number of registers in register group");
-        }
+        self.core.check(result, || {
+            self.core
+                .context("query the number of registers in register group")
+        })?;
 
         // This should be the same, isn't it?
-        assert_eq!(number_of_registers, register_group.n_registers);
+        if number_of_registers != register_group.n_registers {
+            return Err(McdError::RegisterCountMismatch {
+                core: self.core.name(),
+                expected: register_group.n_registers,
+                actual: number_of_registers,
+            });
+        }
 
         let mut registers = vec![mcd_register_info_st::default(); number_of_registers as usize];
 
@@ -95,10 +101,8 @@ impl<'a> RegisterGroups<'a> {
             )
         };
 
-        if result != 0 {
-            return Err(expect_error(Some(self.core)))
-                .with_context(|| "Unable to query registers in group");
-        }
+        self.core
+            .check(result, || self.core.context("query registers in group"))?;
 
         Ok(RegisterGroup {
             core: self.core,
@@ -153,20 +157,24 @@ pub struct Register<'a> {
 
 impl<'a> Register<'a> {
     /// Read the value of this register
-    pub fn read(&self) -> anyhow::Result<u32> {
+    pub fn read(&self) -> Result<u32> {
         let data = self.core.read_bytes(self.register.addr.address, 4)?;
-        let mut cursor = Cursor::new(data);
-        cursor
-            .read_u32::<byteorder::LE>()
-            .with_context(|| "could not transform data to register value")
+        let mut value = [0; 4];
+        value.copy_from_slice(&data[..4]);
+        Ok(u32::from_le_bytes(value))
+    }
+
+    /// Write the value of this register
+    pub fn write(&self, value: u32) -> Result<()> {
+        self.core
+            .write(self.register.addr.address, value.to_le_bytes().to_vec())
     }
 
     /// The name of the register as reported from the debug controller
     pub fn name(&self) -> String {
         unsafe { CStr::from_ptr(&self.register.regname[0] as *const i8) }
-            .to_str()
-            .unwrap()
-            .to_owned()
+            .to_string_lossy()
+            .into_owned()
     }
 }
 
@@ -1,10 +1,9 @@
-use anyhow::Context;
-
-use crate::{error::expect_error, mcd_bindings::mcd_rst_info_st, MCD_LIB};
+use crate::{error::Result, mcd_bindings::mcd_rst_info_st, MCD_LIB};
 
 use super::core::Core;
 
 use std::{
+    borrow::Cow,
     ffi::CStr,
     fmt::{Debug, Display},
 };
@@ -14,12 +13,16 @@ pub struct ResetInfo {
     inner: mcd_rst_info_st,
 }
 
+impl ResetInfo {
+    /// The description of the reset class as reported from the debug controller
+    pub fn description(&self) -> Cow<'_, str> {
+        unsafe { CStr::from_ptr(&self.inner.info_str[0] as *const i8) }.to_string_lossy()
+    }
+}
+
 impl Display for ResetInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let info = unsafe { CStr::from_ptr(&self.inner.info_str[0] as *const i8) }
-            .to_str()
-            .unwrap();
-        f.write_fmt(format_args!("Reset[{:?}]", info))
+        f.write_fmt(format_args!("Reset[{:?}]", self.description()))
     }
 }
 
@@ -49,6 +52,11 @@ impl<'a> ResetClass<'a> {
         1u32 << self.bit_set
     }
 
+    /// The index of this reset class, e.g. 0 for the first reset class
+    pub fn class(&self) -> u8 {
+        self.bit_set
+    }
+
     /// Construct a reset class from a known reset class
     ///
     /// The user should make sure that the class also exists for the specified core,
@@ -61,15 +69,15 @@ impl<'a> ResetClass<'a> {
         }
     }
 
-    pub fn get_info(&self) -> anyhow::Result<ResetInfo> {
+    pub fn get_info(&self) -> Result<ResetInfo> {
         let mut output = mcd_rst_info_st::default();
         let result =
             unsafe { MCD_LIB.mcd_qry_rst_class_info_f(self.core.core, self.bit_set, &mut output) };
 
-        if result != 0 {
-            Err(expect_error(Some(self.core))).with_context(|| "Library reported an error")
-        } else {
-            Ok(output.into())
-        }
+        self.core.check(result, || {
+            self.core.context("query reset class information")
+        })?;
+
+        Ok(output.into())
     }
 }
@@ -0,0 +1,1317 @@
+//! A simulated implementation of the MCD API that does not require the DAS
+//! installation or any hardware, see [SimulatedMcd]
+//!
+//! The simulation emulates a single server with a single system. Cores,
+//! memory, registers and reset classes are configured by the user, the code
+//! running on a core is emulated by a [Program] that is executed while the
+//! core runs. Errors can be injected for any [Operation] to exercise the error
+//! paths of this library.
+//!
+//! ```
+//! use rust_mcd::{library, simulation::SimulatedMcd, system::System};
+//!
+//! # fn main() -> rust_mcd::error::Result<()> {
+//! let simulation = SimulatedMcd::new();
+//! let core = simulation.add_core("core0");
+//! simulation.add_reset_class(core, 0, "System reset");
+//! simulation.write_memory(core, 0x7000_0000, b"SEGGER RTT");
+//!
+//! library::set_backend(simulation.clone())?;
+//! library::init()?;
+//!
+//! let system = System::connect()?;
+//! let core = system.get_core(0)?;
+//! assert_eq!(&core.read_bytes(0x7000_0000, 10)?, b"SEGGER RTT");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    ops::Deref,
+    os::raw::{c_char, c_void},
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+use crate::{
+    backend::McdBackend,
+    core::CoreState,
+    error::{self, EventError, McdErrorCode},
+    library,
+    mcd_bindings::{
+        mcd_api_version_st, mcd_bool_t, mcd_char_t, mcd_core_con_info_st, mcd_core_st,
+        mcd_core_state_st, mcd_core_step_type_et, mcd_error_info_st, mcd_impl_version_info_st,
+        mcd_memblock_st, mcd_memspace_st, mcd_register_group_st, mcd_register_info_st,
+        mcd_return_et, mcd_rst_info_st, mcd_server_info_st, mcd_server_st, mcd_trig_set_state_st,
+        mcd_trig_simple_core_st, mcd_trig_state_st, mcd_txlist_st, MCD_CORE_EVENT_NONE,
+        MCD_CORE_EVENT_STOPPED, MCD_CORE_STATE_DEBUG, MCD_CORE_STATE_HALTED,
+        MCD_CORE_STATE_RUNNING, MCD_ERR_EVT_NONE, MCD_GUARANTEED_MIN_PAYLOAD,
+        MCD_RET_ACT_HANDLE_ERROR, MCD_TRIG_TYPE_IP, MCD_TRIG_TYPE_READ, MCD_TRIG_TYPE_RW,
+        MCD_TRIG_TYPE_WRITE, MCD_TX_AT_R, MCD_TX_AT_W, TRUE,
+    },
+};
+
+/// Identifies a group of MCD API functions, used to inject errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    QueryServers,
+    OpenServer,
+    QuerySystems,
+    QueryCores,
+    OpenCore,
+    QueryMemory,
+    QueryRegisters,
+    QueryPayloadSize,
+    ExecuteTransactions,
+    Run,
+    Stop,
+    Step,
+    QueryState,
+    QueryResetClasses,
+    Reset,
+    CreateTrigger,
+    RemoveTrigger,
+    QueryTriggerState,
+    ActivateTriggers,
+}
+
+/// An error the simulation reports instead of executing an [Operation]
+#[derive(Debug, Clone)]
+pub struct SimulatedError {
+    return_value: u32,
+    error_code: u32,
+    error_events: u32,
+    message: String,
+}
+
+impl SimulatedError {
+    /// An error with the given error code that has to be handled by the caller
+    pub fn new(error_code: McdErrorCode, message: impl Into<String>) -> Self {
+        SimulatedError {
+            return_value: MCD_RET_ACT_HANDLE_ERROR as u32,
+            error_code: error_code.code(),
+            error_events: MCD_ERR_EVT_NONE as u32,
+            message: message.into(),
+        }
+    }
+
+    /// The device was reset while the operation was executed
+    pub fn reset_event() -> Self {
+        SimulatedError::new(McdErrorCode::McdErrDeviceAccess, "Device was reset")
+            .with_event(EventError::Reset)
+    }
+
+    /// Report the given event in addition to the error code
+    pub fn with_event(mut self, event: EventError) -> Self {
+        self.error_events = event.library_code();
+        self
+    }
+
+    /// Return the given value from the API function instead of
+    /// `MCD_RET_ACT_HANDLE_ERROR`
+    ///
+    /// The DAS implementation e.g. returns 2 if opening a core must be retried.
+    pub fn with_return_value(mut self, return_value: u32) -> Self {
+        self.return_value = return_value;
+        self
+    }
+
+    fn to_error_info(&self) -> mcd_error_info_st {
+        let mut info = mcd_error_info_st {
+            return_status: self.return_value,
+            error_code: self.error_code,
+            error_events: self.error_events,
+            ..Default::default()
+        };
+        copy_string(&mut info.error_str, &self.message);
+        info
+    }
+}
+
+/// Emulates the code that runs on a simulated core
+///
+/// The program is invoked every time the state of a running core is queried
+/// and once for every single step.
+pub type Program = Box<dyn FnMut(&mut ProgramContext) + Send>;
+
+/// Access to a simulated core from within its [Program]
+pub struct ProgramContext<'a> {
+    core: &'a mut SimulatedCore,
+}
+
+impl<'a> ProgramContext<'a> {
+    /// Read memory of the core
+    pub fn read_memory(&self, address: u64, length: usize) -> Vec<u8> {
+        self.core.read_memory(address, length)
+    }
+
+    /// Write memory of the core, halts the core if an active read/write
+    /// trigger covers the written memory
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) {
+        self.core.write_memory(address, data);
+
+        let end = address + data.len() as u64;
+        let hit = self.core.active_trigger(|trigger| {
+            trigger.is_data_trigger() && trigger.address < end && address < trigger.end()
+        });
+        if let Some(trigger_id) = hit {
+            self.core.halt(MCD_CORE_STATE_DEBUG as u32, trigger_id);
+        }
+    }
+
+    /// Continue execution at the given address, halts the core if an active
+    /// instruction pointer trigger covers the address
+    pub fn execute_at(&mut self, address: u64) {
+        let hit = self.core.active_trigger(|trigger| {
+            trigger.kind == MCD_TRIG_TYPE_IP as u32
+                && trigger.address <= address
+                && address < trigger.end()
+        });
+        if let Some(trigger_id) = hit {
+            self.core.halt(MCD_CORE_STATE_DEBUG as u32, trigger_id);
+        }
+    }
+
+    /// Halt the core, e.g. as if it executed a debug instruction
+    pub fn halt(&mut self) {
+        self.core.halt(MCD_CORE_STATE_DEBUG as u32, 0);
+    }
+
+    /// Whether the core is still running
+    pub fn is_running(&self) -> bool {
+        self.core.state == MCD_CORE_STATE_RUNNING as u32
+    }
+}
+
+/// A simulated MCD implementation, see the [module documentation](self)
+///
+/// Clones share the same simulation, so a clone can be installed with
+/// [crate::library::set_backend] while the original is used to configure and
+/// inspect the simulation.
+#[derive(Clone)]
+pub struct SimulatedMcd {
+    simulation: Arc<Mutex<Simulation>>,
+}
+
+impl Default for SimulatedMcd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedMcd {
+    /// A simulation with a single server and no cores
+    pub fn new() -> Self {
+        SimulatedMcd {
+            simulation: Arc::new(Mutex::new(Simulation::new())),
+        }
+    }
+
+    /// Install a simulation as backend of this process and reserve it for the
+    /// caller until the returned guard is dropped
+    ///
+    /// The backend can only be set once per process, so all callers share the
+    /// same simulation, e.g. tests that run in parallel. It is reset to a single
+    /// server without any cores whenever it is reserved.
+    pub fn exclusive() -> error::Result<ExclusiveSimulation> {
+        static SHARED: OnceLock<SimulatedMcd> = OnceLock::new();
+        static RESERVATION: Mutex<()> = Mutex::new(());
+
+        // A caller that panicked while holding the reservation leaves no state
+        // behind that survives the reset below
+        let reservation = RESERVATION.lock().unwrap_or_else(PoisonError::into_inner);
+        let simulation = match SHARED.get() {
+            Some(simulation) => simulation.clone(),
+            None => {
+                let simulation = SimulatedMcd::new();
+                library::set_backend(simulation.clone())?;
+                library::init()?;
+                SHARED.get_or_init(|| simulation).clone()
+            }
+        };
+
+        *simulation
+            .simulation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Simulation::new();
+
+        Ok(ExclusiveSimulation {
+            simulation,
+            _reservation: reservation,
+        })
+    }
+
+    /// Add a core that is halted initially, returns the index of the core
+    pub fn add_core(&self, name: &str) -> usize {
+        let mut simulation = self.lock();
+        simulation.cores.push(SimulatedCore {
+            name: name.to_owned(),
+            handle: None,
+            memory: BTreeMap::new(),
+            registers: Vec::new(),
+            reset_classes: BTreeMap::new(),
+            payload_size: MCD_GUARANTEED_MIN_PAYLOAD,
+            state: MCD_CORE_STATE_HALTED as u32,
+            events: MCD_CORE_EVENT_NONE as u32,
+            stopped_by_trigger: 0,
+            triggers: BTreeMap::new(),
+            next_trigger_id: 1,
+            program: None,
+            last_error: None,
+        });
+        simulation.cores.len() - 1
+    }
+
+    /// Change the number of servers that are reported to be running
+    ///
+    /// The library only supports a single server, other values can be used to
+    /// test how this is handled.
+    pub fn set_server_count(&self, server_count: u32) {
+        self.lock().server_count = server_count;
+    }
+
+    /// Add a memory mapped register to the register group of the core
+    pub fn add_register(&self, core: usize, name: &str, address: u64) {
+        self.lock().cores[core]
+            .registers
+            .push((name.to_owned(), address));
+    }
+
+    /// Set the value of a register that was added with [Self::add_register]
+    ///
+    /// # Panic
+    /// Panics if the register does not exist.
+    pub fn set_register(&self, core: usize, name: &str, value: u32) {
+        let mut simulation = self.lock();
+        let core = &mut simulation.cores[core];
+        let address = core
+            .registers
+            .iter()
+            .find(|(register, _)| register == name)
+            .map(|(_, address)| *address)
+            .unwrap_or_else(|| panic!("Register {name} does not exist"));
+        core.write_memory(address, &value.to_le_bytes());
+    }
+
+    /// Offer the reset class with the given index for the core
+    pub fn add_reset_class(&self, core: usize, class: u8, description: &str) {
+        assert!(class < 32, "Reset class must be smaller than 32");
+        self.lock().cores[core]
+            .reset_classes
+            .insert(class, description.to_owned());
+    }
+
+    /// Change the maximum payload size of a single transaction
+    pub fn set_payload_size(&self, core: usize, payload_size: u32) {
+        self.lock().cores[core].payload_size = payload_size;
+    }
+
+    /// Write memory of the core, without triggering any breakpoints
+    pub fn write_memory(&self, core: usize, address: u64, data: &[u8]) {
+        self.lock().cores[core].write_memory(address, data);
+    }
+
+    /// Read memory of the core, memory that was never written reads as zero
+    pub fn read_memory(&self, core: usize, address: u64, length: usize) -> Vec<u8> {
+        self.lock().cores[core].read_memory(address, length)
+    }
+
+    /// Emulate the code running on the core with the given program
+    pub fn set_program(
+        &self,
+        core: usize,
+        program: impl FnMut(&mut ProgramContext) + Send + 'static,
+    ) {
+        self.lock().cores[core].program = Some(Box::new(program));
+    }
+
+    /// Let the next call of the operation fail with the given error
+    ///
+    /// Multiple errors for the same operation are reported in the order they
+    /// were injected.
+    pub fn inject_error(&self, operation: Operation, error: SimulatedError) {
+        self.lock()
+            .injected_errors
+            .entry(operation)
+            .or_default()
+            .push_back(error);
+    }
+
+    /// The current state of the core
+    pub fn state(&self, core: usize) -> CoreState {
+        self.lock().cores[core]
+            .state
+            .try_into()
+            .expect("Simulated cores are always in a known state")
+    }
+
+    /// Addresses of the triggers that currently exist for the core
+    pub fn trigger_addresses(&self, core: usize) -> Vec<u64> {
+        self.lock().cores[core]
+            .triggers
+            .values()
+            .map(|trigger| trigger.address)
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Simulation> {
+        self.simulation.lock().unwrap()
+    }
+
+    /// Execute an operation that is not related to a specific core
+    fn with_simulation(
+        &self,
+        operation: Operation,
+        execute: impl FnOnce(&mut Simulation) -> Result<(), SimulatedError>,
+    ) -> mcd_return_et {
+        let mut simulation = self.lock();
+        let result = match simulation.take_injected_error(operation) {
+            Some(error) => Err(error),
+            None => execute(&mut simulation),
+        };
+
+        match result {
+            Ok(()) => {
+                simulation.last_error = None;
+                0
+            }
+            Err(error) => {
+                let return_value = error.return_value;
+                simulation.last_error = Some(error);
+                return_value
+            }
+        }
+    }
+
+    /// Execute an operation on the core the handle refers to
+    fn with_core(
+        &self,
+        core: *const mcd_core_st,
+        operation: Operation,
+        execute: impl FnOnce(&mut SimulatedCore) -> Result<(), SimulatedError>,
+    ) -> mcd_return_et {
+        let mut simulation = self.lock();
+        let Some(index) = simulation.core_index(core) else {
+            let error = SimulatedError::new(McdErrorCode::McdErrParam, "Invalid core handle");
+            let return_value = error.return_value;
+            simulation.last_error = Some(error);
+            return return_value;
+        };
+
+        let injected_error = simulation.take_injected_error(operation);
+        let core = &mut simulation.cores[index];
+        let result = match injected_error {
+            Some(error) => Err(error),
+            None => execute(core),
+        };
+
+        match result {
+            Ok(()) => {
+                core.last_error = None;
+                0
+            }
+            Err(error) => {
+                let return_value = error.return_value;
+                core.last_error = Some(error);
+                return_value
+            }
+        }
+    }
+}
+
+/// The simulation installed as backend, see [SimulatedMcd::exclusive]
+pub struct ExclusiveSimulation {
+    simulation: SimulatedMcd,
+    _reservation: MutexGuard<'static, ()>,
+}
+
+impl Deref for ExclusiveSimulation {
+    type Target = SimulatedMcd;
+
+    fn deref(&self) -> &Self::Target {
+        &self.simulation
+    }
+}
+
+struct Simulation {
+    server_count: u32,
+    server: Option<Handle<mcd_server_st>>,
+    cores: Vec<SimulatedCore>,
+    injected_errors: HashMap<Operation, VecDeque<SimulatedError>>,
+    last_error: Option<SimulatedError>,
+}
+
+impl Simulation {
+    fn new() -> Self {
+        Simulation {
+            server_count: 1,
+            server: None,
+            cores: Vec::new(),
+            injected_errors: HashMap::new(),
+            last_error: None,
+        }
+    }
+
+    fn take_injected_error(&mut self, operation: Operation) -> Option<SimulatedError> {
+        self.injected_errors.get_mut(&operation)?.pop_front()
+    }
+
+    /// Resolve a core handle that was returned by `mcd_open_core_f`
+    fn core_index(&self, core: *const mcd_core_st) -> Option<usize> {
+        self.cores.iter().position(|simulated| {
+            simulated
+                .handle
+                .as_ref()
+                .is_some_and(|handle| std::ptr::eq(handle.0.as_ref(), core))
+        })
+    }
+
+    fn connection_info(&self, core: usize) -> mcd_core_con_info_st {
+        let mut info = mcd_core_con_info_st {
+            core_id: core as u32,
+            ..Default::default()
+        };
+        copy_string(&mut info.host, "localhost");
+        copy_string(&mut info.system, "Simulated system");
+        copy_string(&mut info.core, &self.cores[core].name);
+        info
+    }
+}
+
+struct SimulatedCore {
+    name: String,
+    handle: Option<Handle<mcd_core_st>>,
+    memory: BTreeMap<u64, u8>,
+    registers: Vec<(String, u64)>,
+    reset_classes: BTreeMap<u8, String>,
+    payload_size: u32,
+    state: u32,
+    events: u32,
+    stopped_by_trigger: u32,
+    triggers: BTreeMap<u32, SimulatedTrigger>,
+    next_trigger_id: u32,
+    program: Option<Program>,
+    last_error: Option<SimulatedError>,
+}
+
+impl SimulatedCore {
+    fn read_memory(&self, address: u64, length: usize) -> Vec<u8> {
+        (address..address + length as u64)
+            .map(|address| self.memory.get(&address).copied().unwrap_or(0))
+            .collect()
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) {
+        for (address, byte) in (address..).zip(data) {
+            self.memory.insert(address, *byte);
+        }
+    }
+
+    fn halt(&mut self, state: u32, trigger_id: u32) {
+        self.state = state;
+        self.events = MCD_CORE_EVENT_STOPPED as u32;
+        self.stopped_by_trigger = trigger_id;
+
+        if let Some(trigger) = self.triggers.get_mut(&trigger_id) {
+            trigger.captured = true;
+        }
+    }
+
+    fn active_trigger(&self, matches: impl Fn(&SimulatedTrigger) -> bool) -> Option<u32> {
+        self.triggers
+            .iter()
+            .find(|(_, trigger)| trigger.active && matches(trigger))
+            .map(|(id, _)| *id)
+    }
+
+    /// Let the program of the core execute once, if there is any
+    fn execute_program(&mut self) {
+        let Some(mut program) = self.program.take() else {
+            return;
+        };
+        program(&mut ProgramContext { core: self });
+        self.program = Some(program);
+    }
+
+    fn handle(&mut self) -> *mut mcd_core_st {
+        let handle = self.handle.get_or_insert_with(|| {
+            Handle(Box::new(mcd_core_st {
+                instance: std::ptr::null_mut(),
+                core_con_info: std::ptr::null(),
+            }))
+        });
+        handle.0.as_mut()
+    }
+}
+
+struct SimulatedTrigger {
+    kind: u32,
+    address: u64,
+    range: u64,
+    active: bool,
+    captured: bool,
+}
+
+impl SimulatedTrigger {
+    fn end(&self) -> u64 {
+        self.address + self.range.max(1)
+    }
+
+    fn is_data_trigger(&self) -> bool {
+        [MCD_TRIG_TYPE_RW, MCD_TRIG_TYPE_WRITE]
+            .iter()
+            .any(|kind| *kind as u32 == self.kind)
+    }
+}
+
+/// Owns a structure that is handed out to the user of the MCD API by pointer
+struct Handle<T>(Box<T>);
+
+// SAFETY: The handles only contain pointers that are never dereferenced by the
+// simulation, they are merely used to identify servers and cores.
+unsafe impl<T> Send for Handle<T> {}
+
+/// Copy the string into the null-terminated character array
+fn copy_string(target: &mut [c_char], value: &str) {
+    let length = value.len().min(target.len() - 1);
+    for (target, source) in target.iter_mut().zip(&value.as_bytes()[..length]) {
+        *target = *source as c_char;
+    }
+    target[length] = 0;
+}
+
+/// Write the entries to the output array as specified by the MCD API
+///
+/// If no output array is given or the requested count is zero, only the number
+/// of available entries is reported.
+unsafe fn write_entries<T: Clone>(
+    entries: &[T],
+    start_index: u32,
+    count: *mut u32,
+    output: *mut T,
+) -> Result<(), SimulatedError> {
+    let available = entries.get(start_index as usize..).unwrap_or_default();
+
+    if output.is_null() || *count == 0 {
+        *count = available.len() as u32;
+        return Ok(());
+    }
+
+    let written = available.len().min(*count as usize);
+    std::slice::from_raw_parts_mut(output, written).clone_from_slice(&available[..written]);
+    *count = written as u32;
+    Ok(())
+}
+
+impl McdBackend for SimulatedMcd {
+    unsafe fn mcd_initialize_f(
+        &self,
+        _version_req: *const mcd_api_version_st,
+        impl_info: *mut mcd_impl_version_info_st,
+    ) -> mcd_return_et {
+        copy_string(&mut (*impl_info).vendor, "Simulation");
+        0
+    }
+
+    unsafe fn mcd_exit_f(&self) {}
+
+    unsafe fn mcd_qry_servers_f(
+        &self,
+        _host: *const mcd_char_t,
+        _running: mcd_bool_t,
+        start_index: u32,
+        num_servers: *mut u32,
+        server_info: *mut mcd_server_info_st,
+    ) -> mcd_return_et {
+        self.with_simulation(Operation::QueryServers, |simulation| {
+            let servers: Vec<_> = (0..simulation.server_count)
+                .map(|_| {
+                    let mut info = mcd_server_info_st::default();
+                    copy_string(&mut info.server, "Simulated server");
+                    info
+                })
+                .collect();
+            write_entries(&servers, start_index, num_servers, server_info)
+        })
+    }
+
+    unsafe fn mcd_open_server_f(
+        &self,
+        _system_key: *const mcd_char_t,
+        _config_string: *const mcd_char_t,
+        server: *mut *mut mcd_server_st,
+    ) -> mcd_return_et {
+        self.with_simulation(Operation::OpenServer, |simulation| {
+            let handle = simulation.server.get_or_insert_with(|| {
+                Handle(Box::new(mcd_server_st {
+                    instance: std::ptr::null_mut(),
+                    host: std::ptr::null(),
+                    config_string: std::ptr::null(),
+                }))
+            });
+            *server = handle.0.as_mut();
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_qry_systems_f(
+        &self,
+        start_index: u32,
+        num_systems: *mut u32,
+        system_con_info: *mut mcd_core_con_info_st,
+    ) -> mcd_return_et {
+        self.with_simulation(Operation::QuerySystems, |_| {
+            let mut system = mcd_core_con_info_st::default();
+            copy_string(&mut system.host, "localhost");
+            copy_string(&mut system.system, "Simulated system");
+            write_entries(&[system], start_index, num_systems, system_con_info)
+        })
+    }
+
+    unsafe fn mcd_qry_cores_f(
+        &self,
+        _connection_info: *const mcd_core_con_info_st,
+        start_index: u32,
+        num_cores: *mut u32,
+        core_con_info: *mut mcd_core_con_info_st,
+    ) -> mcd_return_et {
+        self.with_simulation(Operation::QueryCores, |simulation| {
+            let cores: Vec<_> = (0..simulation.cores.len())
+                .map(|core| simulation.connection_info(core))
+                .collect();
+            write_entries(&cores, start_index, num_cores, core_con_info)
+        })
+    }
+
+    unsafe fn mcd_open_core_f(
+        &self,
+        core_con_info: *const mcd_core_con_info_st,
+        core: *mut *mut mcd_core_st,
+    ) -> mcd_return_et {
+        self.with_simulation(Operation::OpenCore, |simulation| {
+            let index = (*core_con_info).core_id as usize;
+            let simulated = simulation.cores.get_mut(index).ok_or_else(|| {
+                SimulatedError::new(McdErrorCode::McdErrParam, "Core does not exist")
+            })?;
+            *core = simulated.handle();
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_qry_error_info_f(
+        &self,
+        core: *const mcd_core_st,
+        error_info: *mut mcd_error_info_st,
+    ) {
+        let simulation = self.lock();
+        let error = match simulation.core_index(core) {
+            Some(index) => simulation.cores[index].last_error.as_ref(),
+            None => simulation.last_error.as_ref(),
+        };
+
+        *error_info = error.map(SimulatedError::to_error_info).unwrap_or_default();
+    }
+
+    unsafe fn mcd_qry_mem_spaces_f(
+        &self,
+        core: *const mcd_core_st,
+        start_index: u32,
+        num_mem_spaces: *mut u32,
+        mem_spaces: *mut mcd_memspace_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryMemory, |_| {
+            let mut space = mcd_memspace_st {
+                mem_space_id: 1,
+                bits_per_mau: 8,
+                min_addr: 0,
+                max_addr: u32::MAX as u64,
+                ..Default::default()
+            };
+            copy_string(&mut space.mem_space_name, "Simulated memory");
+            write_entries(&[space], start_index, num_mem_spaces, mem_spaces)
+        })
+    }
+
+    unsafe fn mcd_qry_mem_blocks_f(
+        &self,
+        core: *const mcd_core_st,
+        _mem_space_id: u32,
+        start_index: u32,
+        num_mem_blocks: *mut u32,
+        mem_blocks: *mut mcd_memblock_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryMemory, |_| {
+            write_entries(&[], start_index, num_mem_blocks, mem_blocks)
+        })
+    }
+
+    unsafe fn mcd_qry_reg_groups_f(
+        &self,
+        core: *const mcd_core_st,
+        start_index: u32,
+        num_reg_groups: *mut u32,
+        reg_groups: *mut mcd_register_group_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryRegisters, |core| {
+            let mut group = mcd_register_group_st {
+                reg_group_id: 1,
+                n_registers: core.registers.len() as u32,
+                ..Default::default()
+            };
+            copy_string(&mut group.reg_group_name, "Simulated registers");
+            write_entries(&[group], start_index, num_reg_groups, reg_groups)
+        })
+    }
+
+    unsafe fn mcd_qry_reg_map_f(
+        &self,
+        core: *const mcd_core_st,
+        reg_group_id: u32,
+        start_index: u32,
+        num_regs: *mut u32,
+        reg_info: *mut mcd_register_info_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryRegisters, |core| {
+            if reg_group_id != 1 {
+                return Err(SimulatedError::new(
+                    McdErrorCode::McdErrRegGroupId,
+                    "Register group does not exist",
+                ));
+            }
+
+            let registers: Vec<_> = core
+                .registers
+                .iter()
+                .map(|(name, address)| {
+                    let mut register = mcd_register_info_st {
+                        reg_group_id,
+                        regsize: 32,
+                        ..Default::default()
+                    };
+                    register.addr.address = *address;
+                    copy_string(&mut register.regname, name);
+                    register
+                })
+                .collect();
+            write_entries(&registers, start_index, num_regs, reg_info)
+        })
+    }
+
+    unsafe fn mcd_qry_max_payload_size_f(
+        &self,
+        core: *const mcd_core_st,
+        max_payload: *mut u32,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryPayloadSize, |core| {
+            *max_payload = core.payload_size;
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_execute_txlist_f(
+        &self,
+        core: *const mcd_core_st,
+        txlist: *mut mcd_txlist_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::ExecuteTransactions, |core| {
+            let txlist = &mut *txlist;
+            txlist.num_tx_ok = 0;
+
+            let transactions = std::slice::from_raw_parts_mut(txlist.tx, txlist.num_tx as usize);
+            for transaction in transactions {
+                if transaction.num_bytes > core.payload_size {
+                    return Err(SimulatedError::new(
+                        McdErrorCode::McdErrTxlistTx,
+                        "Transaction exceeds the maximum payload size",
+                    ));
+                }
+
+                let data = std::slice::from_raw_parts_mut(
+                    transaction.data,
+                    transaction.num_bytes as usize,
+                );
+                let address = transaction.addr.address;
+                match transaction.access_type as i32 {
+                    MCD_TX_AT_R => {
+                        data.copy_from_slice(&core.read_memory(address, data.len()));
+                    }
+                    MCD_TX_AT_W => core.write_memory(address, data),
+                    _ => {
+                        return Err(SimulatedError::new(
+                            McdErrorCode::McdErrTxlistTx,
+                            "Unsupported access type",
+                        ))
+                    }
+                }
+
+                transaction.num_bytes_ok = transaction.num_bytes;
+                txlist.num_tx_ok += 1;
+            }
+
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_run_f(&self, core: *const mcd_core_st, _global: mcd_bool_t) -> mcd_return_et {
+        self.with_core(core, Operation::Run, |core| {
+            core.state = MCD_CORE_STATE_RUNNING as u32;
+            core.events = MCD_CORE_EVENT_NONE as u32;
+            core.stopped_by_trigger = 0;
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_stop_f(&self, core: *const mcd_core_st, _global: mcd_bool_t) -> mcd_return_et {
+        self.with_core(core, Operation::Stop, |core| {
+            core.halt(MCD_CORE_STATE_HALTED as u32, 0);
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_step_f(
+        &self,
+        core: *const mcd_core_st,
+        _global: mcd_bool_t,
+        _step_type: mcd_core_step_type_et,
+        n_steps: u32,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::Step, |core| {
+            if core.state == MCD_CORE_STATE_RUNNING as u32 {
+                return Err(SimulatedError::new(
+                    McdErrorCode::McdErrUsage,
+                    "Cannot step a running core",
+                ));
+            }
+
+            core.state = MCD_CORE_STATE_RUNNING as u32;
+            for _ in 0..n_steps {
+                core.execute_program();
+                if core.state != MCD_CORE_STATE_RUNNING as u32 {
+                    return Ok(());
+                }
+            }
+            core.halt(MCD_CORE_STATE_HALTED as u32, 0);
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_qry_state_f(
+        &self,
+        core: *const mcd_core_st,
+        state: *mut mcd_core_state_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryState, |core| {
+            if core.state == MCD_CORE_STATE_RUNNING as u32 {
+                core.execute_program();
+            }
+
+            *state = mcd_core_state_st {
+                state: core.state,
+                event: core.events,
+                trig_id: core.stopped_by_trigger,
+                ..Default::default()
+            };
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_qry_rst_classes_f(
+        &self,
+        core: *const mcd_core_st,
+        rst_class_vector: *mut u32,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryResetClasses, |core| {
+            *rst_class_vector = core
+                .reset_classes
+                .keys()
+                .fold(0, |vector, class| vector | (1 << class));
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_qry_rst_class_info_f(
+        &self,
+        core: *const mcd_core_st,
+        rst_class: u8,
+        rst_info: *mut mcd_rst_info_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryResetClasses, |core| {
+            let description = core.reset_classes.get(&rst_class).ok_or_else(|| {
+                SimulatedError::new(McdErrorCode::McdErrParam, "Reset class does not exist")
+            })?;
+
+            let mut info = mcd_rst_info_st {
+                class_vector: 1 << rst_class,
+                ..Default::default()
+            };
+            copy_string(&mut info.info_str, description);
+            *rst_info = info;
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_rst_f(
+        &self,
+        core: *const mcd_core_st,
+        rst_class_vector: u32,
+        rst_and_halt: mcd_bool_t,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::Reset, |core| {
+            let available = core
+                .reset_classes
+                .keys()
+                .fold(0u32, |vector, class| vector | (1 << class));
+            if rst_class_vector == 0 || rst_class_vector & !available != 0 {
+                return Err(SimulatedError::new(
+                    McdErrorCode::McdErrParam,
+                    "Reset class is not available",
+                ));
+            }
+
+            if rst_and_halt == TRUE {
+                core.halt(MCD_CORE_STATE_HALTED as u32, 0);
+            } else {
+                core.state = MCD_CORE_STATE_RUNNING as u32;
+                core.events = MCD_CORE_EVENT_NONE as u32;
+                core.stopped_by_trigger = 0;
+            }
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_create_trig_f(
+        &self,
+        core: *const mcd_core_st,
+        trig: *mut c_void,
+        trig_id: *mut u32,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::CreateTrigger, |core| {
+            let trigger = &*(trig as *const mcd_trig_simple_core_st);
+
+            let supported = [
+                MCD_TRIG_TYPE_IP,
+                MCD_TRIG_TYPE_READ,
+                MCD_TRIG_TYPE_WRITE,
+                MCD_TRIG_TYPE_RW,
+            ];
+            if !supported.iter().any(|kind| *kind as u32 == trigger.type_) {
+                return Err(SimulatedError::new(
+                    McdErrorCode::McdErrTrigCreate,
+                    "Trigger type is not supported",
+                ));
+            }
+
+            let id = core.next_trigger_id;
+            core.next_trigger_id += 1;
+            core.triggers.insert(
+                id,
+                SimulatedTrigger {
+                    kind: trigger.type_,
+                    address: trigger.addr_start.address,
+                    range: trigger.addr_range,
+                    active: false,
+                    captured: false,
+                },
+            );
+            *trig_id = id;
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_remove_trig_f(&self, core: *const mcd_core_st, trig_id: u32) -> mcd_return_et {
+        self.with_core(core, Operation::RemoveTrigger, |core| {
+            core.triggers.remove(&trig_id).map(|_| ()).ok_or_else(|| {
+                SimulatedError::new(McdErrorCode::McdErrTrigAccess, "Trigger does not exist")
+            })
+        })
+    }
+
+    unsafe fn mcd_qry_trig_state_f(
+        &self,
+        core: *const mcd_core_st,
+        trig_id: u32,
+        trig_state: *mut mcd_trig_state_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryTriggerState, |core| {
+            let trigger = core.triggers.get(&trig_id).ok_or_else(|| {
+                SimulatedError::new(McdErrorCode::McdErrTrigAccess, "Trigger does not exist")
+            })?;
+
+            *trig_state = mcd_trig_state_st {
+                active: trigger.active as u32,
+                captured: trigger.captured as u32,
+                captured_valid: TRUE,
+                ..Default::default()
+            };
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_activate_trig_set_f(&self, core: *const mcd_core_st) -> mcd_return_et {
+        self.with_core(core, Operation::ActivateTriggers, |core| {
+            for trigger in core.triggers.values_mut() {
+                trigger.active = true;
+            }
+            Ok(())
+        })
+    }
+
+    unsafe fn mcd_qry_trig_set_state_f(
+        &self,
+        core: *const mcd_core_st,
+        trig_state: *mut mcd_trig_set_state_st,
+    ) -> mcd_return_et {
+        self.with_core(core, Operation::QueryTriggerState, |core| {
+            *trig_state = mcd_trig_set_state_st {
+                active: core.triggers.values().any(|trigger| trigger.active) as u32,
+                ..Default::default()
+            };
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{breakpoint::TriggerType, error::McdError, system::System};
+
+    #[test]
+    fn system_reports_the_simulated_cores() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        simulation.add_core("core1");
+
+        let system = System::connect().unwrap();
+        assert_eq!(system.core_count(), 2);
+
+        let core = system.get_core(1).unwrap();
+        assert_eq!(core.name(), "core1");
+        assert_eq!(core.id(), 1);
+
+        assert!(matches!(
+            system.get_core(2),
+            Err(McdError::NoSuchCore { index: 2, count: 2 })
+        ));
+    }
+
+    #[test]
+    fn connecting_requires_a_single_server() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.set_server_count(2);
+
+        assert!(matches!(
+            System::connect(),
+            Err(McdError::UnsupportedServerCount(2))
+        ));
+    }
+
+    #[test]
+    fn opening_a_core_is_retried() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        let retry =
+            SimulatedError::new(McdErrorCode::McdErrGeneral, "Core is busy").with_return_value(2);
+        for _ in 0..4 {
+            simulation.inject_error(Operation::OpenCore, retry.clone());
+        }
+
+        let system = System::connect().unwrap();
+        assert_eq!(system.get_core(0).unwrap().name(), "core0");
+
+        for _ in 0..5 {
+            simulation.inject_error(Operation::OpenCore, retry.clone());
+        }
+        assert!(matches!(
+            system.get_core(0),
+            Err(McdError::OpenCoreRetriesExceeded(5))
+        ));
+    }
+
+    #[test]
+    fn other_errors_when_opening_a_core_are_not_retried() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        simulation.inject_error(
+            Operation::OpenCore,
+            SimulatedError::new(McdErrorCode::McdErrConnection, "Connection lost"),
+        );
+
+        let system = System::connect().unwrap();
+        let error = system.get_core(0).unwrap_err();
+        assert_eq!(error.error_code(), Some(McdErrorCode::McdErrConnection));
+    }
+
+    #[test]
+    fn memory_is_read_and_written() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.write_memory(index, 0x7000_0000, &[1, 2, 3, 4]);
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+        assert_eq!(core.read_bytes(0x7000_0002, 4).unwrap(), [3, 4, 0, 0]);
+
+        core.write(0x7000_0004, vec![5, 6]).unwrap();
+        assert_eq!(
+            simulation.read_memory(index, 0x7000_0000, 6),
+            [1, 2, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn transfers_are_split_by_the_payload() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.set_payload_size(index, 8);
+        let data: Vec<u8> = (0..21).collect();
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+        core.write(0x7000_0003, data.clone()).unwrap();
+        assert_eq!(simulation.read_memory(index, 0x7000_0003, 21), data);
+        assert_eq!(core.read_bytes(0x7000_0003, 21).unwrap(), data);
+        assert_eq!(core.read_bytes(0x7000_0010, 8).unwrap(), data[13..]);
+        assert!(core.read_bytes(0x7000_0000, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn registers_are_grouped() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.add_register(index, "PC", 0xF881_FE08);
+        simulation.add_register(index, "PSW", 0xF881_FE04);
+        simulation.set_register(index, "PC", 0x8000_0020);
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+        let groups = core.register_groups().unwrap();
+        let group = groups.get_group(0).unwrap();
+
+        let names: Vec<_> = group.registers().map(|register| register.name()).collect();
+        assert_eq!(names, ["PC", "PSW"]);
+        assert_eq!(group.register("PC").unwrap().read().unwrap(), 0x8000_0020);
+
+        group.register("PSW").unwrap().write(0xB80).unwrap();
+        assert_eq!(
+            simulation.read_memory(index, 0xF881_FE04, 4),
+            0xB80u32.to_le_bytes()
+        );
+
+        assert!(group.register("A11").is_none());
+        assert!(matches!(
+            groups.get_group(1),
+            Err(McdError::NoSuchRegisterGroup {
+                index: 1,
+                count: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn cores_are_reset_with_the_offered_classes() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.add_reset_class(index, 0, "System reset");
+        simulation.add_reset_class(index, 2, "Core reset");
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+        let classes: Vec<_> = core
+            .get_reset_classes()
+            .unwrap()
+            .map(|class| class.class())
+            .collect();
+        assert_eq!(classes, [0, 2]);
+
+        let class = core.get_reset_class(2).unwrap();
+        assert_eq!(class.get_info().unwrap().description(), "Core reset");
+        assert!(matches!(
+            core.get_reset_class(1),
+            Err(McdError::ResetClassUnavailable { class: 1, .. })
+        ));
+
+        core.run().unwrap();
+        core.reset(class, true).unwrap();
+        assert_eq!(simulation.state(index), CoreState::Halted);
+        core.reset(class, false).unwrap();
+        assert_eq!(simulation.state(index), CoreState::Running);
+    }
+
+    #[test]
+    fn resets_are_reported_as_events() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.inject_error(Operation::QueryState, SimulatedError::reset_event());
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+
+        let error = core.query_state().unwrap_err();
+        assert!(error.is_reset());
+        assert!(error.is_access_denied());
+        assert_eq!(error.event(), Some(EventError::Reset));
+
+        // The error is only reported once, resets can be skipped gracefully
+        simulation.inject_error(Operation::QueryState, SimulatedError::reset_event());
+        let info = core.query_state_gracefully(McdError::is_reset).unwrap();
+        assert_eq!(info.state, CoreState::Halted);
+    }
+
+    #[test]
+    fn programs_halt_on_triggers() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        let mut counter = 0u32;
+        simulation.set_program(index, move |context| {
+            counter += 1;
+            context.write_memory(0x7000_0000, &counter.to_le_bytes());
+            context.execute_at(0x8000_0000 + counter as u64 * 4);
+        });
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+        let trigger = core
+            .create_breakpoint(TriggerType::IP, 0x8000_000C, 1)
+            .unwrap();
+        core.download_triggers().unwrap();
+        assert_eq!(simulation.trigger_addresses(index), [0x8000_000C]);
+
+        core.run().unwrap();
+        while core.query_state().unwrap().state == CoreState::Running {}
+
+        let info = core.query_state().unwrap();
+        assert_eq!(info.state, CoreState::Debug);
+        assert!(info.events.stopped);
+        assert_eq!(trigger.get_state().unwrap().captured, Some(true));
+        assert_eq!(core.read_bytes(0x7000_0000, 4).unwrap(), 3u32.to_le_bytes());
+
+        trigger.remove().unwrap();
+        assert!(simulation.trigger_addresses(index).is_empty());
+    }
+
+    #[test]
+    fn stepping_executes_the_program_once() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        let mut steps = 0u32;
+        simulation.set_program(index, move |context| {
+            steps += 1;
+            context.write_memory(0x7000_0000, &steps.to_le_bytes());
+        });
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+        core.step().unwrap();
+        core.step().unwrap();
+        assert_eq!(simulation.state(index), CoreState::Halted);
+        assert_eq!(
+            simulation.read_memory(index, 0x7000_0000, 4),
+            2u32.to_le_bytes()
+        );
+
+        core.run().unwrap();
+        assert!(core.step().is_err());
+    }
+}
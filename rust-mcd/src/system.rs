@@ -5,10 +5,8 @@
 
 use std::ptr::{self, NonNull};
 
-use anyhow::{bail, Context};
-
 use crate::{
-    error::get_error,
+    error::{check, ErrorContext, McdError, Result},
     library::scan_open_servers,
     mcd_bindings::{mcd_core_con_info_st, mcd_server_st},
     MCD_LIB,
@@ -30,14 +28,11 @@ impl System {
     /// that server at the same time.
     ///
     /// The implementation was mainly inferred from the MCD demo project.
-    pub fn connect() -> anyhow::Result<System> {
+    pub fn connect() -> Result<System> {
         let open_servers = scan_open_servers()?;
 
         if open_servers != 1 {
-            bail!(
-                "Library only supports exactly one server, found {}",
-                open_servers
-            );
+            return Err(McdError::UnsupportedServerCount(open_servers));
         }
 
         let mut server_info = core::ptr::null_mut::<mcd_server_st>();
@@ -52,9 +47,7 @@ impl System {
         let result = unsafe {
             MCD_LIB.mcd_open_server_f(system_key.as_ptr(), config.as_ptr(), &mut server_info)
         };
-        if result != 0 {
-            return Err(get_error(None).unwrap()).with_context(|| "Cannot connect to server");
-        }
+        check(result, None, || ErrorContext::new("connect to server"))?;
 
         log::trace!("Scanning for systems attached to the server");
 
@@ -65,19 +58,15 @@ impl System {
 
         let result =
             unsafe { MCD_LIB.mcd_qry_systems_f(0, &mut num_systems, system_info.as_mut_ptr()) };
-        if result != 0 {
-            return Err(get_error(None).unwrap()).with_context(|| "Initial query to system failed");
-        }
+        check(result, None, || {
+            ErrorContext::new("query systems of the server")
+        })?;
 
         log::trace!("Scanning for cores in the given system");
 
-        let core_count = MCD_LIB
-            .query_core_count(&system_info[0])
-            .context("Cannot obtain core count")?;
+        let core_count = MCD_LIB.query_core_count(&system_info[0])?;
 
-        let core_info = MCD_LIB
-            .query_core_info(&system_info[0], 0, core_count)
-            .with_context(|| format!("Cannot obtain information for {core_count} core(s)"))?;
+        let core_info = MCD_LIB.query_core_info(&system_info[0], 0, core_count)?;
 
         Ok(System {
             core_connection: core_info,
@@ -86,9 +75,15 @@ impl System {
 
     /// Open a connection to a core of this system
     ///
-    /// # Panic
-    /// This method will panic if the index exceeds the number of cores available
-    pub fn get_core(&self, core_index: usize) -> Result<Core, anyhow::Error> {
+    /// Fails if the index exceeds the number of cores available.
+    pub fn get_core(&self, core_index: usize) -> Result<Core<'_>> {
+        let core_connection = self
+            .core_connection
+            .get(core_index)
+            .ok_or(McdError::NoSuchCore {
+                index: core_index,
+                count: self.core_count(),
+            })?;
         let mut reference = ptr::null_mut();
 
         // I observed that in certain circumstances opening a core can fail (mcd_open_core_f
@@ -96,24 +91,20 @@ impl System {
         const TRIES: usize = 5;
 
         for nth_try in 0..TRIES {
-            let result = unsafe {
-                MCD_LIB.mcd_open_core_f(&self.core_connection[core_index], &mut reference)
-            };
+            let result = unsafe { MCD_LIB.mcd_open_core_f(core_connection, &mut reference) };
             match result {
                 0 => {
-                    return Ok(Core::new(
-                        unsafe { NonNull::new(reference).unwrap().as_ref() },
-                        &self.core_connection[core_index],
-                    ))
+                    let reference = NonNull::new(reference).ok_or(McdError::Unreported {
+                        context: ErrorContext::new("open core"),
+                        return_code: result,
+                    })?;
+                    return Ok(Core::new(unsafe { reference.as_ref() }, core_connection));
                 }
                 2 => log::trace!("Retrying to open core, try number {nth_try}"),
-                _ => {
-                    return Err(get_error(None).unwrap())
-                        .with_context(|| "MCD library reported an error")
-                }
+                _ => check(result, None, || ErrorContext::new("open core"))?,
             }
         }
-        bail!("Could not open core after {TRIES} tries");
+        Err(McdError::OpenCoreRetriesExceeded(TRIES))
     }
 
     /// The number of cores connected to this system
@@ -21,7 +21,7 @@ impl Type {
 }
 
 /// Helper method to create a transaction
-pub fn create_transaction(address: u64, transaction_type: Type, buffer: &mut [u8]) -> mcd_tx_st {
+pub fn create_transaction(address: u64, transaction_type: &Type, buffer: &mut [u8]) -> mcd_tx_st {
     mcd_tx_st {
         addr: mcd_addr_st {
             address,
@@ -0,0 +1,439 @@
+//! Observes the state of multiple cores, see [StateWatcher]
+//!
+//! The MCD API reports state changes of a core only when its state is queried,
+//! so the watcher polls the cores at a configurable interval instead of
+//! spinning on [Core::query_state].
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::{Core, CoreEvents, CoreState},
+    error::Result,
+};
+
+/// Interval at which the cores are queried if not configured otherwise
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A change of the state or the events of a core
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    /// The index of the core within the watcher, see [StateWatcher::watch]
+    pub core: usize,
+    /// The state observed before, or [None] if this is the first observation
+    pub previous: Option<CoreState>,
+    /// The state of the core
+    pub state: CoreState,
+    /// The events the core reported along with its state
+    pub events: CoreEvents,
+}
+
+impl StateChange {
+    /// Whether the core does not execute code anymore, e.g. because it hit a
+    /// breakpoint or was stopped
+    pub fn is_halted(&self) -> bool {
+        self.state != CoreState::Running
+    }
+}
+
+/// A core observed by the [StateWatcher]
+struct WatchedCore<'a> {
+    core: &'a Core<'a>,
+    ignore_resets: bool,
+    last: Option<(CoreState, CoreEvents)>,
+}
+
+/// Polls the state of a set of cores and reports their transitions
+///
+/// ```no_run
+/// # fn example(core: &rust_mcd::core::Core<'_>) -> rust_mcd::error::Result<()> {
+/// use std::time::Duration;
+/// use rust_mcd::watcher::StateWatcher;
+///
+/// let mut watcher = StateWatcher::new().with_poll_interval(Duration::from_millis(50));
+/// watcher.watch(core);
+/// core.run()?;
+/// if let Some(halt) = watcher.wait_for_halt(Some(Duration::from_secs(1)))? {
+///     println!("Core {} halted in state {:?}", halt.core, halt.state);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct StateWatcher<'a> {
+    cores: Vec<WatchedCore<'a>>,
+    poll_interval: Duration,
+}
+
+impl<'a> Default for StateWatcher<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> StateWatcher<'a> {
+    /// Create a watcher without any cores, see [Self::watch]
+    pub fn new() -> Self {
+        StateWatcher {
+            cores: Vec::new(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Set the interval at which the cores are queried while waiting
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// The interval at which the cores are queried while waiting
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Observe the given core, returns its index within this watcher
+    ///
+    /// Any error while querying the state of the core is reported.
+    pub fn watch(&mut self, core: &'a Core<'a>) -> usize {
+        self.add(core, false)
+    }
+
+    /// Like [Self::watch], but errors caused by a reset of the core are ignored
+    ///
+    /// This is useful for cores that are reset by other cores, e.g. secondary
+    /// cores that are started by the main core.
+    pub fn watch_ignoring_resets(&mut self, core: &'a Core<'a>) -> usize {
+        self.add(core, true)
+    }
+
+    fn add(&mut self, core: &'a Core<'a>, ignore_resets: bool) -> usize {
+        self.cores.push(WatchedCore {
+            core,
+            ignore_resets,
+            last: None,
+        });
+        self.cores.len() - 1
+    }
+
+    /// Query all cores once and return the cores whose state or events changed
+    /// since the last query
+    ///
+    /// On the first query, all cores are reported.
+    pub fn poll(&mut self) -> Result<Vec<StateChange>> {
+        let mut changes = Vec::new();
+        for (index, watched) in self.cores.iter_mut().enumerate() {
+            let ignore_resets = watched.ignore_resets;
+            let info = watched
+                .core
+                .query_state_gracefully(|error| ignore_resets && error.is_reset())?;
+
+            let observation = (info.state, info.events);
+            if watched.last.as_ref() == Some(&observation) {
+                continue;
+            }
+
+            let previous = watched
+                .last
+                .replace(observation.clone())
+                .map(|(state, _)| state);
+            let (state, events) = observation;
+            log::trace!("Core {index} changed from {previous:?} to {state:?} ({events:?})");
+            changes.push(StateChange {
+                core: index,
+                previous,
+                state,
+                events,
+            });
+        }
+
+        Ok(changes)
+    }
+
+    /// Block until any of the cores halts, returns the latest observation of
+    /// the first halted core
+    ///
+    /// Returns [None] if no core halted within the timeout. A timeout of zero
+    /// checks the cores once without blocking, if no timeout is given, this
+    /// function waits indefinitely.
+    pub fn wait_for_halt(&mut self, timeout: Option<Duration>) -> Result<Option<StateChange>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let changes = self.poll()?;
+            let halted = changes
+                .into_iter()
+                .find(StateChange::is_halted)
+                .or_else(|| self.halted());
+            if let Some(halted) = halted {
+                return Ok(Some(halted));
+            }
+
+            if !self.sleep_until(deadline) {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Iterate over the state changes of all cores
+    ///
+    /// The iterator ends when no change is observed within the timeout after
+    /// the iteration started, or after the first error. If no timeout is given,
+    /// the iterator never ends on its own.
+    pub fn transitions(&mut self, timeout: Option<Duration>) -> Transitions<'_, 'a> {
+        Transitions {
+            watcher: self,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            pending: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// The latest observation of the first core that does not run
+    fn halted(&self) -> Option<StateChange> {
+        self.cores
+            .iter()
+            .enumerate()
+            .find_map(|(index, watched)| match &watched.last {
+                Some((state, events)) if *state != CoreState::Running => Some(StateChange {
+                    core: index,
+                    previous: Some(*state),
+                    state: *state,
+                    events: events.clone(),
+                }),
+                _ => None,
+            })
+    }
+
+    /// Sleep for the poll interval, but not past the deadline
+    ///
+    /// Returns false if the deadline has passed.
+    fn sleep_until(&self, deadline: Option<Instant>) -> bool {
+        let sleep_time = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return false;
+                }
+                remaining.min(self.poll_interval)
+            }
+            None => self.poll_interval,
+        };
+
+        std::thread::sleep(sleep_time);
+        true
+    }
+}
+
+/// Iterator over the state changes of the cores, see [StateWatcher::transitions]
+pub struct Transitions<'w, 'a> {
+    watcher: &'w mut StateWatcher<'a>,
+    deadline: Option<Instant>,
+    pending: VecDeque<StateChange>,
+    failed: bool,
+}
+
+impl<'w, 'a> Iterator for Transitions<'w, 'a> {
+    type Item = Result<StateChange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(Ok(change));
+            }
+            if self.failed {
+                return None;
+            }
+
+            match self.watcher.poll() {
+                Ok(changes) if changes.is_empty() => {
+                    if !self.watcher.sleep_until(self.deadline) {
+                        return None;
+                    }
+                }
+                Ok(changes) => self.pending.extend(changes),
+                Err(error) => {
+                    self.failed = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulation"))]
+mod tests {
+    use super::*;
+    use crate::{
+        simulation::{Operation, SimulatedError, SimulatedMcd},
+        system::System,
+    };
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn poll_reports_changes_only() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        simulation.add_core("core1");
+
+        let system = System::connect().unwrap();
+        let (core0, core1) = (system.get_core(0).unwrap(), system.get_core(1).unwrap());
+        let mut watcher = StateWatcher::new();
+        assert_eq!(watcher.watch(&core0), 0);
+        assert_eq!(watcher.watch(&core1), 1);
+
+        let changes = watcher.poll().unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(changes
+            .iter()
+            .all(|change| change.previous.is_none() && change.state == CoreState::Halted));
+        assert!(watcher.poll().unwrap().is_empty());
+
+        core1.run().unwrap();
+        let changes = watcher.poll().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].core, 1);
+        assert_eq!(changes[0].previous, Some(CoreState::Halted));
+        assert_eq!(changes[0].state, CoreState::Running);
+        assert!(!changes[0].is_halted());
+    }
+
+    #[test]
+    fn wait_for_halt_returns_the_halted_core() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        simulation.add_core("core1");
+        let mut executions = 0;
+        simulation.set_program(1, move |context| {
+            executions += 1;
+            if executions == 3 {
+                context.halt();
+            }
+        });
+
+        let system = System::connect().unwrap();
+        let (core0, core1) = (system.get_core(0).unwrap(), system.get_core(1).unwrap());
+        core0.run().unwrap();
+        core1.run().unwrap();
+
+        let mut watcher = StateWatcher::new().with_poll_interval(POLL_INTERVAL);
+        watcher.watch(&core0);
+        watcher.watch(&core1);
+        let halted = watcher
+            .wait_for_halt(Some(Duration::from_secs(1)))
+            .unwrap()
+            .expect("Core 1 halts after three queries");
+        assert_eq!(halted.core, 1);
+        assert_eq!(halted.previous, Some(CoreState::Running));
+        assert_eq!(halted.state, CoreState::Debug);
+        assert!(halted.events.stopped);
+
+        // A core that was already observed halted is reported again
+        let halted = watcher
+            .wait_for_halt(Some(Duration::ZERO))
+            .unwrap()
+            .unwrap();
+        assert_eq!(halted.core, 1);
+        assert_eq!(halted.previous, Some(CoreState::Debug));
+    }
+
+    #[test]
+    fn wait_for_halt_times_out() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+        core.run().unwrap();
+
+        let mut watcher = StateWatcher::new().with_poll_interval(POLL_INTERVAL);
+        watcher.watch(&core);
+        assert!(watcher
+            .wait_for_halt(Some(Duration::ZERO))
+            .unwrap()
+            .is_none());
+
+        let timeout = Duration::from_millis(20);
+        let started = Instant::now();
+        assert!(watcher.wait_for_halt(Some(timeout)).unwrap().is_none());
+        assert!(started.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn resets_during_the_wait_are_reported_unless_ignored() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+        core.run().unwrap();
+        simulation.set_program(0, |context| context.halt());
+
+        simulation.inject_error(Operation::QueryState, SimulatedError::reset_event());
+        let mut watcher = StateWatcher::new().with_poll_interval(POLL_INTERVAL);
+        watcher.watch(&core);
+        let error = watcher
+            .wait_for_halt(Some(Duration::from_secs(1)))
+            .unwrap_err();
+        assert!(error.is_reset());
+
+        simulation.inject_error(Operation::QueryState, SimulatedError::reset_event());
+        let mut watcher = StateWatcher::new().with_poll_interval(POLL_INTERVAL);
+        watcher.watch_ignoring_resets(&core);
+        let halted = watcher
+            .wait_for_halt(Some(Duration::from_secs(1)))
+            .unwrap()
+            .expect("Core halts after the reset");
+        assert_eq!(halted.state, CoreState::Debug);
+    }
+
+    #[test]
+    fn transitions_are_reported_in_order() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        let mut executions = 0;
+        simulation.set_program(0, move |context| {
+            executions += 1;
+            if executions == 3 {
+                context.halt();
+            }
+        });
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+        core.run().unwrap();
+
+        let mut watcher = StateWatcher::new().with_poll_interval(POLL_INTERVAL);
+        watcher.watch(&core);
+        let transitions: Vec<_> = watcher
+            .transitions(Some(Duration::from_millis(20)))
+            .map(|change| {
+                let change = change.unwrap();
+                (change.previous, change.state)
+            })
+            .collect();
+        assert_eq!(
+            transitions,
+            [
+                (None, CoreState::Running),
+                (Some(CoreState::Running), CoreState::Debug)
+            ]
+        );
+    }
+
+    #[test]
+    fn transitions_end_after_an_error() {
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        simulation.inject_error(Operation::QueryState, SimulatedError::reset_event());
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+        let mut watcher = StateWatcher::new().with_poll_interval(POLL_INTERVAL);
+        watcher.watch(&core);
+
+        let mut transitions = watcher.transitions(None);
+        assert!(transitions.next().unwrap().is_err());
+        assert!(transitions.next().is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    fmt::Display,
     path::Path,
     process::{Command, Stdio},
 };
@@ -7,17 +8,142 @@ use std::{
 use anyhow::Context;
 use colored::{Color, Colorize};
 use elf::{endian::AnyEndian, ElfBytes};
-use tricore_common::backtrace::{csa::SavedContext, Stacktrace};
+use serde::Serialize;
+use tricore_common::{
+    backtrace::{
+        csa::{LowerContext, SavedContext, UpperContext},
+        pcxi::PCXI,
+        psw::PSW,
+        walker::{FrameKind, SavedFrame, WalkStop},
+        GlobalRegisters, Stacktrace,
+    },
+    run::HaltedCores,
+    trap::{Trap, TrapCause, VectorTables},
+};
 
+#[derive(Serialize)]
 pub struct BackTraceInfo {
     stack_frames: Vec<StackFrameInfo>,
+    /// Details about the innermost trap, if any of the frames is a trap handler
+    trap: Option<TrapDetails>,
+    /// Why the backtrace ended after the last frame
+    walk_stop: WalkStop,
+}
+
+/// Details about a trap, obtained from the trap status registers of the core
+#[derive(Serialize)]
+pub struct TrapDetails {
+    #[serde(flatten)]
+    pub trap: TrapInfo,
+    /// The address of the memory access that caused the trap, if known
+    pub faulting_address: Option<u32>,
+    /// The flags of the relevant trap status register that are set
+    pub status_flags: Vec<String>,
+}
+
+impl TrapDetails {
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("Trap: {}", self.trap)];
+        if let Some(cause) = &self.trap.cause {
+            lines.push(format!("  {}", cause.explanation));
+        }
+        if let Some(address) = self.faulting_address {
+            lines.push(format!("  Faulting address: {address:#010X}"));
+        }
+        lines.extend(self.status_flags.iter().map(|flag| format!("  {flag}")));
+        lines
+    }
 }
 
 impl BackTraceInfo {
+    /// The symbolized stack frames, starting with the frame the device is
+    /// currently halted in
+    pub fn frames(&self) -> &[StackFrameInfo] {
+        &self.stack_frames
+    }
+
+    /// Details about the innermost trap, if the device halted within a trap handler
+    pub fn trap(&self) -> Option<&TrapDetails> {
+        self.trap.as_ref()
+    }
+
     pub fn log_stdout(&self) {
+        self.log_frames(false);
+    }
+
+    /// Print the backtrace like [Self::log_stdout], including the registers
+    /// saved with each frame
+    pub fn log_stdout_verbose(&self) {
+        self.log_frames(true);
+    }
+
+    fn log_frames(&self, verbose: bool) {
         for f in self.stack_frames.iter() {
             f.log_stdout();
+            if verbose {
+                for line in f.registers.iter().flat_map(FrameRegisters::lines) {
+                    println!("{}", line.dimmed());
+                }
+            }
+        }
+        if !self.walk_stop.is_complete() {
+            println!(
+                "{}",
+                format!("Backtrace incomplete: {}", self.walk_stop).yellow()
+            );
+        }
+        if let Some(trap) = &self.trap {
+            let mut lines = trap.lines().into_iter();
+            if let Some(summary) = lines.next() {
+                println!("{}", summary.bold().red());
+            }
+            for line in lines {
+                println!("{line}");
+            }
+        }
+    }
+
+    /// Print only the frame the device is currently halted in
+    pub fn log_location(&self) {
+        if let Some(f) = self.stack_frames.first() {
+            f.log_stdout();
+        }
+    }
+
+    /// The trap class of the frame the device is currently halted in, if it
+    /// halted in a trap handler
+    pub fn trap_class(&self) -> Option<u8> {
+        self.stack_frames
+            .first()
+            .and_then(|f| f.is_trap.as_ref())
+            .map(|trap| trap.class)
+    }
+}
+
+/// Formats the backtrace like [BackTraceInfo::log_stdout], but without colors
+///
+/// The alternate format `{:#}` includes the registers saved with each frame.
+impl Display for BackTraceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for frame in self.stack_frames.iter() {
+            write!(f, "{:#8X} -> {}", frame.address, frame.info.function)?;
+            if let Some(label) = frame.label() {
+                write!(f, " -> {label}")?;
+            }
+            writeln!(f, "\n└────────── @ {}", frame.info.module)?;
+            if f.alternate() {
+                for line in frame.registers.iter().flat_map(FrameRegisters::lines) {
+                    writeln!(f, "{line}")?;
+                }
+            }
         }
+        if !self.walk_stop.is_complete() {
+            writeln!(f, "Backtrace incomplete: {}", self.walk_stop)?;
+        }
+        for line in self.trap.iter().flat_map(TrapDetails::lines) {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
     }
 }
 
@@ -25,83 +151,436 @@ pub trait ParseInfo {
     fn addr2line(&self, elf_file: &Path) -> anyhow::Result<BackTraceInfo>;
 }
 
+/// Print the backtrace of each halted core, starting with the core that halted
+/// the device
+///
+/// On devices with multiple cores, each backtrace is preceded by the ID of its
+/// core and the core that halted the device is highlighted.
+pub fn log_halted_cores(
+    halted_cores: &HaltedCores,
+    elf_file: &Path,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let trigger = halted_cores
+        .stacktraces
+        .iter()
+        .filter(|(core, _)| **core == halted_cores.trigger);
+    let others = halted_cores
+        .stacktraces
+        .iter()
+        .filter(|(core, _)| **core != halted_cores.trigger);
+    let multi_core = halted_cores.stacktraces.len() > 1;
+
+    for (core, stacktrace) in trigger.chain(others) {
+        if multi_core && *core == halted_cores.trigger {
+            println!(
+                "{}",
+                format!("Core {core} (halted the device)").bold().red()
+            );
+        } else if multi_core {
+            println!("{}", format!("Core {core}").bold());
+        }
+
+        let backtrace_info = stacktrace.addr2line(elf_file)?;
+        if verbose {
+            backtrace_info.log_stdout_verbose();
+        } else {
+            backtrace_info.log_stdout();
+        }
+    }
+
+    Ok(())
+}
+
 impl ParseInfo for Stacktrace {
     fn addr2line(&self, elf_file: &Path) -> anyhow::Result<BackTraceInfo> {
         let mut registry = Addr2LineRegistry::new(elf_file);
-        let trap_metadata = TrapMetadata::from_elf(elf_file).unwrap_or(TrapMetadata::empty());
-
-        registry.load(
-            self.stack_frames
-                .iter()
-                .map(|ctx| ctx.return_address())
-                .chain([self.current_pc].into_iter())
-                .chain([self.current_upper.a11].into_iter()),
-        )?;
+        let trap_metadata = TrapMetadata::new(self, elf_file);
 
-        let mut stack_frames = Vec::new();
+        let saved = group_contexts(&self.stack_frames);
 
-        let current_trapinfo = trap_metadata
-            .trap_class(self.current_pc)
-            .map(|class| TrapInfo {
-                class,
-                trap_id: self.current_upper.d15 as u8,
-            });
+        // The code of each frame was entered by saving the upper context of the
+        // next frame, its return address is stored with the previous frame
+        let mut addresses = vec![self.current_pc, self.current_upper.a11];
+        addresses.extend(saved.iter().map(|saved| saved.upper.a11));
+        registry.load(addresses.iter().copied())?;
 
-        stack_frames.push(StackFrameInfo {
-            address: self.current_pc,
-            is_trap: current_trapinfo,
-            info: registry.get_address_info(self.current_pc)?,
-        });
-
-        stack_frames.push(StackFrameInfo {
-            address: self.current_upper.a11,
-            is_trap: None,
-            info: registry.get_address_info(self.current_upper.a11)?,
-        });
-
-        for ctx in self.stack_frames.iter() {
-            let is_trap = if let SavedContext::Upper(ctx) = ctx {
-                trap_metadata.trap_class(ctx.a11).map(|class| TrapInfo {
-                    class,
-                    trap_id: ctx.d15.try_into().unwrap(),
-                })
-            } else {
-                None
+        let mut stack_frames = Vec::new();
+        for (index, address) in addresses.into_iter().enumerate() {
+            // The registers of the outermost frame are not saved anywhere
+            let registers = match index {
+                0 => Some(FrameRegisters::Current {
+                    pc: self.current_pc,
+                    globals: self.current_globals,
+                    upper: self.current_upper,
+                    lower: self.current_lower,
+                }),
+                _ => saved.get(index - 1).map(|registers| FrameRegisters::Saved {
+                    upper: registers.upper,
+                    lower: registers.lower.clone(),
+                }),
             };
+            // The trap handler receives the TIN in D15
+            let d15 = match &registers {
+                Some(FrameRegisters::Current { upper, .. })
+                | Some(FrameRegisters::Saved { upper, .. }) => upper.d15,
+                None => 0,
+            };
+            let kind = saved.get(index).map(|saved| saved.kind);
+            let interrupt_priority = trap_metadata.interrupt_priority(address).or(match kind {
+                Some(FrameKind::Interrupt { priority }) => Some(priority),
+                _ => None,
+            });
 
             stack_frames.push(StackFrameInfo {
-                address: ctx.return_address(),
-                is_trap,
-                info: registry.get_address_info(ctx.return_address())?,
-            })
+                address,
+                kind,
+                is_trap: trap_metadata
+                    .trap_class(address)
+                    .map(|class| TrapInfo::new(class, d15 as u8)),
+                interrupt_priority,
+                registers,
+                info: registry.get_address_info(address)?,
+            });
         }
 
-        Ok(BackTraceInfo { stack_frames })
+        let trap = stack_frames
+            .iter()
+            .find_map(|frame| frame.is_trap.as_ref())
+            .map(|trap_info| {
+                let trap = trap_info.trap();
+                TrapDetails {
+                    trap: TrapInfo::new(trap.class, trap.tin),
+                    faulting_address: self.trap_registers.faulting_address(&trap),
+                    status_flags: self.trap_registers.status_flags(&trap),
+                }
+            });
+
+        Ok(BackTraceInfo {
+            stack_frames,
+            trap,
+            walk_stop: self.walk_stop.clone(),
+        })
+    }
+}
+
+/// An upper context with the lower contexts that were saved after it
+struct SavedRegisters {
+    kind: FrameKind,
+    upper: UpperContext,
+    lower: Vec<LowerContext>,
+}
+
+/// Group each lower context with the upper context that follows it in the chain
+///
+/// A lower context is saved explicitly, e.g. at the entry of an interrupt
+/// handler, and holds the registers of the same code as the next upper context.
+/// Lower contexts at the end of the chain are dropped.
+fn group_contexts(frames: &[SavedFrame]) -> Vec<SavedRegisters> {
+    let mut grouped = Vec::new();
+    let mut lower = Vec::new();
+    for frame in frames {
+        match frame.context {
+            SavedContext::Lower(context) => lower.push(context),
+            SavedContext::Upper(upper) => grouped.push(SavedRegisters {
+                kind: frame.kind,
+                upper,
+                lower: std::mem::take(&mut lower),
+            }),
+        }
     }
+    grouped
 }
 
+/// A single symbolized stack frame
+#[derive(Serialize)]
 pub struct StackFrameInfo {
-    address: u32,
-    is_trap: Option<TrapInfo>,
-    info: Addr2LineInfo,
+    pub address: u32,
+    /// How the code of the frame was entered, unknown for the outermost frame
+    pub kind: Option<FrameKind>,
+    /// Set if the address belongs to a trap handler
+    #[serde(rename = "trap")]
+    pub is_trap: Option<TrapInfo>,
+    /// Set if the address belongs to the handler of an interrupt with this priority
+    pub interrupt_priority: Option<u8>,
+    /// The registers of the frame, if they are known
+    pub registers: Option<FrameRegisters>,
+    #[serde(flatten)]
+    pub info: Addr2LineInfo,
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-struct TrapInfo {
-    class: u8,
-    trap_id: u8,
+/// The registers that belong to a stack frame
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameRegisters {
+    /// The registers of the core when it halted
+    Current {
+        pc: u32,
+        globals: GlobalRegisters,
+        upper: UpperContext,
+        lower: LowerContext,
+    },
+    /// The contexts that were saved in the CSA link chain when the code of the
+    /// frame called a function, or was interrupted
+    Saved {
+        upper: UpperContext,
+        lower: Vec<LowerContext>,
+    },
+}
+
+impl FrameRegisters {
+    /// Format the registers as indented lines, with PSW and PCXI decoded into
+    /// their fields
+    fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        match self {
+            FrameRegisters::Current {
+                pc,
+                globals,
+                upper,
+                lower,
+            } => {
+                lines.extend(register_rows(&[
+                    ("PC", *pc),
+                    ("A0", globals.a0),
+                    ("A1", globals.a1),
+                    ("A8", globals.a8),
+                    ("A9", globals.a9),
+                ]));
+                lines.extend(lower_context_lines(lower));
+                lines.extend(upper_context_lines(upper));
+            }
+            FrameRegisters::Saved { upper, lower } => {
+                lines.push("    saved upper context".to_owned());
+                lines.extend(upper_context_lines(upper));
+                for lower in lower {
+                    lines.push("    saved lower context".to_owned());
+                    lines.extend(lower_context_lines(lower));
+                    lines.push(format!("    {}", describe_pcxi(lower.pcxi)));
+                }
+            }
+        }
+        lines
+    }
+
+    /// The registers of the frame by their names
+    ///
+    /// Registers of additional saved lower contexts are prefixed with their
+    /// position in the CSA link chain, e.g. `lower[1].D4`.
+    pub fn registers(&self) -> Vec<(String, u32)> {
+        let mut registers: Vec<(String, u32)> = Vec::new();
+        let mut extend = |prefix: &str, list: &[(&str, u32)]| {
+            registers.extend(
+                list.iter()
+                    .map(|(name, value)| (format!("{prefix}{name}"), *value)),
+            )
+        };
+        match self {
+            FrameRegisters::Current {
+                pc,
+                globals,
+                upper,
+                lower,
+            } => {
+                extend(
+                    "",
+                    &[
+                        ("PC", *pc),
+                        ("A0", globals.a0),
+                        ("A1", globals.a1),
+                        ("A8", globals.a8),
+                        ("A9", globals.a9),
+                    ],
+                );
+                extend("", &lower_context_registers(lower));
+                extend("", &upper_context_registers(upper));
+            }
+            FrameRegisters::Saved { upper, lower } => {
+                extend("", &upper_context_registers(upper));
+                for (index, lower) in lower.iter().enumerate() {
+                    let prefix = match index {
+                        0 => String::new(),
+                        index => format!("lower[{index}]."),
+                    };
+                    extend(&prefix, &lower_context_registers(lower));
+                }
+            }
+        }
+        registers
+    }
+}
+
+fn lower_context_registers(lower: &LowerContext) -> [(&'static str, u32); 15] {
+    [
+        ("A2", lower.a2),
+        ("A3", lower.a3),
+        ("A4", lower.a4),
+        ("A5", lower.a5),
+        ("A6", lower.a6),
+        ("A7", lower.a7),
+        ("A11", lower.a11),
+        ("D0", lower.d0),
+        ("D1", lower.d1),
+        ("D2", lower.d2),
+        ("D3", lower.d3),
+        ("D4", lower.d4),
+        ("D5", lower.d5),
+        ("D6", lower.d6),
+        ("D7", lower.d7),
+    ]
+}
+
+fn lower_context_lines(lower: &LowerContext) -> Vec<String> {
+    register_rows(&lower_context_registers(lower))
+}
+
+fn upper_context_registers(upper: &UpperContext) -> [(&'static str, u32); 16] {
+    [
+        ("A10", upper.a10),
+        ("A11", upper.a11),
+        ("A12", upper.a12),
+        ("A13", upper.a13),
+        ("A14", upper.a14),
+        ("A15", upper.a15),
+        ("D8", upper.d8),
+        ("D9", upper.d9),
+        ("D10", upper.d10),
+        ("D11", upper.d11),
+        ("D12", upper.d12),
+        ("D13", upper.d13),
+        ("D14", upper.d14),
+        ("D15", upper.d15),
+        ("PSW", upper.psw),
+        ("PCXI", u32::from(upper.pcxi)),
+    ]
+}
+
+fn upper_context_lines(upper: &UpperContext) -> Vec<String> {
+    // PSW and PCXI are decoded into their fields instead
+    let mut lines = register_rows(&upper_context_registers(upper)[..14]);
+    lines.push(format!("    {}", describe_psw(PSW::from(upper.psw))));
+    lines.push(format!("    {}", describe_pcxi(upper.pcxi)));
+    lines
+}
+
+/// Format the registers with four registers per line
+fn register_rows(registers: &[(&str, u32)]) -> Vec<String> {
+    registers
+        .chunks(4)
+        .map(|row| {
+            let row: Vec<String> = row
+                .iter()
+                .map(|(name, value)| format!("{name:>4} {value:#010X}"))
+                .collect();
+            format!("  {}", row.join("  "))
+        })
+        .collect()
+}
+
+fn describe_psw(psw: PSW) -> String {
+    let io_privilege = match psw.io_privilege() {
+        0 => "user-0",
+        1 => "user-1",
+        2 => "supervisor",
+        _ => "reserved",
+    };
+    let status_flags: Vec<&str> = [
+        (psw.carry(), "C"),
+        (psw.overflow(), "V"),
+        (psw.sticky_overflow(), "SV"),
+        (psw.advance_overflow(), "AV"),
+        (psw.sticky_advance_overflow(), "SAV"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect();
+
+    format!(
+        "PSW  {:#010X}: CDC={:#04X} CDE={} GW={} IS={} IO={io_privilege} PRS={} S={} USB=[{}]",
+        u32::from(psw),
+        psw.call_depth_counter(),
+        psw.call_depth_count_enable() as u8,
+        psw.global_write_permission() as u8,
+        psw.interrupt_stack() as u8,
+        psw.protection_register_set(),
+        psw.safe_task() as u8,
+        status_flags.join(" ")
+    )
+}
+
+fn describe_pcxi(pcxi: PCXI) -> String {
+    let previous_context = match pcxi.get_context() {
+        Some(link_word) => format!("{:#010X}", link_word.get_context_address()),
+        None => "none".to_owned(),
+    };
+
+    format!(
+        "PCXI {:#010X}: PCPN={} PIE={} UL={} PCX={previous_context}",
+        u32::from(pcxi),
+        pcxi.previous_priority(),
+        pcxi.previous_interrupt_enable() as u8,
+        pcxi.is_upper() as u8,
+    )
+}
+
+/// Identifies the trap a trap handler handles
+#[derive(Debug, Serialize)]
+pub struct TrapInfo {
+    pub class: u8,
+    /// The trap identification number within the class
+    pub trap_id: u8,
+    pub class_name: &'static str,
+    /// The cause of the trap, if the trap is known
+    pub cause: Option<TrapCause>,
+}
+
+impl TrapInfo {
+    pub fn new(class: u8, trap_id: u8) -> Self {
+        let trap = Trap {
+            class,
+            tin: trap_id,
+        };
+        TrapInfo {
+            class,
+            trap_id,
+            class_name: trap.class_name(),
+            cause: trap.cause(),
+        }
+    }
+
+    pub fn trap(&self) -> Trap {
+        Trap {
+            class: self.class,
+            tin: self.trap_id,
+        }
+    }
+}
+
+impl Display for TrapInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.trap().fmt(f)
+    }
 }
 
 impl StackFrameInfo {
+    /// Describes the frame if its code is a trap or interrupt handler
+    pub fn label(&self) -> Option<String> {
+        if let Some(trap) = &self.is_trap {
+            return Some(format!("trap handler for {trap}"));
+        }
+        if let Some(priority) = self.interrupt_priority {
+            return Some(format!("interrupt handler for priority {priority}"));
+        }
+        (self.kind == Some(FrameKind::Trap)).then(|| "trap handler".to_owned())
+    }
+
     fn log_stdout(&self) {
         let address = self.address;
         let function = &self.info.function;
         let module = &self.info.module;
         let trap_info = self
-            .is_trap
-            .as_ref()
-            .map(|info| format!("-> detected as trap handler {info:?}"))
+            .label()
+            .map(|label| format!("-> {label}"))
             .unwrap_or_else(|| "".into());
 
         println!(
@@ -118,10 +597,25 @@ impl StackFrameInfo {
     }
 }
 
-#[derive(Clone)]
-struct Addr2LineInfo {
-    function: String,
-    module: String,
+/// The function and source location of an address, as reported by `addr2line`
+#[derive(Clone, Serialize)]
+pub struct Addr2LineInfo {
+    pub function: String,
+    /// The source file and line
+    pub module: String,
+}
+
+impl Addr2LineInfo {
+    /// The source file and line, if `addr2line` knows them
+    pub fn source_location(&self) -> Option<(&str, u32)> {
+        // The line may be followed by e.g. ' (discriminator 2)'
+        let location = self.module.split(" (").next()?;
+        let (file, line) = location.rsplit_once(':')?;
+        match (file, line.parse()) {
+            ("??", _) | (_, Err(_)) | (_, Ok(0)) => None,
+            (file, Ok(line)) => Some((file, line)),
+        }
+    }
 }
 
 struct Addr2LineRegistry<'a> {
@@ -140,7 +634,7 @@ impl<'a> Addr2LineRegistry<'a> {
     fn get_address_info(&mut self, address: u32) -> anyhow::Result<Addr2LineInfo> {
         let Some(info) = self.registry.get(&address) else {
             self.load([address].into_iter())?;
-            return Ok(self.registry.get(&address).unwrap().clone())
+            return Ok(self.registry.get(&address).unwrap().clone());
         };
 
         Ok(info.clone())
@@ -188,20 +682,36 @@ impl<'a> Addr2LineRegistry<'a> {
     }
 }
 
-/// Captures information about the currently installed trap tables
-struct TrapMetadata {
-    /// This field will contain the base address of the trap table if one was found
-    trap_symbol: Option<u32>,
+/// Captures information about the vector tables of a core
+pub(crate) struct TrapMetadata {
+    vector_tables: VectorTables,
 }
 
 impl TrapMetadata {
-    /// Extract information about available trap tables from the given elf file
+    /// Obtain the vector tables the core of the stacktrace is configured with
     ///
-    /// If this function call fails, [TrapMetadata::empty] may be used to create
-    /// a stub variant of this structure.
-    fn from_elf(elf_file: &Path) -> anyhow::Result<Self> {
-        let elf_data = std::fs::read(elf_file).unwrap();
-        let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data).unwrap();
+    /// If the trap vector table could not be read from the core, it is looked up
+    /// in the given elf file instead.
+    pub(crate) fn new(stacktrace: &Stacktrace, elf_file: &Path) -> Self {
+        let mut vector_tables = stacktrace.vector_tables;
+        if vector_tables.btv.is_none() {
+            match Self::trap_table_from_elf(elf_file) {
+                Ok(trap_table) => vector_tables.btv = Some(trap_table),
+                Err(error) => log::warn!(
+                    "BTV register is not available and the trap table is unknown, trap handlers cannot be identified: {error:#}"
+                ),
+            }
+        }
+
+        TrapMetadata { vector_tables }
+    }
+
+    /// Find the base address of the trap table in the given elf file
+    fn trap_table_from_elf(elf_file: &Path) -> anyhow::Result<u32> {
+        let elf_data = std::fs::read(elf_file)
+            .with_context(|| format!("Cannot read elf file {}", elf_file.display()))?;
+        let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data)
+            .with_context(|| "Cannot parse elf file")?;
 
         let (symbols, strings) = elf
             .symbol_table()
@@ -214,7 +724,7 @@ impl TrapMetadata {
             .iter()
             .find_map(|symbol| {
                 let Ok(symbol_name) = strings.get(symbol.st_name as usize) else {
-                    return None
+                    return None;
                 };
 
                 if !VALID_SYMBOLS.contains(&symbol_name) {
@@ -230,32 +740,22 @@ impl TrapMetadata {
                 )
             })?;
 
-        Ok(TrapMetadata {
-            trap_symbol: Some(trap_symbol.try_into().unwrap()),
-        })
-    }
-
-    /// Creates an instance with no metadata associated to it
-    ///
-    /// When [`trap_class`][Self::trap_class] is called on such an instance,
-    /// it will always return [None].
-    pub fn empty() -> Self {
-        TrapMetadata { trap_symbol: None }
+        trap_symbol
+            .try_into()
+            .with_context(|| "Trap table address exceeds 32 bits")
     }
 
     /// Based on the metadata in this structure, attempt to identify the trap
     /// class based on the given address
     ///
     /// This address is usually the program counter of the program when it hit the trap.
-    fn trap_class(&self, address: u32) -> Option<u8> {
-        let offset_from_start = address.checked_sub(self.trap_symbol?)?;
-
-        if offset_from_start > 8 * 32 {
-            return None;
-        }
-
-        let class = offset_from_start / 32;
+    pub(crate) fn trap_class(&self, address: u32) -> Option<u8> {
+        self.vector_tables.trap_class(address)
+    }
 
-        Some(class as u8)
+    /// Attempt to identify the priority of the interrupt whose handler contains
+    /// the given address
+    pub(crate) fn interrupt_priority(&self, address: u32) -> Option<u8> {
+        self.vector_tables.interrupt_priority(address)
     }
 }
@@ -3,7 +3,15 @@ use std::{fs, io::Write, path::Path};
 use crate::elf::elf_to_hex;
 
 pub use imp::Config;
-use tricore_common::{backtrace::Stacktrace, Chip};
+use tricore_common::{
+    backtrace::walker::CsaRegion,
+    debug::DebugTarget,
+    dump::{CoreDump, DumpRequest},
+    reset::{CoreResetClasses, ResetConfig},
+    run::{HaltReason, RunControl},
+    watch::{WatchRequest, WatchSample},
+    Chip,
+};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "docker")] {
@@ -43,9 +51,65 @@ impl<C: Chip> ChipInterfaceImpl<C> {
     pub fn read_rtt<W: Write>(
         &self,
         rtt_control_block_address: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
         decoder: W,
-    ) -> anyhow::Result<Stacktrace> {
-        self.implementation
-            .read_rtt(rtt_control_block_address, decoder)
+    ) -> anyhow::Result<HaltReason> {
+        self.implementation.read_rtt(
+            rtt_control_block_address,
+            reset,
+            control,
+            csa_regions,
+            decoder,
+        )
+    }
+
+    /// Like [Chip::watch]
+    #[allow(clippy::too_many_arguments)]
+    pub fn watch<W: Write>(
+        &self,
+        rtt_control_block_address: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
+        request: &WatchRequest,
+        decoder: W,
+        samples: &mut dyn FnMut(WatchSample) -> anyhow::Result<()>,
+    ) -> anyhow::Result<HaltReason> {
+        self.implementation.watch(
+            rtt_control_block_address,
+            reset,
+            control,
+            csa_regions,
+            request,
+            decoder,
+            samples,
+        )
+    }
+
+    /// Like [Chip::reset]
+    pub fn reset(&self, reset: &ResetConfig) -> anyhow::Result<()> {
+        self.implementation.reset(reset)
+    }
+
+    /// Like [Chip::list_reset_classes]
+    pub fn list_reset_classes(&self) -> anyhow::Result<Vec<CoreResetClasses>> {
+        self.implementation.list_reset_classes()
+    }
+
+    /// Like [Chip::capture_dump]
+    pub fn capture_dump(&self, request: &DumpRequest) -> anyhow::Result<CoreDump> {
+        self.implementation.capture_dump(request)
+    }
+
+    /// Like [Chip::debug]
+    pub fn debug<R, F: FnOnce(&mut dyn DebugTarget) -> anyhow::Result<R>>(
+        &self,
+        csa_regions: &[CsaRegion],
+        control: &RunControl,
+        session: F,
+    ) -> anyhow::Result<R> {
+        self.implementation.debug(csa_regions, control, session)
     }
 }
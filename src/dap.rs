@@ -0,0 +1,1080 @@
+//! A Debug Adapter Protocol server, such that editors like VS Code can debug
+//! the device, see [serve]
+//!
+//! The editor starts `tricore-probe dap` and exchanges messages with it over
+//! stdin and stdout. The `launch` request flashes the binary, resets the device
+//! and starts a debug session. Each core is presented as a thread, its stack
+//! frames are obtained from the CSA link chain like the backtraces of a run.
+//! Breakpoints are set on source lines with the line tables of the debug
+//! information, global variables can be evaluated and the defmt output is shown
+//! in the debug console.
+//!
+//! Like the GDB server, the session is all-stop: continuing resumes all cores,
+//! once any of them halts the others are halted as well. Stepping executes
+//! single instructions of the selected core, there is no stepping by source line.
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+use tricore_common::{
+    debug::{Breakpoint, BreakpointKind, CoreMemory, CoreStatus, DebugCommand, DebugTarget},
+    reset::ResetConfig,
+    rtt::{BufferParameters, RttControlBlock, HOST_CONNECTED},
+    run::RunControl,
+};
+
+use crate::{
+    backtrace::{BackTraceInfo, ParseInfo},
+    chip_interface::{ChipInterface, Config},
+    defmt::DefmtDecoder,
+    elf::{csa_regions, symbol_address},
+    inspect::{DebugInfo, Value},
+};
+
+/// Interval at which running cores are checked for a halt and the RTT data is read
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The core that reads the RTT control block
+const RTT_CORE: usize = 0;
+
+/// The arguments of the `launch` request, given in the launch configuration of
+/// the editor
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchArguments {
+    /// Path to the binary
+    program: PathBuf,
+    /// Whether flashing should be skipped
+    #[serde(default)]
+    no_flash: bool,
+    /// Whether the cores stay halted once the session started
+    #[serde(default)]
+    stop_on_entry: bool,
+}
+
+/// Serve a single debug session for the editor over stdin and stdout
+///
+/// The device is reset with the given configuration after flashing, stepping
+/// over a call is stopped according to the control. Returns once the editor
+/// disconnects.
+pub fn serve(backend: Config, reset: &ResetConfig, control: &RunControl) -> anyhow::Result<()> {
+    let requests = spawn_reader();
+    let mut client = Client {
+        seq: 0,
+        output: Box::new(std::io::stdout()),
+    };
+
+    let (request, launch) = loop {
+        let Ok(request) = requests.recv() else {
+            return Ok(());
+        };
+        match command(&request) {
+            "initialize" => client.respond(&request, capabilities())?,
+            "launch" => match LaunchArguments::deserialize(&request["arguments"]) {
+                Ok(launch) => break (request, launch),
+                Err(error) => {
+                    client.respond_error(&request, &format!("Invalid launch arguments: {error}"))?
+                }
+            },
+            "disconnect" => return client.respond(&request, Json::Null),
+            _ => client.respond_error(&request, "The session was not launched yet")?,
+        }
+    };
+
+    let chip = match prepare(&mut client, backend, reset, &launch) {
+        Ok(chip) => chip,
+        Err(error) => {
+            client.respond_error(&request, &format!("{error:#}"))?;
+            return Err(error);
+        }
+    };
+
+    let (lines, log) = mpsc::channel();
+    let mut decoder = match DefmtDecoder::spawn_forwarding(&launch.program, lines) {
+        Ok(decoder) => Some(decoder),
+        Err(error) => {
+            client.output(
+                "console",
+                &format!("The defmt output is not shown: {error:#}"),
+            )?;
+            None
+        }
+    };
+    let debug_info = match DebugInfo::load(&launch.program) {
+        Ok(debug_info) => Some(debug_info),
+        Err(error) => {
+            client.output(
+                "console",
+                &format!("Breakpoints on source lines are not available: {error:#}"),
+            )?;
+            None
+        }
+    };
+
+    let result = chip.debug(&csa_regions(&launch.program)?, control, |target| {
+        let rtt = decoder.as_ref().map(|decoder| RttReader {
+            block: RttControlBlock::new(decoder.rtt_control_block_address() as u32),
+            buffer: None,
+        });
+        let mut session = Session {
+            target,
+            client: &mut client,
+            elf_file: &launch.program,
+            debug_info,
+            rtt,
+            decoder: decoder.as_mut(),
+            log: &log,
+            running: false,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            step_out: None,
+            handles: Vec::new(),
+            backtraces: HashMap::new(),
+        };
+        session.run(&request, &launch, &requests)
+    });
+
+    if let Some(decoder) = decoder {
+        decoder.finish()?;
+    }
+    result
+}
+
+/// Connect to the device, flash the binary and reset the device
+fn prepare(
+    client: &mut Client,
+    backend: Config,
+    reset: &ResetConfig,
+    launch: &LaunchArguments,
+) -> anyhow::Result<ChipInterface> {
+    let chip = ChipInterface::new(backend)?;
+    if !launch.no_flash {
+        client.output("console", &format!("Flashing {}", launch.program.display()))?;
+        chip.flash_elf(&launch.program, false)?;
+    }
+    chip.reset(reset)?;
+    Ok(chip)
+}
+
+fn capabilities() -> Json {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsFunctionBreakpoints": true,
+        "supportsEvaluateForHovers": true,
+    })
+}
+
+fn command(request: &Json) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+/// Read the messages of the editor on a separate thread, such that the cores
+/// can be polled while no request arrives
+fn spawn_reader() -> Receiver<Json> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        loop {
+            match read_message(&mut stdin) {
+                Ok(Some(message)) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    log::error!("Cannot read message from the editor: {error:#}");
+                    break;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+/// Read a message with its `Content-Length` header, [None] at the end of input
+fn read_message(reader: &mut impl BufRead) -> anyhow::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse()?);
+        }
+    }
+
+    let mut body = vec![0; length.context("Missing Content-Length header")?];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+/// Sends responses and events to the editor
+struct Client {
+    seq: i64,
+    output: Box<dyn Write>,
+}
+
+impl Client {
+    fn send(&mut self, mut message: Json) -> anyhow::Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        let body = serde_json::to_vec(&message)?;
+
+        write!(self.output, "Content-Length: {}\r\n\r\n", body.len())?;
+        self.output.write_all(&body)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> anyhow::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn respond_error(&mut self, request: &Json, message: &str) -> anyhow::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+            "body": { "error": { "id": 1, "format": message } },
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> anyhow::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    /// Show a line in the debug console
+    fn output(&mut self, category: &str, line: &str) -> anyhow::Result<()> {
+        self.event(
+            "output",
+            json!({ "category": category, "output": format!("{line}\n") }),
+        )
+    }
+}
+
+/// What the editor refers to by the IDs of frames and variables
+enum Handle {
+    Frame { core: usize, index: usize },
+    Registers { core: usize, index: usize },
+    Value(Value),
+}
+
+/// A debug session of the editor
+struct Session<'a> {
+    target: &'a mut dyn DebugTarget,
+    client: &'a mut Client,
+    elf_file: &'a Path,
+    debug_info: Option<DebugInfo>,
+    rtt: Option<RttReader>,
+    decoder: Option<&'a mut DefmtDecoder>,
+    /// The decoded defmt output
+    log: &'a Receiver<String>,
+    running: bool,
+    /// The breakpoints on source lines by their file, installed on all cores
+    source_breakpoints: HashMap<PathBuf, Vec<Breakpoint>>,
+    function_breakpoints: Vec<Breakpoint>,
+    /// The temporary breakpoint at the return address while stepping out
+    step_out: Option<(usize, Breakpoint)>,
+    /// The objects that IDs refer to, the ID is the index plus one. Only valid
+    /// until the cores resume
+    handles: Vec<Handle>,
+    backtraces: HashMap<usize, BackTraceInfo>,
+}
+
+impl<'a> Session<'a> {
+    fn run(
+        &mut self,
+        launch_request: &Json,
+        launch: &LaunchArguments,
+        requests: &Receiver<Json>,
+    ) -> anyhow::Result<()> {
+        // Breakpoints are set before the cores run, code that runs before the
+        // session started is not halted at
+        self.halt_all(None);
+        self.client.respond(launch_request, Json::Null)?;
+        self.client.event("initialized", Json::Null)?;
+
+        loop {
+            match requests.recv_timeout(POLL_INTERVAL) {
+                Ok(request) => {
+                    let name = command(&request).to_owned();
+                    match self.handle(&name, &request["arguments"], launch) {
+                        Ok(body) => self.client.respond(&request, body)?,
+                        Err(error) => self.client.respond_error(&request, &format!("{error:#}"))?,
+                    }
+                    if name == "disconnect" {
+                        break;
+                    }
+                    if let Some(event) = self.after_response(&name, &request["arguments"]) {
+                        self.client.event("stopped", event?)?;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            self.poll()?;
+        }
+
+        self.forward_log()
+    }
+
+    fn handle(
+        &mut self,
+        command: &str,
+        arguments: &Json,
+        launch: &LaunchArguments,
+    ) -> anyhow::Result<Json> {
+        match command {
+            "configurationDone" => {
+                if !launch.stop_on_entry {
+                    self.resume();
+                }
+                Ok(Json::Null)
+            }
+            "threads" => {
+                let threads: Vec<_> = (0..self.target.core_count())
+                    .map(|core| json!({ "id": core + 1, "name": format!("Core {core}") }))
+                    .collect();
+                Ok(json!({ "threads": threads }))
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "stackTrace" => self.stack_trace(arguments),
+            "scopes" => {
+                let (core, index) = match self.handle_of(&arguments["frameId"])? {
+                    Handle::Frame { core, index } => (*core, *index),
+                    _ => bail!("Invalid frame"),
+                };
+                let registers = self.insert_handle(Handle::Registers { core, index });
+                Ok(json!({ "scopes": [{
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": registers,
+                    "expensive": false,
+                }] }))
+            }
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                self.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            // The stopped event is sent after the response, see [Self::after_response]
+            "next" | "stepIn" => Ok(Json::Null),
+            "stepOut" => {
+                let core = self.thread_core(&arguments["threadId"])?;
+                let return_address = self
+                    .backtrace(core)?
+                    .frames()
+                    .get(1)
+                    .map(|frame| frame.address)
+                    .context("The function has no caller")?;
+                let breakpoint = Breakpoint {
+                    kind: BreakpointKind::Execution,
+                    address: return_address,
+                    length: 4,
+                };
+                self.target.insert_breakpoint(core, breakpoint)?;
+                self.step_out = Some((core, breakpoint));
+                self.resume();
+                Ok(Json::Null)
+            }
+            "pause" => Ok(Json::Null),
+            "disconnect" => {
+                self.remove_breakpoints();
+                self.resume();
+                Ok(Json::Null)
+            }
+            command => bail!("Request {command:?} is not supported"),
+        }
+    }
+
+    /// Execute requests that halt the cores once they were answered, returns
+    /// the body of the stopped event
+    fn after_response(&mut self, command: &str, arguments: &Json) -> Option<anyhow::Result<Json>> {
+        let command = match command {
+            "next" => DebugCommand::StepOver,
+            "stepIn" => DebugCommand::StepInstruction,
+            "pause" => {
+                self.halt_all(None);
+                let core = self.thread_core(&arguments["threadId"]).unwrap_or(0);
+                return Some(Ok(stopped("pause", core, None)));
+            }
+            _ => return None,
+        };
+
+        Some(self.thread_core(&arguments["threadId"]).and_then(|core| {
+            self.invalidate();
+            self.target.execute(core, command)?;
+            Ok(stopped("step", core, None))
+        }))
+    }
+
+    /// Forward the defmt output and check whether a running core halted
+    fn poll(&mut self) -> anyhow::Result<()> {
+        if let (Some(rtt), Some(decoder)) = (&mut self.rtt, &mut self.decoder) {
+            match rtt.poll(&mut *self.target) {
+                Ok(data) if !data.is_empty() => {
+                    decoder.write_all(&data)?;
+                    decoder.flush()?;
+                }
+                Ok(_) => {}
+                Err(error) => log::debug!("Cannot read RTT data: {error:#}"),
+            }
+        }
+        self.forward_log()?;
+
+        if !self.running {
+            return Ok(());
+        }
+        for core in 0..self.target.core_count() {
+            let Ok(CoreStatus::Halted(stacktrace)) =
+                self.target.execute(core, DebugCommand::Status)
+            else {
+                continue;
+            };
+            self.halt_all(Some(core));
+
+            let pc = stacktrace.current_pc;
+            let event = match self.step_out.take() {
+                Some((step_core, breakpoint)) => {
+                    if let Err(error) = self.target.remove_breakpoint(step_core, breakpoint) {
+                        log::warn!("Cannot remove breakpoint at the return address: {error:#}");
+                    }
+                    match step_core == core && breakpoint.address == pc {
+                        true => stopped("step", core, None),
+                        false => self.halt_event(core, pc),
+                    }
+                }
+                None => self.halt_event(core, pc),
+            };
+            self.client.event("stopped", event)?;
+            break;
+        }
+        Ok(())
+    }
+
+    /// The stopped event of a core that halted by itself
+    fn halt_event(&self, core: usize, pc: u32) -> Json {
+        let at_breakpoint = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .chain(self.function_breakpoints.iter())
+            .any(|breakpoint| breakpoint.address == pc);
+        match at_breakpoint {
+            true => stopped("breakpoint", core, None),
+            false => stopped(
+                "exception",
+                core,
+                Some(format!("Core {core} halted at {pc:#010X}")),
+            ),
+        }
+    }
+
+    fn forward_log(&mut self) -> anyhow::Result<()> {
+        while let Ok(line) = self.log.try_recv() {
+            self.client.output("stdout", &line)?;
+        }
+        Ok(())
+    }
+
+    /// Resume all cores, failures are only logged
+    fn resume(&mut self) {
+        self.invalidate();
+        for core in 0..self.target.core_count() {
+            if let Err(error) = self.target.execute(core, DebugCommand::Continue) {
+                log::warn!("Cannot resume core {core}: {error:#}");
+            }
+        }
+        self.running = true;
+    }
+
+    /// Halt all cores except the given one, failures are only logged
+    fn halt_all(&mut self, except: Option<usize>) {
+        for core in (0..self.target.core_count()).filter(|core| Some(*core) != except) {
+            if let Err(error) = self.target.execute(core, DebugCommand::Halt) {
+                log::warn!("Cannot halt core {core}: {error:#}");
+            }
+        }
+        self.running = false;
+    }
+
+    /// Forget the frames and variables, which change once a core runs
+    fn invalidate(&mut self) {
+        self.handles.clear();
+        self.backtraces.clear();
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> anyhow::Result<Json> {
+        let file = PathBuf::from(
+            arguments["source"]["path"]
+                .as_str()
+                .context("The source has no path")?,
+        );
+        for breakpoint in self.source_breakpoints.remove(&file).unwrap_or_default() {
+            self.remove_from_all(breakpoint);
+        }
+
+        let mut installed = Vec::new();
+        let mut results = Vec::new();
+        let lines = arguments["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for line in lines
+            .iter()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        {
+            let result = self
+                .debug_info
+                .as_ref()
+                .context("The binary has no debug information")
+                .and_then(|debug_info| debug_info.line_address(&file, line as u32))
+                .and_then(|location| location.context("No code belongs to the line"))
+                .and_then(|(line, address)| {
+                    let breakpoint = execution_breakpoint(address);
+                    self.insert_on_all(breakpoint)?;
+                    Ok((line, breakpoint))
+                });
+            results.push(match result {
+                Ok((line, breakpoint)) => {
+                    installed.push(breakpoint);
+                    json!({ "verified": true, "line": line })
+                }
+                Err(error) => {
+                    json!({ "verified": false, "line": line, "message": format!("{error:#}") })
+                }
+            });
+        }
+
+        self.source_breakpoints.insert(file, installed);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Json) -> anyhow::Result<Json> {
+        for breakpoint in std::mem::take(&mut self.function_breakpoints) {
+            self.remove_from_all(breakpoint);
+        }
+
+        let mut results = Vec::new();
+        let breakpoints = arguments["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for name in breakpoints
+            .iter()
+            .filter_map(|breakpoint| breakpoint["name"].as_str())
+        {
+            let result = symbol_address(self.elf_file, name).and_then(|address| {
+                let breakpoint = execution_breakpoint(address);
+                self.insert_on_all(breakpoint)?;
+                Ok(breakpoint)
+            });
+            results.push(match result {
+                Ok(breakpoint) => {
+                    self.function_breakpoints.push(breakpoint);
+                    json!({ "verified": true })
+                }
+                Err(error) => json!({ "verified": false, "message": format!("{error:#}") }),
+            });
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn insert_on_all(&mut self, breakpoint: Breakpoint) -> anyhow::Result<()> {
+        for core in 0..self.target.core_count() {
+            if let Err(error) = self.target.insert_breakpoint(core, breakpoint) {
+                // Leave no breakpoint behind that the editor does not know about
+                for installed in 0..core {
+                    let _ = self.target.remove_breakpoint(installed, breakpoint);
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the breakpoint from all cores, failures are only logged
+    fn remove_from_all(&mut self, breakpoint: Breakpoint) {
+        for core in 0..self.target.core_count() {
+            if let Err(error) = self.target.remove_breakpoint(core, breakpoint) {
+                log::warn!("Cannot remove breakpoint from core {core}: {error:#}");
+            }
+        }
+    }
+
+    fn remove_breakpoints(&mut self) {
+        let source_breakpoints: Vec<_> = self.source_breakpoints.drain().collect();
+        let breakpoints = source_breakpoints
+            .into_iter()
+            .flat_map(|(_, breakpoints)| breakpoints)
+            .chain(std::mem::take(&mut self.function_breakpoints));
+        for breakpoint in breakpoints.collect::<Vec<_>>() {
+            self.remove_from_all(breakpoint);
+        }
+        if let Some((core, breakpoint)) = self.step_out.take() {
+            let _ = self.target.remove_breakpoint(core, breakpoint);
+        }
+    }
+
+    fn stack_trace(&mut self, arguments: &Json) -> anyhow::Result<Json> {
+        let core = self.thread_core(&arguments["threadId"])?;
+        let frames: Vec<_> = self
+            .backtrace(core)?
+            .frames()
+            .iter()
+            .map(|frame| {
+                let name = match frame.label() {
+                    Some(label) => format!("{} ({label})", frame.info.function),
+                    None => frame.info.function.clone(),
+                };
+                let (source, line) = match frame.info.source_location() {
+                    Some((path, line)) => {
+                        let name = Path::new(path)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned());
+                        (json!({ "name": name, "path": path }), line)
+                    }
+                    None => (Json::Null, 0),
+                };
+                (name, source, line, frame.address)
+            })
+            .collect();
+
+        let frames: Vec<_> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, source, line, address))| {
+                let id = self.insert_handle(Handle::Frame { core, index });
+                json!({
+                    "id": id,
+                    "name": name,
+                    "source": source,
+                    "line": line,
+                    "column": 0,
+                    "instructionPointerReference": format!("{address:#010X}"),
+                })
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn variables(&mut self, arguments: &Json) -> anyhow::Result<Json> {
+        let frame = match self.handle_of(&arguments["variablesReference"])? {
+            Handle::Registers { core, index } => (*core, *index),
+            Handle::Value(value) => return Ok(self.variables_of(children(value))),
+            Handle::Frame { .. } => bail!("Invalid variable reference"),
+        };
+
+        let (core, index) = frame;
+        let registers = self
+            .backtrace(core)?
+            .frames()
+            .get(index)
+            .and_then(|frame| frame.registers.as_ref())
+            .map(|registers| registers.registers())
+            .unwrap_or_default();
+        let registers = registers
+            .into_iter()
+            .map(|(name, value)| (name, Value::Scalar(format!("{value:#010X}"))))
+            .collect();
+        Ok(self.variables_of(registers))
+    }
+
+    fn variables_of(&mut self, children: Vec<(String, Value)>) -> Json {
+        let variables: Vec<_> = children
+            .into_iter()
+            .map(|(name, value)| {
+                let reference = self.value_reference(&value);
+                json!({
+                    "name": name,
+                    "value": value.to_string(),
+                    "variablesReference": reference,
+                })
+            })
+            .collect();
+        json!({ "variables": variables })
+    }
+
+    /// Read a global variable by its name
+    fn evaluate(&mut self, arguments: &Json) -> anyhow::Result<Json> {
+        let expression = arguments["expression"]
+            .as_str()
+            .context("Missing expression")?
+            .trim();
+        let core = match self.handle_of(&arguments["frameId"]) {
+            Ok(Handle::Frame { core, .. }) => *core,
+            _ => 0,
+        };
+
+        let debug_info = self
+            .debug_info
+            .as_ref()
+            .context("The binary has no debug information")?;
+        let variable = debug_info.variable(expression)?;
+        let memory = CoreMemory {
+            target: &*self.target,
+            core,
+        };
+        let value = debug_info.read(&variable, &memory)?;
+
+        let reference = self.value_reference(&value);
+        Ok(json!({
+            "result": value.to_string(),
+            "type": variable.type_name,
+            "variablesReference": reference,
+        }))
+    }
+
+    /// The reference to the fields or elements of the value, zero for scalars
+    fn value_reference(&mut self, value: &Value) -> usize {
+        match children(value).is_empty() {
+            true => 0,
+            false => self.insert_handle(Handle::Value(value.clone())),
+        }
+    }
+
+    fn insert_handle(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    fn handle_of(&self, id: &Json) -> anyhow::Result<&Handle> {
+        id.as_u64()
+            .and_then(|id| self.handles.get((id as usize).checked_sub(1)?))
+            .context("The reference is not valid anymore")
+    }
+
+    fn thread_core(&self, thread: &Json) -> anyhow::Result<usize> {
+        thread
+            .as_u64()
+            .and_then(|id| (id as usize).checked_sub(1))
+            .filter(|core| *core < self.target.core_count())
+            .with_context(|| format!("Thread {thread} does not exist"))
+    }
+
+    /// The symbolized backtrace of the halted core
+    fn backtrace(&mut self, core: usize) -> anyhow::Result<&BackTraceInfo> {
+        match self.backtraces.entry(core) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let CoreStatus::Halted(stacktrace) =
+                    self.target.execute(core, DebugCommand::Status)?
+                else {
+                    bail!("Core {core} is running");
+                };
+                Ok(entry.insert(stacktrace.addr2line(self.elf_file)?))
+            }
+        }
+    }
+}
+
+/// The body of a stopped event, the thread ID is the index of the core plus one
+fn stopped(reason: &str, core: usize, text: Option<String>) -> Json {
+    json!({
+        "reason": reason,
+        "threadId": core + 1,
+        "allThreadsStopped": true,
+        "text": text,
+    })
+}
+
+fn execution_breakpoint(address: u32) -> Breakpoint {
+    Breakpoint {
+        kind: BreakpointKind::Execution,
+        address,
+        length: 4,
+    }
+}
+
+/// The fields of a struct, the elements of an array or the target of a pointer
+fn children(value: &Value) -> Vec<(String, Value)> {
+    match value {
+        Value::Struct { fields, .. } => fields.clone(),
+        Value::Array { elements, .. } => elements
+            .iter()
+            .enumerate()
+            .map(|(index, element)| (format!("[{index}]"), element.clone()))
+            .collect(),
+        Value::Pointer {
+            target: Some(target),
+            ..
+        } => vec![("*".to_owned(), (**target).clone())],
+        Value::Pointer { target: None, .. } | Value::Scalar(_) | Value::Unavailable(_) => {
+            Vec::new()
+        }
+    }
+}
+
+/// Reads the defmt data from the first up channel of the RTT control block
+/// through the debug session
+///
+/// The control block is looked for on each poll, until the firmware initialized it.
+struct RttReader {
+    block: RttControlBlock,
+    /// The ring buffer, once the control block was found
+    buffer: Option<BufferParameters>,
+}
+
+impl RttReader {
+    /// Read the data written since the last poll
+    fn poll(&mut self, target: &mut dyn DebugTarget) -> anyhow::Result<Vec<u8>> {
+        let buffer = match self.buffer {
+            Some(buffer) => buffer,
+            None => {
+                if !self.block.is_initialized(&memory(&*target))? {
+                    return Ok(Vec::new());
+                }
+                log::info!("Detected RTT control block");
+                // Set the flag that the host is connected
+                target.write_memory(
+                    RTT_CORE,
+                    self.block.flags_addr(),
+                    &HOST_CONNECTED.to_le_bytes(),
+                )?;
+                *self
+                    .buffer
+                    .insert(self.block.read_buffer(&memory(&*target))?)
+            }
+        };
+
+        let write_index = self.block.read_write_index(&memory(&*target))?;
+        let read_index = self.block.read_read_index(&memory(&*target))?;
+        if write_index == read_index {
+            return Ok(Vec::new());
+        }
+        let data = buffer.read_data(&memory(&*target), read_index, write_index)?;
+        target.write_memory(
+            RTT_CORE,
+            self.block.host_read_index_addr(),
+            &write_index.to_le_bytes(),
+        )?;
+        Ok(data)
+    }
+}
+
+/// The memory of the core that reads the RTT control block
+fn memory(target: &dyn DebugTarget) -> CoreMemory<'_> {
+    CoreMemory {
+        target,
+        core: RTT_CORE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{elf::elf_with_symbols, gdb::tests::MockTarget, inspect::tests::Builder};
+
+    /// Collects the messages sent to the editor
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn messages(&self) -> Vec<Json> {
+            let data = self.0.borrow();
+            let mut reader = data.as_slice();
+            std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+        }
+    }
+
+    /// Frame the messages like the editor
+    fn framed(messages: &[Json]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for message in messages {
+            let body = serde_json::to_vec(message).unwrap();
+            write!(data, "Content-Length: {}\r\n\r\n", body.len()).unwrap();
+            data.extend(body);
+        }
+        data
+    }
+
+    fn request(seq: i64, command: &str, arguments: Json) -> Json {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    #[test]
+    fn messages_are_read_by_their_content_length() {
+        let first = json!({ "seq": 1, "command": "initialize" });
+        let second = json!({ "seq": 2, "command": "launch", "arguments": { "program": "ä" } });
+        let data = framed(&[first.clone(), second.clone()]);
+
+        let mut reader = data.as_slice();
+        assert_eq!(read_message(&mut reader).unwrap(), Some(first));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(second));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn other_headers_are_ignored() {
+        let data = b"Content-Type: application/vscode-jsonrpc\r\nContent-Length: 2\r\n\r\n{}";
+
+        let message = read_message(&mut data.as_slice()).unwrap();
+        assert_eq!(message, Some(json!({})));
+    }
+
+    #[test]
+    fn messages_without_content_length_are_rejected() {
+        let cases: &[&[u8]] = &[
+            b"Content-Type: application/vscode-jsonrpc\r\n\r\n{}",
+            b"Content-Length: two\r\n\r\n{}",
+            b"Content-Length: 10\r\n\r\n{}",
+        ];
+        for data in cases {
+            assert!(
+                read_message(&mut &data[..]).is_err(),
+                "{}",
+                String::from_utf8_lossy(data)
+            );
+        }
+    }
+
+    #[test]
+    fn breakpoints_halt_the_launched_session() {
+        let elf = elf_with_symbols(&[("main", 0x8000_0000, 0x20)]);
+        let mut builder = Builder::new();
+        builder.line("src/main.rs", 10, 0x8000_0000);
+        let debug_info = builder.finish();
+
+        // Core 0 reaches the breakpoint on its first poll after each resume
+        let mut target = MockTarget::new(2);
+        target.halts_after = Some((0, 0));
+        let (lines, log) = mpsc::channel();
+        lines.send("Hello".to_owned()).unwrap();
+
+        let (sender, requests) = mpsc::channel();
+        let source = json!({ "path": "/project/src/main.rs" });
+        for request in [
+            request(
+                2,
+                "setBreakpoints",
+                json!({ "source": source, "breakpoints": [{ "line": 9 }, { "line": 11 }] }),
+            ),
+            request(3, "configurationDone", Json::Null),
+            request(4, "stackTrace", json!({ "threadId": 1 })),
+            request(5, "continue", json!({ "threadId": 1 })),
+            request(6, "disconnect", Json::Null),
+        ] {
+            sender.send(request).unwrap();
+        }
+
+        let output = Output::default();
+        let mut client = Client {
+            seq: 0,
+            output: Box::new(output.clone()),
+        };
+        let launch = LaunchArguments {
+            program: elf.path().to_owned(),
+            no_flash: true,
+            stop_on_entry: false,
+        };
+        let mut session = Session {
+            target: &mut target,
+            client: &mut client,
+            elf_file: elf.path(),
+            debug_info: Some(debug_info),
+            rtt: None,
+            decoder: None,
+            log: &log,
+            running: false,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            step_out: None,
+            handles: Vec::new(),
+            backtraces: HashMap::new(),
+        };
+        session
+            .run(&request(1, "launch", Json::Null), &launch, &requests)
+            .unwrap();
+
+        let messages = output.messages();
+        let kinds: Vec<_> = messages
+            .iter()
+            .map(|message| match message["type"].as_str().unwrap() {
+                "response" => format!("{} response", command(message)),
+                _ => format!("{} event", message["event"].as_str().unwrap()),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "launch response",
+                "initialized event",
+                "setBreakpoints response",
+                "output event",
+                "configurationDone response",
+                "stopped event",
+                "stackTrace response",
+                "continue response",
+                "stopped event",
+                "disconnect response",
+            ]
+        );
+        let seqs: Vec<_> = messages
+            .iter()
+            .map(|message| message["seq"].clone())
+            .collect();
+        assert_eq!(seqs, (1..=10).map(Json::from).collect::<Vec<_>>());
+        assert!(messages
+            .iter()
+            .filter(|message| message["type"] == "response")
+            .all(|response| response["success"] == true));
+
+        // The defmt output is forwarded once the first request was answered
+        assert_eq!(messages[3]["body"]["output"], "Hello\n");
+        // The code of line 10 is used for the breakpoint on line 9, no code
+        // follows line 11
+        let breakpoints = &messages[2]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0], json!({ "verified": true, "line": 10 }));
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert_eq!(breakpoints[1]["line"], 11);
+        for stopped in [&messages[5], &messages[8]] {
+            assert_eq!(stopped["body"]["reason"], "breakpoint");
+            assert_eq!(stopped["body"]["threadId"], 1);
+        }
+
+        let frames = messages[6]["body"]["stackFrames"].as_array().unwrap();
+        assert_eq!(frames[0]["id"], 1);
+        assert_eq!(frames[0]["instructionPointerReference"], "0x80000000");
+        assert_eq!(messages[7]["body"]["allThreadsContinued"], true);
+
+        let breakpoint = execution_breakpoint(0x8000_0000);
+        assert_eq!(target.inserted, [(0, breakpoint), (1, breakpoint)]);
+        assert!(target.breakpoints.is_empty());
+    }
+}
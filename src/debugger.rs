@@ -0,0 +1,211 @@
+//! Interactive control of the cores of a chip, see [run_session]
+
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use colored::Colorize;
+use tricore_common::debug::{CoreMemory, CoreStatus, DebugCommand, DebugTarget};
+
+use crate::{backtrace::ParseInfo, elf::symbol_address, inspect::DebugInfo};
+
+const HELP: &str = "Available commands:
+  halt, h              stop the selected core
+  continue, c          let the selected core run
+  step, s              execute a single instruction
+  next, n              execute a single instruction, stepping over calls
+  until, u <location>  run until the address or symbol is reached
+  status               show the state of the selected core
+  bt [full]            show the backtrace of the selected core, 'full' adds
+                       the registers saved with each frame
+  print, p <symbol>    read a global variable and print it according to
+                       its type
+  core <index>         select the core that commands apply to
+  help                 show this message
+  quit, q              end the debug session";
+
+/// A command entered by the user
+enum UserCommand {
+    Execute(DebugCommand),
+    /// Show the backtrace, with all registers if the flag is set
+    Backtrace(bool),
+    /// Print the global variable with the given name
+    Print(String),
+    SelectCore(usize),
+    Help,
+    Quit,
+}
+
+/// Reads commands from stdin and executes them on the target until the user
+/// quits the session
+///
+/// Symbols and locations are resolved with the given elf file.
+pub fn run_session(
+    target: &mut dyn DebugTarget,
+    elf_file: &Path,
+    initial_core: usize,
+) -> anyhow::Result<()> {
+    if initial_core >= target.core_count() {
+        bail!(
+            "Core {initial_core} does not exist, there are {} cores",
+            target.core_count()
+        );
+    }
+
+    let mut core = initial_core;
+    // Loaded on the first 'print', as reading the debug sections takes a while
+    let mut debug_info = None;
+    println!(
+        "Debug session with {} cores started, type 'help' for a list of commands",
+        target.core_count()
+    );
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("{} ", format!("(core {core})").bold());
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let command = match parse_command(line.trim(), elf_file) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(error) => {
+                println!("{}", format!("{error:#}").red());
+                continue;
+            }
+        };
+
+        let result = match command {
+            UserCommand::Execute(command) => target
+                .execute(core, command)
+                .and_then(|status| print_status(&status, elf_file, Detail::Location)),
+            UserCommand::Backtrace(registers) => target
+                .execute(core, DebugCommand::Status)
+                .and_then(|status| {
+                    let detail = if registers {
+                        Detail::Registers
+                    } else {
+                        Detail::Backtrace
+                    };
+                    print_status(&status, elf_file, detail)
+                }),
+            UserCommand::Print(symbol) => {
+                print_variable(&*target, core, &symbol, elf_file, &mut debug_info)
+            }
+            UserCommand::SelectCore(index) if index < target.core_count() => {
+                core = index;
+                Ok(())
+            }
+            UserCommand::SelectCore(index) => Err(anyhow::Error::msg(format!(
+                "Core {index} does not exist, there are {} cores",
+                target.core_count()
+            ))),
+            UserCommand::Help => {
+                println!("{HELP}");
+                Ok(())
+            }
+            UserCommand::Quit => return Ok(()),
+        };
+
+        if let Err(error) = result {
+            println!("{}", format!("{error:#}").red());
+        }
+    }
+}
+
+/// Read a global variable through the core and print it
+fn print_variable(
+    target: &dyn DebugTarget,
+    core: usize,
+    symbol: &str,
+    elf_file: &Path,
+    debug_info: &mut Option<DebugInfo>,
+) -> anyhow::Result<()> {
+    let debug_info = match debug_info {
+        Some(debug_info) => debug_info,
+        None => debug_info.insert(DebugInfo::load(elf_file)?),
+    };
+    let variable = debug_info.variable(symbol)?;
+    let value = debug_info.read(&variable, &CoreMemory { target, core })?;
+    println!("{} = {value:#}", variable.name);
+    Ok(())
+}
+
+/// Parse a line of user input, returns [None] for empty lines
+fn parse_command(line: &str, elf_file: &Path) -> anyhow::Result<Option<UserCommand>> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let argument = words.next();
+
+    let command = match (command, argument) {
+        ("halt" | "h", None) => UserCommand::Execute(DebugCommand::Halt),
+        ("continue" | "c", None) => UserCommand::Execute(DebugCommand::Continue),
+        ("step" | "s", None) => UserCommand::Execute(DebugCommand::StepInstruction),
+        ("next" | "n", None) => UserCommand::Execute(DebugCommand::StepOver),
+        ("until" | "u", Some(location)) => {
+            UserCommand::Execute(DebugCommand::RunTo(parse_location(location, elf_file)?))
+        }
+        ("status", None) => UserCommand::Execute(DebugCommand::Status),
+        ("bt", None) => UserCommand::Backtrace(false),
+        ("bt", Some("full")) => UserCommand::Backtrace(true),
+        ("print" | "p", Some(symbol)) => UserCommand::Print(symbol.to_owned()),
+        ("core", Some(index)) => UserCommand::SelectCore(
+            index
+                .parse()
+                .with_context(|| format!("Invalid core index {index:?}"))?,
+        ),
+        ("help", None) => UserCommand::Help,
+        ("quit" | "q", None) => UserCommand::Quit,
+        _ => bail!("Invalid command {line:?}, type 'help' for a list of commands"),
+    };
+
+    Ok(Some(command))
+}
+
+/// Interpret the location either as a hexadecimal address or a symbol name
+fn parse_location(location: &str, elf_file: &Path) -> anyhow::Result<u32> {
+    if let Some(address) = location
+        .strip_prefix("0x")
+        .or_else(|| location.strip_prefix("0X"))
+    {
+        return u32::from_str_radix(address, 16)
+            .with_context(|| format!("Invalid address {location:?}"));
+    }
+
+    symbol_address(elf_file, location)
+}
+
+/// How much of a halted core is shown, see [print_status]
+enum Detail {
+    /// Only the location the core is halted at
+    Location,
+    /// The full backtrace
+    Backtrace,
+    /// The full backtrace with the registers saved with each frame
+    Registers,
+}
+
+fn print_status(status: &CoreStatus, elf_file: &Path, detail: Detail) -> anyhow::Result<()> {
+    match status {
+        CoreStatus::Running => println!("{}", "Core is running".green()),
+        CoreStatus::Halted(stacktrace) => {
+            let info = stacktrace.addr2line(elf_file)?;
+            println!("{}", "Core halted".yellow());
+            match detail {
+                Detail::Location => info.log_location(),
+                Detail::Backtrace => info.log_stdout(),
+                Detail::Registers => info.log_stdout_verbose(),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,15 +1,20 @@
 //! Handles decoding of defmt byte streams, see [DefmtDecoder]
 use std::{
     fs,
-    io::Write,
+    io::{BufRead, BufReader, Write},
     path::Path,
-    process::{Child, Command, Stdio},
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::mpsc::Sender,
+    thread::JoinHandle,
 };
 
 use anyhow::Context;
 
+use defmt_json_schema::v1::JsonFrame;
 use elf::{endian::AnyEndian, ElfBytes};
 
+use crate::output::{OutputEvent, OutputFormat};
+
 /// A structure that is able to decode a byte stream as defmt data
 ///
 /// This is implemented by spawning `defmt-print` and piping the output to the
@@ -18,6 +23,43 @@ use elf::{endian::AnyEndian, ElfBytes};
 pub struct DefmtDecoder {
     spawned_decoder: Child,
     rtt_symbol_address: u64,
+    output_scanner: Option<JoinHandle<DecodedOutput>>,
+}
+
+/// What was found in the output of the decoder, see [DefmtDecoder::finish]
+///
+/// This is only available if the output of the decoder is processed, see
+/// [DefmtDecoder::spawn_scanning].
+#[derive(Debug, Default)]
+pub struct DecodedOutput {
+    /// The markers found in the output in the order they appeared
+    pub markers: Vec<String>,
+    /// The decoded log frames, including their level and location
+    pub frames: Vec<JsonFrame>,
+    /// The decoded lines as printed, only available if the output was captured,
+    /// see [DefmtDecoder::spawn_capturing]
+    pub captured: Vec<String>,
+}
+
+/// How the output of `defmt-print` is handled
+enum OutputMode {
+    /// Print the output directly to stdout
+    Inherit,
+    /// Process the decoded frames, see [scan_output]
+    Scan {
+        markers: Vec<String>,
+        output: ScanOutput,
+    },
+}
+
+/// What happens with the decoded frames when they are processed
+enum ScanOutput {
+    /// Print the frames to stdout in the given format
+    Print(OutputFormat),
+    /// Keep the formatted frames, see [DecodedOutput::captured]
+    Capture,
+    /// Send the formatted lines to the receiver as soon as they are decoded
+    Forward(Sender<String>),
 }
 
 impl DefmtDecoder {
@@ -26,6 +68,55 @@ impl DefmtDecoder {
     /// This function will fail if the user did not install the program, e.g. via
     /// `cargo install defmt-print`.
     pub fn spawn(elf_file: &Path) -> anyhow::Result<DefmtDecoder> {
+        Self::spawn_with(elf_file, OutputMode::Inherit)
+    }
+
+    /// Like [Self::spawn], but the decoded frames are collected and scanned for
+    /// any of the given markers, see [Self::finish]
+    ///
+    /// The frames are printed in the given format.
+    pub fn spawn_scanning(
+        elf_file: &Path,
+        markers: Vec<String>,
+        format: OutputFormat,
+    ) -> anyhow::Result<DefmtDecoder> {
+        Self::spawn_with(
+            elf_file,
+            OutputMode::Scan {
+                markers,
+                output: ScanOutput::Print(format),
+            },
+        )
+    }
+
+    /// Like [Self::spawn_scanning], but the decoded output is not printed and
+    /// returned by [Self::finish] instead
+    pub fn spawn_capturing(elf_file: &Path, markers: Vec<String>) -> anyhow::Result<DefmtDecoder> {
+        Self::spawn_with(
+            elf_file,
+            OutputMode::Scan {
+                markers,
+                output: ScanOutput::Capture,
+            },
+        )
+    }
+
+    /// Like [Self::spawn_scanning], but the formatted lines are sent to the
+    /// given channel instead of printed, e.g. when stdout is used otherwise
+    pub fn spawn_forwarding(
+        elf_file: &Path,
+        lines: Sender<String>,
+    ) -> anyhow::Result<DefmtDecoder> {
+        Self::spawn_with(
+            elf_file,
+            OutputMode::Scan {
+                markers: Vec::new(),
+                output: ScanOutput::Forward(lines),
+            },
+        )
+    }
+
+    fn spawn_with(elf_file: &Path, mode: OutputMode) -> anyhow::Result<DefmtDecoder> {
         let elf_data = fs::read(elf_file).unwrap();
         let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data).unwrap();
 
@@ -38,7 +129,7 @@ impl DefmtDecoder {
             .iter()
             .find_map(|symbol| {
                 let Ok(symbol_name) = strings.get(symbol.st_name as usize) else {
-                    return None
+                    return None;
                 };
 
                 if symbol_name != "_SEGGER_RTT" {
@@ -50,10 +141,24 @@ impl DefmtDecoder {
             .ok_or_else(|| anyhow::Error::msg("Elf file does not have _SEGGER_RTT symbol"))?;
 
         let mut defmt_print_process = Command::new("defmt-print");
-        let spawned_decoder = defmt_print_process
+        // Signals like Ctrl-C are handled by this process, the decoder must keep
+        // running to print the remaining data
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut defmt_print_process, 0);
+        #[cfg(windows)]
+        std::os::windows::process::CommandExt::creation_flags(
+            &mut defmt_print_process,
+            CREATE_NEW_PROCESS_GROUP,
+        );
+        // Only process the output if necessary, defmt-print colors its output
+        // only when printing to a terminal
+        match mode {
+            OutputMode::Inherit => defmt_print_process.stdout(Stdio::inherit()),
+            OutputMode::Scan { .. } => defmt_print_process.stdout(Stdio::piped()).arg("--json"),
+        };
+        let mut spawned_decoder = defmt_print_process
             .stdin(Stdio::piped())
             .stderr(Stdio::inherit())
-            .stdout(Stdio::inherit())
             .env("DEFMT_LOG", "trace")
             .env("RUST_LOG", "trace")
             .arg("--verbose")
@@ -62,9 +167,19 @@ impl DefmtDecoder {
             .spawn()
             .with_context(|| "Cannot spawn 'defmt-print' to decode log frames. Did you run 'cargo install defmt-print'?")?;
 
+        let output_scanner = match (spawned_decoder.stdout.take(), mode) {
+            (Some(stdout), OutputMode::Scan { markers, output }) => {
+                Some(std::thread::spawn(move || {
+                    scan_output(stdout, markers, output)
+                }))
+            }
+            _ => None,
+        };
+
         Ok(DefmtDecoder {
             spawned_decoder,
             rtt_symbol_address,
+            output_scanner,
         })
     }
 
@@ -74,8 +189,121 @@ impl DefmtDecoder {
     pub fn rtt_control_block_address(&self) -> u64 {
         self.rtt_symbol_address
     }
+
+    /// Signal the end of the data and wait until all of it was printed
+    ///
+    /// Returns the markers found in the output and the captured output, see
+    /// [Self::spawn_scanning] and [Self::spawn_capturing].
+    pub fn finish(mut self) -> anyhow::Result<DecodedOutput> {
+        drop(self.spawned_decoder.stdin.take());
+        self.spawned_decoder
+            .wait()
+            .with_context(|| "Failed to wait for 'defmt-print' to finish")?;
+
+        match self.output_scanner.take() {
+            Some(scanner) => scanner
+                .join()
+                .map_err(|_| anyhow::Error::msg("Failed to scan the output of 'defmt-print'")),
+            None => Ok(DecodedOutput::default()),
+        }
+    }
+}
+
+/// Collect the frames and markers of the JSON output of the decoder
+///
+/// In the pretty format, the frames are formatted similar to the plain output
+/// of `defmt-print`. The output is either captured or forwarded to stdout.
+fn scan_output(
+    decoder_output: ChildStdout,
+    markers: Vec<String>,
+    output: ScanOutput,
+) -> DecodedOutput {
+    let mut decoded = DecodedOutput::default();
+    let stdout = std::io::stdout();
+    for line in BufReader::new(decoder_output).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        let frame = match serde_json::from_str::<JsonFrame>(&line) {
+            Ok(frame) => frame,
+            // The first line announces the schema version
+            Err(_) if line.contains("schema_version") => continue,
+            Err(_) => {
+                // Not a frame, e.g. a diagnostic message of the decoder
+                match &output {
+                    ScanOutput::Capture => decoded.captured.push(line),
+                    ScanOutput::Print(_) => log::warn!("{line}"),
+                    ScanOutput::Forward(lines) => {
+                        let _ = lines.send(line);
+                    }
+                }
+                continue;
+            }
+        };
+
+        decoded.markers.extend(
+            markers
+                .iter()
+                .filter(|marker| frame.data.contains(marker.as_str()))
+                .cloned(),
+        );
+
+        match &output {
+            ScanOutput::Capture => decoded.captured.extend(format_frame(&frame)),
+            ScanOutput::Forward(lines) => {
+                for line in format_frame(&frame) {
+                    let _ = lines.send(line);
+                }
+            }
+            ScanOutput::Print(OutputFormat::Pretty) => {
+                let mut stdout = stdout.lock();
+                for line in format_frame(&frame) {
+                    let _ = writeln!(stdout, "{line}");
+                }
+            }
+            ScanOutput::Print(OutputFormat::Json) => OutputEvent::Log(&frame).emit(),
+        }
+        decoded.frames.push(frame);
+    }
+    decoded
+}
+
+/// Format the frame like the verbose output of `defmt-print`
+pub fn format_frame(frame: &JsonFrame) -> Vec<String> {
+    let level = frame
+        .level
+        .map(|level| format!("{:<5} ", level.as_str()))
+        .unwrap_or_default();
+    let timestamp = match frame.target_timestamp.as_str() {
+        "" => String::new(),
+        timestamp => format!("{timestamp} "),
+    };
+    let mut lines = vec![format!("{timestamp}{level}{}", frame.data)];
+
+    let location = &frame.location;
+    if let (Some(file), Some(line)) = (&location.file, location.line) {
+        let module = location
+            .module_path
+            .as_ref()
+            .map(|path| {
+                std::iter::once(path.crate_name.as_str())
+                    .chain(path.modules.iter().map(String::as_str))
+                    .chain(std::iter::once(path.function.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("::")
+            })
+            .unwrap_or_default();
+        lines.push(format!("└─ {module} @ {file}:{line}"));
+    }
+
+    lines
 }
 
+/// See the `CreateProcess` documentation of the Windows API
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
 impl Write for DefmtDecoder {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.spawned_decoder.stdin.as_mut().unwrap().write(buf)
@@ -0,0 +1,292 @@
+//! Saves the state of a halted device to a file and analyzes it offline
+//!
+//! A core dump is written after the device halted if [DumpConfig::dump] is
+//! given. It contains the registers of all cores, the context save areas, the
+//! stacks, additional memory regions and the RTT buffer. With [analyze], the
+//! backtraces and the defmt output are obtained from the dump and the binary
+//! without a connection to the device.
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use colored::Colorize;
+use tricore_common::{
+    backtrace::Stacktrace,
+    dump::{CoreDump, DumpRegion, DumpRequest, DUMP_FORMAT_VERSION},
+};
+
+use crate::{
+    backtrace::ParseInfo,
+    chip_interface::ChipInterface,
+    defmt::DefmtDecoder,
+    elf::{build_id, csa_regions},
+    stack::{stack_layout, stack_usage, DEFAULT_CSA_WARNING_THRESHOLD},
+};
+
+/// Configures whether and what is written to a core dump when the device halts
+#[derive(clap::Args, Debug)]
+pub struct DumpConfig {
+    /// Write a core dump to the given path when the device halts, which can be
+    /// inspected later with the 'analyze' subcommand
+    #[arg(long, value_name = "PATH")]
+    pub dump: Option<PathBuf>,
+
+    /// An additional memory region to include in the core dump, e.g. a DSPR or
+    /// the LMU, given as '<address>:<length>' in hex. May be given multiple times
+    #[arg(long = "dump-region", value_name = "ADDRESS:LENGTH", value_parser = parse_region)]
+    pub dump_regions: Vec<(u32, u32)>,
+
+    /// The number of bytes of each stack to include in the core dump, starting
+    /// at the stack pointer
+    #[arg(long, value_name = "BYTES", default_value_t = 4096)]
+    pub dump_stack_size: u32,
+}
+
+impl DumpConfig {
+    /// Capture a core dump of the halted device and write it to the configured path
+    ///
+    /// Does nothing if no core dump is requested.
+    pub fn write(
+        &self,
+        command_server: &ChipInterface,
+        elf_file: &Path,
+        rtt_control_block_address: u64,
+    ) -> anyhow::Result<()> {
+        let Some(path) = &self.dump else {
+            return Ok(());
+        };
+
+        let request = self.request(elf_file, rtt_control_block_address)?;
+        let mut dump = command_server.capture_dump(&request)?;
+        dump.build_id = build_id(elf_file)?;
+
+        let file = File::create(path)
+            .with_context(|| format!("Cannot create core dump {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &dump)
+            .with_context(|| format!("Cannot write core dump {}", path.display()))?;
+        writer.flush()?;
+
+        println!("Core dump written to {}", path.display());
+        Ok(())
+    }
+
+    /// The memory the core dump is made of besides the registers
+    ///
+    /// The context save areas are taken from the elf file, such that the CSA link
+    /// chains of all cores can be walked offline.
+    fn request(
+        &self,
+        elf_file: &Path,
+        rtt_control_block_address: u64,
+    ) -> anyhow::Result<DumpRequest> {
+        let csa = csa_regions(elf_file)?
+            .into_iter()
+            .map(|region| ("csa", region.start, region.end - region.start));
+        let regions = self
+            .dump_regions
+            .iter()
+            .map(|(address, length)| ("region", *address, *length));
+
+        let regions = csa
+            .chain(regions)
+            .map(|(name, address, length)| DumpRegion {
+                name: name.to_owned(),
+                core: 0,
+                address,
+                length,
+            })
+            .collect();
+
+        Ok(DumpRequest {
+            regions,
+            stack_size: self.dump_stack_size,
+            rtt_control_block: u32::try_from(rtt_control_block_address).ok(),
+        })
+    }
+}
+
+/// Print the defmt output, the backtrace and the stack usage of each core stored
+/// in a core dump
+///
+/// The elf file must be the binary that ran on the device when the dump was
+/// captured.
+pub fn analyze(dump_file: &Path, elf_file: &Path, verbose_backtrace: bool) -> anyhow::Result<()> {
+    let dump = load(dump_file)?;
+
+    let elf_build_id = build_id(elf_file)?;
+    if dump.build_id.is_some() && dump.build_id != elf_build_id {
+        log::warn!(
+            "The build ID of {} does not match the core dump, backtraces may be wrong",
+            elf_file.display()
+        );
+    }
+
+    match &dump.rtt {
+        Some(rtt) => {
+            println!("{}", "RTT output".bold());
+            let mut defmt_decoder = DefmtDecoder::spawn(elf_file)?;
+            defmt_decoder.write_all(&rtt.history())?;
+            defmt_decoder.finish()?;
+        }
+        None => log::info!("Core dump does not contain the RTT buffer"),
+    }
+
+    let regions = csa_regions(elf_file)?;
+    for image in dump.cores.iter() {
+        println!("{}", format!("Core {}", image.core).bold());
+        for missing in image.missing.iter() {
+            let line = format!(
+                "{} bytes of the {} region at {:#010X} are missing: {}",
+                missing.length, missing.name, missing.address, missing.reason
+            );
+            println!("{}", line.yellow());
+        }
+        let Some(core) = dump.core(image.core) else {
+            continue;
+        };
+        let stacktrace = match Stacktrace::capture(&core, &regions) {
+            Ok(stacktrace) => stacktrace,
+            Err(error) => {
+                println!("{}", format!("No backtrace available: {error:#}").yellow());
+                continue;
+            }
+        };
+
+        let backtrace_info = stacktrace.addr2line(elf_file)?;
+        if verbose_backtrace {
+            backtrace_info.log_stdout_verbose();
+        } else {
+            backtrace_info.log_stdout();
+        }
+    }
+
+    // The stacks are not painted for core dumps, so only the current usage is known
+    let usage = stack_usage(&dump, &stack_layout(elf_file)?, false);
+    if !usage.is_empty() {
+        println!("{}", "Stack usage".bold());
+        for core in usage.iter() {
+            core.log_stdout(DEFAULT_CSA_WARNING_THRESHOLD);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a core dump written by [DumpConfig::write]
+fn load(dump_file: &Path) -> anyhow::Result<CoreDump> {
+    let file = File::open(dump_file)
+        .with_context(|| format!("Cannot open core dump {}", dump_file.display()))?;
+    let dump: CoreDump = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Cannot parse core dump {}", dump_file.display()))?;
+
+    if dump.version != DUMP_FORMAT_VERSION {
+        anyhow::bail!(
+            "Core dump has version {}, but only version {DUMP_FORMAT_VERSION} is supported",
+            dump.version
+        );
+    }
+    Ok(dump)
+}
+
+/// Parse a memory region given as '<address>:<length>' in hex
+fn parse_region(input: &str) -> anyhow::Result<(u32, u32)> {
+    let (address, length) = input
+        .split_once(':')
+        .with_context(|| "Region must be given as '<address>:<length>'")?;
+    let parse_hex = |value: &str| {
+        let value = value.trim_start_matches("0x").trim_start_matches("0X");
+        u32::from_str_radix(value, 16).with_context(|| format!("{value:?} is not a hex number"))
+    };
+    Ok((parse_hex(address)?, parse_hex(length)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_are_parsed_as_hex() {
+        assert_eq!(
+            parse_region("0x70000000:0x100").unwrap(),
+            (0x7000_0000, 0x100)
+        );
+        assert_eq!(parse_region("b0000000:40").unwrap(), (0xB000_0000, 0x40));
+        assert!(parse_region("70000000").is_err());
+        assert!(parse_region("70000000:4g").is_err());
+    }
+
+    /// Captures a dump through a simulated core, of which a part of a region
+    /// cannot be read, writes it to a file and analyzes it
+    #[cfg(feature = "windows")]
+    #[test]
+    fn dumps_are_captured_written_and_analyzed() {
+        use rust_mcd::{
+            error::McdErrorCode,
+            simulation::{Operation, SimulatedError, SimulatedMcd},
+            system::System,
+        };
+        use tricore_common::access::MemoryAccess;
+
+        use crate::elf::elf_with_symbols;
+
+        const REGION: u32 = 0x7000_0000;
+
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.set_payload_size(index, 16);
+        let region: Vec<u8> = (0..0x40).collect();
+        simulation.write_memory(index, REGION as u64, &region);
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+
+        let elf = elf_with_symbols(&[]);
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("dump.json");
+        let config = DumpConfig {
+            dump: Some(path.clone()),
+            dump_regions: vec![(REGION, 0x40)],
+            dump_stack_size: 0x20,
+        };
+        let request = config.request(elf.path(), u64::MAX).unwrap();
+        assert_eq!(request.rtt_control_block, None);
+
+        // The core has no registers, so the first transaction reads the region
+        // and fails
+        simulation.inject_error(
+            Operation::ExecuteTransactions,
+            SimulatedError::new(McdErrorCode::McdErrTxlistTx, "Bus error"),
+        );
+        let dump = tricore_windows::dump::capture_dump(&[core], &request).unwrap();
+        let file = File::create(&path).unwrap();
+        serde_json::to_writer(file, &dump).unwrap();
+
+        let dump = load(&path).unwrap();
+        let image = &dump.cores[0];
+        let blocks: Vec<_> = image
+            .memory
+            .iter()
+            .map(|block| (block.name.as_str(), block.address, block.data.len()))
+            .collect();
+        assert_eq!(blocks, [("region", REGION + 0x10, 0x30),]);
+        assert_eq!(image.missing.len(), 1);
+        let missing = &image.missing[0];
+        assert_eq!((missing.address, missing.length), (REGION, 0x10));
+        assert!(missing.reason.contains("Bus error"), "{}", missing.reason);
+
+        let core = dump.core(0).unwrap();
+        assert_eq!(
+            core.read_memory(REGION + 0x24, 4).unwrap(),
+            [36, 37, 38, 39]
+        );
+        let error = core.read_memory(REGION + 0xC, 8).unwrap_err().to_string();
+        assert!(error.contains("region"), "{error}");
+        assert!(error.contains("missing from the dump"), "{error}");
+        assert!(error.contains("Bus error"), "{error}");
+
+        analyze(&path, elf.path(), false).unwrap();
+    }
+}
@@ -1,11 +1,21 @@
 //! Utilities to work with elf files
 
+use std::collections::HashMap;
 use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
 use std::process::{Command, Stdio};
 
+use ::elf::{
+    abi::{STT_FUNC, STT_OBJECT},
+    endian::AnyEndian,
+    note::{Note, NoteGnuBuildId},
+    ElfBytes,
+};
 use anyhow::Context;
-use std::io::Write;
+use std::{fmt::Write as _, io::Write};
 use tempfile::TempDir;
+use tricore_common::backtrace::walker::CsaRegion;
 
 /// Interpret the given data as a hex file and convert it to the Intel hex format
 ///
@@ -56,3 +66,239 @@ pub fn elf_to_hex(data: &[u8]) -> anyhow::Result<String> {
     drop(temporary_directory);
     Ok(hex_file)
 }
+
+/// Look up the address of the symbol with the given name in the elf file
+pub fn symbol_address(elf_file: &Path, name: &str) -> anyhow::Result<u32> {
+    symbol_range(elf_file, name).map(|range| range.start)
+}
+
+/// Look up the addresses the symbol with the given name occupies in the elf file
+///
+/// For symbols without a size, e.g. labels, the range only contains the address
+/// of the symbol.
+pub fn symbol_range(elf_file: &Path, name: &str) -> anyhow::Result<Range<u32>> {
+    let elf_data = std::fs::read(elf_file)
+        .with_context(|| format!("Cannot read elf file {}", elf_file.display()))?;
+    let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data)
+        .with_context(|| "Could not parse elf file")?;
+
+    let (symbols, strings) = elf
+        .symbol_table()
+        .with_context(|| "Could not parse symbol table from elf file")?
+        .with_context(|| "Elf file does not have symbol table")?;
+
+    let symbol = symbols
+        .iter()
+        .find(|symbol| {
+            strings
+                .get(symbol.st_name as usize)
+                .is_ok_and(|symbol_name| symbol_name == name)
+        })
+        .with_context(|| format!("Could not find symbol {name:?} in elf file"))?;
+
+    let address = symbol.st_value;
+    let end = address + symbol.st_size.max(1);
+    let start: u32 = address
+        .try_into()
+        .with_context(|| format!("Address {address:#X} of symbol {name:?} exceeds 32 bits"))?;
+    let end: u32 = end
+        .try_into()
+        .with_context(|| format!("Symbol {name:?} exceeds the 32 bit address space"))?;
+
+    Ok(start..end)
+}
+
+/// Look up the symbols that are placed in the section with the given name
+///
+/// Only data objects and functions are considered, e.g. labels defined by the
+/// linker script are ignored. The symbols are returned with their address,
+/// ordered by address. Returns an empty list if the elf file does not contain
+/// the section.
+pub fn section_symbols(elf_file: &Path, section: &str) -> anyhow::Result<Vec<(String, u32)>> {
+    let elf_data = std::fs::read(elf_file)
+        .with_context(|| format!("Cannot read elf file {}", elf_file.display()))?;
+    let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data)
+        .with_context(|| "Could not parse elf file")?;
+
+    let (section_headers, section_names) = elf
+        .section_headers_with_strtab()
+        .with_context(|| "Could not parse section headers from elf file")?;
+    let (Some(section_headers), Some(section_names)) = (section_headers, section_names) else {
+        return Ok(Vec::new());
+    };
+    let Some(section_index) = section_headers.iter().position(|header| {
+        section_names
+            .get(header.sh_name as usize)
+            .is_ok_and(|name| name == section)
+    }) else {
+        return Ok(Vec::new());
+    };
+
+    let (symbols, strings) = elf
+        .symbol_table()
+        .with_context(|| "Could not parse symbol table from elf file")?
+        .with_context(|| "Elf file does not have symbol table")?;
+
+    let mut section_symbols = symbols
+        .iter()
+        .filter(|symbol| symbol.st_shndx as usize == section_index && symbol.st_name != 0)
+        .filter(|symbol| matches!(symbol.st_symtype(), STT_OBJECT | STT_FUNC))
+        .map(|symbol| {
+            let name = strings
+                .get(symbol.st_name as usize)
+                .with_context(|| "Could not read symbol name from elf file")?;
+            let address: u32 = symbol.st_value.try_into().with_context(|| {
+                format!(
+                    "Address {:#X} of symbol {name:?} exceeds 32 bits",
+                    symbol.st_value
+                )
+            })?;
+            Ok((name.to_owned(), address))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    section_symbols.sort_by_key(|(_, address)| *address);
+
+    Ok(section_symbols)
+}
+
+/// Look up the regions that hold the context save areas in the elf file
+///
+/// The regions are defined by the linker script, either per core as with the
+/// Infineon iLLD (`__CSA0` to `__CSA0_END`, `__CSA1` to `__CSA1_END`, ...) or as
+/// a single region (`__CSA_BEGIN` to `__CSA_END`). Returns an empty list if the
+/// elf file does not define any of these symbols.
+pub fn csa_regions(elf_file: &Path) -> anyhow::Result<Vec<CsaRegion>> {
+    let addresses = prefixed_symbols(elf_file, "__CSA")?;
+
+    let bounds = (0..8)
+        .map(|core| (format!("__CSA{core}"), format!("__CSA{core}_END")))
+        .chain([("__CSA_BEGIN".to_owned(), "__CSA_END".to_owned())]);
+    let regions = bounds
+        .filter_map(|(start, end)| {
+            Some(CsaRegion {
+                start: *addresses.get(start.as_str())?,
+                end: *addresses.get(end.as_str())?,
+            })
+        })
+        .filter(|region| region.start < region.end)
+        .collect();
+
+    Ok(regions)
+}
+
+/// Look up the addresses of all symbols whose name starts with the given prefix
+///
+/// This is used for the symbols defined by the linker script, e.g. the bounds of
+/// the stacks and context save areas. Symbols with addresses that exceed 32 bits
+/// are ignored.
+pub fn prefixed_symbols(elf_file: &Path, prefix: &str) -> anyhow::Result<HashMap<String, u32>> {
+    let elf_data = std::fs::read(elf_file)
+        .with_context(|| format!("Cannot read elf file {}", elf_file.display()))?;
+    let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data)
+        .with_context(|| "Could not parse elf file")?;
+
+    let (symbols, strings) = elf
+        .symbol_table()
+        .with_context(|| "Could not parse symbol table from elf file")?
+        .with_context(|| "Elf file does not have symbol table")?;
+
+    let mut addresses = HashMap::new();
+    for symbol in symbols.iter() {
+        let Ok(name) = strings.get(symbol.st_name as usize) else {
+            continue;
+        };
+        if name.starts_with(prefix) {
+            if let Ok(address) = u32::try_from(symbol.st_value) {
+                addresses.insert(name.to_owned(), address);
+            }
+        }
+    }
+
+    Ok(addresses)
+}
+
+/// Read the GNU build ID of the elf file, hex encoded
+///
+/// Returns [None] if the binary was linked without `--build-id`.
+pub fn build_id(elf_file: &Path) -> anyhow::Result<Option<String>> {
+    let elf_data = std::fs::read(elf_file)
+        .with_context(|| format!("Cannot read elf file {}", elf_file.display()))?;
+    let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data)
+        .with_context(|| "Could not parse elf file")?;
+
+    let Some(section) = elf
+        .section_header_by_name(".note.gnu.build-id")
+        .with_context(|| "Could not parse section headers from elf file")?
+    else {
+        return Ok(None);
+    };
+    let mut notes = elf
+        .section_data_as_notes(&section)
+        .with_context(|| "Could not parse build ID note from elf file")?;
+
+    let build_id = notes.find_map(|note| match note {
+        Note::GnuBuildId(NoteGnuBuildId(id)) => {
+            Some(id.iter().fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            }))
+        }
+        _ => None,
+    });
+    Ok(build_id)
+}
+
+/// Write an elf file that only holds a symbol table, for tests
+///
+/// The symbols are given as (name, address, size) and are absolute functions.
+#[cfg(test)]
+pub(crate) fn elf_with_symbols(symbols: &[(&str, u32, u32)]) -> tempfile::NamedTempFile {
+    const HEADER_SIZE: u32 = 52;
+    const SHN_ABS: u16 = 0xFFF1;
+
+    let mut strings = vec![0];
+    let mut table = vec![0; 16];
+    for (name, address, size) in symbols {
+        table.extend((strings.len() as u32).to_le_bytes());
+        table.extend(address.to_le_bytes());
+        table.extend(size.to_le_bytes());
+        // A global function
+        table.extend([0x12, 0]);
+        table.extend(SHN_ABS.to_le_bytes());
+        strings.extend(name.as_bytes());
+        strings.push(0);
+    }
+
+    let symtab_offset = HEADER_SIZE;
+    let strtab_offset = symtab_offset + table.len() as u32;
+    let headers_offset = (strtab_offset + strings.len() as u32 + 3) & !3;
+
+    // 32 bit little endian executable for TriCore without program headers
+    let mut data = b"\x7fELF\x01\x01\x01".to_vec();
+    data.resize(16, 0);
+    data.extend(2u16.to_le_bytes());
+    data.extend(44u16.to_le_bytes());
+    for word in [1, 0, 0, headers_offset, 0] {
+        data.extend(u32::to_le_bytes(word));
+    }
+    for half in [HEADER_SIZE as u16, 32, 0, 40, 3, 0] {
+        data.extend(u16::to_le_bytes(half));
+    }
+    data.extend(&table);
+    data.extend(&strings);
+    data.resize(headers_offset as usize, 0);
+
+    // The null section, the symbol table and the string table it links to
+    let sections = [
+        [0; 10],
+        [0, 2, 0, 0, symtab_offset, table.len() as u32, 2, 1, 4, 16],
+        [0, 3, 0, 0, strtab_offset, strings.len() as u32, 0, 0, 1, 0],
+    ];
+    for word in sections.iter().flatten() {
+        data.extend(word.to_le_bytes());
+    }
+
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&data).unwrap();
+    file
+}
@@ -0,0 +1,980 @@
+//! A GDB remote serial protocol server on top of a [DebugTarget], see [serve]
+//!
+//! GDB connects over TCP, e.g. with `target remote :3333`. Each core of the
+//! chip is presented as a thread, the thread ID is the index of the core plus
+//! one. The server runs in all-stop mode: continuing resumes all cores, and
+//! once any of them halts, the other cores are halted as well.
+//!
+//! Breakpoints and watchpoints are installed on all cores. Watchpoints trigger
+//! on any access, the debug controller does not distinguish reads and writes.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use anyhow::Context;
+use tricore_common::debug::{Breakpoint, BreakpointKind, CoreStatus, DebugCommand, DebugTarget};
+
+/// The registers in the order GDB expects them for TriCore
+const REGISTERS: [&str; 37] = [
+    "D0", "D1", "D2", "D3", "D4", "D5", "D6", "D7", "D8", "D9", "D10", "D11", "D12", "D13", "D14",
+    "D15", "A0", "A1", "A2", "A3", "A4", "A5", "A6", "A7", "A8", "A9", "A10", "A11", "A12", "A13",
+    "A14", "A15", "LCX", "FCX", "PCXI", "PSW", "PC",
+];
+
+/// The largest packet GDB may send, announced in `qSupported`
+const PACKET_SIZE: usize = 0x1000;
+
+/// Interval at which running cores are checked for a halt and the connection
+/// for an interrupt
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The byte GDB sends to interrupt the running target
+const INTERRUPT: u8 = 0x03;
+
+/// Signal numbers reported in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Accept GDB connections on the given address and serve them one after another
+///
+/// All cores are halted when GDB connects. When GDB detaches, the breakpoints
+/// are removed and the cores resume. This function only returns on errors.
+pub fn serve(target: &mut dyn DebugTarget, address: SocketAddr) -> anyhow::Result<()> {
+    let listener =
+        TcpListener::bind(address).with_context(|| format!("Cannot listen on {address}"))?;
+    println!(
+        "Waiting for GDB on {address}, {} cores are available as threads",
+        target.core_count()
+    );
+
+    for stream in listener.incoming() {
+        let stream = stream.with_context(|| "Cannot accept GDB connection")?;
+        let peer = stream.peer_addr()?;
+        println!("GDB connected from {peer}");
+
+        let mut session = Session {
+            target: &mut *target,
+            connection: Connection::new(stream)?,
+            selected: 0,
+            breakpoints: Vec::new(),
+        };
+        match session.run() {
+            Ok(()) => println!("GDB disconnected"),
+            Err(error) => log::error!("GDB session ended: {error:#}"),
+        }
+        session.remove_breakpoints();
+    }
+    Ok(())
+}
+
+/// A packet or the interrupt byte received from GDB
+enum Received {
+    Packet(String),
+    Interrupt,
+}
+
+/// Frames packets according to the remote serial protocol
+struct Connection {
+    stream: TcpStream,
+    /// Whether GDB requested to skip acknowledgments, see `QStartNoAckMode`
+    no_ack: bool,
+    /// Bytes received while waiting for an interrupt, they are read before the
+    /// stream
+    pending: VecDeque<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> anyhow::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            no_ack: false,
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        match self.pending.pop_front() {
+            Some(byte) => Ok(Some(byte)),
+            None => self.read_stream_byte(),
+        }
+    }
+
+    fn read_stream_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Wait for the next packet, returns [None] once GDB closed the connection
+    fn receive(&mut self) -> anyhow::Result<Option<Received>> {
+        loop {
+            let Some(byte) = self.read_byte()? else {
+                return Ok(None);
+            };
+            match byte {
+                INTERRUPT => return Ok(Some(Received::Interrupt)),
+                b'$' => {}
+                // Acknowledgments and noise between packets
+                _ => continue,
+            }
+
+            let mut data = Vec::new();
+            let checksum = loop {
+                let Some(byte) = self.read_byte()? else {
+                    return Ok(None);
+                };
+                if byte == b'#' {
+                    let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                        return Ok(None);
+                    };
+                    break u8::from_str_radix(std::str::from_utf8(&[high, low])?, 16).ok();
+                }
+                data.push(byte);
+            };
+
+            let valid = checksum == Some(data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)));
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                let packet = String::from_utf8_lossy(&data).into_owned();
+                log::trace!("GDB -> {packet}");
+                return Ok(Some(Received::Packet(packet)));
+            }
+            log::debug!("Discarding packet with invalid checksum");
+        }
+    }
+
+    /// Send a packet, retransmitting it until GDB acknowledges it
+    fn send(&mut self, data: &str) -> anyhow::Result<()> {
+        log::trace!("GDB <- {data}");
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.extend([b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let checksum = packet[1..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        packet.extend(format!("#{checksum:02x}").bytes());
+
+        loop {
+            self.stream.write_all(&packet)?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // Pending bytes were received before the packet was sent, the
+            // acknowledgment follows them on the stream
+            match self.read_stream_byte()? {
+                Some(b'+') => return Ok(()),
+                Some(b'-') => continue,
+                Some(byte) => {
+                    log::debug!("Expected acknowledgment from GDB, received {byte:#04X}");
+                    self.pending.push_back(byte);
+                    return Ok(());
+                }
+                None => anyhow::bail!("GDB closed the connection"),
+            }
+        }
+    }
+
+    /// Check whether GDB sent the interrupt byte, without blocking
+    ///
+    /// Other bytes are kept for [Connection::receive], GDB may send packets
+    /// before it interrupts the target.
+    fn interrupted(&mut self) -> anyhow::Result<bool> {
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let result = self.read_stream_byte();
+        self.stream.set_read_timeout(None)?;
+        match result {
+            Ok(Some(INTERRUPT)) => Ok(true),
+            Ok(Some(byte)) => {
+                self.pending.push_back(byte);
+                Ok(false)
+            }
+            Ok(None) => anyhow::bail!("GDB closed the connection"),
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// What to do after a packet was handled
+enum Outcome {
+    Reply(String),
+    /// GDB detached or killed the session
+    Close,
+}
+
+/// Serves a single GDB connection
+struct Session<'a> {
+    target: &'a mut dyn DebugTarget,
+    connection: Connection,
+    /// The index of the core that registers and execution commands apply to
+    selected: usize,
+    /// The breakpoints GDB installed, on all cores
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl<'a> Session<'a> {
+    fn run(&mut self) -> anyhow::Result<()> {
+        self.halt_all(None);
+
+        while let Some(received) = self.connection.receive()? {
+            let packet = match received {
+                Received::Packet(packet) => packet,
+                // The cores are already halted
+                Received::Interrupt => continue,
+            };
+
+            let outcome = self.handle(&packet).unwrap_or_else(|error| {
+                log::warn!("Cannot handle GDB packet {packet:?}: {error:#}");
+                Outcome::Reply("E01".to_owned())
+            });
+            match outcome {
+                Outcome::Reply(reply) => {
+                    self.connection.send(&reply)?;
+                    if packet == "QStartNoAckMode" {
+                        self.connection.no_ack = true;
+                    }
+                }
+                Outcome::Close => {
+                    self.connection.send("OK")?;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> anyhow::Result<Outcome> {
+        let reply = |reply: &str| Ok(Outcome::Reply(reply.to_owned()));
+        // The packet may start with any character, e.g. if it was not valid UTF-8
+        let command_length = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, arguments) = packet.split_at(command_length);
+
+        match command {
+            "?" => reply(&self.stop_reply(SIGTRAP)),
+            "g" => {
+                let registers = self.target.read_registers(self.selected)?;
+                let mut data = String::new();
+                for name in REGISTERS {
+                    match registers.get(name) {
+                        Some(value) => data.push_str(&hex(&value.to_le_bytes())),
+                        None => data.push_str("xxxxxxxx"),
+                    }
+                }
+                reply(&data)
+            }
+            "G" => {
+                let data = parse_hex(arguments)?;
+                for (name, value) in REGISTERS.iter().zip(data.chunks_exact(4)) {
+                    let value = u32::from_le_bytes(value.try_into()?);
+                    self.target.write_register(self.selected, name, value)?;
+                }
+                reply("OK")
+            }
+            "p" => {
+                let name = register_name(arguments)?;
+                let registers = self.target.read_registers(self.selected)?;
+                match registers.get(name) {
+                    Some(value) => reply(&hex(&value.to_le_bytes())),
+                    None => reply("xxxxxxxx"),
+                }
+            }
+            "P" => {
+                let (number, value) = arguments.split_once('=').context("Missing value")?;
+                let value = u32::from_le_bytes(parse_hex(value)?.as_slice().try_into()?);
+                self.target
+                    .write_register(self.selected, register_name(number)?, value)?;
+                reply("OK")
+            }
+            "m" => {
+                let (address, length) = parse_range(arguments)?;
+                let length = length.min(PACKET_SIZE as u32 / 2);
+                let data = self
+                    .target
+                    .read_memory(self.selected, address, length as usize)?;
+                reply(&hex(&data))
+            }
+            "M" => {
+                let (range, data) = arguments.split_once(':').context("Missing data")?;
+                let (address, _) = parse_range(range)?;
+                self.target
+                    .write_memory(self.selected, address, &parse_hex(data)?)?;
+                reply("OK")
+            }
+            "c" => {
+                self.jump(arguments)?;
+                self.resume()
+            }
+            "s" => {
+                self.jump(arguments)?;
+                self.target
+                    .execute(self.selected, DebugCommand::StepInstruction)?;
+                reply(&self.stop_reply(SIGTRAP))
+            }
+            "Z" | "z" => {
+                let breakpoint = parse_breakpoint(arguments)?;
+                let Some(breakpoint) = breakpoint else {
+                    // Unsupported breakpoint types are reported with an empty reply
+                    return reply("");
+                };
+                if command == "Z" {
+                    self.insert_breakpoint(breakpoint)?;
+                } else {
+                    self.remove_breakpoint(breakpoint)?;
+                }
+                reply("OK")
+            }
+            "H" => {
+                // The operation is 'g' or 'c', both select the core
+                let thread = arguments.get(1..).unwrap_or_default();
+                if let Some(core) = self.parse_thread(thread)? {
+                    self.selected = core;
+                }
+                reply("OK")
+            }
+            "T" => match self.parse_thread(arguments) {
+                Ok(_) => reply("OK"),
+                Err(_) => reply("E01"),
+            },
+            "D" => {
+                self.remove_breakpoints();
+                for core in 0..self.target.core_count() {
+                    if let Err(error) = self.target.execute(core, DebugCommand::Continue) {
+                        log::warn!("Cannot resume core {core}: {error:#}");
+                    }
+                }
+                Ok(Outcome::Close)
+            }
+            "k" => Ok(Outcome::Close),
+            "q" | "Q" => self.query(packet),
+            _ => reply(""),
+        }
+    }
+
+    /// Answer general queries
+    fn query(&mut self, packet: &str) -> anyhow::Result<Outcome> {
+        let reply = |reply: String| Ok(Outcome::Reply(reply));
+
+        if packet.starts_with("qSupported") {
+            return reply(format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+"
+            ));
+        }
+        if let Some(annex) = packet.strip_prefix("qXfer:features:read:") {
+            let (name, range) = annex.split_once(':').context("Missing range")?;
+            if name != "target.xml" {
+                return reply("E00".to_owned());
+            }
+            let (offset, length) = parse_range(range)?;
+            let description = target_description();
+            let start = (offset as usize).min(description.len());
+            let end = (start + length as usize).min(description.len());
+            let prefix = if end == description.len() { 'l' } else { 'm' };
+            return reply(format!("{prefix}{}", &description[start..end]));
+        }
+        if let Some(thread) = packet.strip_prefix("qThreadExtraInfo,") {
+            let core = self.parse_thread(thread)?.unwrap_or(self.selected);
+            let state = match self.target.execute(core, DebugCommand::Status) {
+                Ok(CoreStatus::Running) => "running",
+                Ok(CoreStatus::Halted(_)) => "halted",
+                Err(_) => "unknown",
+            };
+            return reply(hex(format!("Core {core}, {state}").as_bytes()));
+        }
+
+        match packet {
+            "QStartNoAckMode" => reply("OK".to_owned()),
+            "qfThreadInfo" => {
+                let threads: Vec<_> = (0..self.target.core_count())
+                    .map(|core| format!("{:x}", core + 1))
+                    .collect();
+                reply(format!("m{}", threads.join(",")))
+            }
+            "qsThreadInfo" => reply("l".to_owned()),
+            "qC" => reply(format!("QC{:x}", self.selected + 1)),
+            "qAttached" => reply("1".to_owned()),
+            "qSymbol::" => reply("OK".to_owned()),
+            _ => reply(String::new()),
+        }
+    }
+
+    /// The core given by a GDB thread ID, [None] for any thread
+    fn parse_thread(&self, thread: &str) -> anyhow::Result<Option<usize>> {
+        match thread {
+            "0" | "-1" => Ok(None),
+            thread => {
+                let id = usize::from_str_radix(thread, 16)?;
+                let core = id.checked_sub(1).context("Invalid thread ID")?;
+                if core >= self.target.core_count() {
+                    anyhow::bail!("Thread {id} does not exist");
+                }
+                Ok(Some(core))
+            }
+        }
+    }
+
+    fn stop_reply(&self, signal: u8) -> String {
+        format!("T{signal:02x}thread:{:x};", self.selected + 1)
+    }
+
+    /// Set the program counter of the selected core, if an address is given
+    fn jump(&mut self, address: &str) -> anyhow::Result<()> {
+        if address.is_empty() {
+            return Ok(());
+        }
+        let address = u32::from_str_radix(address, 16)?;
+        self.target.write_register(self.selected, "PC", address)
+    }
+
+    /// Resume all cores and wait until one of them halts or GDB interrupts
+    fn resume(&mut self) -> anyhow::Result<Outcome> {
+        for core in 0..self.target.core_count() {
+            if let Err(error) = self.target.execute(core, DebugCommand::Continue) {
+                log::warn!("Cannot resume core {core}: {error:#}");
+            }
+        }
+
+        loop {
+            if self.connection.interrupted()? {
+                self.halt_all(None);
+                return Ok(Outcome::Reply(self.stop_reply(SIGINT)));
+            }
+
+            for core in 0..self.target.core_count() {
+                if let Ok(CoreStatus::Halted(_)) = self.target.execute(core, DebugCommand::Status) {
+                    log::debug!("Core {core} halted");
+                    self.selected = core;
+                    self.halt_all(Some(core));
+                    return Ok(Outcome::Reply(self.stop_reply(SIGTRAP)));
+                }
+            }
+        }
+    }
+
+    /// Halt all cores except the given one, failures are only logged
+    fn halt_all(&mut self, except: Option<usize>) {
+        for core in (0..self.target.core_count()).filter(|core| Some(*core) != except) {
+            if let Err(error) = self.target.execute(core, DebugCommand::Halt) {
+                log::warn!("Cannot halt core {core}: {error:#}");
+            }
+        }
+    }
+
+    fn insert_breakpoint(&mut self, breakpoint: Breakpoint) -> anyhow::Result<()> {
+        for core in 0..self.target.core_count() {
+            if let Err(error) = self.target.insert_breakpoint(core, breakpoint) {
+                // Leave no breakpoint behind that GDB does not know about
+                for installed in 0..core {
+                    let _ = self.target.remove_breakpoint(installed, breakpoint);
+                }
+                return Err(error);
+            }
+        }
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> anyhow::Result<()> {
+        self.breakpoints
+            .retain(|installed| *installed != breakpoint);
+        for core in 0..self.target.core_count() {
+            self.target.remove_breakpoint(core, breakpoint)?;
+        }
+        Ok(())
+    }
+
+    /// Remove all breakpoints GDB installed, failures are only logged
+    fn remove_breakpoints(&mut self) {
+        for breakpoint in std::mem::take(&mut self.breakpoints) {
+            for core in 0..self.target.core_count() {
+                if let Err(error) = self.target.remove_breakpoint(core, breakpoint) {
+                    log::warn!("Cannot remove breakpoint from core {core}: {error:#}");
+                }
+            }
+        }
+    }
+}
+
+/// The target description, which tells GDB the registers in [REGISTERS]
+fn target_description() -> String {
+    let types: BTreeMap<&str, &str> =
+        [("A10", "data_ptr"), ("A11", "code_ptr"), ("PC", "code_ptr")]
+            .into_iter()
+            .collect();
+
+    let mut description = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>tricore</architecture>\
+         <feature name=\"org.gnu.gdb.tricore.core\">",
+    );
+    for (number, name) in REGISTERS.iter().enumerate() {
+        let _ = write!(
+            description,
+            "<reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{number}\"/>",
+            name.to_lowercase(),
+            types.get(name).unwrap_or(&"uint32")
+        );
+    }
+    description.push_str("</feature></target>");
+    description
+}
+
+/// The name of the register with the given GDB register number
+fn register_name(number: &str) -> anyhow::Result<&'static str> {
+    let number = usize::from_str_radix(number, 16)?;
+    REGISTERS
+        .get(number)
+        .copied()
+        .with_context(|| format!("Register {number} does not exist"))
+}
+
+/// Parse `<address>,<length>` in hex
+fn parse_range(range: &str) -> anyhow::Result<(u32, u32)> {
+    let (address, length) = range.split_once(',').context("Missing length")?;
+    Ok((
+        u32::from_str_radix(address, 16)?,
+        u32::from_str_radix(length, 16)?,
+    ))
+}
+
+/// Parse `<type>,<address>,<kind>` of a `Z` or `z` packet, [None] for
+/// unsupported types
+fn parse_breakpoint(arguments: &str) -> anyhow::Result<Option<Breakpoint>> {
+    let (kind, range) = arguments.split_once(',').context("Missing address")?;
+    let kind = match kind {
+        // Software breakpoints are implemented with triggers as well
+        "0" | "1" => BreakpointKind::Execution,
+        "2" | "3" | "4" => BreakpointKind::Access,
+        _ => return Ok(None),
+    };
+    let (address, length) = parse_range(range)?;
+    Ok(Some(Breakpoint {
+        kind,
+        address,
+        length,
+    }))
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn parse_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            let byte = hex.get(index..index + 2).context("Odd number of digits")?;
+            Ok(u8::from_str_radix(byte, 16)?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{net::TcpStream, thread::JoinHandle};
+
+    use tricore_common::{
+        backtrace::{walker::WalkStop, GlobalRegisters, Stacktrace},
+        trap::{TrapRegisters, VectorTables},
+    };
+
+    use super::*;
+
+    /// A target whose cores run until they were polled a number of times
+    #[derive(Default)]
+    pub(crate) struct MockTarget {
+        cores: usize,
+        memory: BTreeMap<u32, u8>,
+        pub(crate) breakpoints: Vec<(usize, Breakpoint)>,
+        /// Every breakpoint that was inserted, including removed ones
+        pub(crate) inserted: Vec<(usize, Breakpoint)>,
+        commands: Vec<(usize, DebugCommand)>,
+        /// The core that halts by itself, after its status was queried this often
+        pub(crate) halts_after: Option<(usize, usize)>,
+        running: Vec<bool>,
+    }
+
+    impl MockTarget {
+        pub(crate) fn new(cores: usize) -> Self {
+            MockTarget {
+                cores,
+                running: vec![false; cores],
+                ..MockTarget::default()
+            }
+        }
+
+        fn halted() -> CoreStatus {
+            CoreStatus::Halted(Box::new(Stacktrace {
+                current_pc: 0x8000_0000,
+                current_upper: Default::default(),
+                current_lower: Default::default(),
+                current_globals: GlobalRegisters::default(),
+                stack_frames: Vec::new(),
+                walk_stop: WalkStop::EndOfChain,
+                trap_registers: TrapRegisters::default(),
+                vector_tables: VectorTables::default(),
+            }))
+        }
+    }
+
+    impl DebugTarget for MockTarget {
+        fn core_count(&self) -> usize {
+            self.cores
+        }
+
+        fn execute(&mut self, core: usize, command: DebugCommand) -> anyhow::Result<CoreStatus> {
+            self.commands.push((core, command));
+            match command {
+                DebugCommand::Continue => self.running[core] = true,
+                DebugCommand::Status => {
+                    if let Some((halting, polls)) = self.halts_after.as_mut() {
+                        if *halting == core && self.running[core] {
+                            match polls.checked_sub(1) {
+                                Some(remaining) => *polls = remaining,
+                                None => self.running[core] = false,
+                            }
+                        }
+                    }
+                }
+                _ => self.running[core] = false,
+            }
+            Ok(match self.running[core] {
+                true => CoreStatus::Running,
+                false => MockTarget::halted(),
+            })
+        }
+
+        fn read_memory(&self, _: usize, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+            Ok((address..address + length as u32)
+                .map(|address| self.memory.get(&address).copied().unwrap_or_default())
+                .collect())
+        }
+
+        fn write_memory(&mut self, _: usize, address: u32, data: &[u8]) -> anyhow::Result<()> {
+            for (address, byte) in (address..).zip(data) {
+                self.memory.insert(address, *byte);
+            }
+            Ok(())
+        }
+
+        fn read_registers(&self, _: usize) -> anyhow::Result<BTreeMap<String, u32>> {
+            Ok([("PC".to_owned(), 0x8000_0000)].into_iter().collect())
+        }
+
+        fn write_register(&mut self, _: usize, _: &str, _: u32) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn insert_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()> {
+            self.breakpoints.push((core, breakpoint));
+            self.inserted.push((core, breakpoint));
+            Ok(())
+        }
+
+        fn remove_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()> {
+            let index = self
+                .breakpoints
+                .iter()
+                .position(|installed| *installed == (core, breakpoint))
+                .context("Breakpoint is not installed")?;
+            self.breakpoints.remove(index);
+            Ok(())
+        }
+    }
+
+    /// The GDB side of a session with the given target
+    struct Gdb {
+        stream: TcpStream,
+        server: JoinHandle<MockTarget>,
+    }
+
+    impl Gdb {
+        fn connect(mut target: MockTarget) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server_stream, _) = listener.accept().unwrap();
+
+            let server = std::thread::spawn(move || {
+                let mut session = Session {
+                    target: &mut target,
+                    connection: Connection::new(server_stream).unwrap(),
+                    selected: 0,
+                    breakpoints: Vec::new(),
+                };
+                session.run().unwrap();
+                session.remove_breakpoints();
+                target
+            });
+            Gdb { stream, server }
+        }
+
+        fn write(&mut self, data: &[u8]) {
+            self.stream.write_all(data).unwrap();
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        fn send(&mut self, packet: &str) {
+            let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            self.write(format!("${packet}#{checksum:02x}").as_bytes());
+        }
+
+        /// Read a packet and check its checksum, without acknowledging it
+        fn receive(&mut self) -> String {
+            assert_eq!(self.read_byte(), b'$');
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte(), self.read_byte()];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+            assert_eq!(
+                checksum,
+                data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+            );
+            String::from_utf8(data).unwrap()
+        }
+
+        /// Send a packet and return the acknowledged reply
+        fn request(&mut self, packet: &str) -> String {
+            self.send(packet);
+            assert_eq!(self.read_byte(), b'+');
+            let reply = self.receive();
+            self.write(b"+");
+            reply
+        }
+
+        /// Close the connection and return the target once the session ended
+        fn disconnect(self) -> MockTarget {
+            drop(self.stream);
+            self.server.join().unwrap()
+        }
+    }
+
+    #[test]
+    fn packets_with_invalid_checksums_are_rejected() {
+        let mut gdb = Gdb::connect(MockTarget::new(1));
+
+        gdb.write(b"$?#00");
+        assert_eq!(gdb.read_byte(), b'-');
+        assert_eq!(gdb.request("?"), "T05thread:1;");
+
+        gdb.disconnect();
+    }
+
+    #[test]
+    fn replies_are_retransmitted_until_acknowledged() {
+        let mut gdb = Gdb::connect(MockTarget::new(2));
+
+        gdb.send("qC");
+        assert_eq!(gdb.read_byte(), b'+');
+        assert_eq!(gdb.receive(), "QC1");
+        gdb.write(b"-");
+        assert_eq!(gdb.receive(), "QC1");
+        gdb.write(b"+");
+        assert_eq!(gdb.request("qfThreadInfo"), "m1,2");
+
+        gdb.disconnect();
+    }
+
+    #[test]
+    fn acknowledgments_stop_after_no_ack_mode() {
+        let mut gdb = Gdb::connect(MockTarget::new(1));
+
+        assert!(gdb
+            .request("qSupported:multiprocess+")
+            .contains("QStartNoAckMode+"));
+        assert_eq!(gdb.request("QStartNoAckMode"), "OK");
+
+        gdb.send("qC");
+        assert_eq!(gdb.receive(), "QC1");
+        gdb.send("qAttached");
+        assert_eq!(gdb.receive(), "1");
+
+        gdb.disconnect();
+    }
+
+    #[test]
+    fn memory_is_read_and_written() {
+        let mut gdb = Gdb::connect(MockTarget::new(1));
+
+        assert_eq!(gdb.request("M70000000,4:deadbeef"), "OK");
+        assert_eq!(gdb.request("m70000000,4"), "deadbeef");
+        assert_eq!(gdb.request("m70000002,4"), "beef0000");
+        // Replies must fit into a packet
+        assert_eq!(gdb.request("m70000000,2000").len(), PACKET_SIZE);
+        assert_eq!(gdb.request("m70000000"), "E01");
+
+        let target = gdb.disconnect();
+        assert_eq!(target.memory.get(&0x7000_0003), Some(&0xEF));
+    }
+
+    #[test]
+    fn breakpoints_are_installed_on_all_cores() {
+        let mut gdb = Gdb::connect(MockTarget::new(2));
+        let execution = Breakpoint {
+            kind: BreakpointKind::Execution,
+            address: 0x8000_0100,
+            length: 4,
+        };
+        let access = Breakpoint {
+            kind: BreakpointKind::Access,
+            address: 0x7000_0000,
+            length: 2,
+        };
+
+        assert_eq!(gdb.request("Z0,80000100,4"), "OK");
+        assert_eq!(gdb.request("Z2,70000000,2"), "OK");
+        // Unsupported breakpoint types
+        assert_eq!(gdb.request("Z9,80000100,4"), "");
+        assert_eq!(gdb.request("z0,80000100,4"), "OK");
+        assert_eq!(gdb.request("z0,80000100,4"), "E01");
+
+        let target = gdb.disconnect();
+        assert_eq!(
+            target.inserted,
+            [(0, execution), (1, execution), (0, access), (1, access)]
+        );
+        // The remaining breakpoints are removed when GDB disconnects
+        assert!(target.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn interrupt_halts_all_cores_during_continue() {
+        let mut gdb = Gdb::connect(MockTarget::new(2));
+
+        gdb.send("c");
+        assert_eq!(gdb.read_byte(), b'+');
+        gdb.write(&[INTERRUPT]);
+        assert_eq!(gdb.receive(), "T02thread:1;");
+        gdb.write(b"+");
+
+        let target = gdb.disconnect();
+        assert_eq!(target.running, [false, false]);
+        let resumed = target
+            .commands
+            .iter()
+            .filter(|(_, command)| *command == DebugCommand::Continue)
+            .count();
+        assert_eq!(resumed, 2);
+    }
+
+    #[test]
+    fn packets_sent_during_continue_are_handled_after_the_halt() {
+        let mut gdb = Gdb::connect(MockTarget::new(1));
+
+        gdb.send("c");
+        assert_eq!(gdb.read_byte(), b'+');
+        gdb.send("qC");
+        // Give the server time to poll the packet before the interrupt
+        std::thread::sleep(POLL_INTERVAL * 2);
+        gdb.write(&[INTERRUPT]);
+        assert_eq!(gdb.receive(), "T02thread:1;");
+        gdb.write(b"+");
+        assert_eq!(gdb.read_byte(), b'+');
+        assert_eq!(gdb.receive(), "QC1");
+        gdb.write(b"+");
+
+        gdb.disconnect();
+    }
+
+    #[test]
+    fn packets_starting_with_non_ascii_are_unsupported() {
+        let mut gdb = Gdb::connect(MockTarget::new(1));
+
+        // Invalid UTF-8 is replaced by a multi-byte character
+        let checksum = [0xFF, b'g'].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        gdb.write(b"$\xFFg#");
+        gdb.write(format!("{checksum:02x}").as_bytes());
+        assert_eq!(gdb.read_byte(), b'+');
+        assert_eq!(gdb.receive(), "");
+        gdb.write(b"+");
+        assert_eq!(gdb.request("\u{e9}"), "");
+        assert_eq!(gdb.request("?"), "T05thread:1;");
+
+        gdb.disconnect();
+    }
+
+    #[test]
+    fn continue_reports_the_core_that_halted() {
+        let mut target = MockTarget::new(2);
+        target.halts_after = Some((1, 3));
+        let mut gdb = Gdb::connect(target);
+
+        assert_eq!(gdb.request("c"), "T05thread:2;");
+        assert_eq!(gdb.request("qC"), "QC2");
+
+        let target = gdb.disconnect();
+        assert_eq!(target.running, [false, false]);
+        assert_eq!(target.commands.last(), Some(&(0, DebugCommand::Halt)));
+    }
+
+    #[test]
+    fn target_description_is_paged() {
+        let description = target_description();
+        let length = description.len();
+        let mut gdb = Gdb::connect(MockTarget::new(1));
+
+        let first = gdb.request("qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &description[..0x10]));
+
+        // The last chunk is marked with 'l', even if it fills the requested length
+        let exact = gdb.request(&format!("qXfer:features:read:target.xml:0,{length:x}"));
+        assert_eq!(exact, format!("l{description}"));
+        let short = gdb.request(&format!(
+            "qXfer:features:read:target.xml:0,{:x}",
+            length - 1
+        ));
+        assert_eq!(short, format!("m{}", &description[..length - 1]));
+        let tail = gdb.request(&format!(
+            "qXfer:features:read:target.xml:{:x},100",
+            length - 1
+        ));
+        assert_eq!(tail, format!("l{}", &description[length - 1..]));
+        let past = gdb.request(&format!("qXfer:features:read:target.xml:{length:x},100"));
+        assert_eq!(past, "l");
+
+        assert_eq!(gdb.request("qXfer:features:read:other.xml:0,10"), "E00");
+
+        gdb.disconnect();
+    }
+
+    #[test]
+    fn detach_resumes_all_cores() {
+        let mut gdb = Gdb::connect(MockTarget::new(2));
+
+        assert_eq!(gdb.request("Z0,80000100,4"), "OK");
+        assert_eq!(gdb.request("D"), "OK");
+
+        let target = gdb.disconnect();
+        assert!(target.breakpoints.is_empty());
+        assert_eq!(target.running, [true, true]);
+    }
+}
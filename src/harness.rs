@@ -0,0 +1,580 @@
+//! Runs a suite written with `defmt-test` and reports each test case on its own
+//!
+//! `defmt-test` runs all test cases of a suite in a single run of the firmware.
+//! It announces each test case in the defmt output with [ANNOUNCEMENT] and ends
+//! a suite that passed with `all tests passed!`, a failing test case panics,
+//! which halts the device. A binary is recognized as a suite if its defmt table
+//! contains the announcement. The suite runs once, its output is split at the
+//! announcements: every test case that was followed by another one passed, the
+//! outcome of the last one is obtained as in [TestRunnerConfig::evaluate].
+//!
+//! Test cases after a failing one are not run, since `defmt-test` cannot select
+//! the test cases to run. For the same reason the test cases are only known
+//! once they ran, the ones that did not run are reported as ignored by their
+//! number. `embedded-test` is not supported, it selects the test cases
+//! through semihosting, which is not available for TriCore.
+//!
+//! The report follows the output of libtest, such that tools that parse the
+//! results of `cargo test` show the individual test cases.
+use std::{
+    io::Write,
+    path::Path,
+    process::ExitCode,
+    time::{Duration, Instant, SystemTime},
+};
+
+use colored::Colorize;
+use defmt_json_schema::v1::JsonFrame;
+use serde::Deserialize;
+use serde_json::json;
+use tricore_common::{
+    reset::ResetConfig,
+    run::{HaltReason, RunControl, StopReason},
+};
+
+use crate::{
+    backtrace::log_halted_cores,
+    chip_interface::ChipInterface,
+    defmt::{format_frame, DefmtDecoder},
+    elf::{csa_regions, section_symbols},
+    output::OutputFormat,
+    report::{RunRecord, RunReport},
+    test_runner::TestRunnerConfig,
+    EXIT_CANCELLED,
+};
+
+/// The format string `defmt-test` announces each test case with, the arguments
+/// are the number of the test case, the number of test cases and its name
+pub const ANNOUNCEMENT: &str = "({=usize}/{=usize}) running `{=str}`...";
+
+/// The section of the elf file that holds the interned strings of defmt
+const DEFMT_SECTION: &str = ".defmt";
+
+/// The symbols in [DEFMT_SECTION] are named after a JSON description of the
+/// interned string
+#[derive(Deserialize)]
+struct DefmtSymbol {
+    data: String,
+}
+
+/// A single test case of the suite
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    /// The number the firmware announced the test case with, starting at 1
+    pub number: usize,
+    pub name: String,
+}
+
+/// The output of a single test case, see [split_at_announcements]
+#[derive(Debug)]
+struct TestRun {
+    test_case: TestCase,
+    /// The number of test cases in the suite, as announced by the firmware
+    count: usize,
+    /// When the test case was announced, as unix timestamp in nanoseconds
+    started: i64,
+    frames: Vec<JsonFrame>,
+}
+
+/// A suite of test cases written with `defmt-test`
+pub struct TestSuite<'a> {
+    elf_file: &'a Path,
+}
+
+impl<'a> TestSuite<'a> {
+    /// Recognize a suite by the announcement of its test cases in the defmt
+    /// table, returns [None] if the binary is not a suite
+    pub fn from_elf(elf_file: &'a Path) -> anyhow::Result<Option<Self>> {
+        let is_suite = section_symbols(elf_file, DEFMT_SECTION)?
+            .iter()
+            .filter_map(|(symbol, _)| serde_json::from_str::<DefmtSymbol>(symbol).ok())
+            .any(|symbol| symbol.data == ANNOUNCEMENT);
+
+        Ok(is_suite.then_some(TestSuite { elf_file }))
+    }
+
+    /// Run the suite and report the outcome of each test case
+    ///
+    /// The binary must already be flashed. The run control limits the suite
+    /// as a whole. The result of each test case is added to the run report.
+    pub fn run(
+        &self,
+        command_server: &ChipInterface,
+        reset: &ResetConfig,
+        control: &RunControl,
+        config: &TestRunnerConfig,
+        format: OutputFormat,
+        run_report: &mut RunReport,
+    ) -> anyhow::Result<ExitCode> {
+        let elf_file = self.elf_file;
+
+        let mut defmt_decoder = DefmtDecoder::spawn_capturing(elf_file, Vec::new())?;
+        let halt_reason = command_server.read_rtt(
+            defmt_decoder.rtt_control_block_address(),
+            reset,
+            control,
+            &csa_regions(elf_file)?,
+            &mut defmt_decoder,
+        )?;
+        let decoded = defmt_decoder.finish()?;
+        let finished = unix_timestamp();
+
+        let (setup, mut runs) = split_at_announcements(decoded.frames);
+        match runs.first_mut() {
+            Some(first) => {
+                first.frames.splice(0..0, setup);
+            }
+            // The suite failed before the first test case, e.g. in its setup
+            None => runs.push(TestRun {
+                test_case: TestCase {
+                    number: 1,
+                    name: self.name(),
+                },
+                count: 1,
+                started: setup.first().map_or(finished, |frame| frame.host_timestamp),
+                frames: setup,
+            }),
+        }
+
+        // A test case lasts until the next one is announced
+        let ends = runs.iter().skip(1).map(|run| run.started).chain([finished]);
+        let mut durations = runs
+            .iter()
+            .zip(ends)
+            .map(|(run, ended)| {
+                Duration::from_nanos(ended.saturating_sub(run.started).max(0) as u64)
+            })
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        let mut report = Report::new(format, elf_file);
+        report.suite_started(runs[0].count);
+
+        let last = runs.pop().expect("The suite has at least one test run");
+        for (run, duration) in runs.into_iter().zip(durations.by_ref()) {
+            report.test_started(&run.test_case);
+            report.test_finished(TestResult::new(run, None, duration, None));
+        }
+
+        report.test_started(&last.test_case);
+        let failure = failure(&halt_reason, &last.frames, config, elf_file);
+        let reason = match failure {
+            Some(_) => "the suite stops at the first failure",
+            None => "the suite ended before it was announced",
+        };
+        let remaining = not_run(&last);
+        let duration = durations.next().unwrap_or_default();
+        report.test_finished(TestResult::new(last, failure, duration, Some(halt_reason)));
+
+        for test_case in remaining {
+            report.test_not_run(test_case, reason);
+        }
+
+        report.finish(control, run_report)
+    }
+
+    /// The name of the suite, taken from the binary
+    fn name(&self) -> String {
+        self.elf_file
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "suite".to_owned())
+    }
+}
+
+/// Why the last test case that ran failed, [None] if it passed
+fn failure(
+    halt_reason: &HaltReason,
+    frames: &[JsonFrame],
+    config: &TestRunnerConfig,
+    elf_file: &Path,
+) -> Option<String> {
+    match (halt_reason, halt_reason.stacktrace()) {
+        (HaltReason::DebugHit(_), Some(stacktrace)) => {
+            let found_markers: Vec<_> = config
+                .markers()
+                .into_iter()
+                .filter(|marker| frames.iter().any(|frame| frame.data.contains(marker)))
+                .collect();
+            let outcome = config.evaluate(stacktrace, elf_file, &found_markers);
+            (!outcome.passed()).then(|| outcome.to_string())
+        }
+        (HaltReason::DebugHit(_), None) => {
+            Some("Device halted, but the halted core was not captured".to_owned())
+        }
+        (HaltReason::Stopped(StopReason::Timeout, _), _) => Some("Timeout elapsed".to_owned()),
+        (HaltReason::Stopped(StopReason::Cancelled, _), _) => Some("Run cancelled".to_owned()),
+    }
+}
+
+/// The test cases that follow the last one that ran, only their numbers are known
+fn not_run(last: &TestRun) -> Vec<TestCase> {
+    (last.test_case.number + 1..=last.count)
+        .map(|number| TestCase {
+            number,
+            name: format!("test case {number} of {}", last.count),
+        })
+        .collect()
+}
+
+/// Split the output of a suite at the announcements of the test cases
+///
+/// Returns the frames before the first announcement and the frames of each test
+/// case, starting with its announcement.
+fn split_at_announcements(frames: Vec<JsonFrame>) -> (Vec<JsonFrame>, Vec<TestRun>) {
+    let mut setup = Vec::new();
+    let mut runs: Vec<TestRun> = Vec::new();
+    for frame in frames {
+        if let Some((number, count, name)) = parse_announcement(&frame.data) {
+            runs.push(TestRun {
+                test_case: TestCase {
+                    number,
+                    name: name.to_owned(),
+                },
+                count,
+                started: frame.host_timestamp,
+                frames: Vec::new(),
+            });
+        }
+
+        match runs.last_mut() {
+            Some(run) => run.frames.push(frame),
+            None => setup.push(frame),
+        }
+    }
+
+    (setup, runs)
+}
+
+/// Parse the number, the count and the name of an announced test case, see
+/// [ANNOUNCEMENT]
+fn parse_announcement(data: &str) -> Option<(usize, usize, &str)> {
+    let (progress, rest) = data.strip_prefix('(')?.split_once(") running `")?;
+    let name = rest.strip_suffix("`...")?;
+    let (number, count) = progress.split_once('/')?;
+    Some((number.parse().ok()?, count.parse().ok()?, name))
+}
+
+/// The current time in the format of [JsonFrame::host_timestamp]
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as i64)
+}
+
+/// The result of a single test case
+struct TestResult {
+    test_case: TestCase,
+    /// Why the test case failed, [None] if it passed
+    failure: Option<String>,
+    /// The decoded defmt output of the test case
+    output: Vec<String>,
+    frames: Vec<JsonFrame>,
+    /// Why the suite ended, only known for the last test case that ran
+    halt_reason: Option<HaltReason>,
+    duration: Duration,
+}
+
+impl TestResult {
+    fn new(
+        run: TestRun,
+        failure: Option<String>,
+        duration: Duration,
+        halt_reason: Option<HaltReason>,
+    ) -> Self {
+        TestResult {
+            test_case: run.test_case,
+            failure,
+            output: run.frames.iter().flat_map(format_frame).collect(),
+            frames: run.frames,
+            halt_reason,
+            duration,
+        }
+    }
+}
+
+/// Prints the events of a test suite in the configured format
+struct Report<'a> {
+    format: OutputFormat,
+    elf_file: &'a Path,
+    started: Instant,
+    results: Vec<TestResult>,
+    /// The test cases that did not run and why
+    not_run: Vec<(TestCase, String)>,
+}
+
+impl<'a> Report<'a> {
+    fn new(format: OutputFormat, elf_file: &'a Path) -> Self {
+        Report {
+            format,
+            elf_file,
+            started: Instant::now(),
+            results: Vec::new(),
+            not_run: Vec::new(),
+        }
+    }
+
+    fn suite_started(&mut self, test_count: usize) {
+        self.started = Instant::now();
+        match self.format {
+            OutputFormat::Pretty => println!("\nrunning {test_count} tests"),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({ "type": "suite", "event": "started", "test_count": test_count })
+            ),
+        }
+    }
+
+    fn test_started(&mut self, test_case: &TestCase) {
+        match self.format {
+            OutputFormat::Pretty => {
+                print!("test {} ... ", test_case.name);
+                let _ = std::io::stdout().flush();
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json!({ "type": "test", "event": "started", "name": test_case.name })
+            ),
+        }
+    }
+
+    fn test_finished(&mut self, result: TestResult) {
+        match (self.format, &result.failure) {
+            (OutputFormat::Pretty, None) => println!("{}", "ok".green()),
+            (OutputFormat::Pretty, Some(_)) => println!("{}", "FAILED".red()),
+            (OutputFormat::Json, None) => println!(
+                "{}",
+                json!({
+                    "type": "test",
+                    "event": "ok",
+                    "name": result.test_case.name,
+                    "exec_time": result.duration.as_secs_f64(),
+                })
+            ),
+            (OutputFormat::Json, Some(failure)) => {
+                let mut stdout = result.output.join("\n");
+                stdout.push_str(&format!("\n{failure}\n"));
+                println!(
+                    "{}",
+                    json!({
+                        "type": "test",
+                        "event": "failed",
+                        "name": result.test_case.name,
+                        "exec_time": result.duration.as_secs_f64(),
+                        "stdout": stdout,
+                    })
+                )
+            }
+        }
+
+        self.results.push(result);
+    }
+
+    /// Report a test case that did not run as ignored
+    fn test_not_run(&mut self, test_case: TestCase, reason: &str) {
+        let reason = format!("not run, {reason}");
+        match self.format {
+            OutputFormat::Pretty => println!(
+                "test {} ... {}",
+                test_case.name,
+                format!("ignored, {reason}").yellow()
+            ),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "type": "test",
+                    "event": "ignored",
+                    "name": test_case.name,
+                    "message": reason,
+                })
+            ),
+        }
+
+        self.not_run.push((test_case, reason));
+    }
+
+    /// Print the summary of the suite, returns whether all test cases passed
+    fn suite_finished(&mut self) -> anyhow::Result<bool> {
+        let failed: Vec<_> = self
+            .results
+            .iter()
+            .filter(|result| result.failure.is_some())
+            .collect();
+        let passed = self.results.len() - failed.len();
+        let ignored = self.not_run.len();
+        let exec_time = self.started.elapsed().as_secs_f64();
+        let success = failed.is_empty();
+
+        match self.format {
+            OutputFormat::Pretty => {
+                if !success {
+                    println!("\nfailures:\n");
+                    for result in failed.iter() {
+                        println!("---- {} stdout ----", result.test_case.name);
+                        for line in result.output.iter() {
+                            println!("{line}");
+                        }
+                        if let Some(failure) = &result.failure {
+                            println!("{}", failure.red());
+                        }
+                        let halted_cores = result
+                            .halt_reason
+                            .as_ref()
+                            .and_then(HaltReason::halted_cores);
+                        if let Some(halted_cores) = halted_cores {
+                            log_halted_cores(halted_cores, self.elf_file, false)?;
+                        }
+                        println!();
+                    }
+                    println!("\nfailures:");
+                    for result in failed.iter() {
+                        println!("    {}", result.test_case.name);
+                    }
+                }
+
+                let status = if success {
+                    "ok".green()
+                } else {
+                    "FAILED".red()
+                };
+                println!(
+                    "\ntest result: {status}. {passed} passed; {} failed; {ignored} ignored; 0 measured; 0 filtered out; finished in {exec_time:.2}s\n",
+                    failed.len()
+                );
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "type": "suite",
+                    "event": if success { "ok" } else { "failed" },
+                    "passed": passed,
+                    "failed": failed.len(),
+                    "ignored": ignored,
+                    "measured": 0,
+                    "filtered_out": 0,
+                    "exec_time": exec_time,
+                })
+            ),
+        }
+
+        Ok(success)
+    }
+
+    /// Print the summary and add the results to the run report, returns the
+    /// exit code of the suite
+    fn finish(
+        mut self,
+        control: &RunControl,
+        run_report: &mut RunReport,
+    ) -> anyhow::Result<ExitCode> {
+        let passed = self.suite_finished()?;
+        run_report
+            .runs
+            .extend(self.results.into_iter().map(|result| {
+                RunRecord::new(
+                    &result.test_case.name,
+                    result.failure.is_none(),
+                    result.failure.unwrap_or_else(|| "Test passed".to_owned()),
+                    result.duration,
+                    result.frames,
+                    result.halt_reason,
+                    self.elf_file,
+                )
+            }));
+        run_report
+            .runs
+            .extend(self.not_run.into_iter().map(|(test_case, reason)| {
+                RunRecord::skipped(&test_case.name, format!("Test {reason}"))
+            }));
+
+        if control.cancellation.is_cancelled() {
+            Ok(ExitCode::from(EXIT_CANCELLED))
+        } else if passed {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use defmt_json_schema::v1::Location;
+
+    use super::*;
+
+    fn frame(data: &str, host_timestamp: i64) -> JsonFrame {
+        JsonFrame {
+            data: data.to_owned(),
+            host_timestamp,
+            level: None,
+            location: Location {
+                file: None,
+                line: None,
+                module_path: None,
+            },
+            target_timestamp: String::new(),
+        }
+    }
+
+    #[test]
+    fn announcements_are_parsed() {
+        assert_eq!(
+            parse_announcement("(2/13) running `reads_adc`..."),
+            Some((2, 13, "reads_adc"))
+        );
+        assert_eq!(parse_announcement("(2/13) running `reads_adc`"), None);
+        assert_eq!(parse_announcement("(a/13) running `reads_adc`..."), None);
+        assert_eq!(parse_announcement("all tests passed!"), None);
+    }
+
+    #[test]
+    fn output_is_split_at_announcements() {
+        let frames = vec![
+            frame("init", 1),
+            frame("(1/2) running `first`...", 2),
+            frame("inside first", 3),
+            frame("(2/2) running `second`...", 5),
+            frame("all tests passed!", 8),
+        ];
+
+        let (setup, runs) = split_at_announcements(frames);
+        assert_eq!(setup.len(), 1);
+        assert_eq!(setup[0].data, "init");
+
+        let names: Vec<_> = runs.iter().map(|run| run.test_case.name.as_str()).collect();
+        assert_eq!(names, ["first", "second"]);
+        assert_eq!(runs[0].count, 2);
+        assert_eq!(runs[0].started, 2);
+        assert_eq!(runs[0].frames.len(), 2);
+        assert_eq!(runs[1].test_case.number, 2);
+        assert_eq!(runs[1].frames[1].data, "all tests passed!");
+    }
+
+    #[test]
+    fn test_cases_after_the_last_one_did_not_run() {
+        let frames = vec![
+            frame("(1/4) running `first`...", 1),
+            frame("(2/4) running `second`...", 2),
+        ];
+        let (_, runs) = split_at_announcements(frames);
+
+        let remaining = not_run(runs.last().unwrap());
+        let names: Vec<_> = remaining
+            .iter()
+            .map(|test_case| test_case.name.as_str())
+            .collect();
+        assert_eq!(names, ["test case 3 of 4", "test case 4 of 4"]);
+        assert_eq!(remaining[0].number, 3);
+
+        let (_, runs) = split_at_announcements(vec![frame("(4/4) running `last`...", 1)]);
+        assert!(not_run(&runs[0]).is_empty());
+    }
+
+    #[test]
+    fn defmt_symbols_are_recognized() {
+        let symbol = r#"{"package":"defmt-test","tag":"defmt_println","data":"({=usize}/{=usize}) running `{=str}`...","disambiguator":"1","crate_name":"tests"}"#;
+        let symbol: DefmtSymbol = serde_json::from_str(symbol).unwrap();
+        assert_eq!(symbol.data, ANNOUNCEMENT);
+    }
+}
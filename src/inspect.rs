@@ -0,0 +1,1631 @@
+//! Reads global variables from the device and prints them according to their
+//! type, see [DebugInfo]
+//!
+//! Variables are looked up in the DWARF debug information of the elf file, either
+//! by their name, the trailing part of their path such as `state::COUNTER` or
+//! their linkage name.
+//! Their memory is interpreted with the type from the debug information: base
+//! types, structs, unions, C-like and Rust enums and arrays are supported, pointers
+//! are followed one level.
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use elf::{endian::AnyEndian, ElfBytes};
+use gimli::{
+    constants, AttributeValue, DebugInfoOffset, DebuggingInformationEntry, Dwarf, EndianSlice,
+    Endianity, Operation, RunTimeEndian, SectionId, Unit, UnitOffset,
+};
+use tricore_common::access::MemoryAccess;
+
+type Reader<'a> = EndianSlice<'a, RunTimeEndian>;
+
+/// The number of bytes that are read at most for a single variable
+const MAX_READ: u64 = 64 * 1024;
+
+/// The number of array elements that are shown at most
+const MAX_ELEMENTS: u64 = 32;
+
+/// The number of nested types after which a value is not shown anymore
+const MAX_NESTING: usize = 32;
+
+/// The debug sections of an elf file
+pub struct DebugInfo {
+    sections: HashMap<&'static str, Vec<u8>>,
+    endian: RunTimeEndian,
+}
+
+/// A global or static variable found in the debug information
+#[derive(Debug, Clone)]
+pub struct Variable {
+    /// The path of the variable, including its namespaces
+    pub name: String,
+    pub address: u32,
+    pub type_name: String,
+    /// The size of the variable in bytes, [None] if its type has no size
+    pub size: Option<u64>,
+    unit: DebugInfoOffset,
+    type_offset: Option<UnitOffset>,
+}
+
+impl DebugInfo {
+    /// Read the debug sections of the elf file
+    pub fn load(elf_file: &Path) -> anyhow::Result<Self> {
+        let elf_data = std::fs::read(elf_file)
+            .with_context(|| format!("Cannot read elf file {}", elf_file.display()))?;
+        let elf = ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data)
+            .with_context(|| "Could not parse elf file")?;
+
+        let endian = match elf.ehdr.endianness {
+            AnyEndian::Little => RunTimeEndian::Little,
+            AnyEndian::Big => RunTimeEndian::Big,
+        };
+
+        let section_ids = [
+            SectionId::DebugAbbrev,
+            SectionId::DebugAddr,
+            SectionId::DebugInfo,
+            SectionId::DebugLine,
+            SectionId::DebugRanges,
+            SectionId::DebugRngLists,
+            SectionId::DebugStr,
+            SectionId::DebugStrOffsets,
+            SectionId::DebugLineStr,
+        ];
+        let mut sections = HashMap::new();
+        for id in section_ids {
+            let Some(header) = elf
+                .section_header_by_name(id.name())
+                .with_context(|| "Could not parse section headers from elf file")?
+            else {
+                continue;
+            };
+            let (data, compression) = elf
+                .section_data(&header)
+                .with_context(|| format!("Could not read section {}", id.name()))?;
+            if compression.is_some() {
+                bail!("Compressed debug sections are not supported");
+            }
+            sections.insert(id.name(), data.to_vec());
+        }
+
+        if !sections.contains_key(SectionId::DebugInfo.name()) {
+            bail!("The elf file does not contain debug information");
+        }
+
+        Ok(DebugInfo { sections, endian })
+    }
+
+    fn dwarf(&self) -> anyhow::Result<Dwarf<Reader<'_>>> {
+        let dwarf = Dwarf::load(|id| -> gimli::Result<Reader<'_>> {
+            let data = self.sections.get(id.name()).map(Vec::as_slice);
+            Ok(EndianSlice::new(data.unwrap_or_default(), self.endian))
+        })?;
+        Ok(dwarf)
+    }
+
+    /// Find the variable with the given name, path or linkage name
+    ///
+    /// Only variables with a static address are found. If multiple variables
+    /// match, e.g. statics with the same name in different modules, the first
+    /// one is returned.
+    pub fn variable(&self, name: &str) -> anyhow::Result<Variable> {
+        let dwarf = self.dwarf()?;
+        let mut candidates = Vec::new();
+
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            let Some(unit_offset) = header.offset().as_debug_info_offset() else {
+                continue;
+            };
+            let unit = dwarf.unit(header)?;
+
+            // The namespace each entry opens, indexed by the depth of the entry
+            let mut scopes: Vec<Option<String>> = Vec::new();
+            let mut depth = 0;
+            let mut entries = unit.entries();
+            while let Some((delta, entry)) = entries.next_dfs()? {
+                depth += delta;
+                scopes.truncate(depth.max(0) as usize);
+
+                let entry_name = attr_string(&dwarf, &unit, entry, constants::DW_AT_name);
+                let path = scopes
+                    .iter()
+                    .flatten()
+                    .cloned()
+                    .chain(entry_name.clone())
+                    .collect::<Vec<_>>()
+                    .join("::");
+                scopes.push(
+                    entry_name
+                        .clone()
+                        .filter(|_| entry.tag() == constants::DW_TAG_namespace),
+                );
+
+                if entry.tag() != constants::DW_TAG_variable {
+                    continue;
+                }
+                let linkage_name = attr_string(&dwarf, &unit, entry, constants::DW_AT_linkage_name);
+                // The path may be given without the leading namespaces, e.g. the crate
+                let matches = path == name
+                    || path.ends_with(&format!("::{name}"))
+                    || linkage_name.as_deref() == Some(name);
+                if !matches {
+                    continue;
+                }
+                let Some(address) = static_address(&dwarf, &unit, entry)? else {
+                    continue;
+                };
+
+                let type_offset = type_reference(entry)?;
+                let types = Types {
+                    dwarf: &dwarf,
+                    unit: &unit,
+                    endian: self.endian,
+                };
+                candidates.push(Variable {
+                    name: path,
+                    address,
+                    type_name: types.type_name(type_offset, 0),
+                    size: types.size_of(type_offset, 0),
+                    unit: unit_offset,
+                    type_offset,
+                });
+            }
+        }
+
+        let mut candidates = candidates.into_iter();
+        let variable = candidates.next().with_context(|| {
+            format!("Could not find static variable {name:?} in the debug information")
+        })?;
+        let others: Vec<_> = candidates.map(|other| other.name).collect();
+        if !others.is_empty() {
+            log::warn!(
+                "{name:?} is ambiguous, showing {} instead of {}",
+                variable.name,
+                others.join(", ")
+            );
+        }
+
+        Ok(variable)
+    }
+
+    /// The address of the first instruction of a source line, e.g. to halt there
+    ///
+    /// The file matches if one of the paths ends with the other. If no code
+    /// belongs to the line, the next line with code is used instead. Returns the
+    /// line that was used and the address, [None] if the file has no code after
+    /// the line.
+    pub fn line_address(&self, file: &Path, line: u32) -> anyhow::Result<Option<(u32, u32)>> {
+        let dwarf = self.dwarf()?;
+        let mut best: Option<(u32, u32)> = None;
+
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            // Whether a file of the line table is the given file, by its index
+            let mut matching_files = HashMap::new();
+
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let Some(row_line) = row.line() else {
+                    continue;
+                };
+                let row_line = row_line.get() as u32;
+                let address = row.address() as u32;
+                // Code removed by the linker is left at address zero
+                if !row.is_stmt() || row.end_sequence() || address == 0 || row_line < line {
+                    continue;
+                }
+                if best.is_some_and(|best| best <= (row_line, address)) {
+                    continue;
+                }
+
+                let matches = match matching_files.get(&row.file_index()) {
+                    Some(matches) => *matches,
+                    None => {
+                        let matches = match row.file(header) {
+                            Some(entry) => {
+                                let path = file_path(&dwarf, &unit, header, entry)?;
+                                path.ends_with(file) || file.ends_with(&path)
+                            }
+                            None => false,
+                        };
+                        matching_files.insert(row.file_index(), matches);
+                        matches
+                    }
+                };
+                if matches {
+                    best = Some((row_line, address));
+                }
+            }
+        }
+
+        Ok(best)
+    }
+
+    /// Read the variable from memory and interpret it according to its type
+    pub fn read(&self, variable: &Variable, memory: &dyn MemoryAccess) -> anyhow::Result<Value> {
+        let length = variable
+            .read_length()
+            .with_context(|| format!("The size of type {} is not known", variable.type_name))?;
+        let data = memory
+            .read_memory(variable.address, length)
+            .with_context(|| {
+                format!(
+                    "Cannot read {} at {:#010X}",
+                    variable.name, variable.address
+                )
+            })?;
+
+        self.interpret(variable, &data, memory, 0)
+    }
+
+    /// Interpret the data, read from the address of the variable, according to
+    /// its type
+    ///
+    /// Pointers are not followed, since only the memory of the variable itself
+    /// is available.
+    pub fn decode(&self, variable: &Variable, data: &[u8]) -> anyhow::Result<Value> {
+        self.interpret(variable, data, &NoMemory, 1)
+    }
+
+    fn interpret(
+        &self,
+        variable: &Variable,
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+    ) -> anyhow::Result<Value> {
+        let dwarf = self.dwarf()?;
+        let header = dwarf.debug_info.header_from_offset(variable.unit)?;
+        let unit = dwarf.unit(header)?;
+        let types = Types {
+            dwarf: &dwarf,
+            unit: &unit,
+            endian: self.endian,
+        };
+
+        Ok(types.value(variable.type_offset, data, memory, pointer_depth, 0))
+    }
+}
+
+impl Variable {
+    /// The number of bytes read for the variable, [None] if its size is not known
+    pub fn read_length(&self) -> Option<usize> {
+        self.size.map(|size| size.min(MAX_READ) as usize)
+    }
+}
+
+/// Memory that cannot be read, see [DebugInfo::decode]
+struct NoMemory;
+
+impl MemoryAccess for NoMemory {
+    fn read_memory(&self, address: u32, _length: usize) -> anyhow::Result<Vec<u8>> {
+        bail!("Memory at {address:#010X} is not available")
+    }
+}
+
+/// A value read from memory, formatted according to its type
+///
+/// The [Display] implementation prints the value similar to the debug output of
+/// Rust, on a single line by default and spread over multiple lines with `{:#}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A number, boolean, character or enumerator
+    Scalar(String),
+    /// A struct, union or variant of a Rust enum with its fields
+    Struct {
+        type_name: String,
+        fields: Vec<(String, Value)>,
+    },
+    /// The first elements of an array, and the number of elements left out
+    Array { elements: Vec<Value>, omitted: u64 },
+    /// A pointer and the value it points to, if it was followed
+    Pointer {
+        address: u64,
+        target: Option<Box<Value>>,
+    },
+    /// The value cannot be shown, e.g. because its type is not supported
+    Unavailable(String),
+}
+
+impl Value {
+    /// Whether the value is printed on a single line
+    fn is_inline(&self) -> bool {
+        match self {
+            Value::Scalar(_) | Value::Unavailable(_) => true,
+            Value::Struct { fields, .. } => fields.is_empty(),
+            Value::Array { elements, .. } => elements.iter().all(Value::is_inline),
+            Value::Pointer { target, .. } => {
+                target.as_ref().map_or(true, |target| target.is_inline())
+            }
+        }
+    }
+
+    /// The scalar values contained in this value, labelled with their path
+    /// starting at the given name, e.g. `state.values[2]`
+    ///
+    /// Pointers are represented by their address.
+    pub fn leaves(&self, path: &str) -> Vec<(String, String)> {
+        match self {
+            Value::Scalar(value) => vec![(path.to_owned(), value.clone())],
+            Value::Unavailable(reason) => vec![(path.to_owned(), format!("<{reason}>"))],
+            Value::Pointer { address, .. } => vec![(path.to_owned(), format!("{address:#010X}"))],
+            Value::Struct { fields, .. } => fields
+                .iter()
+                .flat_map(|(name, value)| value.leaves(&format!("{path}.{name}")))
+                .collect(),
+            Value::Array { elements, .. } => elements
+                .iter()
+                .enumerate()
+                .flat_map(|(index, element)| element.leaves(&format!("{path}[{index}]")))
+                .collect(),
+        }
+    }
+
+    fn write_line(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Struct { type_name, fields } if !fields.is_empty() => {
+                write!(f, "{type_name} {{ ")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{name}: ")?;
+                    value.write_line(f)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Array { elements, omitted } => {
+                write!(f, "[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write_line(f)?;
+                }
+                if *omitted > 0 {
+                    write!(f, ", … {omitted} more")?;
+                }
+                write!(f, "]")
+            }
+            Value::Pointer {
+                address,
+                target: Some(target),
+            } => {
+                write!(f, "{address:#010X} -> ")?;
+                target.write_line(f)
+            }
+            _ => self.write(f, 0),
+        }
+    }
+
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        let inner = indent + 4;
+        match self {
+            Value::Scalar(value) => write!(f, "{value}"),
+            Value::Unavailable(reason) => write!(f, "<{reason}>"),
+            Value::Pointer { address, target } => {
+                write!(f, "{address:#010X}")?;
+                if let Some(target) = target {
+                    write!(f, " -> ")?;
+                    target.write(f, indent)?;
+                }
+                Ok(())
+            }
+            Value::Struct { type_name, fields } if fields.is_empty() => write!(f, "{type_name}"),
+            Value::Struct { type_name, fields } => {
+                writeln!(f, "{type_name} {{")?;
+                for (name, value) in fields {
+                    write!(f, "{:inner$}{name}: ", "")?;
+                    value.write(f, inner)?;
+                    writeln!(f, ",")?;
+                }
+                write!(f, "{:indent$}}}", "")
+            }
+            Value::Array { .. } if self.is_inline() => self.write_line(f),
+            Value::Array { elements, omitted } => {
+                writeln!(f, "[")?;
+                for element in elements {
+                    write!(f, "{:inner$}", "")?;
+                    element.write(f, inner)?;
+                    writeln!(f, ",")?;
+                }
+                if *omitted > 0 {
+                    writeln!(f, "{:inner$}… {omitted} more", "")?;
+                }
+                write!(f, "{:indent$}]", "")
+            }
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            self.write(f, 0)
+        } else {
+            self.write_line(f)
+        }
+    }
+}
+
+/// Interprets memory according to the types of a compilation unit
+struct Types<'a> {
+    dwarf: &'a Dwarf<Reader<'a>>,
+    unit: &'a Unit<Reader<'a>>,
+    endian: RunTimeEndian,
+}
+
+type Entry<'abbrev, 'unit, 'a> = DebuggingInformationEntry<'abbrev, 'unit, Reader<'a>>;
+
+impl<'a> Types<'a> {
+    fn entry(&self, offset: UnitOffset) -> anyhow::Result<Entry<'_, '_, 'a>> {
+        Ok(self.unit.entry(offset)?)
+    }
+
+    fn name(&self, entry: &Entry<'_, '_, 'a>) -> Option<String> {
+        attr_string(self.dwarf, self.unit, entry, constants::DW_AT_name)
+    }
+
+    fn udata(&self, entry: &Entry<'_, '_, 'a>, attribute: constants::DwAt) -> Option<u64> {
+        entry
+            .attr_value(attribute)
+            .ok()
+            .flatten()
+            .and_then(|value| value.udata_value())
+    }
+
+    /// The children of the entry with the given offset
+    fn children(&self, offset: UnitOffset) -> anyhow::Result<Vec<UnitOffset>> {
+        let mut tree = self.unit.entries_tree(Some(offset))?;
+        let root = tree.root()?;
+        let mut children = root.children();
+        let mut offsets = Vec::new();
+        while let Some(child) = children.next()? {
+            offsets.push(child.entry().offset());
+        }
+        Ok(offsets)
+    }
+
+    /// The name of the type, [None] stands for `void`
+    fn type_name(&self, offset: Option<UnitOffset>, nesting: usize) -> String {
+        let Some(offset) = offset else {
+            return "void".to_owned();
+        };
+        let Ok(entry) = self.entry(offset) else {
+            return "?".to_owned();
+        };
+        if let Some(name) = self.name(&entry) {
+            return name;
+        }
+        if nesting > MAX_NESTING {
+            return "…".to_owned();
+        }
+
+        let inner = type_reference(&entry).ok().flatten();
+        match entry.tag() {
+            constants::DW_TAG_pointer_type => format!("*{}", self.type_name(inner, nesting + 1)),
+            constants::DW_TAG_reference_type | constants::DW_TAG_rvalue_reference_type => {
+                format!("&{}", self.type_name(inner, nesting + 1))
+            }
+            constants::DW_TAG_const_type => format!("const {}", self.type_name(inner, nesting + 1)),
+            constants::DW_TAG_volatile_type => {
+                format!("volatile {}", self.type_name(inner, nesting + 1))
+            }
+            constants::DW_TAG_array_type => {
+                let element = self.type_name(inner, nesting + 1);
+                let dimensions = self.dimensions(offset).unwrap_or_default();
+                let mut name = element;
+                for count in dimensions.iter().rev() {
+                    name = match count {
+                        Some(count) => format!("[{name}; {count}]"),
+                        None => format!("[{name}]"),
+                    };
+                }
+                name
+            }
+            constants::DW_TAG_subroutine_type => "fn".to_owned(),
+            _ => "{anonymous}".to_owned(),
+        }
+    }
+
+    /// The size of the type in bytes, [None] if it has no size
+    fn size_of(&self, offset: Option<UnitOffset>, nesting: usize) -> Option<u64> {
+        let entry = self.entry(offset?).ok()?;
+        if let Some(size) = self.udata(&entry, constants::DW_AT_byte_size) {
+            return Some(size);
+        }
+        if nesting > MAX_NESTING {
+            return None;
+        }
+
+        let inner = type_reference(&entry).ok().flatten();
+        match entry.tag() {
+            constants::DW_TAG_pointer_type
+            | constants::DW_TAG_reference_type
+            | constants::DW_TAG_rvalue_reference_type => {
+                Some(self.unit.encoding().address_size as u64)
+            }
+            constants::DW_TAG_typedef
+            | constants::DW_TAG_const_type
+            | constants::DW_TAG_volatile_type
+            | constants::DW_TAG_restrict_type
+            | constants::DW_TAG_atomic_type => self.size_of(inner, nesting + 1),
+            constants::DW_TAG_array_type => {
+                let element = self.size_of(inner, nesting + 1)?;
+                let dimensions = self.dimensions(offset?).ok()?;
+                dimensions
+                    .into_iter()
+                    .try_fold(element, |size, count| Some(size * count?))
+            }
+            _ => None,
+        }
+    }
+
+    /// The number of elements in each dimension of an array type
+    fn dimensions(&self, offset: UnitOffset) -> anyhow::Result<Vec<Option<u64>>> {
+        let mut dimensions = Vec::new();
+        for child in self.children(offset)? {
+            let entry = self.entry(child)?;
+            if entry.tag() != constants::DW_TAG_subrange_type {
+                continue;
+            }
+            let count = self.udata(&entry, constants::DW_AT_count).or_else(|| {
+                let lower = self
+                    .udata(&entry, constants::DW_AT_lower_bound)
+                    .unwrap_or(0);
+                let upper = self.udata(&entry, constants::DW_AT_upper_bound)?;
+                Some((upper + 1).saturating_sub(lower))
+            });
+            dimensions.push(count);
+        }
+        Ok(dimensions)
+    }
+
+    /// Read an unsigned number of up to 8 bytes
+    fn unsigned(&self, data: &[u8]) -> Option<u64> {
+        match data.len() {
+            1 => Some(data[0] as u64),
+            2 => Some(self.endian.read_u16(data) as u64),
+            4 => Some(self.endian.read_u32(data) as u64),
+            8 => Some(self.endian.read_u64(data)),
+            _ => None,
+        }
+    }
+
+    /// Interpret the data as a value of the given type
+    ///
+    /// Pointers are followed until the given pointer depth reaches one.
+    fn value(
+        &self,
+        offset: Option<UnitOffset>,
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+        nesting: usize,
+    ) -> Value {
+        let Some(offset) = offset else {
+            return Value::Unavailable("void".to_owned());
+        };
+        if nesting > MAX_NESTING {
+            return Value::Unavailable("nested too deeply".to_owned());
+        }
+        match self.read_value(offset, data, memory, pointer_depth, nesting) {
+            Ok(value) => value,
+            Err(error) => Value::Unavailable(format!("{error:#}")),
+        }
+    }
+
+    fn read_value(
+        &self,
+        offset: UnitOffset,
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+        nesting: usize,
+    ) -> anyhow::Result<Value> {
+        let entry = self.entry(offset)?;
+        let inner = type_reference(&entry)?;
+        let size = self.size_of(Some(offset), 0);
+        let data = match size {
+            Some(size) => data
+                .get(..size as usize)
+                .with_context(|| format!("not read, {size} bytes needed"))?,
+            None => data,
+        };
+
+        let value = match entry.tag() {
+            constants::DW_TAG_base_type => self.base_value(&entry, data),
+            constants::DW_TAG_typedef
+            | constants::DW_TAG_const_type
+            | constants::DW_TAG_volatile_type
+            | constants::DW_TAG_restrict_type
+            | constants::DW_TAG_atomic_type => {
+                self.value(inner, data, memory, pointer_depth, nesting + 1)
+            }
+            constants::DW_TAG_pointer_type
+            | constants::DW_TAG_reference_type
+            | constants::DW_TAG_rvalue_reference_type => {
+                let address = self.unsigned(data).context("unsupported pointer size")?;
+                let target = (pointer_depth == 0 && address != 0)
+                    .then(|| self.pointee(inner, address, memory, nesting))
+                    .flatten();
+                Value::Pointer {
+                    address,
+                    target: target.map(Box::new),
+                }
+            }
+            constants::DW_TAG_enumeration_type => self.enum_value(offset, data)?,
+            constants::DW_TAG_structure_type
+            | constants::DW_TAG_class_type
+            | constants::DW_TAG_union_type => {
+                self.struct_value(offset, data, memory, pointer_depth, nesting)?
+            }
+            constants::DW_TAG_array_type => {
+                let dimensions = self.dimensions(offset)?;
+                self.array_value(inner, &dimensions, data, memory, pointer_depth, nesting)
+            }
+            tag => Value::Unavailable(format!(
+                "type {} ({tag}) is not supported",
+                self.type_name(Some(offset), 0)
+            )),
+        };
+        Ok(value)
+    }
+
+    fn base_value(&self, entry: &Entry<'_, '_, 'a>, data: &[u8]) -> Value {
+        let encoding = match entry.attr_value(constants::DW_AT_encoding) {
+            Ok(Some(AttributeValue::Encoding(encoding))) => encoding,
+            _ => return Value::Unavailable("unknown encoding".to_owned()),
+        };
+        if data.is_empty() {
+            return Value::Scalar(self.name(entry).unwrap_or_else(|| "()".to_owned()));
+        }
+        let Some(raw) = self.unsigned(data) else {
+            return Value::Unavailable(format!("{} byte numbers are not supported", data.len()));
+        };
+        let signed = {
+            let shift = 64 - data.len() * 8;
+            ((raw << shift) as i64) >> shift
+        };
+
+        let value = match encoding {
+            constants::DW_ATE_boolean => (raw != 0).to_string(),
+            constants::DW_ATE_float if data.len() == 4 => f32::from_bits(raw as u32).to_string(),
+            constants::DW_ATE_float if data.len() == 8 => f64::from_bits(raw).to_string(),
+            constants::DW_ATE_signed => signed.to_string(),
+            constants::DW_ATE_signed_char | constants::DW_ATE_unsigned_char => {
+                match char::from_u32(raw as u32).filter(char::is_ascii_graphic) {
+                    Some(character) => format!("{raw} {character:?}"),
+                    None => raw.to_string(),
+                }
+            }
+            constants::DW_ATE_UTF => match char::from_u32(raw as u32) {
+                Some(character) => format!("{character:?}"),
+                None => format!("{raw:#X}"),
+            },
+            _ => raw.to_string(),
+        };
+        Value::Scalar(value)
+    }
+
+    /// Read the value a pointer points to
+    fn pointee(
+        &self,
+        offset: Option<UnitOffset>,
+        address: u64,
+        memory: &dyn MemoryAccess,
+        nesting: usize,
+    ) -> Option<Value> {
+        let size = self.size_of(offset, 0).filter(|size| *size > 0)?;
+        let address = u32::try_from(address).ok()?;
+        let value = match memory.read_memory(address, size.min(MAX_READ) as usize) {
+            Ok(data) => self.value(offset, &data, memory, 1, nesting + 1),
+            Err(error) => Value::Unavailable(format!("{error:#}")),
+        };
+        Some(value)
+    }
+
+    fn enum_value(&self, offset: UnitOffset, data: &[u8]) -> anyhow::Result<Value> {
+        let type_name = self.type_name(Some(offset), 0);
+        let raw = self.unsigned(data).context("unsupported enum size")?;
+        let signed = {
+            let shift = 64 - data.len() * 8;
+            ((raw << shift) as i64) >> shift
+        };
+
+        for child in self.children(offset)? {
+            let entry = self.entry(child)?;
+            if entry.tag() != constants::DW_TAG_enumerator {
+                continue;
+            }
+            let matches = match entry.attr_value(constants::DW_AT_const_value)? {
+                Some(value) => {
+                    value.udata_value() == Some(raw) || value.sdata_value() == Some(signed)
+                }
+                None => false,
+            };
+            if matches {
+                let name = self.name(&entry).unwrap_or_default();
+                return Ok(Value::Scalar(format!("{type_name}::{name}")));
+            }
+        }
+
+        Ok(Value::Scalar(format!("{type_name}({signed})")))
+    }
+
+    fn struct_value(
+        &self,
+        offset: UnitOffset,
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+        nesting: usize,
+    ) -> anyhow::Result<Value> {
+        let type_name = self.type_name(Some(offset), 0);
+        let mut fields = Vec::new();
+        for child in self.children(offset)? {
+            let entry = self.entry(child)?;
+            match entry.tag() {
+                constants::DW_TAG_member => {
+                    let name = self.name(&entry).unwrap_or_default();
+                    let value = self.member_value(&entry, data, memory, pointer_depth, nesting);
+                    fields.push((name, value));
+                }
+                // Rust enums are structs with a variant part
+                constants::DW_TAG_variant_part => {
+                    return self.variant_value(
+                        &type_name,
+                        child,
+                        data,
+                        memory,
+                        pointer_depth,
+                        nesting,
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Value::Struct { type_name, fields })
+    }
+
+    /// Read a member of a struct, including bit fields
+    fn member_value(
+        &self,
+        entry: &Entry<'_, '_, 'a>,
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+        nesting: usize,
+    ) -> Value {
+        let Ok(member_type) = type_reference(entry) else {
+            return Value::Unavailable("invalid type".to_owned());
+        };
+        let location = self
+            .udata(entry, constants::DW_AT_data_member_location)
+            .unwrap_or(0);
+
+        let Some(bit_size) = self.udata(entry, constants::DW_AT_bit_size) else {
+            let field_data = data.get(location as usize..).unwrap_or_default();
+            return self.value(member_type, field_data, memory, pointer_depth, nesting + 1);
+        };
+
+        // Bit fields are extracted from the little endian storage unit
+        let bit_offset = match self.udata(entry, constants::DW_AT_data_bit_offset) {
+            Some(bit_offset) => bit_offset,
+            None => {
+                let storage = self
+                    .udata(entry, constants::DW_AT_byte_size)
+                    .or_else(|| self.size_of(member_type, 0))
+                    .unwrap_or(4);
+                let legacy_offset = self.udata(entry, constants::DW_AT_bit_offset).unwrap_or(0);
+                location * 8 + (storage * 8).saturating_sub(legacy_offset + bit_size)
+            }
+        };
+        let start = (bit_offset / 8) as usize;
+        let mut storage = [0; 8];
+        for (index, byte) in storage.iter_mut().enumerate() {
+            *byte = data.get(start + index).copied().unwrap_or(0);
+        }
+        let bits = (u64::from_le_bytes(storage) >> (bit_offset % 8))
+            & (u64::MAX >> (64 - bit_size.min(64)));
+
+        let size = self.size_of(member_type, 0).unwrap_or(4).min(8) as usize;
+        self.value(
+            member_type,
+            &bits.to_le_bytes()[..size],
+            memory,
+            pointer_depth,
+            nesting + 1,
+        )
+    }
+
+    /// Read the active variant of a Rust enum
+    fn variant_value(
+        &self,
+        type_name: &str,
+        variant_part: UnitOffset,
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+        nesting: usize,
+    ) -> anyhow::Result<Value> {
+        let part = self.entry(variant_part)?;
+        let discriminant = match part.attr_value(constants::DW_AT_discr)? {
+            Some(AttributeValue::UnitRef(member)) => {
+                let member = self.entry(member)?;
+                let location = self
+                    .udata(&member, constants::DW_AT_data_member_location)
+                    .unwrap_or(0) as usize;
+                let size = self.size_of(type_reference(&member)?, 0).unwrap_or(0) as usize;
+                data.get(location..location + size)
+                    .and_then(|data| self.unsigned(data))
+            }
+            _ => None,
+        };
+
+        let mut default = None;
+        let mut active = None;
+        for child in self.children(variant_part)? {
+            let variant = self.entry(child)?;
+            if variant.tag() != constants::DW_TAG_variant {
+                continue;
+            }
+            match self.udata(&variant, constants::DW_AT_discr_value) {
+                Some(value) if Some(value) == discriminant => active = Some(child),
+                Some(_) => {}
+                None => default = Some(child),
+            }
+        }
+        let variant = active
+            .or(default)
+            .with_context(|| format!("unknown variant of {type_name}"))?;
+
+        // Each variant holds a single member whose type is the struct of the variant
+        let member = self
+            .children(variant)?
+            .into_iter()
+            .map(|child| self.entry(child))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .find(|entry| entry.tag() == constants::DW_TAG_member)
+            .with_context(|| format!("variant of {type_name} without data"))?;
+        let variant_name = self.name(&member).unwrap_or_default();
+
+        let value = match self.member_value(&member, data, memory, pointer_depth, nesting) {
+            Value::Struct { fields, .. } => Value::Struct {
+                type_name: format!("{type_name}::{variant_name}"),
+                fields,
+            },
+            value => value,
+        };
+        Ok(value)
+    }
+
+    fn array_value(
+        &self,
+        element_type: Option<UnitOffset>,
+        dimensions: &[Option<u64>],
+        data: &[u8],
+        memory: &dyn MemoryAccess,
+        pointer_depth: usize,
+        nesting: usize,
+    ) -> Value {
+        let Some((count, inner_dimensions)) = dimensions.split_first() else {
+            return self.value(element_type, data, memory, pointer_depth, nesting + 1);
+        };
+        let Some(count) = *count else {
+            return Value::Unavailable("array of unknown length".to_owned());
+        };
+        let element_size = inner_dimensions
+            .iter()
+            .try_fold(self.size_of(element_type, 0).unwrap_or(0), |size, count| {
+                Some(size * (*count)?)
+            })
+            .unwrap_or(0);
+
+        let shown = count.min(MAX_ELEMENTS);
+        let elements = (0..shown)
+            .map(|index| {
+                let start = (index * element_size) as usize;
+                let element_data = data.get(start..).unwrap_or_default();
+                self.array_value(
+                    element_type,
+                    inner_dimensions,
+                    element_data,
+                    memory,
+                    pointer_depth,
+                    nesting + 1,
+                )
+            })
+            .collect();
+
+        Value::Array {
+            elements,
+            omitted: count - shown,
+        }
+    }
+}
+
+/// The type referenced by the entry, [None] stands for `void`
+fn type_reference(entry: &Entry<'_, '_, '_>) -> anyhow::Result<Option<UnitOffset>> {
+    match entry.attr_value(constants::DW_AT_type)? {
+        Some(AttributeValue::UnitRef(offset)) => Ok(Some(offset)),
+        Some(other) => bail!("unsupported type reference {other:?}"),
+        None => Ok(None),
+    }
+}
+
+/// The path of a file in a line table, relative paths are completed with the
+/// directory of the compilation unit
+fn file_path(
+    dwarf: &Dwarf<Reader<'_>>,
+    unit: &Unit<Reader<'_>>,
+    header: &gimli::LineProgramHeader<Reader<'_>>,
+    entry: &gimli::FileEntry<Reader<'_>>,
+) -> anyhow::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    if let Some(directory) = unit.comp_dir {
+        path.push(directory.to_string_lossy().as_ref());
+    }
+    if let Some(directory) = entry.directory(header) {
+        path.push(
+            dwarf
+                .attr_string(unit, directory)?
+                .to_string_lossy()
+                .as_ref(),
+        );
+    }
+    path.push(
+        dwarf
+            .attr_string(unit, entry.path_name())?
+            .to_string_lossy()
+            .as_ref(),
+    );
+    Ok(path)
+}
+
+/// Read a string attribute of the entry
+fn attr_string(
+    dwarf: &Dwarf<Reader<'_>>,
+    unit: &Unit<Reader<'_>>,
+    entry: &Entry<'_, '_, '_>,
+    attribute: constants::DwAt,
+) -> Option<String> {
+    let value = entry.attr_value(attribute).ok()??;
+    let string = dwarf.attr_string(unit, value).ok()?;
+    Some(string.to_string_lossy().into_owned())
+}
+
+/// The address of a variable whose location is a single `DW_OP_addr`
+///
+/// Returns [None] for variables without a static address, e.g. locals or
+/// declarations.
+fn static_address(
+    dwarf: &Dwarf<Reader<'_>>,
+    unit: &Unit<Reader<'_>>,
+    entry: &Entry<'_, '_, '_>,
+) -> anyhow::Result<Option<u32>> {
+    let Some(AttributeValue::Exprloc(expression)) = entry.attr_value(constants::DW_AT_location)?
+    else {
+        return Ok(None);
+    };
+
+    let mut operations = expression.operations(unit.encoding());
+    let address = match operations.next()? {
+        Some(Operation::Address { address }) => address,
+        Some(Operation::AddressIndex { index }) => dwarf.address(unit, index)?,
+        _ => return Ok(None),
+    };
+    if operations.next()?.is_some() {
+        return Ok(None);
+    }
+
+    Ok(u32::try_from(address).ok())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use gimli::{
+        write::{
+            self, Address, DwarfUnit, EndianVec, Expression, LineProgram, LineString, Sections,
+            UnitEntryId,
+        },
+        Encoding, Format, LineEncoding,
+    };
+
+    use super::*;
+
+    /// Writes the debug information of a little endian 32 bit target
+    pub(crate) struct Builder {
+        dwarf: DwarfUnit,
+    }
+
+    pub(crate) type Attribute = (constants::DwAt, write::AttributeValue);
+
+    pub(crate) fn name(name: &str) -> Attribute {
+        (
+            constants::DW_AT_name,
+            write::AttributeValue::String(name.as_bytes().to_vec()),
+        )
+    }
+
+    fn size(size: u64) -> Attribute {
+        (
+            constants::DW_AT_byte_size,
+            write::AttributeValue::Udata(size),
+        )
+    }
+
+    fn of_type(id: UnitEntryId) -> Attribute {
+        (constants::DW_AT_type, write::AttributeValue::UnitRef(id))
+    }
+
+    fn location(offset: u64) -> Attribute {
+        (
+            constants::DW_AT_data_member_location,
+            write::AttributeValue::Udata(offset),
+        )
+    }
+
+    fn count(count: u64) -> Attribute {
+        (constants::DW_AT_count, write::AttributeValue::Udata(count))
+    }
+
+    impl Builder {
+        pub(crate) fn new() -> Self {
+            let encoding = Encoding {
+                format: Format::Dwarf32,
+                version: 4,
+                address_size: 4,
+            };
+            Builder {
+                dwarf: DwarfUnit::new(encoding),
+            }
+        }
+
+        pub(crate) fn root(&self) -> UnitEntryId {
+            self.dwarf.unit.root()
+        }
+
+        pub(crate) fn add(
+            &mut self,
+            parent: UnitEntryId,
+            tag: constants::DwTag,
+            attributes: Vec<Attribute>,
+        ) -> UnitEntryId {
+            let id = self.dwarf.unit.add(parent, tag);
+            let entry = self.dwarf.unit.get_mut(id);
+            for (attribute, value) in attributes {
+                entry.set(attribute, value);
+            }
+            id
+        }
+
+        pub(crate) fn base(
+            &mut self,
+            type_name: &str,
+            encoding: constants::DwAte,
+            bytes: u64,
+        ) -> UnitEntryId {
+            let root = self.root();
+            self.add(
+                root,
+                constants::DW_TAG_base_type,
+                vec![
+                    name(type_name),
+                    size(bytes),
+                    (
+                        constants::DW_AT_encoding,
+                        write::AttributeValue::Encoding(encoding),
+                    ),
+                ],
+            )
+        }
+
+        pub(crate) fn structure(&mut self, type_name: &str, bytes: u64) -> UnitEntryId {
+            let root = self.root();
+            self.add(
+                root,
+                constants::DW_TAG_structure_type,
+                vec![name(type_name), size(bytes)],
+            )
+        }
+
+        pub(crate) fn member(
+            &mut self,
+            parent: UnitEntryId,
+            member: &str,
+            type_id: UnitEntryId,
+            offset: u64,
+        ) {
+            self.add(
+                parent,
+                constants::DW_TAG_member,
+                vec![name(member), of_type(type_id), location(offset)],
+            );
+        }
+
+        /// An array type with the given number of elements in each dimension
+        pub(crate) fn array(&mut self, element: UnitEntryId, dimensions: &[u64]) -> UnitEntryId {
+            let root = self.root();
+            let array = self.add(root, constants::DW_TAG_array_type, vec![of_type(element)]);
+            for dimension in dimensions {
+                self.add(
+                    array,
+                    constants::DW_TAG_subrange_type,
+                    vec![count(*dimension)],
+                );
+            }
+            array
+        }
+
+        pub(crate) fn pointer(&mut self, target: UnitEntryId) -> UnitEntryId {
+            let root = self.root();
+            self.add(root, constants::DW_TAG_pointer_type, vec![of_type(target)])
+        }
+
+        pub(crate) fn variable(
+            &mut self,
+            parent: UnitEntryId,
+            variable: &str,
+            type_id: UnitEntryId,
+            address: u32,
+        ) {
+            let mut expression = Expression::new();
+            expression.op_addr(Address::Constant(address as u64));
+            self.add(
+                parent,
+                constants::DW_TAG_variable,
+                vec![
+                    name(variable),
+                    of_type(type_id),
+                    (
+                        constants::DW_AT_location,
+                        write::AttributeValue::Exprloc(expression),
+                    ),
+                ],
+            );
+        }
+
+        /// Let the instruction at the address belong to the line of the source file
+        pub(crate) fn line(&mut self, file: &str, line: u64, address: u32) {
+            let encoding = self.dwarf.unit.encoding();
+            let program = &mut self.dwarf.unit.line_program;
+            if program.is_none() {
+                *program = LineProgram::new(
+                    encoding,
+                    LineEncoding::default(),
+                    LineString::String(b"/project".to_vec()),
+                    LineString::String(b"src/lib.rs".to_vec()),
+                    None,
+                );
+            }
+            let directory = program.default_directory();
+            let file = program.add_file(LineString::String(file.into()), directory, None);
+
+            program.begin_sequence(Some(Address::Constant(address as u64)));
+            program.row().file = file;
+            program.row().line = line;
+            program.generate_row();
+            program.end_sequence(4);
+        }
+
+        pub(crate) fn finish(mut self) -> DebugInfo {
+            let mut sections = Sections::new(EndianVec::new(RunTimeEndian::Little));
+            self.dwarf.write(&mut sections).unwrap();
+
+            let mut data = HashMap::new();
+            sections
+                .for_each(|id, section| -> gimli::Result<()> {
+                    if !section.slice().is_empty() {
+                        data.insert(id.name(), section.slice().to_vec());
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            DebugInfo {
+                sections: data,
+                endian: RunTimeEndian::Little,
+            }
+        }
+    }
+
+    /// Memory consisting of separate blocks
+    struct Memory(Vec<(u32, Vec<u8>)>);
+
+    impl MemoryAccess for Memory {
+        fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+            self.0
+                .iter()
+                .find_map(|(start, data)| {
+                    let offset = address.checked_sub(*start)? as usize;
+                    data.get(offset..offset + length).map(<[u8]>::to_vec)
+                })
+                .with_context(|| format!("{length} bytes at {address:#010X} are not mapped"))
+        }
+    }
+
+    /// Read a variable of the given type, stored with the given data
+    fn read(info: &DebugInfo, variable: &str, data: &[u8]) -> Value {
+        let variable = info.variable(variable).unwrap();
+        let memory = Memory(vec![(variable.address, data.to_vec())]);
+        info.read(&variable, &memory).unwrap()
+    }
+
+    fn scalar(value: &str) -> Value {
+        Value::Scalar(value.to_owned())
+    }
+
+    #[test]
+    fn base_types_are_decoded() {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let types = [
+            ("U32", builder.base("u32", constants::DW_ATE_unsigned, 4)),
+            ("I16", builder.base("i16", constants::DW_ATE_signed, 2)),
+            ("I64", builder.base("i64", constants::DW_ATE_signed, 8)),
+            ("BOOL", builder.base("bool", constants::DW_ATE_boolean, 1)),
+            ("F32", builder.base("f32", constants::DW_ATE_float, 4)),
+            ("F64", builder.base("f64", constants::DW_ATE_float, 8)),
+            (
+                "BYTE",
+                builder.base("u8", constants::DW_ATE_unsigned_char, 1),
+            ),
+            ("CHAR", builder.base("char", constants::DW_ATE_UTF, 4)),
+            ("UNIT", builder.base("()", constants::DW_ATE_unsigned, 0)),
+            ("U128", builder.base("u128", constants::DW_ATE_unsigned, 16)),
+        ];
+        for (index, (variable, type_id)) in types.into_iter().enumerate() {
+            builder.variable(root, variable, type_id, 0x7000_0000 + index as u32 * 0x20);
+        }
+        let info = builder.finish();
+
+        assert_eq!(
+            read(&info, "U32", &[0x78, 0x56, 0x34, 0x12]),
+            scalar("305419896")
+        );
+        assert_eq!(read(&info, "I16", &[0xFE, 0xFF]), scalar("-2"));
+        assert_eq!(read(&info, "I64", &(-5i64).to_le_bytes()), scalar("-5"));
+        assert_eq!(read(&info, "BOOL", &[1]), scalar("true"));
+        assert_eq!(read(&info, "F32", &1.5f32.to_le_bytes()), scalar("1.5"));
+        assert_eq!(
+            read(&info, "F64", &(-0.25f64).to_le_bytes()),
+            scalar("-0.25")
+        );
+        assert_eq!(read(&info, "BYTE", b"A"), scalar("65 'A'"));
+        assert_eq!(read(&info, "BYTE", &[0x0A]), scalar("10"));
+        assert_eq!(
+            read(&info, "CHAR", &('ä' as u32).to_le_bytes()),
+            scalar("'ä'")
+        );
+        assert_eq!(read(&info, "UNIT", &[]), scalar("()"));
+        assert!(matches!(
+            read(&info, "U128", &[0; 16]),
+            Value::Unavailable(reason) if reason.contains("16 byte")
+        ));
+
+        let variable = info.variable("I16").unwrap();
+        assert_eq!(variable.type_name, "i16");
+        assert_eq!(variable.size, Some(2));
+    }
+
+    #[test]
+    fn structs_are_decoded() {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let i32_type = builder.base("i32", constants::DW_ATE_signed, 4);
+        let u32_type = builder.base("u32", constants::DW_ATE_unsigned, 4);
+        let point = builder.structure("Point", 8);
+        builder.member(point, "x", i32_type, 0);
+        builder.member(point, "y", i32_type, 4);
+        let line = builder.structure("Line", 16);
+        builder.member(line, "start", point, 0);
+        builder.member(line, "end", point, 8);
+        let flags = builder.structure("Flags", 4);
+        for (member, bit_offset, bit_size) in [("low", 0, 3), ("high", 3, 5), ("top", 30, 2)] {
+            builder.add(
+                flags,
+                constants::DW_TAG_member,
+                vec![
+                    name(member),
+                    of_type(u32_type),
+                    (
+                        constants::DW_AT_data_bit_offset,
+                        write::AttributeValue::Udata(bit_offset),
+                    ),
+                    (
+                        constants::DW_AT_bit_size,
+                        write::AttributeValue::Udata(bit_size),
+                    ),
+                ],
+            );
+        }
+        builder.variable(root, "LINE", line, 0x7000_0000);
+        builder.variable(root, "FLAGS", flags, 0x7000_0100);
+        let info = builder.finish();
+
+        let data: Vec<u8> = [1i32, -2, 3, -4]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let value = read(&info, "LINE", &data);
+        assert_eq!(
+            value.to_string(),
+            "Line { start: Point { x: 1, y: -2 }, end: Point { x: 3, y: -4 } }"
+        );
+        assert_eq!(
+            format!("{value:#}"),
+            "Line {\n    start: Point {\n        x: 1,\n        y: -2,\n    },\n    end: Point {\n        x: 3,\n        y: -4,\n    },\n}"
+        );
+        assert_eq!(
+            value.leaves("LINE"),
+            [
+                ("LINE.start.x", "1"),
+                ("LINE.start.y", "-2"),
+                ("LINE.end.x", "3"),
+                ("LINE.end.y", "-4"),
+            ]
+            .map(|(path, value)| (path.to_owned(), value.to_owned()))
+        );
+
+        let value = read(
+            &info,
+            "FLAGS",
+            &(0b10 << 30 | 0b10101 << 3 | 0b011u32).to_le_bytes(),
+        );
+        assert_eq!(value.to_string(), "Flags { low: 3, high: 21, top: 2 }");
+    }
+
+    #[test]
+    fn arrays_are_decoded() {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let u16_type = builder.base("u16", constants::DW_ATE_unsigned, 2);
+        let u8_type = builder.base("u8", constants::DW_ATE_unsigned, 1);
+        let values = builder.array(u16_type, &[3]);
+        let matrix = builder.array(u8_type, &[2, 3]);
+        let long = builder.array(u8_type, &[40]);
+        let unbounded = builder.array(u8_type, &[]);
+        builder.add(unbounded, constants::DW_TAG_subrange_type, vec![]);
+        let bounded = builder.array(u8_type, &[]);
+        builder.add(
+            bounded,
+            constants::DW_TAG_subrange_type,
+            vec![
+                (
+                    constants::DW_AT_lower_bound,
+                    write::AttributeValue::Udata(1),
+                ),
+                (
+                    constants::DW_AT_upper_bound,
+                    write::AttributeValue::Udata(4),
+                ),
+            ],
+        );
+        builder.variable(root, "VALUES", values, 0x7000_0000);
+        builder.variable(root, "MATRIX", matrix, 0x7000_0100);
+        builder.variable(root, "LONG", long, 0x7000_0200);
+        builder.variable(root, "UNBOUNDED", unbounded, 0x7000_0300);
+        builder.variable(root, "BOUNDED", bounded, 0x7000_0400);
+        let info = builder.finish();
+
+        let variable = info.variable("VALUES").unwrap();
+        assert_eq!(variable.type_name, "[u16; 3]");
+        assert_eq!(variable.size, Some(6));
+        assert_eq!(
+            read(&info, "VALUES", &[1, 0, 2, 0, 3, 1]).to_string(),
+            "[1, 2, 259]"
+        );
+
+        let variable = info.variable("MATRIX").unwrap();
+        assert_eq!(variable.type_name, "[[u8; 3]; 2]");
+        assert_eq!(variable.size, Some(6));
+        assert_eq!(
+            read(&info, "MATRIX", &[1, 2, 3, 4, 5, 6]).to_string(),
+            "[[1, 2, 3], [4, 5, 6]]"
+        );
+
+        let data: Vec<u8> = (0..40).collect();
+        let Value::Array { elements, omitted } = read(&info, "LONG", &data) else {
+            panic!("LONG is not an array");
+        };
+        assert_eq!(elements.len() as u64, MAX_ELEMENTS);
+        assert_eq!(elements.last(), Some(&scalar("31")));
+        assert_eq!(omitted, 8);
+
+        let variable = info.variable("UNBOUNDED").unwrap();
+        assert_eq!(variable.type_name, "[u8]");
+        assert_eq!(variable.size, None);
+        assert!(info.read(&variable, &Memory(Vec::new())).is_err());
+
+        assert_eq!(info.variable("BOUNDED").unwrap().size, Some(4));
+    }
+
+    #[test]
+    fn enums_are_decoded() {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let mode = builder.add(
+            root,
+            constants::DW_TAG_enumeration_type,
+            vec![name("Mode"), size(1)],
+        );
+        for (enumerator, value) in [
+            ("Off", write::AttributeValue::Udata(0)),
+            ("On", write::AttributeValue::Udata(1)),
+            ("Error", write::AttributeValue::Sdata(-1)),
+        ] {
+            builder.add(
+                mode,
+                constants::DW_TAG_enumerator,
+                vec![name(enumerator), (constants::DW_AT_const_value, value)],
+            );
+        }
+
+        // A Rust enum is a struct with a variant part, whose variants hold a
+        // member with the struct of the variant
+        let u32_type = builder.base("u32", constants::DW_ATE_unsigned, 4);
+        let option = builder.structure("Option<u32>", 8);
+        let none = builder.add(
+            option,
+            constants::DW_TAG_structure_type,
+            vec![name("None"), size(8)],
+        );
+        let some = builder.add(
+            option,
+            constants::DW_TAG_structure_type,
+            vec![name("Some"), size(8)],
+        );
+        builder.member(some, "__0", u32_type, 4);
+        let part = builder.add(option, constants::DW_TAG_variant_part, vec![]);
+        let discriminant = builder.add(
+            part,
+            constants::DW_TAG_member,
+            vec![of_type(u32_type), location(0)],
+        );
+        builder.dwarf.unit.get_mut(part).set(
+            constants::DW_AT_discr,
+            write::AttributeValue::UnitRef(discriminant),
+        );
+        for (variant_name, variant_type, value) in [("None", none, 0), ("Some", some, 1)] {
+            let variant = builder.add(
+                part,
+                constants::DW_TAG_variant,
+                vec![(
+                    constants::DW_AT_discr_value,
+                    write::AttributeValue::Udata(value),
+                )],
+            );
+            builder.member(variant, variant_name, variant_type, 0);
+        }
+
+        builder.variable(root, "MODE", mode, 0x7000_0000);
+        builder.variable(root, "OPTION", option, 0x7000_0100);
+        let info = builder.finish();
+
+        assert_eq!(read(&info, "MODE", &[1]), scalar("Mode::On"));
+        assert_eq!(read(&info, "MODE", &[0xFF]), scalar("Mode::Error"));
+        assert_eq!(read(&info, "MODE", &[7]), scalar("Mode(7)"));
+
+        assert_eq!(
+            read(&info, "OPTION", &[1, 0, 0, 0, 42, 0, 0, 0]).to_string(),
+            "Option<u32>::Some { __0: 42 }"
+        );
+        assert_eq!(
+            read(&info, "OPTION", &[0, 0, 0, 0, 42, 0, 0, 0]).to_string(),
+            "Option<u32>::None"
+        );
+        assert!(matches!(
+            read(&info, "OPTION", &[2, 0, 0, 0, 42, 0, 0, 0]),
+            Value::Unavailable(reason) if reason.contains("unknown variant")
+        ));
+    }
+
+    #[test]
+    fn pointers_are_followed_one_level() {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let i32_type = builder.base("i32", constants::DW_ATE_signed, 4);
+        let point = builder.structure("Point", 8);
+        builder.member(point, "x", i32_type, 0);
+        builder.member(point, "y", i32_type, 4);
+        let point_pointer = builder.pointer(point);
+        let nested = builder.pointer(point_pointer);
+        builder.variable(root, "POINTER", point_pointer, 0x7000_0000);
+        builder.variable(root, "NESTED", nested, 0x7000_0010);
+        let info = builder.finish();
+
+        let memory = Memory(vec![
+            (0x7000_0000, vec![0x00, 0x01, 0x00, 0x70]),
+            (0x7000_0010, vec![0x00, 0x00, 0x00, 0x70]),
+            (0x7000_0100, vec![1, 0, 0, 0, 0xFE, 0xFF, 0xFF, 0xFF]),
+        ]);
+        let variable = info.variable("POINTER").unwrap();
+        assert_eq!(variable.type_name, "*Point");
+        assert_eq!(variable.size, Some(4));
+        let value = info.read(&variable, &memory).unwrap();
+        assert_eq!(value.to_string(), "0x70000100 -> Point { x: 1, y: -2 }");
+        assert_eq!(
+            value.leaves("POINTER"),
+            [("POINTER".to_owned(), "0x70000100".to_owned())]
+        );
+
+        // Decoding only has the memory of the variable itself
+        let value = info.decode(&variable, &[0x00, 0x01, 0x00, 0x70]).unwrap();
+        assert_eq!(value.to_string(), "0x70000100");
+
+        let variable = info.variable("NESTED").unwrap();
+        assert_eq!(variable.type_name, "**Point");
+        let value = info.read(&variable, &memory).unwrap();
+        assert_eq!(value.to_string(), "0x70000000 -> 0x70000100");
+
+        // Null pointers and unreadable targets are not followed
+        let value = read(&info, "POINTER", &[0; 4]);
+        assert_eq!(
+            value,
+            Value::Pointer {
+                address: 0,
+                target: None
+            }
+        );
+        let value = read(&info, "POINTER", &[0x00, 0x00, 0x00, 0x60]);
+        assert!(value.to_string().starts_with("0x60000000 -> <"));
+    }
+
+    #[test]
+    fn variables_are_found_by_path_and_linkage_name() {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let u32_type = builder.base("u32", constants::DW_ATE_unsigned, 4);
+        let app = builder.add(root, constants::DW_TAG_namespace, vec![name("app")]);
+        let state = builder.add(app, constants::DW_TAG_namespace, vec![name("state")]);
+        builder.variable(state, "COUNTER", u32_type, 0x7000_0000);
+        let mangled = builder.add(
+            state,
+            constants::DW_TAG_variable,
+            vec![
+                name("MANGLED"),
+                of_type(u32_type),
+                (
+                    constants::DW_AT_linkage_name,
+                    write::AttributeValue::String(b"_ZN3app5state7MANGLED".to_vec()),
+                ),
+            ],
+        );
+        let mut expression = Expression::new();
+        expression.op_addr(Address::Constant(0x7000_0004));
+        builder.dwarf.unit.get_mut(mangled).set(
+            constants::DW_AT_location,
+            write::AttributeValue::Exprloc(expression),
+        );
+        // A declaration without a location
+        builder.add(
+            root,
+            constants::DW_TAG_variable,
+            vec![name("EXTERNAL"), of_type(u32_type)],
+        );
+        let info = builder.finish();
+
+        for path in ["COUNTER", "state::COUNTER", "app::state::COUNTER"] {
+            let variable = info.variable(path).unwrap();
+            assert_eq!(variable.name, "app::state::COUNTER");
+            assert_eq!(variable.address, 0x7000_0000);
+        }
+        let variable = info.variable("_ZN3app5state7MANGLED").unwrap();
+        assert_eq!(variable.name, "app::state::MANGLED");
+        assert_eq!(variable.address, 0x7000_0004);
+
+        assert!(info.variable("te::COUNTER").is_err());
+        assert!(info.variable("EXTERNAL").is_err());
+    }
+}
@@ -1,30 +1,97 @@
 #![doc = include_str!("../README.md")]
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 
 pub mod backtrace;
 pub mod chip_interface;
+pub mod dap;
+pub mod debugger;
 pub mod defmt;
+pub mod dump;
 pub mod elf;
-use backtrace::ParseInfo;
+pub mod gdb;
+pub mod harness;
+pub mod inspect;
+pub mod output;
+pub mod report;
+pub mod stack;
+pub mod test_runner;
+pub mod watch;
+use backtrace::{log_halted_cores, BackTraceInfo, ParseInfo};
 use chip_interface::ChipInterface;
 use defmt::DefmtDecoder;
+use dump::DumpConfig;
+use elf::csa_regions;
+use harness::TestSuite;
+use inspect::DebugInfo;
 use log::LevelFilter;
+use output::{OutputEvent, OutputFormat};
+use report::{ReportTarget, RunRecord, RunReport};
+use stack::StackConfig;
+use test_runner::TestRunnerConfig;
+use tricore_common::debug::CoreMemory;
+use tricore_common::reset::ResetConfig;
+use tricore_common::run::{HaltReason, RunControl, StopReason};
+use watch::{parse_interval, RecordTarget, WatchSession};
 
 /// Simple program to flash and interface with tricore chips
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Flash and run a binary if no subcommand is given
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Sets the log level
+    #[arg(short, long, value_enum, global = true, required = false, default_value_t = LogLevel::Warn)]
+    log_level: LogLevel,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Reset the device or list the reset classes it offers
+    Reset(ResetArgs),
+    /// Interactively halt, step and resume the cores of the device
+    Debug(DebugArgs),
+    /// Print the backtraces and the defmt output stored in a core dump
+    Analyze(AnalyzeArgs),
+    /// Read global variables from the device and print them according to their type
+    Inspect(InspectArgs),
+    /// Run the binary and periodically print global variables, while the defmt
+    /// output continues
+    Watch(WatchArgs),
+    /// Serve the GDB remote serial protocol, the cores are available as threads
+    Gdb(GdbArgs),
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for editors like
+    /// VS Code
+    Dap(DapArgs),
+    /// Keep the docker container running between invocations
+    #[cfg(feature = "docker")]
+    Daemon {
+        #[command(subcommand)]
+        command: tricore_docker::persistent::DaemonCommand,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
     /// Whether flashing should be skipped
     #[arg(long, default_value_t = false)]
     no_flash: bool,
 
     /// Path to the binary
-    #[arg(value_parser = existing_path)]
-    elf: PathBuf,
+    #[arg(value_parser = existing_path, required = true)]
+    elf: Option<PathBuf>,
 
     /// Configuration for the backend
     #[command(flatten)]
@@ -35,12 +102,187 @@ struct Args {
     #[arg(long, default_value_t = false)]
     halt_memtool: bool,
 
-    /// Sets the log level
-    #[arg(short, long, value_enum, required = false, default_value_t = LogLevel::Warn)]
-    log_level: LogLevel,
+    /// Configuration how the device is reset before running the binary
+    #[command(flatten)]
+    reset: ResetConfig,
+
+    /// Configuration when the run is stopped before the device halts
+    #[command(flatten)]
+    control: RunControl,
+
+    /// Configuration how the exit code is obtained when running tests
+    #[command(flatten)]
+    test: TestRunnerConfig,
+
+    /// Write a report of the run for CI systems, given as 'junit=<path>' or
+    /// 'json=<path>'. May be given multiple times
+    #[arg(long = "report", value_name = "FORMAT=PATH")]
+    reports: Vec<ReportTarget>,
+
+    /// Format of the output on stdout. With 'json', each decoded log frame, the
+    /// halt of the device and each frame of the backtrace is printed as a single
+    /// line of JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Pretty)]
+    format: OutputFormat,
+
+    /// Print all registers saved with each frame of the backtrace, with PSW and
+    /// PCXI decoded into their fields
+    #[arg(long, default_value_t = false)]
+    verbose_backtrace: bool,
+
+    /// Configuration of the core dump written when the device halts
+    #[command(flatten)]
+    dump: DumpConfig,
+
+    /// Configuration of the stack usage reported when the device halts
+    #[command(flatten)]
+    stack: StackConfig,
 }
 
-fn main() -> anyhow::Result<()> {
+#[derive(clap::Args, Debug)]
+struct ResetArgs {
+    /// List the reset classes available for each core instead of resetting the device
+    #[arg(long, default_value_t = false)]
+    list: bool,
+
+    /// Configuration for the backend
+    #[command(flatten)]
+    backend: chip_interface::Config,
+
+    /// Configuration how the device is reset
+    #[command(flatten)]
+    reset: ResetConfig,
+}
+
+#[derive(clap::Args, Debug)]
+struct DebugArgs {
+    /// Path to the binary running on the device, used to resolve symbols and
+    /// source locations
+    #[arg(value_parser = existing_path)]
+    elf: PathBuf,
+
+    /// Configuration for the backend
+    #[command(flatten)]
+    backend: chip_interface::Config,
+
+    /// The core that is selected when the session starts
+    #[arg(long, default_value_t = 0)]
+    core: usize,
+
+    /// Configuration when 'until' or 'next' over a call is stopped before the
+    /// core reaches its target
+    #[command(flatten)]
+    control: RunControl,
+}
+
+#[derive(clap::Args, Debug)]
+struct AnalyzeArgs {
+    /// Path to the core dump, written with '--dump'
+    #[arg(value_parser = existing_path)]
+    dump: PathBuf,
+
+    /// Path to the binary that ran on the device when the core dump was written
+    #[arg(value_parser = existing_path)]
+    elf: PathBuf,
+
+    /// Print all registers saved with each frame of the backtrace, with PSW and
+    /// PCXI decoded into their fields
+    #[arg(long, default_value_t = false)]
+    verbose_backtrace: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct InspectArgs {
+    /// Path to the binary running on the device, its debug information
+    /// describes the variables
+    #[arg(value_parser = existing_path)]
+    elf: PathBuf,
+
+    /// The variables to print, given by their name, their path such as
+    /// 'app::COUNTER' or their linkage name
+    #[arg(required = true)]
+    symbols: Vec<String>,
+
+    /// Configuration for the backend
+    #[command(flatten)]
+    backend: chip_interface::Config,
+
+    /// The core the memory is read through, relevant for core local variables
+    #[arg(long, default_value_t = 0)]
+    core: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Path to the binary
+    #[arg(value_parser = existing_path)]
+    elf: PathBuf,
+
+    /// The variables to sample, given as for 'inspect'
+    #[arg(required = true)]
+    symbols: Vec<String>,
+
+    /// The time between two samples, e.g. '50ms' or '1s'. Samples are taken at
+    /// most as often as the RTT data is polled, see '--poll-interval'
+    #[arg(long, value_parser = parse_interval, default_value = "100ms")]
+    interval: Duration,
+
+    /// Write all samples to a file for plotting, given as 'csv=<path>' or
+    /// 'json=<path>'. May be given multiple times
+    #[arg(long = "record", value_name = "FORMAT=PATH")]
+    records: Vec<RecordTarget>,
+
+    /// Whether flashing should be skipped
+    #[arg(long, default_value_t = false)]
+    no_flash: bool,
+
+    /// Configuration for the backend
+    #[command(flatten)]
+    backend: chip_interface::Config,
+
+    /// Configuration how the device is reset before running the binary
+    #[command(flatten)]
+    reset: ResetConfig,
+
+    /// Configuration when the run is stopped before the device halts
+    #[command(flatten)]
+    control: RunControl,
+}
+
+#[derive(clap::Args, Debug)]
+struct GdbArgs {
+    /// The address GDB connects to, e.g. with 'target remote :3333'
+    #[arg(long, default_value = "127.0.0.1:3333")]
+    listen: SocketAddr,
+
+    /// Configuration for the backend
+    #[command(flatten)]
+    backend: chip_interface::Config,
+}
+
+#[derive(clap::Args, Debug)]
+struct DapArgs {
+    /// Configuration for the backend
+    #[command(flatten)]
+    backend: chip_interface::Config,
+
+    /// Configuration how the device is reset after flashing
+    #[command(flatten)]
+    reset: ResetConfig,
+
+    /// Configuration when stepping over a call is stopped before the core
+    /// returns
+    #[command(flatten)]
+    control: RunControl,
+}
+
+/// Exit code if the run was stopped because the timeout elapsed, as used by `timeout`
+const EXIT_TIMEOUT: u8 = 124;
+
+/// Exit code if the run was stopped by a signal, as used by shells for SIGINT
+const EXIT_CANCELLED: u8 = 130;
+
+fn main() -> anyhow::Result<ExitCode> {
     let args = Args::parse();
 
     env_logger::init();
@@ -54,27 +296,334 @@ fn main() -> anyhow::Result<()> {
 
     log::set_max_level(log_filter);
 
+    match args.command {
+        None => run(args.run),
+        Some(Command::Reset(reset_args)) => reset(reset_args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Debug(debug_args)) => debug(debug_args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Analyze(analyze_args)) => dump::analyze(
+            analyze_args.dump.as_path(),
+            analyze_args.elf.as_path(),
+            analyze_args.verbose_backtrace,
+        )
+        .map(|_| ExitCode::SUCCESS),
+        Some(Command::Inspect(inspect_args)) => inspect(inspect_args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Watch(watch_args)) => watch(watch_args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Gdb(gdb_args)) => gdb(gdb_args).map(|_| ExitCode::SUCCESS),
+        Some(Command::Dap(dap_args)) => {
+            dap::serve(dap_args.backend, &dap_args.reset, &dap_args.control)
+                .map(|_| ExitCode::SUCCESS)
+        }
+        #[cfg(feature = "docker")]
+        Some(Command::Daemon { command }) => daemon(command).map(|_| ExitCode::SUCCESS),
+    }
+}
+
+/// Flash the binary and decode its defmt data until the device halts
+///
+/// The run is stopped early when the timeout elapses or the process receives
+/// SIGINT or SIGTERM, a second signal aborts immediately.
+fn run(mut args: RunArgs) -> anyhow::Result<ExitCode> {
+    let elf = args.elf.expect("elf is a required argument");
+
+    let test_suite = if args.test.test_runner {
+        TestSuite::from_elf(elf.as_path())?
+    } else {
+        None
+    };
+
+    args.stack.paint(elf.as_path(), &mut args.reset)?;
+
     let command_server = ChipInterface::new(args.backend)?;
+    let mut report = RunReport::new(elf.as_path());
 
     if !args.no_flash {
-        command_server.flash_elf(args.elf.as_path(), args.halt_memtool)?;
+        let started = Instant::now();
+        let flashed = command_server.flash_elf(elf.as_path(), args.halt_memtool);
+        report.record_flash(started, &flashed);
+        if flashed.is_err() {
+            report.write(&args.reports)?;
+        }
+        flashed?;
     } else {
         log::warn!("Flashing skipped - this might lead to malformed defmt data!")
     }
 
-    let mut defmt_decoder = DefmtDecoder::spawn(args.elf.as_path())?;
+    install_signal_handler(&args.control)?;
 
-    let backtrace = command_server.read_rtt(
-        defmt_decoder.rtt_control_block_address(),
+    if let Some(test_suite) = test_suite {
+        let exit_code = test_suite.run(
+            &command_server,
+            &args.reset,
+            &args.control,
+            &args.test,
+            args.format,
+            &mut report,
+        )?;
+        report.write(&args.reports)?;
+        return Ok(exit_code);
+    }
+
+    // The decoded frames are only processed if they are needed
+    let scan_output =
+        args.test.test_runner || !args.reports.is_empty() || args.format == OutputFormat::Json;
+    let mut defmt_decoder = if scan_output {
+        DefmtDecoder::spawn_scanning(elf.as_path(), args.test.markers(), args.format)?
+    } else {
+        DefmtDecoder::spawn(elf.as_path())?
+    };
+
+    let rtt_control_block_address = defmt_decoder.rtt_control_block_address();
+    let started = Instant::now();
+    let halt_reason = command_server.read_rtt(
+        rtt_control_block_address,
+        &args.reset,
+        &args.control,
+        &csa_regions(elf.as_path())?,
         &mut defmt_decoder,
     )?;
+    let decoded = defmt_decoder.finish()?;
+
+    let (message, exit_code, passed) = match (&halt_reason, halt_reason.stacktrace()) {
+        (HaltReason::DebugHit(_), Some(stacktrace)) if args.test.test_runner => {
+            let outcome = args
+                .test
+                .evaluate(stacktrace, elf.as_path(), &decoded.markers);
+            (outcome.to_string(), outcome.exit_code(), outcome.passed())
+        }
+        (HaltReason::DebugHit(_), _) => ("Device halted".to_owned(), ExitCode::SUCCESS, true),
+        (HaltReason::Stopped(StopReason::Timeout, _), _) => (
+            "Timeout elapsed".to_owned(),
+            ExitCode::from(EXIT_TIMEOUT),
+            false,
+        ),
+        (HaltReason::Stopped(StopReason::Cancelled, _), _) => (
+            "Run cancelled".to_owned(),
+            ExitCode::from(EXIT_CANCELLED),
+            false,
+        ),
+    };
+
+    let backtrace_info = halt_reason
+        .stacktrace()
+        .map(|backtrace| backtrace.addr2line(elf.as_path()))
+        .transpose()?;
+
+    match (args.format, halt_reason.halted_cores()) {
+        (OutputFormat::Json, _) => {
+            let trap_class = backtrace_info.as_ref().and_then(BackTraceInfo::trap_class);
+            OutputEvent::halt(&halt_reason, &message, passed, trap_class).emit();
+            for frame in backtrace_info.iter().flat_map(BackTraceInfo::frames) {
+                OutputEvent::Frame(frame).emit();
+            }
+        }
+        // A passed test does not need a backtrace
+        (OutputFormat::Pretty, Some(_)) if passed && args.test.test_runner => {
+            println!("{}", message.green())
+        }
+        (OutputFormat::Pretty, Some(halted_cores)) => {
+            println!("{}", format!("{message}, backtrace as follows").red());
+            log_halted_cores(halted_cores, elf.as_path(), args.verbose_backtrace)?;
+        }
+        (OutputFormat::Pretty, None) => {
+            println!("{}", format!("{message}, device was left running").red())
+        }
+    }
+
+    if args.stack.enabled() && halt_reason.stacktrace().is_some() {
+        match args.stack.measure(&command_server, elf.as_path()) {
+            Ok(usage) if args.format == OutputFormat::Json => usage
+                .iter()
+                .for_each(|core| OutputEvent::Stack(core).emit()),
+            Ok(usage) => {
+                println!("{}", "Stack usage".bold());
+                for core in usage.iter() {
+                    core.log_stdout(args.stack.csa_warning_threshold);
+                }
+            }
+            Err(error) => log::error!("Cannot measure the stack usage: {error:#}"),
+        }
+    }
+
+    if halt_reason.stacktrace().is_some() {
+        let written = args
+            .dump
+            .write(&command_server, elf.as_path(), rtt_control_block_address);
+        if let Err(error) = written {
+            log::error!("Cannot write core dump: {error:#}");
+        }
+    }
+
+    report.runs.push(RunRecord::new(
+        "run",
+        passed,
+        message,
+        started.elapsed(),
+        decoded.frames,
+        Some(halt_reason),
+        elf.as_path(),
+    ));
+    report.write(&args.reports)?;
+
+    Ok(exit_code)
+}
 
-    let backtrace_info = backtrace.addr2line(args.elf.as_path())?;
+/// Stop the run on SIGINT or SIGTERM, a second signal aborts immediately
+fn install_signal_handler(control: &RunControl) -> anyhow::Result<()> {
+    let cancellation = control.cancellation.clone();
+    ctrlc::set_handler(move || {
+        if cancellation.is_cancelled() {
+            std::process::exit(EXIT_CANCELLED.into());
+        }
+        eprintln!("Stopping, press Ctrl-C again to abort immediately");
+        cancellation.cancel();
+    })
+    .with_context(|| "Cannot install signal handler")
+}
 
-    println!("{}", "Device halted, backtrace as follows".red());
-    backtrace_info.log_stdout();
+/// Reset the device, or list the available reset classes
+fn reset(args: ResetArgs) -> anyhow::Result<()> {
+    let command_server = ChipInterface::new(args.backend)?;
 
-    Ok(()) as Result<(), anyhow::Error>
+    if args.list {
+        for core in command_server.list_reset_classes()? {
+            println!("{}", format!("Core {}", core.core).bold());
+            for class in core.classes.iter() {
+                println!("  {:>2}: {}", class.index, class.description);
+            }
+        }
+        return Ok(());
+    }
+
+    command_server.reset(&args.reset)
+}
+
+/// Start an interactive debug session on the device
+///
+/// SIGINT or SIGTERM stop a running 'until' or 'next', a second signal aborts
+/// immediately.
+fn debug(args: DebugArgs) -> anyhow::Result<()> {
+    let csa_regions = csa_regions(args.elf.as_path())?;
+    let command_server = ChipInterface::new(args.backend)?;
+
+    install_signal_handler(&args.control)?;
+    command_server.debug(&csa_regions, &args.control, |target| {
+        debugger::run_session(target, args.elf.as_path(), args.core)
+    })
+}
+
+/// Start, stop or query the daemon of the docker backend
+#[cfg(feature = "docker")]
+fn daemon(command: tricore_docker::persistent::DaemonCommand) -> anyhow::Result<()> {
+    use tricore_docker::persistent::{self, DaemonCommand};
+
+    match command {
+        DaemonCommand::Start(args) => println!("{}", persistent::start(args)?),
+        DaemonCommand::Stop => {
+            if persistent::stop()? {
+                println!("Daemon is stopping");
+            } else {
+                println!("Daemon is not running");
+            }
+        }
+        DaemonCommand::Status => match persistent::status()? {
+            Some(status) => println!("{status}"),
+            None => println!("Daemon is not running"),
+        },
+        DaemonCommand::Run(args) => persistent::run(args)?,
+    }
+    Ok(())
+}
+
+/// Serve GDB connections until the process is stopped
+fn gdb(args: GdbArgs) -> anyhow::Result<()> {
+    let command_server = ChipInterface::new(args.backend)?;
+
+    // Without an elf file the CSA regions are not known, the chains are not restricted.
+    // GDB interrupts running cores itself, its commands do not run to an address
+    command_server.debug(&[], &RunControl::default(), |target| {
+        gdb::serve(target, args.listen)
+    })
+}
+
+fn inspect(args: InspectArgs) -> anyhow::Result<()> {
+    // Resolve all variables first, such that typos do not require a connection
+    let debug_info = DebugInfo::load(args.elf.as_path())?;
+    let variables = args
+        .symbols
+        .iter()
+        .map(|symbol| debug_info.variable(symbol))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let csa_regions = csa_regions(args.elf.as_path())?;
+    let command_server = ChipInterface::new(args.backend)?;
+    command_server.debug(&csa_regions, &RunControl::default(), |target| {
+        let memory = CoreMemory {
+            target: &*target,
+            core: args.core,
+        };
+        for variable in variables.iter() {
+            match debug_info.read(variable, &memory) {
+                Ok(value) => println!("{} = {value:#}", variable.name.bold()),
+                Err(error) => println!("{} = {}", variable.name.bold(), format!("{error:#}").red()),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Flash and run the binary, sampling the variables until the device halts
+fn watch(args: WatchArgs) -> anyhow::Result<()> {
+    let debug_info = DebugInfo::load(args.elf.as_path())?;
+    let variables = args
+        .symbols
+        .iter()
+        .map(|symbol| {
+            let variable = debug_info.variable(symbol)?;
+            if variable.size.is_none() {
+                anyhow::bail!("The size of {} is not known", variable.name);
+            }
+            Ok(variable)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut session = WatchSession::new(&debug_info, &variables, &args.records)?;
+
+    let command_server = ChipInterface::new(args.backend)?;
+    if !args.no_flash {
+        command_server.flash_elf(args.elf.as_path(), false)?;
+    } else {
+        log::warn!("Flashing skipped - this might lead to malformed defmt data!")
+    }
+
+    install_signal_handler(&args.control)?;
+
+    let mut defmt_decoder = DefmtDecoder::spawn(args.elf.as_path())?;
+    let halt_reason = command_server.watch(
+        defmt_decoder.rtt_control_block_address(),
+        &args.reset,
+        &args.control,
+        &csa_regions(args.elf.as_path())?,
+        &session.request(args.interval),
+        &mut defmt_decoder,
+        &mut |sample| session.sample(sample),
+    )?;
+    defmt_decoder.finish()?;
+    session.finish()?;
+
+    let message = match halt_reason {
+        HaltReason::DebugHit(_) => "Device halted",
+        HaltReason::Stopped(StopReason::Timeout, _) => "Timeout elapsed",
+        HaltReason::Stopped(StopReason::Cancelled, _) => "Watch stopped",
+    };
+    match halt_reason.halted_cores() {
+        Some(halted_cores) => {
+            println!("{}", format!("{message}, backtrace as follows").red());
+            log_halted_cores(halted_cores, args.elf.as_path(), false)
+        }
+        None => {
+            println!("{}", format!("{message}, device was left running").red());
+            Ok(())
+        }
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -0,0 +1,72 @@
+//! Defines how the output of a run is printed to stdout, see [OutputFormat]
+use defmt_json_schema::v1::JsonFrame;
+use serde::Serialize;
+use tricore_common::run::{HaltReason, StopReason};
+
+use crate::{backtrace::StackFrameInfo, stack::CoreStackUsage};
+
+/// Format of the output on stdout
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable output
+    Pretty,
+    /// One JSON object per line for each event, see [OutputEvent]
+    Json,
+}
+
+/// An event printed as a single line when the output format is
+/// [OutputFormat::Json]
+///
+/// The kind of the event is given by its `type` field.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OutputEvent<'a> {
+    /// A decoded defmt frame
+    Log(&'a JsonFrame),
+    /// The device halted or the run was stopped
+    Halt {
+        /// One of `debug`, `timeout` or `cancelled`
+        reason: &'static str,
+        message: &'a str,
+        /// Whether the run is considered successful, e.g. a test passed
+        passed: bool,
+        /// The trap class if the device halted in a trap handler
+        trap_class: Option<u8>,
+    },
+    /// A stack frame of the backtrace, printed after the halt event starting
+    /// with the frame the device is halted in
+    Frame(&'a StackFrameInfo),
+    /// The stack usage of a core, printed after the backtrace if requested
+    Stack(&'a CoreStackUsage),
+}
+
+impl<'a> OutputEvent<'a> {
+    /// Create the halt event for the given halt reason
+    pub fn halt(
+        halt_reason: &HaltReason,
+        message: &'a str,
+        passed: bool,
+        trap_class: Option<u8>,
+    ) -> Self {
+        let reason = match halt_reason {
+            HaltReason::DebugHit(_) => "debug",
+            HaltReason::Stopped(StopReason::Timeout, _) => "timeout",
+            HaltReason::Stopped(StopReason::Cancelled, _) => "cancelled",
+        };
+
+        OutputEvent::Halt {
+            reason,
+            message,
+            passed,
+            trap_class,
+        }
+    }
+
+    /// Print the event as a single line of JSON
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => println!("{line}"),
+            Err(error) => log::error!("Cannot serialize output event: {error}"),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Writes structured reports of a run for CI systems, see [RunReport]
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use defmt_json_schema::v1::JsonFrame;
+use serde::Serialize;
+use tricore_common::run::{HaltReason, StopReason};
+
+use crate::{
+    backtrace::{BackTraceInfo, ParseInfo},
+    defmt::format_frame,
+};
+
+/// A report file given as `<format>=<path>`
+#[derive(Debug, Clone)]
+pub struct ReportTarget {
+    pub format: ReportFileFormat,
+    pub path: PathBuf,
+}
+
+impl FromStr for ReportTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once('=')
+            .with_context(|| format!("Expected <junit|json>=<path>, got {s:?}"))?;
+
+        let format = match format.trim() {
+            format if format.eq_ignore_ascii_case("junit") => ReportFileFormat::Junit,
+            format if format.eq_ignore_ascii_case("json") => ReportFileFormat::Json,
+            format => bail!("Unknown report format {format:?}, expected 'junit' or 'json'"),
+        };
+        if path.is_empty() {
+            bail!("The path of the report must not be empty");
+        }
+
+        Ok(ReportTarget {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// The file format of a report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFileFormat {
+    /// JUnit XML, as understood by most CI systems
+    Junit,
+    /// The [RunReport] serialized as JSON
+    Json,
+}
+
+/// Everything that happened during a run, from flashing to the halt of the device
+#[derive(Serialize)]
+pub struct RunReport {
+    /// The binary that was run
+    pub elf: PathBuf,
+    /// The result of flashing the binary, [None] if flashing was skipped
+    pub flash: Option<FlashRecord>,
+    /// The runs of the binary, one per test case when running a test suite
+    pub runs: Vec<RunRecord>,
+    /// Duration of the whole run in seconds
+    pub duration: f64,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// The result of flashing the binary
+#[derive(Serialize)]
+pub struct FlashRecord {
+    /// Duration in seconds
+    pub duration: f64,
+    /// Why flashing failed, [None] on success
+    pub error: Option<String>,
+}
+
+/// The result of running the binary until the device halted
+#[derive(Serialize)]
+pub struct RunRecord {
+    pub name: String,
+    /// Whether the run is considered successful, e.g. a test passed
+    pub passed: bool,
+    /// Whether the run did not happen, e.g. a test case of a suite after a
+    /// failing one
+    pub skipped: bool,
+    /// A description of the outcome
+    pub message: String,
+    /// Duration in seconds
+    pub duration: f64,
+    /// The decoded defmt frames
+    pub log: Vec<JsonFrame>,
+    /// Why the run ended, [None] for test cases that were followed by another
+    /// one of the suite
+    pub halt_reason: Option<HaltReason>,
+    /// The trap class if the device halted in a trap handler
+    pub trap_class: Option<u8>,
+    /// The symbolized backtrace, if the cores were halted
+    pub backtrace: Option<BackTraceInfo>,
+}
+
+impl RunRecord {
+    /// Create a record, the backtrace of the halt reason is symbolized with the
+    /// given elf file
+    pub fn new(
+        name: &str,
+        passed: bool,
+        message: String,
+        duration: Duration,
+        log: Vec<JsonFrame>,
+        halt_reason: Option<HaltReason>,
+        elf_file: &Path,
+    ) -> Self {
+        let backtrace = halt_reason
+            .as_ref()
+            .and_then(HaltReason::stacktrace)
+            .and_then(|stacktrace| match stacktrace.addr2line(elf_file) {
+                Ok(backtrace) => Some(backtrace),
+                Err(error) => {
+                    log::warn!("Cannot symbolize backtrace for the report: {error:#}");
+                    None
+                }
+            });
+
+        RunRecord {
+            name: name.to_owned(),
+            passed,
+            skipped: false,
+            message,
+            duration: duration.as_secs_f64(),
+            log,
+            trap_class: backtrace.as_ref().and_then(BackTraceInfo::trap_class),
+            halt_reason,
+            backtrace,
+        }
+    }
+
+    /// Create a record of a run that did not happen, the message tells why
+    pub fn skipped(name: &str, message: String) -> Self {
+        RunRecord {
+            name: name.to_owned(),
+            passed: false,
+            skipped: true,
+            message,
+            duration: 0.0,
+            log: Vec::new(),
+            halt_reason: None,
+            trap_class: None,
+            backtrace: None,
+        }
+    }
+}
+
+impl RunReport {
+    pub fn new(elf_file: &Path) -> Self {
+        RunReport {
+            elf: elf_file.to_owned(),
+            flash: None,
+            runs: Vec::new(),
+            duration: 0.0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Record the result of flashing that started at the given instant
+    pub fn record_flash(&mut self, started: Instant, result: &anyhow::Result<()>) {
+        self.flash = Some(FlashRecord {
+            duration: started.elapsed().as_secs_f64(),
+            error: result.as_ref().err().map(|error| format!("{error:#}")),
+        });
+    }
+
+    /// Write the report to all of the given targets
+    pub fn write(&mut self, targets: &[ReportTarget]) -> anyhow::Result<()> {
+        self.duration = self.started.elapsed().as_secs_f64();
+
+        for target in targets {
+            let contents = match target.format {
+                ReportFileFormat::Json => serde_json::to_string_pretty(self)
+                    .with_context(|| "Cannot serialize the report")?,
+                ReportFileFormat::Junit => self.to_junit(),
+            };
+            fs::write(&target.path, contents)
+                .with_context(|| format!("Cannot write the report to {}", target.path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Format the report as JUnit XML
+    ///
+    /// Flashing and each run are reported as separate test cases, runs that did
+    /// not happen are reported as skipped.
+    fn to_junit(&self) -> String {
+        let suite_name = self
+            .elf
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "tricore-probe".to_owned());
+        let flash_failed = self
+            .flash
+            .as_ref()
+            .is_some_and(|flash| flash.error.is_some());
+        let tests = self.runs.len() + self.flash.iter().len();
+        let skipped = self.runs.iter().filter(|run| run.skipped).count();
+        let failures = self
+            .runs
+            .iter()
+            .filter(|run| !run.passed && !run.skipped)
+            .count()
+            + flash_failed as usize;
+
+        let mut xml = String::new();
+        let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let _ = writeln!(
+            xml,
+            r#"<testsuites name="tricore-probe" tests="{tests}" failures="{failures}" errors="0" time="{:.3}">"#,
+            self.duration
+        );
+        let _ = writeln!(
+            xml,
+            r#"  <testsuite name="{}" tests="{tests}" failures="{failures}" errors="0" skipped="{skipped}" time="{:.3}">"#,
+            escape_xml(&suite_name),
+            self.duration
+        );
+
+        if let Some(flash) = &self.flash {
+            let _ = write!(
+                xml,
+                r#"    <testcase name="flash" classname="{}" time="{:.3}""#,
+                escape_xml(&suite_name),
+                flash.duration
+            );
+            match &flash.error {
+                Some(error) => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <failure message=\"{}\" type=\"flash\"/>\n    </testcase>",
+                        escape_xml(error)
+                    );
+                }
+                None => {
+                    let _ = writeln!(xml, "/>");
+                }
+            }
+        }
+
+        for run in self.runs.iter() {
+            let _ = writeln!(
+                xml,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}">"#,
+                escape_xml(&run.name),
+                escape_xml(&suite_name),
+                run.duration
+            );
+            if run.skipped {
+                let _ = writeln!(
+                    xml,
+                    "      <skipped message=\"{}\"/>",
+                    escape_xml(&run.message)
+                );
+            } else if !run.passed {
+                let failure_type = match &run.halt_reason {
+                    Some(HaltReason::DebugHit(_)) if run.trap_class.is_some() => "trap",
+                    Some(HaltReason::DebugHit(_)) | None => "halt",
+                    Some(HaltReason::Stopped(StopReason::Timeout, _)) => "timeout",
+                    Some(HaltReason::Stopped(StopReason::Cancelled, _)) => "cancelled",
+                };
+                let mut details = run.message.clone();
+                if let Some(backtrace) = &run.backtrace {
+                    details.push_str("\n\n");
+                    details.push_str(&format!("{backtrace:#}"));
+                }
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\" type=\"{failure_type}\">{}</failure>",
+                    escape_xml(&run.message),
+                    escape_xml(&details)
+                );
+            }
+            let log = run
+                .log
+                .iter()
+                .flat_map(format_frame)
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ = writeln!(
+                xml,
+                "      <system-out>{}</system-out>\n    </testcase>",
+                escape_xml(&log)
+            );
+        }
+
+        let _ = writeln!(xml, "  </testsuite>\n</testsuites>");
+        xml
+    }
+}
+
+/// Escape the text for use in XML attributes and elements
+///
+/// Characters that are not allowed in XML are replaced.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_runs_are_not_failures() {
+        let mut report = RunReport::new(Path::new("suite.elf"));
+        report.runs.push(RunRecord::new(
+            "first",
+            false,
+            "Trap".to_owned(),
+            Duration::from_secs(1),
+            Vec::new(),
+            None,
+            Path::new("suite.elf"),
+        ));
+        report.runs.push(RunRecord::skipped(
+            "test case 2 of 2",
+            "Test not run".to_owned(),
+        ));
+
+        let xml = report.to_junit();
+        assert!(xml.contains(r#"tests="2" failures="1" errors="0" skipped="1""#));
+        assert!(xml.contains(r#"<failure message="Trap" type="halt">"#));
+        assert!(xml.contains(r#"<skipped message="Test not run"/>"#));
+    }
+}
@@ -0,0 +1,608 @@
+//! Analyzes the stack and context save area usage of each core, see [StackConfig]
+//!
+//! The stacks and context save areas are located by the symbols the linker
+//! script defines per core, as done by the Infineon iLLD: `__USTACK0_END` to
+//! `__USTACK0` for the user stack, `__ISTACK0_END` to `__ISTACK0` for the
+//! interrupt stack and `__CSA0` to `__CSA0_END` for the context save areas of
+//! core 0, and so on for the other cores.
+//!
+//! The current depth of a stack is given by the stack pointer. To measure the
+//! deepest the stack ever got, the stacks are painted with [STACK_PAINT] before
+//! the binary runs, the high-water mark is the lowest address that was
+//! overwritten. The usage of the context save areas is given by the length of
+//! the free list, which starts at the FCX register.
+use std::{collections::HashSet, path::Path};
+
+use colored::Colorize;
+use serde::Serialize;
+use tricore_common::{
+    access::{MemoryAccess, RegisterAccess},
+    backtrace::{csa::CSA_SIZE, pcxi::PCXI},
+    dump::{CoreDump, CoreView, DumpRegion, DumpRequest},
+    reset::{MemoryWrite, ResetConfig},
+};
+
+use crate::{chip_interface::ChipInterface, elf::prefixed_symbols};
+
+/// The pattern the stacks are painted with, see [StackConfig::paint_stacks]
+pub const STACK_PAINT: u32 = 0xA5A5_A5A5;
+
+/// The number of free context save areas below which a warning is printed
+pub const DEFAULT_CSA_WARNING_THRESHOLD: u32 = 16;
+
+/// The number of cores the linker symbols are looked up for
+const MAX_CORES: usize = 8;
+
+/// Configures whether the stack usage of each core is reported when the device halts
+#[derive(clap::Args, Debug)]
+pub struct StackConfig {
+    /// Print how much of the stacks and context save areas of each core is used
+    /// when the device halts
+    #[arg(long, default_value_t = false)]
+    pub stack_usage: bool,
+
+    /// Fill the stacks with a pattern before the binary runs, such that the
+    /// maximum stack usage is reported as well. Implies '--stack-usage'
+    #[arg(long, default_value_t = false)]
+    pub paint_stacks: bool,
+
+    /// Warn if fewer context save areas are free on a core when the device halts
+    #[arg(long, value_name = "CONTEXTS", default_value_t = DEFAULT_CSA_WARNING_THRESHOLD)]
+    pub csa_warning_threshold: u32,
+}
+
+impl StackConfig {
+    /// Whether the stack usage is reported
+    pub fn enabled(&self) -> bool {
+        self.stack_usage || self.paint_stacks
+    }
+
+    /// Add memory writes to the reset configuration that paint the stacks of
+    /// all cores, if configured
+    pub fn paint(&self, elf_file: &Path, reset: &mut ResetConfig) -> anyhow::Result<()> {
+        if !self.paint_stacks {
+            return Ok(());
+        }
+
+        for layout in stack_layout(elf_file)? {
+            for stack in layout.stacks {
+                log::debug!(
+                    "Painting {} of core {} at {:#010X}",
+                    stack.name,
+                    layout.core,
+                    stack.start
+                );
+                reset.memory_writes.push(MemoryWrite {
+                    address: stack.start,
+                    data: STACK_PAINT.to_le_bytes().repeat(stack.size() as usize / 4),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the stack usage of all cores from the halted device
+    pub fn measure(
+        &self,
+        command_server: &ChipInterface,
+        elf_file: &Path,
+    ) -> anyhow::Result<Vec<CoreStackUsage>> {
+        let layouts = stack_layout(elf_file)?;
+        let dump = command_server.capture_dump(&self.dump_request(&layouts))?;
+        Ok(stack_usage(&dump, &layouts, self.paint_stacks))
+    }
+
+    /// The memory that is captured to measure the usage, the stacks are only
+    /// captured if they were painted
+    fn dump_request(&self, layouts: &[CoreLayout]) -> DumpRequest {
+        let mut regions = Vec::new();
+        for layout in layouts.iter() {
+            let stacks = layout.stacks.iter().filter(|_| self.paint_stacks);
+            for region in stacks.chain(layout.csa.iter()) {
+                regions.push(DumpRegion {
+                    name: region.name.to_owned(),
+                    core: layout.core,
+                    address: region.start,
+                    length: region.size(),
+                });
+            }
+        }
+
+        DumpRequest {
+            regions,
+            stack_size: 0,
+            rtt_control_block: None,
+        }
+    }
+}
+
+/// A stack or the context save areas of a core
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub name: &'static str,
+    pub start: u32,
+    /// The first address after the region
+    pub end: u32,
+}
+
+impl MemoryRegion {
+    /// The size of the region in bytes
+    pub fn size(&self) -> u32 {
+        self.end - self.start
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+}
+
+/// The stacks and context save areas of a core as defined by the linker script
+#[derive(Debug, Clone)]
+pub struct CoreLayout {
+    pub core: usize,
+    pub stacks: Vec<MemoryRegion>,
+    pub csa: Option<MemoryRegion>,
+}
+
+/// Look up the stacks and context save areas of each core in the elf file
+///
+/// Cores without any of the symbols are left out.
+pub fn stack_layout(elf_file: &Path) -> anyhow::Result<Vec<CoreLayout>> {
+    let symbols = prefixed_symbols(elf_file, "__")?;
+    let region = |name: &'static str, first: String, second: String| {
+        let first = *symbols.get(&first)?;
+        let second = *symbols.get(&second)?;
+        // The stacks grow downwards, so the linker scripts define them from the
+        // end to the start
+        let region = MemoryRegion {
+            name,
+            start: first.min(second),
+            end: first.max(second),
+        };
+        (region.size() > 0).then_some(region)
+    };
+
+    let layouts = (0..MAX_CORES)
+        .map(|core| CoreLayout {
+            core,
+            stacks: [
+                region(
+                    "user stack",
+                    format!("__USTACK{core}_END"),
+                    format!("__USTACK{core}"),
+                ),
+                region(
+                    "interrupt stack",
+                    format!("__ISTACK{core}_END"),
+                    format!("__ISTACK{core}"),
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            csa: region("csa", format!("__CSA{core}"), format!("__CSA{core}_END")),
+        })
+        .filter(|layout| !layout.stacks.is_empty() || layout.csa.is_some())
+        .collect();
+
+    Ok(layouts)
+}
+
+/// How much of the stacks and context save areas of a core is used
+#[derive(Debug, Serialize)]
+pub struct CoreStackUsage {
+    pub core: usize,
+    pub stacks: Vec<StackUsage>,
+    /// [None] if the core does not define its context save areas
+    pub csa: Option<CsaUsage>,
+}
+
+/// How much of a stack is used, all sizes in bytes
+#[derive(Debug, Serialize)]
+pub struct StackUsage {
+    pub name: &'static str,
+    pub size: u32,
+    /// The bytes above the stack pointer, [None] if the stack pointer is not
+    /// within this stack
+    pub current: Option<u32>,
+    /// The most bytes that were ever used, [None] if the stack was not painted
+    pub high_water: Option<u32>,
+}
+
+impl StackUsage {
+    /// Whether the whole stack was used, so it possibly overflowed
+    pub fn exhausted(&self) -> bool {
+        self.high_water == Some(self.size) || self.current == Some(self.size)
+    }
+}
+
+/// How many context save areas of a core are free
+#[derive(Debug, Serialize)]
+pub struct CsaUsage {
+    /// The number of context save areas of the core
+    pub total: u32,
+    /// The number of context save areas that can be used before the free list
+    /// reaches the limit of the LCX register, [None] if the free list could not
+    /// be read
+    pub free: Option<u32>,
+}
+
+/// Obtain the stack usage of each core from the registers and memory in the dump
+///
+/// The high-water marks are only obtained if the stacks were painted.
+pub fn stack_usage(dump: &CoreDump, layouts: &[CoreLayout], painted: bool) -> Vec<CoreStackUsage> {
+    layouts
+        .iter()
+        .filter_map(|layout| {
+            let core = dump.core(layout.core)?;
+            Some(CoreStackUsage {
+                core: layout.core,
+                stacks: layout
+                    .stacks
+                    .iter()
+                    .map(|stack| measure_stack(&core, stack, painted))
+                    .collect(),
+                csa: layout.csa.map(|csa| measure_csa(&core, &csa)),
+            })
+        })
+        .collect()
+}
+
+fn measure_stack(core: &CoreView<'_>, stack: &MemoryRegion, painted: bool) -> StackUsage {
+    let current = core
+        .read_register("A10")
+        .ok()
+        .filter(|stack_pointer| stack.contains(*stack_pointer) || *stack_pointer == stack.end)
+        .map(|stack_pointer| stack.end - stack_pointer);
+
+    // The first word from the bottom of the stack that does not hold the paint
+    // anymore was used at some point
+    let high_water = painted
+        .then(|| core.read_memory(stack.start, stack.size() as usize).ok())
+        .flatten()
+        .map(|data| {
+            let unused = data
+                .chunks_exact(4)
+                .take_while(|word| *word == STACK_PAINT.to_le_bytes())
+                .count() as u32
+                * 4;
+            stack.size() - unused
+        });
+
+    StackUsage {
+        name: stack.name,
+        size: stack.size(),
+        current,
+        high_water,
+    }
+}
+
+fn measure_csa(core: &CoreView<'_>, csa: &MemoryRegion) -> CsaUsage {
+    let total = csa.size() / CSA_SIZE as u32;
+    let free = match count_free_contexts(core, total) {
+        Ok(free) => Some(free),
+        Err(error) => {
+            log::warn!("Cannot read the free context list: {error:#}");
+            None
+        }
+    };
+    CsaUsage { total, free }
+}
+
+/// Follow the free list from FCX until the limit given by LCX
+///
+/// The list is not followed for more than the given number of contexts, which
+/// also guards against cycles in a corrupted list.
+fn count_free_contexts(core: &CoreView<'_>, max_contexts: u32) -> anyhow::Result<u32> {
+    let link_address = |word: u32| {
+        PCXI::from(word)
+            .get_context()
+            .map(|link_word| link_word.get_context_address())
+    };
+    let limit = link_address(core.read_register("LCX")?);
+
+    let mut visited = HashSet::new();
+    let mut free = 0;
+    let mut next = link_address(core.read_register("FCX")?);
+    while let Some(address) = next {
+        if Some(address) == limit || free >= max_contexts || !visited.insert(address) {
+            break;
+        }
+        free += 1;
+
+        let data = core.read_memory(address, 4)?;
+        next = link_address(u32::from_le_bytes(data.as_slice().try_into()?));
+    }
+
+    Ok(free)
+}
+
+impl CoreStackUsage {
+    /// Print the usage, with a warning if a stack may have overflowed or fewer
+    /// than the given number of context save areas are free
+    pub fn log_stdout(&self, csa_warning_threshold: u32) {
+        println!("{}", format!("Core {}", self.core).bold());
+        for stack in self.stacks.iter() {
+            let mut line = format!("  {:<16}", stack.name);
+            if let Some(current) = stack.current {
+                line.push_str(&format!(
+                    " {current} of {} bytes used ({}%)",
+                    stack.size,
+                    percent(current, stack.size)
+                ));
+            } else {
+                line.push_str(&format!(" {} bytes, not in use", stack.size));
+            }
+            if let Some(high_water) = stack.high_water {
+                line.push_str(&format!(
+                    ", at most {high_water} bytes ({}%)",
+                    percent(high_water, stack.size)
+                ));
+            }
+
+            if stack.exhausted() {
+                println!("{}", format!("{line}, possibly overflowed").red());
+            } else {
+                println!("{line}");
+            }
+        }
+
+        let Some(csa) = &self.csa else {
+            return;
+        };
+        match csa.free {
+            Some(free) => {
+                let line = format!("  {:<16} {free} of {} free", "context saves", csa.total);
+                if free < csa_warning_threshold {
+                    println!("{}", format!("{line}, close to exhaustion").yellow());
+                } else {
+                    println!("{line}");
+                }
+            }
+            None => println!("  {:<16} {} in total", "context saves", csa.total),
+        }
+    }
+}
+
+fn percent(used: u32, size: u32) -> u32 {
+    (used as u64 * 100 / size.max(1) as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tricore_common::dump::{CoreImage, MemoryBlock};
+
+    use super::*;
+    use crate::elf::elf_with_symbols;
+
+    const USER_STACK: u32 = 0x7000_0000;
+    const INTERRUPT_STACK: u32 = 0x7000_0200;
+    const CSA: u32 = 0x7000_1000;
+
+    /// A user stack of 256 bytes, an interrupt stack of 128 bytes and 8 context
+    /// save areas on core 0, and only a user stack on core 1
+    fn elf_file() -> tempfile::NamedTempFile {
+        elf_with_symbols(&[
+            ("__USTACK0_END", USER_STACK, 0),
+            ("__USTACK0", USER_STACK + 0x100, 0),
+            ("__ISTACK0_END", INTERRUPT_STACK, 0),
+            ("__ISTACK0", INTERRUPT_STACK + 0x80, 0),
+            ("__CSA0", CSA, 0),
+            ("__CSA0_END", CSA + 8 * CSA_SIZE as u32, 0),
+            ("__USTACK1_END", 0x6000_0000, 0),
+            ("__USTACK1", 0x6000_0040, 0),
+            // An empty stack is left out
+            ("__ISTACK1_END", 0x6000_0100, 0),
+            ("__ISTACK1", 0x6000_0100, 0),
+        ])
+    }
+
+    fn config(paint_stacks: bool) -> StackConfig {
+        StackConfig {
+            stack_usage: true,
+            paint_stacks,
+            csa_warning_threshold: DEFAULT_CSA_WARNING_THRESHOLD,
+        }
+    }
+
+    /// The link word to the context save area with the given index
+    fn link(index: u32) -> u32 {
+        let address = CSA + index * CSA_SIZE as u32;
+        (address >> 28) << 16 | (address & 0x0FFF_FFFF) >> 6
+    }
+
+    #[test]
+    fn stacks_are_looked_up_per_core() {
+        let elf = elf_file();
+        let layouts = stack_layout(elf.path()).unwrap();
+
+        assert_eq!(layouts.len(), 2);
+        let bounds: Vec<_> = layouts[0]
+            .stacks
+            .iter()
+            .map(|stack| (stack.name, stack.start, stack.size()))
+            .collect();
+        assert_eq!(
+            bounds,
+            [
+                ("user stack", USER_STACK, 0x100),
+                ("interrupt stack", INTERRUPT_STACK, 0x80)
+            ]
+        );
+        assert_eq!(layouts[0].csa.unwrap().size(), 8 * CSA_SIZE as u32);
+        assert_eq!((layouts[1].core, layouts[1].stacks.len()), (1, 1));
+        assert!(layouts[1].csa.is_none());
+    }
+
+    #[test]
+    fn stacks_are_painted_if_configured() {
+        let elf = elf_file();
+
+        let mut reset = ResetConfig::default();
+        config(false).paint(elf.path(), &mut reset).unwrap();
+        assert!(reset.memory_writes.is_empty());
+
+        config(true).paint(elf.path(), &mut reset).unwrap();
+        let writes: Vec<_> = reset
+            .memory_writes
+            .iter()
+            .map(|write| (write.address, write.data.len()))
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                (USER_STACK, 0x100),
+                (INTERRUPT_STACK, 0x80),
+                (0x6000_0000, 0x40)
+            ]
+        );
+        assert!(reset.memory_writes[0]
+            .data
+            .chunks_exact(4)
+            .all(|word| word == STACK_PAINT.to_le_bytes()));
+    }
+
+    #[test]
+    fn only_painted_stacks_are_captured() {
+        let elf = elf_file();
+        let layouts = stack_layout(elf.path()).unwrap();
+
+        let names = |request: DumpRequest| -> Vec<_> {
+            request
+                .regions
+                .into_iter()
+                .map(|region| (region.name, region.core))
+                .collect()
+        };
+        assert_eq!(
+            names(config(false).dump_request(&layouts)),
+            [("csa".to_owned(), 0)]
+        );
+        assert_eq!(names(config(true).dump_request(&layouts)).len(), 4);
+    }
+
+    #[test]
+    fn usage_is_measured_from_the_dump() {
+        let elf = elf_file();
+        let layouts = stack_layout(elf.path()).unwrap();
+
+        // The deepest call used 0x40 bytes, the stack pointer is 0x20 bytes deep
+        let mut user_stack = STACK_PAINT.to_le_bytes().repeat(0x100 / 4);
+        user_stack[0xC0..].fill(0);
+        // The free list starts at the fourth context save area and ends before
+        // the seventh, which LCX points to
+        let mut csa = vec![0; 8 * CSA_SIZE];
+        for index in 3..7 {
+            let offset = index as usize * CSA_SIZE;
+            csa[offset..offset + 4].copy_from_slice(&link(index + 1).to_le_bytes());
+        }
+        let dump = CoreDump {
+            version: 0,
+            build_id: None,
+            cores: vec![CoreImage {
+                core: 0,
+                registers: BTreeMap::from([
+                    ("A10".to_owned(), USER_STACK + 0xE0),
+                    ("FCX".to_owned(), link(3)),
+                    ("LCX".to_owned(), link(6)),
+                ]),
+                memory: vec![
+                    MemoryBlock {
+                        name: "user stack".to_owned(),
+                        address: USER_STACK,
+                        data: user_stack,
+                    },
+                    MemoryBlock {
+                        name: "interrupt stack".to_owned(),
+                        address: INTERRUPT_STACK,
+                        data: STACK_PAINT.to_le_bytes().repeat(0x80 / 4),
+                    },
+                    MemoryBlock {
+                        name: "csa".to_owned(),
+                        address: CSA,
+                        data: csa,
+                    },
+                ],
+                missing: Vec::new(),
+            }],
+            rtt: None,
+        };
+
+        // Core 1 is not part of the dump
+        let usage = stack_usage(&dump, &layouts, true);
+        assert_eq!(usage.len(), 1);
+        let stacks: Vec<_> = usage[0]
+            .stacks
+            .iter()
+            .map(|stack| (stack.name, stack.current, stack.high_water))
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                ("user stack", Some(0x20), Some(0x40)),
+                ("interrupt stack", None, Some(0)),
+            ]
+        );
+        let csa = usage[0].csa.as_ref().unwrap();
+        assert_eq!((csa.total, csa.free), (8, Some(3)));
+
+        // Without paint the high-water mark is not known
+        let usage = stack_usage(&dump, &layouts, false);
+        assert_eq!(usage[0].stacks[0].high_water, None);
+    }
+
+    #[test]
+    fn overflowed_stacks_are_exhausted() {
+        let stack = |current, high_water| StackUsage {
+            name: "user stack",
+            size: 0x100,
+            current,
+            high_water,
+        };
+        assert!(!stack(Some(0x20), Some(0xFC)).exhausted());
+        assert!(stack(None, Some(0x100)).exhausted());
+        assert!(stack(Some(0x100), None).exhausted());
+    }
+
+    /// Paints the stacks and measures them through a simulated core whose
+    /// transactions are smaller than the stacks
+    #[cfg(feature = "windows")]
+    #[test]
+    fn painted_high_water_mark_is_measured_on_the_simulated_backend() {
+        use rust_mcd::{simulation::SimulatedMcd, system::System};
+
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        let index = simulation.add_core("core0");
+        simulation.set_payload_size(index, 16);
+        for (offset, register) in ["A10", "FCX", "LCX"].into_iter().enumerate() {
+            simulation.add_register(index, register, 0xF881_0000 + 4 * offset as u64);
+        }
+        let system = System::connect().unwrap();
+        let core = system.get_core(index).unwrap();
+
+        let elf = elf_file();
+        let config = config(true);
+        let mut reset = ResetConfig::default();
+        config.paint(elf.path(), &mut reset).unwrap();
+        tricore_windows::reset::write_memory(&core, &reset).unwrap();
+
+        // The firmware used 0x44 bytes of the user stack and returned
+        simulation.write_memory(index, USER_STACK as u64 + 0xBC, &[0; 0x44]);
+        simulation.set_register(index, "A10", USER_STACK + 0x100);
+
+        let layouts = stack_layout(elf.path()).unwrap();
+        let dump =
+            tricore_windows::dump::capture_dump(&[core], &config.dump_request(&layouts)).unwrap();
+        let usage = stack_usage(&dump, &layouts, true);
+
+        let stacks: Vec<_> = usage[0]
+            .stacks
+            .iter()
+            .map(|stack| (stack.current, stack.high_water))
+            .collect();
+        assert_eq!(stacks, [(Some(0), Some(0x44)), (None, Some(0))]);
+        assert_eq!(usage[0].csa.as_ref().unwrap().free, Some(0));
+    }
+}
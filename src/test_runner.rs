@@ -0,0 +1,309 @@
+//! Derives the exit code of a run from the firmware, see [TestRunnerConfig]
+//!
+//! This allows to use `tricore-probe` as a cargo runner for tests that execute
+//! on the device.
+use std::{fmt::Display, path::Path, process::ExitCode};
+
+use tricore_common::{backtrace::Stacktrace, trap::Trap};
+
+use crate::{backtrace::TrapMetadata, elf::symbol_range};
+
+/// Configures how the result of a test is obtained from the device
+#[derive(clap::Args, Debug)]
+pub struct TestRunnerConfig {
+    /// Derive the exit code from the firmware when the device halts, instead
+    /// of always exiting successfully
+    #[arg(long, default_value_t = false)]
+    pub test_runner: bool,
+
+    /// The function the firmware halts in to exit, e.g. with `asm!("debug")`.
+    /// The exit code is passed in D4
+    #[arg(long, value_name = "SYMBOL", default_value = "__tricore_exit")]
+    pub exit_symbol: String,
+
+    /// Text in the defmt output that marks a successful test run
+    #[arg(long, value_name = "TEXT", default_value = "all tests passed!")]
+    pub pass_marker: String,
+
+    /// Text in the defmt output that marks a failed test run. May be given
+    /// multiple times
+    #[arg(long, value_name = "TEXT", default_values = ["test failed", "panicked at"])]
+    pub fail_marker: Vec<String>,
+}
+
+impl TestRunnerConfig {
+    /// The markers the defmt output must be scanned for
+    pub fn markers(&self) -> Vec<String> {
+        if !self.test_runner {
+            return Vec::new();
+        }
+
+        std::iter::once(&self.pass_marker)
+            .chain(self.fail_marker.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Determine the outcome of a test run that halted with the given stacktrace
+    ///
+    /// The markers are the ones found in the defmt output, see [Self::markers].
+    /// A halt in a trap handler is always a failure, a halt in the exit function
+    /// takes precedence over the markers.
+    pub fn evaluate(
+        &self,
+        stacktrace: &Stacktrace,
+        elf_file: &Path,
+        found_markers: &[String],
+    ) -> TestOutcome {
+        let trap_class = TrapMetadata::new(stacktrace, elf_file).trap_class(stacktrace.current_pc);
+        if let Some(class) = trap_class {
+            return TestOutcome::Trap(Trap {
+                class,
+                tin: stacktrace.current_upper.d15 as u8,
+            });
+        }
+
+        match symbol_range(elf_file, &self.exit_symbol) {
+            Ok(exit_function) if exit_function.contains(&stacktrace.current_pc) => {
+                return TestOutcome::Exited(stacktrace.current_lower.d4);
+            }
+            Ok(_) => {}
+            Err(error) => log::debug!("Exit function is not available: {error:#}"),
+        }
+
+        let failed = found_markers
+            .iter()
+            .find(|marker| self.fail_marker.contains(marker));
+        if let Some(marker) = failed {
+            return TestOutcome::Marker {
+                marker: marker.clone(),
+                passed: false,
+            };
+        }
+
+        if found_markers.contains(&self.pass_marker) {
+            return TestOutcome::Marker {
+                marker: self.pass_marker.clone(),
+                passed: true,
+            };
+        }
+
+        TestOutcome::Unexplained
+    }
+}
+
+/// The result of a test run, see [TestRunnerConfig::evaluate]
+#[derive(Debug)]
+pub enum TestOutcome {
+    /// The firmware halted in the exit function with the given exit code
+    Exited(u32),
+    /// The device halted in the trap handler of the given trap
+    Trap(Trap),
+    /// The defmt output contained the marker
+    Marker { marker: String, passed: bool },
+    /// The device halted without any indication whether the test passed
+    Unexplained,
+}
+
+impl TestOutcome {
+    pub fn passed(&self) -> bool {
+        match self {
+            TestOutcome::Exited(code) => *code == 0,
+            TestOutcome::Marker { passed, .. } => *passed,
+            TestOutcome::Trap(_) | TestOutcome::Unexplained => false,
+        }
+    }
+
+    /// The exit code of the process for this outcome
+    ///
+    /// The exit code of the firmware is truncated to 8 bits, but never turns a
+    /// failure into a success.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            TestOutcome::Exited(0) => ExitCode::SUCCESS,
+            TestOutcome::Exited(code) => match *code as u8 {
+                0 => ExitCode::FAILURE,
+                code => ExitCode::from(code),
+            },
+            outcome if outcome.passed() => ExitCode::SUCCESS,
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+impl Display for TestOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TestOutcome::Exited(code) => write!(f, "Firmware exited with code {code}"),
+            TestOutcome::Trap(trap) => {
+                write!(f, "Test failed, device halted in {trap}")
+            }
+            TestOutcome::Marker {
+                marker,
+                passed: true,
+            } => write!(f, "Test passed, found {marker:?}"),
+            TestOutcome::Marker {
+                marker,
+                passed: false,
+            } => write!(f, "Test failed, found {marker:?}"),
+            TestOutcome::Unexplained => {
+                write!(
+                    f,
+                    "Test failed, device halted without exiting or reporting a result"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tricore_common::{
+        backtrace::{walker::WalkStop, GlobalRegisters},
+        trap::{TrapRegisters, VectorTables},
+    };
+
+    use super::*;
+    use crate::elf::elf_with_symbols;
+
+    const TRAP_TABLE: u32 = 0x8000_0100;
+    const EXIT: u32 = 0x8000_1000;
+    const MAIN: u32 = 0x8000_2000;
+
+    fn config() -> TestRunnerConfig {
+        TestRunnerConfig {
+            test_runner: true,
+            exit_symbol: "__tricore_exit".to_owned(),
+            pass_marker: "all tests passed!".to_owned(),
+            fail_marker: vec!["test failed".to_owned(), "panicked at".to_owned()],
+        }
+    }
+
+    /// A core halted at the program counter with the exit code in D4 and the
+    /// TIN in D15
+    fn halted_at(pc: u32, d4: u32, d15: u32, btv: Option<u32>) -> Stacktrace {
+        let mut stacktrace = Stacktrace {
+            current_pc: pc,
+            current_upper: Default::default(),
+            current_lower: Default::default(),
+            current_globals: GlobalRegisters::default(),
+            stack_frames: Vec::new(),
+            walk_stop: WalkStop::EndOfChain,
+            trap_registers: TrapRegisters::default(),
+            vector_tables: VectorTables { btv, biv: None },
+        };
+        stacktrace.current_lower.d4 = d4;
+        stacktrace.current_upper.d15 = d15;
+        stacktrace
+    }
+
+    fn markers(markers: &[&str]) -> Vec<String> {
+        markers.iter().map(|marker| marker.to_string()).collect()
+    }
+
+    #[test]
+    fn exit_code_is_taken_from_d4_in_the_exit_function() {
+        let elf = elf_with_symbols(&[("__tricore_exit", EXIT, 0x10)]);
+
+        let outcome = config().evaluate(&halted_at(EXIT + 4, 0, 0, None), elf.path(), &[]);
+        assert!(matches!(outcome, TestOutcome::Exited(0)));
+        assert!(outcome.passed());
+
+        let outcome = config().evaluate(&halted_at(EXIT + 0xE, 3, 0, None), elf.path(), &[]);
+        assert!(matches!(outcome, TestOutcome::Exited(3)));
+        assert!(!outcome.passed());
+
+        // The exit function ends before this address
+        let outcome = config().evaluate(&halted_at(EXIT + 0x10, 0, 0, None), elf.path(), &[]);
+        assert!(matches!(outcome, TestOutcome::Unexplained));
+    }
+
+    #[test]
+    fn trap_takes_precedence_over_the_exit_function_and_markers() {
+        // The exit function covers the trap table, e.g. a trap inside it
+        let elf = elf_with_symbols(&[("__tricore_exit", TRAP_TABLE, 0x1000)]);
+        let found = markers(&["all tests passed!"]);
+
+        let stacktrace = halted_at(TRAP_TABLE + 4 * 32 + 8, 0, 2, Some(TRAP_TABLE));
+        let outcome = config().evaluate(&stacktrace, elf.path(), &found);
+        let TestOutcome::Trap(trap) = outcome else {
+            panic!("Expected a trap, got {outcome:?}");
+        };
+        assert_eq!(trap, Trap { class: 4, tin: 2 });
+        assert!(!TestOutcome::Trap(trap).passed());
+    }
+
+    #[test]
+    fn trap_table_is_taken_from_the_elf_file_without_btv() {
+        let elf = elf_with_symbols(&[("first_trap_table", TRAP_TABLE, 0x100)]);
+
+        let stacktrace = halted_at(TRAP_TABLE + 3 * 32, 0, 4, None);
+        let outcome = config().evaluate(&stacktrace, elf.path(), &[]);
+        assert!(matches!(
+            outcome,
+            TestOutcome::Trap(Trap { class: 3, tin: 4 })
+        ));
+
+        // BTV of the core is preferred over the symbol
+        let stacktrace = halted_at(TRAP_TABLE + 3 * 32, 0, 4, Some(0x8000_4000));
+        let outcome = config().evaluate(&stacktrace, elf.path(), &[]);
+        assert!(matches!(outcome, TestOutcome::Unexplained));
+    }
+
+    #[test]
+    fn exit_function_takes_precedence_over_markers() {
+        let elf = elf_with_symbols(&[("__tricore_exit", EXIT, 0x10)]);
+        let found = markers(&["test failed"]);
+
+        let outcome = config().evaluate(&halted_at(EXIT, 0, 0, None), elf.path(), &found);
+        assert!(matches!(outcome, TestOutcome::Exited(0)));
+    }
+
+    #[test]
+    fn fail_markers_take_precedence_over_the_pass_marker() {
+        let elf = elf_with_symbols(&[("__tricore_exit", EXIT, 0x10)]);
+        let stacktrace = halted_at(MAIN, 0, 0, None);
+
+        let found = markers(&["all tests passed!", "panicked at"]);
+        let outcome = config().evaluate(&stacktrace, elf.path(), &found);
+        let TestOutcome::Marker { marker, passed } = &outcome else {
+            panic!("Expected a marker, got {outcome:?}");
+        };
+        assert_eq!((marker.as_str(), *passed), ("panicked at", false));
+        assert!(!outcome.passed());
+
+        let found = markers(&["all tests passed!"]);
+        let outcome = config().evaluate(&stacktrace, elf.path(), &found);
+        assert!(matches!(&outcome, TestOutcome::Marker { passed: true, .. }));
+        assert!(outcome.passed());
+    }
+
+    #[test]
+    fn halt_without_exit_or_markers_is_unexplained() {
+        let stacktrace = halted_at(MAIN, 0, 0, Some(TRAP_TABLE));
+
+        let elf = elf_with_symbols(&[("__tricore_exit", EXIT, 0x10)]);
+        let found = markers(&["unrelated output"]);
+        let outcome = config().evaluate(&stacktrace, elf.path(), &found);
+        assert!(matches!(outcome, TestOutcome::Unexplained));
+        assert!(!outcome.passed());
+
+        // Without the exit function, the markers are still evaluated
+        let elf = elf_with_symbols(&[]);
+        let found = markers(&["all tests passed!"]);
+        let outcome = config().evaluate(&stacktrace, elf.path(), &found);
+        assert!(outcome.passed());
+    }
+
+    #[test]
+    fn markers_are_only_scanned_for_as_test_runner() {
+        let mut config = config();
+        assert_eq!(
+            config.markers(),
+            ["all tests passed!", "test failed", "panicked at"]
+        );
+
+        config.test_runner = false;
+        assert!(config.markers().is_empty());
+    }
+}
@@ -0,0 +1,426 @@
+//! Samples global variables while the binary runs, see [WatchSession]
+//!
+//! The variables are read by the backend in between reading the RTT data, such
+//! that the defmt output continues while the variables are sampled. Each sample
+//! is interpreted with the debug information of the binary, changed values are
+//! printed and all samples can be recorded for plotting.
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use colored::Colorize;
+use tricore_common::watch::{WatchRequest, WatchSample, WatchedRegion};
+
+use crate::inspect::{DebugInfo, Value, Variable};
+
+/// A file the samples are recorded to, given as `<format>=<path>`
+#[derive(Debug, Clone)]
+pub struct RecordTarget {
+    pub format: RecordFormat,
+    pub path: PathBuf,
+}
+
+impl FromStr for RecordTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once('=')
+            .with_context(|| format!("Expected <csv|json>=<path>, got {s:?}"))?;
+
+        let format = match format.trim() {
+            format if format.eq_ignore_ascii_case("csv") => RecordFormat::Csv,
+            format if format.eq_ignore_ascii_case("json") => RecordFormat::Json,
+            format => bail!("Unknown record format {format:?}, expected 'csv' or 'json'"),
+        };
+        if path.is_empty() {
+            bail!("The path of the record must not be empty");
+        }
+
+        Ok(RecordTarget {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// The file format of a record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// One row per sample, one column per scalar value
+    Csv,
+    /// One JSON object per line and sample, with the scalar values by their path
+    Json,
+}
+
+/// Parse a sample interval such as `50ms` or `1s`, plain numbers are milliseconds
+pub fn parse_interval(value: &str) -> anyhow::Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.strip_suffix("ms") {
+        Some(number) => (number, Duration::from_millis(1)),
+        None => match value.strip_suffix('s') {
+            Some(number) => (number, Duration::from_secs(1)),
+            None => (value, Duration::from_millis(1)),
+        },
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid interval {value:?}, expected e.g. '50ms' or '1s'"))?;
+    if !number.is_finite() || number <= 0.0 {
+        bail!("The interval must be positive");
+    }
+    Ok(unit.mul_f64(number))
+}
+
+/// Interprets the samples of the watched variables, prints changed values and
+/// writes the records
+pub struct WatchSession<'a> {
+    debug_info: &'a DebugInfo,
+    variables: &'a [Variable],
+    /// The number of bytes sampled for each variable
+    lengths: Vec<usize>,
+    /// The columns of the records, the paths of the scalar values
+    columns: Vec<String>,
+    records: Vec<(RecordFormat, BufWriter<File>)>,
+    /// The last value printed for each variable
+    shown: Vec<Option<String>>,
+}
+
+impl<'a> WatchSession<'a> {
+    /// Create the record files and write their headers
+    ///
+    /// Fails if the size of a variable is not known.
+    pub fn new(
+        debug_info: &'a DebugInfo,
+        variables: &'a [Variable],
+        records: &[RecordTarget],
+    ) -> anyhow::Result<Self> {
+        // The columns follow from the types, the values do not matter. Fields of
+        // the variants of Rust enums are only recorded for the first variant
+        let mut columns = Vec::new();
+        let mut lengths = Vec::new();
+        for variable in variables.iter() {
+            let length = variable.read_length().with_context(|| {
+                format!(
+                    "Cannot watch {}, the size of its type {} is not known",
+                    variable.name, variable.type_name
+                )
+            })?;
+            let value = debug_info.decode(variable, &vec![0; length])?;
+            columns.extend(value.leaves(&variable.name).into_iter().map(|leaf| leaf.0));
+            lengths.push(length);
+        }
+
+        let records = records
+            .iter()
+            .map(|target| {
+                let file = File::create(&target.path).with_context(|| {
+                    format!("Cannot create record file {}", target.path.display())
+                })?;
+                let mut writer = BufWriter::new(file);
+                if target.format == RecordFormat::Csv {
+                    let header: Vec<_> = std::iter::once("time")
+                        .chain(columns.iter().map(String::as_str))
+                        .map(csv_field)
+                        .collect();
+                    writeln!(writer, "{}", header.join(","))?;
+                }
+                Ok((target.format, writer))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(WatchSession {
+            debug_info,
+            variables,
+            lengths,
+            columns,
+            records,
+            shown: vec![None; variables.len()],
+        })
+    }
+
+    /// The memory the backend samples for the variables
+    pub fn request(&self, interval: Duration) -> WatchRequest {
+        WatchRequest {
+            regions: self
+                .variables
+                .iter()
+                .zip(&self.lengths)
+                .map(|(variable, length)| WatchedRegion {
+                    address: variable.address,
+                    length: *length as u32,
+                })
+                .collect(),
+            interval,
+        }
+    }
+
+    /// Print the variables that changed since the last sample and record all values
+    pub fn sample(&mut self, sample: WatchSample) -> anyhow::Result<()> {
+        let time = sample.elapsed.as_secs_f64();
+        let mut leaves = HashMap::new();
+
+        for (index, variable) in self.variables.iter().enumerate() {
+            let value = match sample.data.get(index).and_then(Option::as_ref) {
+                Some(data) => self
+                    .debug_info
+                    .decode(variable, data)
+                    .unwrap_or_else(|error| Value::Unavailable(format!("{error:#}"))),
+                None => Value::Unavailable("not readable".to_owned()),
+            };
+            if !matches!(value, Value::Unavailable(_)) {
+                leaves.extend(value.leaves(&variable.name));
+            }
+
+            let text = value.to_string();
+            if self.shown[index].as_ref() != Some(&text) {
+                let timestamp = format!("[{time:>10.3}s]");
+                println!("{} {} = {text}", timestamp.dimmed(), variable.name.bold());
+                self.shown[index] = Some(text);
+            }
+        }
+
+        for (format, writer) in self.records.iter_mut() {
+            match format {
+                RecordFormat::Csv => {
+                    let row: Vec<_> = std::iter::once(time.to_string())
+                        .chain(self.columns.iter().map(|column| {
+                            leaves
+                                .get(column)
+                                .map(|value| csv_field(value))
+                                .unwrap_or_default()
+                        }))
+                        .collect();
+                    writeln!(writer, "{}", row.join(","))?;
+                }
+                RecordFormat::Json => {
+                    let values: serde_json::Map<_, _> = self
+                        .columns
+                        .iter()
+                        .filter_map(|column| {
+                            let value = leaves.get(column)?;
+                            Some((column.clone(), json_value(value)))
+                        })
+                        .collect();
+                    let line = serde_json::json!({ "time": time, "values": values });
+                    writeln!(writer, "{line}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the record files
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        for (_, writer) in self.records.iter_mut() {
+            writer.flush().context("Cannot write record file")?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote the field if it contains characters that are special in CSV
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Numbers and booleans are recorded as such, everything else as a string
+fn json_value(value: &str) -> serde_json::Value {
+    if let Ok(boolean) = value.parse::<bool>() {
+        return boolean.into();
+    }
+    match value.parse::<i64>() {
+        Ok(number) => number.into(),
+        Err(_) => match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => number.into(),
+            _ => value.into(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gimli::constants;
+
+    use super::*;
+    use crate::inspect::tests::{name, Builder};
+
+    #[test]
+    fn intervals_are_parsed() {
+        let cases = [
+            ("50ms", Duration::from_millis(50)),
+            ("1s", Duration::from_secs(1)),
+            ("0.5s", Duration::from_millis(500)),
+            ("250", Duration::from_millis(250)),
+            (" 20 ms ", Duration::from_millis(20)),
+            ("1.5ms", Duration::from_micros(1500)),
+        ];
+        for (value, interval) in cases {
+            assert_eq!(parse_interval(value).unwrap(), interval, "{value:?}");
+        }
+
+        for value in ["", "ms", "0", "-1s", "fast", "1min", "inf", "NaN"] {
+            assert!(parse_interval(value).is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn record_targets_are_parsed() {
+        let target: RecordTarget = "csv=out/samples.csv".parse().unwrap();
+        assert_eq!(target.format, RecordFormat::Csv);
+        assert_eq!(target.path, PathBuf::from("out/samples.csv"));
+
+        // Only the first '=' separates the format
+        let target: RecordTarget = " JSON =a=b.json".parse().unwrap();
+        assert_eq!(target.format, RecordFormat::Json);
+        assert_eq!(target.path, PathBuf::from("a=b.json"));
+
+        for value in ["samples.csv", "xml=samples.xml", "csv=", "=samples.csv"] {
+            assert!(value.parse::<RecordTarget>().is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        let cases = [
+            ("1.5", "1.5"),
+            ("state.values[2]", "state.values[2]"),
+            ("a,b", "\"a,b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("two\nlines", "\"two\nlines\""),
+            ("", ""),
+        ];
+        for (value, field) in cases {
+            assert_eq!(csv_field(value), field, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn json_values_keep_numbers_and_booleans() {
+        let cases = [
+            ("42", serde_json::json!(42)),
+            ("-7", serde_json::json!(-7)),
+            ("1.5", serde_json::json!(1.5)),
+            ("true", serde_json::json!(true)),
+            ("false", serde_json::json!(false)),
+            ("inf", serde_json::json!("inf")),
+            ("NaN", serde_json::json!("NaN")),
+            ("65 'A'", serde_json::json!("65 'A'")),
+            ("0x70000000", serde_json::json!("0x70000000")),
+        ];
+        for (value, json) in cases {
+            assert_eq!(json_value(value), json, "{value:?}");
+        }
+    }
+
+    /// A counter and a struct with two fields
+    fn debug_info() -> DebugInfo {
+        let mut builder = Builder::new();
+        let root = builder.root();
+        let u32_type = builder.base("u32", constants::DW_ATE_unsigned, 4);
+        let bool_type = builder.base("bool", constants::DW_ATE_boolean, 1);
+        let state = builder.structure("State", 8);
+        builder.member(state, "ready", bool_type, 0);
+        builder.member(state, "count", u32_type, 4);
+        let opaque = builder.add(root, constants::DW_TAG_structure_type, vec![name("Opaque")]);
+
+        builder.variable(root, "COUNTER", u32_type, 0x7000_0000);
+        builder.variable(root, "STATE", state, 0x7000_0010);
+        builder.variable(root, "HANDLE", opaque, 0x7000_0020);
+        builder.finish()
+    }
+
+    #[test]
+    fn samples_are_recorded() {
+        let debug_info = debug_info();
+        let variables = [
+            debug_info.variable("COUNTER").unwrap(),
+            debug_info.variable("STATE").unwrap(),
+        ];
+        let directory = tempfile::tempdir().unwrap();
+        let csv = directory.path().join("samples.csv");
+        let json = directory.path().join("samples.json");
+        let records = [
+            RecordTarget {
+                format: RecordFormat::Csv,
+                path: csv.clone(),
+            },
+            RecordTarget {
+                format: RecordFormat::Json,
+                path: json.clone(),
+            },
+        ];
+
+        let mut session = WatchSession::new(&debug_info, &variables, &records).unwrap();
+        let request = session.request(Duration::from_millis(10));
+        assert_eq!(request.interval, Duration::from_millis(10));
+        let regions: Vec<_> = request
+            .regions
+            .iter()
+            .map(|region| (region.address, region.length))
+            .collect();
+        assert_eq!(regions, [(0x7000_0000, 4), (0x7000_0010, 8)]);
+
+        session
+            .sample(WatchSample {
+                elapsed: Duration::from_millis(500),
+                data: vec![Some(vec![7, 0, 0, 0]), Some(vec![1, 0, 0, 0, 3, 0, 0, 0])],
+            })
+            .unwrap();
+        // Values that cannot be read are left empty
+        session
+            .sample(WatchSample {
+                elapsed: Duration::from_secs(1),
+                data: vec![Some(vec![8, 0, 0, 0]), None],
+            })
+            .unwrap();
+        session.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(csv).unwrap(),
+            "time,COUNTER,STATE.ready,STATE.count\n0.5,7,true,3\n1,8,,\n"
+        );
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(json)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({
+                    "time": 0.5,
+                    "values": { "COUNTER": 7, "STATE.ready": true, "STATE.count": 3 }
+                }),
+                serde_json::json!({ "time": 1.0, "values": { "COUNTER": 8 } }),
+            ]
+        );
+    }
+
+    #[test]
+    fn variables_of_unknown_size_are_rejected() {
+        let debug_info = debug_info();
+        let variables = [
+            debug_info.variable("COUNTER").unwrap(),
+            debug_info.variable("HANDLE").unwrap(),
+        ];
+        assert_eq!(variables[1].size, None);
+
+        let error = WatchSession::new(&debug_info, &variables, &[])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("HANDLE"), "{error}");
+    }
+}
@@ -0,0 +1,30 @@
+//! This module defines how the state of a core is accessed, independent of the
+//! backend that provides it, see [MemoryAccess] and [RegisterAccess]
+
+/// Read access to the memory of a core
+pub trait MemoryAccess {
+    /// Read the given number of bytes starting at the address
+    fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Read access to the registers of a core
+///
+/// Registers are identified by their names in the architecture manual, e.g.
+/// `PCXI` or `D15`.
+pub trait RegisterAccess {
+    /// Read the value of the register
+    fn read_register(&self, name: &str) -> anyhow::Result<u32>;
+
+    /// Read a register that is not available on all cores or backends
+    ///
+    /// Returns [None] if the register cannot be read.
+    fn read_optional_register(&self, name: &str) -> Option<u32> {
+        match self.read_register(name) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                log::debug!("Cannot read {name} register: {error:#}");
+                None
+            }
+        }
+    }
+}
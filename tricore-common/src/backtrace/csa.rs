@@ -1,7 +1,13 @@
 //! This module defines structure for CSA's.
 //!
 //! See also https://www.infineon.com/dgdl/tc1_6__architecture_vol1.pdf?fileId=db3a3043372d5cc801373b0f374d5d67#G8.6699641
+use anyhow::{bail, Context};
+
 use super::pcxi::PCXI;
+use crate::access::MemoryAccess;
+
+/// The size of a context save area in bytes
+pub const CSA_SIZE: usize = 64;
 
 /// A link word that points to a CSA
 ///
@@ -18,10 +24,35 @@ impl ContextLinkWord {
     pub fn get_context_address(&self) -> u32 {
         ((self.segment_address as u32) << 28) + ((self.context_offset as u32) << 6)
     }
+
+    /// Load the context this link word points to from memory
+    pub fn load(&self, memory: &impl MemoryAccess) -> anyhow::Result<SavedContext> {
+        let address = self.get_context_address();
+        let bytes = memory
+            .read_memory(address, CSA_SIZE)
+            .with_context(|| format!("Cannot read saved context at {address:#010X}"))?;
+        if bytes.len() != CSA_SIZE {
+            bail!(
+                "Read {} bytes of the saved context at {address:#010X}, expected {CSA_SIZE}",
+                bytes.len()
+            );
+        }
+
+        let mut words = [0; 16];
+        for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        if self.is_upper {
+            Ok(SavedContext::Upper(UpperContext::from_words(words)))
+        } else {
+            Ok(SavedContext::Lower(LowerContext::from_words(words)))
+        }
+    }
 }
 
 /// A saved context
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum SavedContext {
     Upper(UpperContext),
@@ -73,24 +104,76 @@ pub struct UpperContext {
     pub d15: u32,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 #[repr(C)]
 pub struct LowerContext {
     pub pcxi: PCXI,
-    a11: u32,
-    a2: u32,
-    a3: u32,
-    d0: u32,
-    d1: u32,
-    d2: u32,
-    d3: u32,
-    a4: u32,
-    a5: u32,
-    a6: u32,
-    a7: u32,
-    d4: u32,
-    d5: u32,
-    d6: u32,
-    d7: u32,
+    pub a11: u32,
+    pub a2: u32,
+    pub a3: u32,
+    pub d0: u32,
+    pub d1: u32,
+    pub d2: u32,
+    pub d3: u32,
+    pub a4: u32,
+    pub a5: u32,
+    pub a6: u32,
+    pub a7: u32,
+    pub d4: u32,
+    pub d5: u32,
+    pub d6: u32,
+    pub d7: u32,
+}
+
+impl UpperContext {
+    /// Create the context from the words of a context save area, in the order
+    /// they are stored in memory
+    pub fn from_words(words: [u32; 16]) -> Self {
+        let [pcxi, psw, a10, a11, d8, d9, d10, d11, a12, a13, a14, a15, d12, d13, d14, d15] = words;
+        UpperContext {
+            pcxi: pcxi.into(),
+            psw,
+            a10,
+            a11,
+            d8,
+            d9,
+            d10,
+            d11,
+            a12,
+            a13,
+            a14,
+            a15,
+            d12,
+            d13,
+            d14,
+            d15,
+        }
+    }
+}
+
+impl LowerContext {
+    /// Create the context from the words of a context save area, in the order
+    /// they are stored in memory
+    pub fn from_words(words: [u32; 16]) -> Self {
+        let [pcxi, a11, a2, a3, d0, d1, d2, d3, a4, a5, a6, a7, d4, d5, d6, d7] = words;
+        LowerContext {
+            pcxi: pcxi.into(),
+            a11,
+            a2,
+            a3,
+            d0,
+            d1,
+            d2,
+            d3,
+            a4,
+            a5,
+            a6,
+            a7,
+            d4,
+            d5,
+            d6,
+            d7,
+        }
+    }
 }
@@ -1,9 +1,19 @@
 //! This module defines a stacktrace for the tricore architecture that is obtained
 //! by traversing the CSA link chain.
-use self::csa::{SavedContext, UpperContext};
+use self::{
+    csa::{LowerContext, UpperContext},
+    pcxi::PCXI,
+    walker::{walk, ChainStart, CsaRegion, SavedFrame, WalkConfig, WalkStop},
+};
+use crate::{
+    access::{MemoryAccess, RegisterAccess},
+    trap::{TrapRegisters, VectorTables},
+};
 
 pub mod csa;
 pub mod pcxi;
+pub mod psw;
+pub mod walker;
 
 /// A stacktrace, consisting of the saved contexts in the CSA link chain
 #[derive(Debug)]
@@ -11,5 +21,138 @@ pub mod pcxi;
 pub struct Stacktrace {
     pub current_pc: u32,
     pub current_upper: UpperContext,
-    pub stack_frames: Vec<SavedContext>,
+    /// The lower context registers of the core, e.g. to obtain function arguments
+    pub current_lower: LowerContext,
+    /// The global address registers of the core, which are not part of any context
+    pub current_globals: GlobalRegisters,
+    pub stack_frames: Vec<SavedFrame>,
+    /// Why the walk of the CSA link chain stopped after the last saved context
+    pub walk_stop: WalkStop,
+    /// The trap status registers of the core, to decode the cause of a trap
+    pub trap_registers: TrapRegisters,
+    /// The vector tables of the core, to identify trap and interrupt handlers
+    pub vector_tables: VectorTables,
+}
+
+impl Stacktrace {
+    /// Capture the stacktrace of a halted core
+    ///
+    /// The registers of the core are read first, then the CSA link chain is
+    /// walked starting with the PCXI register, see [walk]. The walk stops at the
+    /// first link outside of the given CSA regions, unless no regions are given.
+    pub fn capture<C: MemoryAccess + RegisterAccess>(
+        core: &C,
+        csa_regions: &[CsaRegion],
+    ) -> anyhow::Result<Self> {
+        let register = |name: &str| core.read_register(name);
+
+        let current_upper = UpperContext {
+            pcxi: register("PCXI")?.into(),
+            psw: register("PSW")?,
+            a10: register("A10")?,
+            a11: register("A11")?,
+            d8: register("D8")?,
+            d9: register("D9")?,
+            d10: register("D10")?,
+            d11: register("D11")?,
+            a12: register("A12")?,
+            a13: register("A13")?,
+            a14: register("A14")?,
+            a15: register("A15")?,
+            d12: register("D12")?,
+            d13: register("D13")?,
+            d14: register("D14")?,
+            d15: register("D15")?,
+        };
+
+        let current_lower = LowerContext {
+            pcxi: current_upper.pcxi,
+            a11: current_upper.a11,
+            a2: register("A2")?,
+            a3: register("A3")?,
+            d0: register("D0")?,
+            d1: register("D1")?,
+            d2: register("D2")?,
+            d3: register("D3")?,
+            a4: register("A4")?,
+            a5: register("A5")?,
+            a6: register("A6")?,
+            a7: register("A7")?,
+            d4: register("D4")?,
+            d5: register("D5")?,
+            d6: register("D6")?,
+            d7: register("D7")?,
+        };
+
+        let current_globals = GlobalRegisters {
+            a0: register("A0")?,
+            a1: register("A1")?,
+            a8: register("A8")?,
+            a9: register("A9")?,
+        };
+
+        // The trap status registers and vector table pointers are not available
+        // on all cores, they only provide additional details
+        let optional_register = |name: &str| core.read_optional_register(name);
+
+        let trap_registers = TrapRegisters {
+            pstr: optional_register("PSTR"),
+            dstr: optional_register("DSTR"),
+            datr: optional_register("DATR"),
+            deadd: optional_register("DEADD"),
+            pietr: optional_register("PIETR"),
+            piear: optional_register("PIEAR"),
+            dietr: optional_register("DIETR"),
+            diear: optional_register("DIEAR"),
+        };
+
+        let vector_tables = VectorTables {
+            btv: optional_register("BTV"),
+            biv: optional_register("BIV"),
+        };
+
+        let current_pc = register("PC")?;
+
+        // The first free context must not be part of the chain
+        let free_context = optional_register("FCX")
+            .and_then(|fcx| PCXI::from(fcx).get_context())
+            .map(|link_word| link_word.get_context_address());
+        let config = WalkConfig {
+            free_context,
+            csa_regions: csa_regions.to_vec(),
+            ..WalkConfig::default()
+        };
+        let start = ChainStart {
+            pc: current_pc,
+            upper: &current_upper,
+            // ICR.CCPN
+            current_priority: optional_register("ICR").map(|icr| icr as u8),
+            vector_tables: &vector_tables,
+        };
+        let (stack_frames, walk_stop) = walk(&start, &config, |link_word| link_word.load(core));
+        if !walk_stop.is_complete() {
+            log::warn!("Backtrace is incomplete, the walk stopped: {walk_stop}");
+        }
+
+        Ok(Stacktrace {
+            stack_frames,
+            walk_stop,
+            current_pc,
+            current_upper,
+            current_lower,
+            current_globals,
+            trap_registers,
+            vector_tables,
+        })
+    }
+}
+
+/// The global address registers, which are shared by all contexts
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct GlobalRegisters {
+    pub a0: u32,
+    pub a1: u32,
+    pub a8: u32,
+    pub a9: u32,
 }
@@ -11,10 +11,12 @@ pub struct PCXI {
     #[bits(4)]
     pub previous_segment_address: u8,
     pub is_upper: bool,
+    /// The interrupt enable bit of the previous context
+    pub previous_interrupt_enable: bool,
+    /// The interrupt priority of the previous context
+    pub previous_priority: u8,
     #[bits(2)]
     _reserved: u8,
-    #[bits(9)]
-    _not_implemented: u16,
 }
 
 impl PCXI {
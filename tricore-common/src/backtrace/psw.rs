@@ -0,0 +1,33 @@
+use bitfield_struct::bitfield;
+
+/// The PSW register of the tricore architecture
+///
+/// See https://www.infineon.com/dgdl/tc1_6__architecture_vol1.pdf?fileId=db3a3043372d5cc801373b0f374d5d67#G8.6700025
+#[bitfield(u32)]
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PSW {
+    /// The call depth counter, its width depends on the leading bits
+    #[bits(7)]
+    pub call_depth_counter: u8,
+    pub call_depth_count_enable: bool,
+    /// Whether the global address registers may be written
+    pub global_write_permission: bool,
+    /// Whether the interrupt stack is in use
+    pub interrupt_stack: bool,
+    /// The I/O privilege level: user-0, user-1 or supervisor
+    #[bits(2)]
+    pub io_privilege: u8,
+    /// The active protection register set
+    #[bits(2)]
+    pub protection_register_set: u8,
+    /// Whether the safe task identifier is set
+    pub safe_task: bool,
+    #[bits(12)]
+    _reserved: u16,
+    pub sticky_advance_overflow: bool,
+    pub advance_overflow: bool,
+    pub sticky_overflow: bool,
+    pub overflow: bool,
+    pub carry: bool,
+}
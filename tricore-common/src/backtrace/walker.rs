@@ -0,0 +1,831 @@
+//! This module walks the CSA link chain of a core, see [walk]
+//!
+//! The chain is followed starting with the PCXI register of the core. Each saved
+//! context is classified by how it was saved, see [FrameKind]. The walk stops at
+//! the end of the chain, but also when a link word is invalid, the chain contains
+//! a cycle or the depth limit is reached, see [WalkStop].
+use std::{collections::HashSet, fmt::Display};
+
+use super::{
+    csa::{ContextLinkWord, SavedContext, UpperContext, CSA_SIZE},
+    pcxi::PCXI,
+    psw::PSW,
+};
+use crate::trap::VectorTables;
+
+/// The default number of contexts after which the walk is stopped
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+/// A memory region that holds context save areas
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CsaRegion {
+    pub start: u32,
+    /// The first address after the region
+    pub end: u32,
+}
+
+impl CsaRegion {
+    /// Whether a complete context save area at the address lies within the region
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.start && address.saturating_add(CSA_SIZE as u32) <= self.end
+    }
+}
+
+/// Restricts which links of the chain are followed
+#[derive(Debug, Clone)]
+pub struct WalkConfig {
+    /// The walk stops after this number of contexts
+    pub max_depth: usize,
+    /// The regions that hold the context save areas, links outside of them are
+    /// invalid. If empty, any address is accepted
+    pub csa_regions: Vec<CsaRegion>,
+    /// The address of the first free context, taken from the FCX register. A link
+    /// to this context is invalid, since it is not in use
+    pub free_context: Option<u32>,
+}
+
+impl Default for WalkConfig {
+    fn default() -> Self {
+        WalkConfig {
+            max_depth: DEFAULT_MAX_DEPTH,
+            csa_regions: Vec::new(),
+            free_context: None,
+        }
+    }
+}
+
+/// The state of the core the walk starts from
+pub struct ChainStart<'a> {
+    pub pc: u32,
+    pub upper: &'a UpperContext,
+    /// The current CPU priority number, taken from ICR.CCPN
+    pub current_priority: Option<u8>,
+    pub vector_tables: &'a VectorTables,
+}
+
+/// How a context in the CSA link chain was saved
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum FrameKind {
+    /// A function call saved the upper context of the caller
+    Call,
+    /// An interrupt of the given priority saved the upper context of the
+    /// interrupted code
+    Interrupt { priority: u8 },
+    /// A trap saved the upper context of the trapped code
+    Trap,
+    /// The lower context was saved explicitly, e.g. by `svlcx` or `bisr`. It
+    /// does not start a new frame
+    Lower,
+}
+
+/// A context of the CSA link chain
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SavedFrame {
+    /// The address of the context save area
+    pub address: u32,
+    pub kind: FrameKind,
+    pub context: SavedContext,
+}
+
+/// Why the walk of the CSA link chain stopped
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum WalkStop {
+    /// The last context does not link to another context
+    EndOfChain,
+    /// The link points to a context that is already part of the chain
+    Cycle { address: u32 },
+    /// The chain is longer than [WalkConfig::max_depth]
+    DepthLimit { max_depth: usize },
+    /// The link points outside of the regions that hold context save areas
+    OutsideCsaRegion { address: u32 },
+    /// The link points to the first free context
+    FreeContext { address: u32 },
+    /// The context could not be read
+    ReadError { address: u32, message: String },
+}
+
+impl WalkStop {
+    /// Whether the walk reached the end of the chain, i.e. the backtrace is complete
+    pub fn is_complete(&self) -> bool {
+        *self == WalkStop::EndOfChain
+    }
+}
+
+impl Display for WalkStop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalkStop::EndOfChain => write!(f, "end of the CSA link chain"),
+            WalkStop::Cycle { address } => {
+                write!(f, "the CSA link chain loops back to {address:#010X}")
+            }
+            WalkStop::DepthLimit { max_depth } => {
+                write!(f, "more than {max_depth} saved contexts")
+            }
+            WalkStop::OutsideCsaRegion { address } => write!(
+                f,
+                "link to {address:#010X} is outside of the context save areas"
+            ),
+            WalkStop::FreeContext { address } => {
+                write!(f, "link to {address:#010X} points to a free context")
+            }
+            WalkStop::ReadError { address, message } => {
+                write!(f, "cannot read the context at {address:#010X}: {message}")
+            }
+        }
+    }
+}
+
+/// Check whether a link to the context at the address may be followed
+pub fn check_link(address: u32, config: &WalkConfig) -> Result<(), WalkStop> {
+    if config.free_context == Some(address) {
+        return Err(WalkStop::FreeContext { address });
+    }
+
+    let in_region = config.csa_regions.is_empty()
+        || config
+            .csa_regions
+            .iter()
+            .any(|region| region.contains(address));
+    if !in_region {
+        return Err(WalkStop::OutsideCsaRegion { address });
+    }
+
+    Ok(())
+}
+
+/// Walk the CSA link chain, loading each context with the given function
+///
+/// Returns the saved contexts, starting with the most recently saved one, and
+/// why the walk stopped.
+pub fn walk<F>(start: &ChainStart, config: &WalkConfig, mut load: F) -> (Vec<SavedFrame>, WalkStop)
+where
+    F: FnMut(&ContextLinkWord) -> anyhow::Result<SavedContext>,
+{
+    let mut frames = Vec::new();
+    let mut visited = HashSet::new();
+
+    let mut link: PCXI = start.upper.pcxi;
+    // The state of the code that saved the next context
+    let mut priority = start.current_priority;
+    let mut psw = PSW::from(start.upper.psw);
+    let mut location = start.pc;
+    let mut return_address = start.upper.a11;
+
+    let stop = loop {
+        let Some(link_word) = link.get_context() else {
+            break WalkStop::EndOfChain;
+        };
+        let address = link_word.get_context_address();
+
+        if frames.len() >= config.max_depth {
+            break WalkStop::DepthLimit {
+                max_depth: config.max_depth,
+            };
+        }
+        if !visited.insert(address) {
+            break WalkStop::Cycle { address };
+        }
+        if let Err(stop) = check_link(address, config) {
+            break stop;
+        }
+
+        let context = match load(&link_word) {
+            Ok(context) => context,
+            Err(error) => {
+                break WalkStop::ReadError {
+                    address,
+                    message: format!("{error:#}"),
+                }
+            }
+        };
+
+        // The link records the priority of the code that was running before the
+        // context was saved
+        let previous_priority = link.previous_priority();
+        let kind = match &context {
+            SavedContext::Lower(_) => FrameKind::Lower,
+            SavedContext::Upper(upper) => {
+                // Interrupts raise the priority, while traps and calls keep it. Only
+                // interrupts and traps switch to the interrupt stack
+                let entered_interrupt_stack =
+                    psw.interrupt_stack() && !PSW::from(upper.psw).interrupt_stack();
+                match priority {
+                    Some(priority) if priority > previous_priority => {
+                        FrameKind::Interrupt { priority }
+                    }
+                    _ if entered_interrupt_stack
+                        || start.vector_tables.trap_class(location).is_some() =>
+                    {
+                        FrameKind::Trap
+                    }
+                    _ => FrameKind::Call,
+                }
+            }
+        };
+
+        if let SavedContext::Upper(upper) = &context {
+            psw = PSW::from(upper.psw);
+            location = return_address;
+            return_address = upper.a11;
+        }
+        priority = Some(previous_priority);
+        link = context.pcxi();
+
+        frames.push(SavedFrame {
+            address,
+            kind,
+            context,
+        });
+    };
+
+    (frames, stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{access::MemoryAccess, backtrace::csa::LowerContext};
+
+    /// The first context save area of the fixtures
+    const CSA_BASE: u32 = 0x7000_0000;
+
+    /// The address of the context save area with the given index
+    fn csa(index: u32) -> u32 {
+        CSA_BASE + index * CSA_SIZE as u32
+    }
+
+    /// A link to the context at the address, saved by code running at the priority
+    fn link(address: u32, is_upper: bool, previous_priority: u8) -> PCXI {
+        PCXI::new()
+            .with_previous_segment_address((address >> 28) as u8)
+            .with_previous_context_pointer(((address & 0x0FFF_FFFF) >> 6) as u16)
+            .with_is_upper(is_upper)
+            .with_previous_priority(previous_priority)
+    }
+
+    /// An upper context with the given link, PSW and return address
+    fn upper(pcxi: PCXI, psw: u32, a11: u32) -> UpperContext {
+        UpperContext {
+            pcxi,
+            psw,
+            a11,
+            ..UpperContext::default()
+        }
+    }
+
+    /// The context save areas of a core, stored as words like in memory
+    #[derive(Default)]
+    struct Memory {
+        contexts: HashMap<u32, [u32; 16]>,
+    }
+
+    impl Memory {
+        fn save_upper(&mut self, address: u32, context: UpperContext) {
+            let UpperContext {
+                pcxi,
+                psw,
+                a10,
+                a11,
+                d8,
+                d9,
+                d10,
+                d11,
+                a12,
+                a13,
+                a14,
+                a15,
+                d12,
+                d13,
+                d14,
+                d15,
+            } = context;
+            let words = [
+                pcxi.into(),
+                psw,
+                a10,
+                a11,
+                d8,
+                d9,
+                d10,
+                d11,
+                a12,
+                a13,
+                a14,
+                a15,
+                d12,
+                d13,
+                d14,
+                d15,
+            ];
+            self.contexts.insert(address, words);
+        }
+
+        fn save_lower(&mut self, address: u32, context: LowerContext) {
+            let LowerContext {
+                pcxi,
+                a11,
+                a2,
+                a3,
+                d0,
+                d1,
+                d2,
+                d3,
+                a4,
+                a5,
+                a6,
+                a7,
+                d4,
+                d5,
+                d6,
+                d7,
+            } = context;
+            let words = [
+                pcxi.into(),
+                a11,
+                a2,
+                a3,
+                d0,
+                d1,
+                d2,
+                d3,
+                a4,
+                a5,
+                a6,
+                a7,
+                d4,
+                d5,
+                d6,
+                d7,
+            ];
+            self.contexts.insert(address, words);
+        }
+
+        /// Walk the chain starting with the given upper context of the core
+        fn walk(
+            &self,
+            pc: u32,
+            current: &UpperContext,
+            current_priority: Option<u8>,
+            vector_tables: &VectorTables,
+            config: &WalkConfig,
+        ) -> (Vec<SavedFrame>, WalkStop) {
+            let start = ChainStart {
+                pc,
+                upper: current,
+                current_priority,
+                vector_tables,
+            };
+            walk(&start, config, |link_word| link_word.load(self))
+        }
+
+        /// Walk the chain of a core that runs at priority 0 without vector tables
+        fn walk_task(
+            &self,
+            current: &UpperContext,
+            config: &WalkConfig,
+        ) -> (Vec<SavedFrame>, WalkStop) {
+            self.walk(
+                0x8000_0000,
+                current,
+                Some(0),
+                &VectorTables::default(),
+                config,
+            )
+        }
+    }
+
+    impl MemoryAccess for Memory {
+        fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+            let words = self
+                .contexts
+                .get(&address)
+                .ok_or_else(|| anyhow::anyhow!("no context at {address:#X}"))?;
+            let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+            Ok(bytes[..length].to_vec())
+        }
+    }
+
+    /// A chain of calls with the given number of contexts, the last one ends it
+    fn call_chain(length: u32) -> (Memory, UpperContext) {
+        let mut memory = Memory::default();
+        for index in 0..length {
+            let next = if index + 1 < length {
+                link(csa(index + 1), true, 0)
+            } else {
+                PCXI::new()
+            };
+            memory.save_upper(csa(index), upper(next, 0, 0x8000_1000 + index));
+        }
+        (memory, upper(link(csa(0), true, 0), 0, 0x8000_0FFC))
+    }
+
+    fn kinds(frames: &[SavedFrame]) -> Vec<FrameKind> {
+        frames.iter().map(|frame| frame.kind).collect()
+    }
+
+    #[test]
+    fn walk_ends_at_the_end_of_the_chain() {
+        let (memory, current) = call_chain(3);
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(stop, WalkStop::EndOfChain);
+        assert!(stop.is_complete());
+        let addresses: Vec<u32> = frames.iter().map(|frame| frame.address).collect();
+        assert_eq!(addresses, [csa(0), csa(1), csa(2)]);
+        assert_eq!(kinds(&frames), [FrameKind::Call; 3]);
+    }
+
+    #[test]
+    fn core_without_saved_contexts_has_no_frames() {
+        let (memory, current) = (Memory::default(), upper(PCXI::new(), 0, 0));
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert!(frames.is_empty());
+        assert_eq!(stop, WalkStop::EndOfChain);
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let mut memory = Memory::default();
+        memory.save_upper(csa(0), upper(link(csa(1), true, 0), 0, 0));
+        memory.save_upper(csa(1), upper(link(csa(2), true, 0), 0, 0));
+        memory.save_upper(csa(2), upper(link(csa(1), true, 0), 0, 0));
+        let current = upper(link(csa(0), true, 0), 0, 0);
+
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(stop, WalkStop::Cycle { address: csa(1) });
+        assert!(!stop.is_complete());
+    }
+
+    #[test]
+    fn context_linking_to_itself_is_a_cycle() {
+        let mut memory = Memory::default();
+        memory.save_upper(csa(0), upper(link(csa(0), true, 0), 0, 0));
+        let current = upper(link(csa(0), true, 0), 0, 0);
+
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(stop, WalkStop::Cycle { address: csa(0) });
+    }
+
+    #[test]
+    fn walk_is_limited_to_the_default_depth() {
+        let (memory, current) = call_chain(DEFAULT_MAX_DEPTH as u32 + 10);
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(frames.len(), 1024);
+        assert_eq!(stop, WalkStop::DepthLimit { max_depth: 1024 });
+    }
+
+    #[test]
+    fn chain_of_exactly_the_depth_limit_is_complete() {
+        let (memory, current) = call_chain(DEFAULT_MAX_DEPTH as u32);
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(frames.len(), DEFAULT_MAX_DEPTH);
+        assert_eq!(stop, WalkStop::EndOfChain);
+    }
+
+    #[test]
+    fn walk_stops_at_the_free_context() {
+        let (memory, current) = call_chain(4);
+        let config = WalkConfig {
+            free_context: Some(csa(2)),
+            ..WalkConfig::default()
+        };
+
+        let (frames, stop) = memory.walk_task(&current, &config);
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(stop, WalkStop::FreeContext { address: csa(2) });
+    }
+
+    #[test]
+    fn walk_stops_outside_of_the_csa_regions() {
+        let (mut memory, current) = call_chain(2);
+        // The second context is corrupted and links to the stack
+        memory.save_upper(csa(1), upper(link(0x7001_0000, true, 0), 0, 0));
+        let config = WalkConfig {
+            csa_regions: vec![CsaRegion {
+                start: csa(0),
+                end: csa(16),
+            }],
+            ..WalkConfig::default()
+        };
+
+        let (frames, stop) = memory.walk_task(&current, &config);
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            stop,
+            WalkStop::OutsideCsaRegion {
+                address: 0x7001_0000
+            }
+        );
+    }
+
+    #[test]
+    fn region_must_hold_the_whole_context() {
+        let region = CsaRegion {
+            start: csa(0),
+            end: csa(2),
+        };
+
+        assert!(region.contains(csa(0)));
+        assert!(region.contains(csa(1)));
+        assert!(!region.contains(csa(1) + 4));
+        assert!(!region.contains(csa(2)));
+        assert!(!region.contains(csa(0) - CSA_SIZE as u32));
+    }
+
+    #[test]
+    fn unreadable_contexts_end_the_walk() {
+        let (mut memory, current) = call_chain(3);
+        memory.contexts.remove(&csa(1));
+
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(frames.len(), 1);
+        assert!(matches!(stop, WalkStop::ReadError { address, .. } if address == csa(1)));
+    }
+
+    #[test]
+    fn interrupts_are_recognized_by_the_raised_priority() {
+        let mut memory = Memory::default();
+        // The interrupt of priority 5 interrupted a task, which called a function
+        memory.save_upper(csa(0), upper(link(csa(1), true, 0), 0, 0x8000_2000));
+        memory.save_upper(csa(1), upper(PCXI::new(), 0, 0x8000_3000));
+        let current = upper(link(csa(0), true, 0), 0, 0x8000_1000);
+
+        let (frames, stop) = memory.walk(
+            0x8000_0100,
+            &current,
+            Some(5),
+            &VectorTables::default(),
+            &WalkConfig::default(),
+        );
+
+        assert_eq!(stop, WalkStop::EndOfChain);
+        assert_eq!(
+            kinds(&frames),
+            [FrameKind::Interrupt { priority: 5 }, FrameKind::Call]
+        );
+    }
+
+    #[test]
+    fn nested_interrupts_report_their_own_priority() {
+        let mut memory = Memory::default();
+        // Priority 7 interrupted priority 3, which interrupted the task
+        memory.save_upper(csa(0), upper(link(csa(1), true, 0), 0, 0));
+        memory.save_upper(csa(1), upper(PCXI::new(), 0, 0));
+        let current = upper(link(csa(0), true, 3), 0, 0);
+
+        let (frames, _) = memory.walk(
+            0x8000_0100,
+            &current,
+            Some(7),
+            &VectorTables::default(),
+            &WalkConfig::default(),
+        );
+
+        assert_eq!(
+            kinds(&frames),
+            [
+                FrameKind::Interrupt { priority: 7 },
+                FrameKind::Interrupt { priority: 3 }
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_are_assumed_without_the_current_priority() {
+        let (memory, current) = call_chain(2);
+        let (frames, _) = memory.walk(
+            0x8000_0100,
+            &current,
+            None,
+            &VectorTables::default(),
+            &WalkConfig::default(),
+        );
+
+        assert_eq!(kinds(&frames), [FrameKind::Call; 2]);
+    }
+
+    /// The trap vector table of the fixtures, each trap class has 32 bytes
+    const BTV: u32 = 0x8000_0100;
+
+    /// The PSW of code running on the interrupt stack
+    fn interrupt_stack() -> u32 {
+        PSW::new().with_interrupt_stack(true).into()
+    }
+
+    #[test]
+    fn upper_contexts_are_decoded_from_memory() {
+        let mut memory = Memory::default();
+        let saved = UpperContext {
+            pcxi: PCXI::new(),
+            psw: 0x0000_0B80,
+            a10: 0x7000_8F00,
+            a11: 0x8000_1234,
+            d8: 8,
+            d9: 9,
+            d10: 10,
+            d11: 11,
+            a12: 0xA12,
+            a13: 0xA13,
+            a14: 0xA14,
+            a15: 0xA15,
+            d12: 12,
+            d13: 13,
+            d14: 14,
+            d15: 15,
+        };
+        memory.save_upper(csa(0), saved);
+        let current = upper(link(csa(0), true, 0), 0, 0);
+
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(stop, WalkStop::EndOfChain);
+        let SavedContext::Upper(decoded) = frames[0].context else {
+            panic!("Expected an upper context, got {:?}", frames[0].context);
+        };
+        assert_eq!(u32::from(decoded.pcxi), 0);
+        assert_eq!(
+            [decoded.psw, decoded.a10, decoded.a11],
+            [saved.psw, saved.a10, saved.a11]
+        );
+        assert_eq!(
+            [decoded.d8, decoded.d9, decoded.d10, decoded.d11],
+            [8, 9, 10, 11]
+        );
+        assert_eq!(
+            [decoded.a12, decoded.a13, decoded.a14, decoded.a15],
+            [0xA12, 0xA13, 0xA14, 0xA15]
+        );
+        assert_eq!(
+            [decoded.d12, decoded.d13, decoded.d14, decoded.d15],
+            [12, 13, 14, 15]
+        );
+        assert_eq!(frames[0].context.return_address(), 0x8000_1234);
+    }
+
+    #[test]
+    fn lower_contexts_are_decoded_and_do_not_start_a_frame() {
+        let mut memory = Memory::default();
+        // An interrupt handler of priority 3 saved the lower context with `bisr`,
+        // after the interrupt of the task saved the upper context
+        memory.save_lower(
+            csa(0),
+            LowerContext {
+                pcxi: link(csa(1), true, 0),
+                a11: 0x8000_2000,
+                a2: 0xA2,
+                a3: 0xA3,
+                d0: 0,
+                d1: 1,
+                d2: 2,
+                d3: 3,
+                a4: 0xA4,
+                a5: 0xA5,
+                a6: 0xA6,
+                a7: 0xA7,
+                d4: 4,
+                d5: 5,
+                d6: 6,
+                d7: 7,
+            },
+        );
+        memory.save_upper(csa(1), upper(link(csa(2), true, 0), 0, 0x8000_3000));
+        memory.save_upper(csa(2), upper(PCXI::new(), 0, 0));
+        let current = upper(link(csa(0), false, 3), 0, 0);
+
+        let (frames, stop) = memory.walk(
+            0x8000_0200,
+            &current,
+            Some(3),
+            &VectorTables::default(),
+            &WalkConfig::default(),
+        );
+
+        assert_eq!(stop, WalkStop::EndOfChain);
+        assert_eq!(
+            kinds(&frames),
+            [
+                FrameKind::Lower,
+                FrameKind::Interrupt { priority: 3 },
+                FrameKind::Call
+            ]
+        );
+        let SavedContext::Lower(decoded) = frames[0].context else {
+            panic!("Expected a lower context, got {:?}", frames[0].context);
+        };
+        assert_eq!(
+            decoded.pcxi.get_context().unwrap().get_context_address(),
+            csa(1)
+        );
+        assert_eq!(decoded.a11, 0x8000_2000);
+        assert_eq!(
+            [decoded.a2, decoded.a3, decoded.a4, decoded.a5, decoded.a6, decoded.a7],
+            [0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7]
+        );
+        assert_eq!(
+            [
+                decoded.d0, decoded.d1, decoded.d2, decoded.d3, decoded.d4, decoded.d5, decoded.d6,
+                decoded.d7
+            ],
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        );
+    }
+
+    #[test]
+    fn lower_context_at_the_end_of_the_chain_is_kept() {
+        let mut memory = Memory::default();
+        memory.save_lower(csa(0), LowerContext::default());
+        let current = upper(link(csa(0), false, 0), 0, 0);
+
+        let (frames, stop) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(stop, WalkStop::EndOfChain);
+        assert_eq!(kinds(&frames), [FrameKind::Lower]);
+    }
+
+    #[test]
+    fn traps_are_recognized_by_the_trap_vector_table() {
+        let mut memory = Memory::default();
+        memory.save_upper(csa(0), upper(link(csa(1), true, 0), 0, 0x8000_2000));
+        memory.save_upper(csa(1), upper(PCXI::new(), 0, 0));
+        let current = upper(link(csa(0), true, 0), 0, 0x8000_1000);
+        let vector_tables = VectorTables {
+            btv: Some(BTV),
+            biv: None,
+        };
+
+        // The core halted at the entry of trap class 4
+        let (frames, _) = memory.walk(
+            BTV + 4 * 32,
+            &current,
+            Some(0),
+            &vector_tables,
+            &WalkConfig::default(),
+        );
+
+        assert_eq!(kinds(&frames), [FrameKind::Trap, FrameKind::Call]);
+    }
+
+    #[test]
+    fn traps_are_recognized_by_the_switch_to_the_interrupt_stack() {
+        let mut memory = Memory::default();
+        // The trapped code ran on the user stack, the trap handler runs on the
+        // interrupt stack at the same priority
+        memory.save_upper(csa(0), upper(link(csa(1), true, 0), 0, 0x8000_2000));
+        memory.save_upper(csa(1), upper(PCXI::new(), 0, 0));
+        let current = upper(link(csa(0), true, 0), interrupt_stack(), 0x8000_1000);
+
+        let (frames, _) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(kinds(&frames), [FrameKind::Trap, FrameKind::Call]);
+    }
+
+    #[test]
+    fn calls_on_the_interrupt_stack_are_not_traps() {
+        let mut memory = Memory::default();
+        memory.save_upper(csa(0), upper(PCXI::new(), interrupt_stack(), 0));
+        let current = upper(link(csa(0), true, 0), interrupt_stack(), 0);
+
+        let (frames, _) = memory.walk_task(&current, &WalkConfig::default());
+
+        assert_eq!(kinds(&frames), [FrameKind::Call]);
+    }
+
+    #[test]
+    fn trap_handlers_calling_functions_are_recognized_by_the_return_address() {
+        let mut memory = Memory::default();
+        // The trap handler of class 2 called the function the core halted in
+        memory.save_upper(csa(0), upper(link(csa(1), true, 0), 0, 0x8000_2000));
+        memory.save_upper(csa(1), upper(PCXI::new(), 0, 0));
+        let current = upper(link(csa(0), true, 0), 0, BTV + 2 * 32 + 8);
+        let vector_tables = VectorTables {
+            btv: Some(BTV),
+            biv: None,
+        };
+
+        let (frames, _) = memory.walk(
+            0x8000_4000,
+            &current,
+            Some(0),
+            &vector_tables,
+            &WalkConfig::default(),
+        );
+
+        assert_eq!(kinds(&frames), [FrameKind::Call, FrameKind::Trap]);
+    }
+}
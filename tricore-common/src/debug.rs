@@ -0,0 +1,105 @@
+//! This module defines how the execution of the cores of a chip is controlled
+//! interactively, see [DebugTarget]
+use std::collections::BTreeMap;
+
+use crate::{access::MemoryAccess, backtrace::Stacktrace};
+
+/// An operation that controls the execution of a single core
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum DebugCommand {
+    /// Stop the core
+    Halt,
+    /// Let the core run, without waiting for it to halt again
+    Continue,
+    /// Execute a single instruction
+    StepInstruction,
+    /// Execute a single instruction, but do not descend into called functions
+    StepOver,
+    /// Let the core run until it reaches the given address
+    RunTo(u32),
+    /// Query the state of the core without modifying it
+    Status,
+}
+
+/// What triggers a [Breakpoint]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum BreakpointKind {
+    /// The core halts before it executes the instruction at the address
+    Execution,
+    /// The core halts when it reads or writes memory within the range
+    Access,
+}
+
+/// Halts a core when it reaches an address or accesses memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Breakpoint {
+    pub kind: BreakpointKind,
+    pub address: u32,
+    /// The number of bytes covered, starting at the address
+    pub length: u32,
+}
+
+/// The state of a core after a [DebugCommand] was executed
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum CoreStatus {
+    /// The core is executing code
+    Running,
+    /// The core is halted, the stacktrace describes its current location
+    Halted(Box<Stacktrace>),
+}
+
+/// Implementors allow to control the execution of the cores of a chip
+///
+/// An implementation is only valid during a debug session, see [crate::Chip::debug].
+pub trait DebugTarget {
+    /// The number of cores that can be controlled
+    fn core_count(&self) -> usize;
+
+    /// Execute the command on the core with the given index
+    ///
+    /// Commands that halt the core only return once the core halted.
+    fn execute(&mut self, core: usize, command: DebugCommand) -> anyhow::Result<CoreStatus>;
+
+    /// Read memory through the core with the given index
+    ///
+    /// The core does not need to be halted, core local addresses refer to the
+    /// memory of the given core.
+    fn read_memory(&self, core: usize, address: u32, length: usize) -> anyhow::Result<Vec<u8>>;
+
+    /// Write memory through the core with the given index
+    fn write_memory(&mut self, core: usize, address: u32, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Read all registers of the halted core with the given index, by their
+    /// names in the architecture manual
+    ///
+    /// Registers that cannot be read are left out.
+    fn read_registers(&self, core: usize) -> anyhow::Result<BTreeMap<String, u32>>;
+
+    /// Write a register of the halted core with the given index
+    fn write_register(&mut self, core: usize, name: &str, value: u32) -> anyhow::Result<()>;
+
+    /// Install a breakpoint on the core with the given index
+    ///
+    /// The breakpoint stays installed until it is removed or the debug session
+    /// ends.
+    fn insert_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()>;
+
+    /// Remove a breakpoint that was installed with [DebugTarget::insert_breakpoint]
+    fn remove_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()>;
+}
+
+/// The memory of a core during a debug session, see [DebugTarget::read_memory]
+pub struct CoreMemory<'a> {
+    pub target: &'a dyn DebugTarget,
+    pub core: usize,
+}
+
+impl<'a> MemoryAccess for CoreMemory<'a> {
+    fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        self.target.read_memory(self.core, address, length)
+    }
+}
@@ -0,0 +1,215 @@
+//! This module defines post-mortem core dumps, see [CoreDump]
+//!
+//! A core dump holds the registers of each core and selected memory regions of
+//! a halted device. It provides the same [MemoryAccess] and [RegisterAccess] as a
+//! connected device, such that backtraces can be obtained offline with
+//! [Stacktrace::capture](crate::backtrace::Stacktrace::capture).
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::access::{MemoryAccess, RegisterAccess};
+
+/// The version of the dump format, incremented on incompatible changes
+pub const DUMP_FORMAT_VERSION: u32 = 1;
+
+/// Describes what is captured in a core dump besides the registers
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DumpRequest {
+    /// Memory regions to capture, e.g. the context save areas or RAM
+    pub regions: Vec<DumpRegion>,
+    /// The number of bytes captured starting at the stack pointer of each core
+    pub stack_size: u32,
+    /// The address of the RTT control block, its up buffer is captured
+    pub rtt_control_block: Option<u32>,
+}
+
+/// A memory region to capture
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DumpRegion {
+    /// Describes the contents of the region, e.g. `csa`
+    pub name: String,
+    /// The core the region is read through, relevant for core local addresses
+    pub core: usize,
+    pub address: u32,
+    pub length: u32,
+}
+
+/// The state of a halted device
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CoreDump {
+    /// See [DUMP_FORMAT_VERSION]
+    pub version: u32,
+    /// The GNU build ID of the binary that ran on the device, hex encoded
+    pub build_id: Option<String>,
+    pub cores: Vec<CoreImage>,
+    /// The up buffer of the RTT control block
+    pub rtt: Option<RttSnapshot>,
+}
+
+/// The registers and memory of a single core
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CoreImage {
+    pub core: usize,
+    /// The values of the registers by their names in the architecture manual
+    pub registers: BTreeMap<String, u32>,
+    /// The memory regions read through this core
+    pub memory: Vec<MemoryBlock>,
+    /// The parts of the requested regions that could not be read
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub missing: Vec<MissingBlock>,
+}
+
+/// The contents of a memory region
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct MemoryBlock {
+    pub name: String,
+    pub address: u32,
+    #[cfg_attr(feature = "serde", serde(with = "hex_bytes"))]
+    pub data: Vec<u8>,
+}
+
+/// A part of a memory region that could not be read
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct MissingBlock {
+    pub name: String,
+    pub address: u32,
+    pub length: u32,
+    /// Why the memory could not be read
+    pub reason: String,
+}
+
+impl MissingBlock {
+    fn overlaps(&self, address: u32, length: usize) -> bool {
+        let end = address as u64 + length as u64;
+        (address as u64) < self.address as u64 + self.length as u64 && end > self.address as u64
+    }
+}
+
+/// The up buffer of an RTT control block when the device halted
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct RttSnapshot {
+    pub buffer_address: u32,
+    /// The offset the device writes the next byte to
+    pub write_offset: u32,
+    /// The offset the host reads the next byte from
+    pub read_offset: u32,
+    #[cfg_attr(feature = "serde", serde(with = "hex_bytes"))]
+    pub buffer: Vec<u8>,
+}
+
+impl RttSnapshot {
+    /// The contents of the ring buffer, starting with the oldest byte
+    ///
+    /// The oldest data may already be overwritten in part, so the first frame
+    /// is possibly incomplete.
+    pub fn history(&self) -> Vec<u8> {
+        let split = (self.write_offset as usize).min(self.buffer.len());
+        let (newest, oldest) = self.buffer.split_at(split);
+        oldest.iter().chain(newest.iter()).copied().collect()
+    }
+}
+
+impl CoreDump {
+    /// Access the registers and memory of the core with the given index
+    ///
+    /// Memory that was not read through this core is looked up in the regions
+    /// of the other cores.
+    pub fn core(&self, index: usize) -> Option<CoreView<'_>> {
+        let image = self.cores.iter().find(|image| image.core == index)?;
+        Some(CoreView { dump: self, image })
+    }
+}
+
+/// A core of a [CoreDump], see [CoreDump::core]
+pub struct CoreView<'a> {
+    dump: &'a CoreDump,
+    image: &'a CoreImage,
+}
+
+impl<'a> MemoryAccess for CoreView<'a> {
+    fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        let other_cores = self
+            .dump
+            .cores
+            .iter()
+            .filter(|image| image.core != self.image.core);
+        let blocks = std::iter::once(self.image)
+            .chain(other_cores)
+            .flat_map(|image| image.memory.iter());
+
+        for block in blocks {
+            let Some(offset) = address.checked_sub(block.address) else {
+                continue;
+            };
+            let data = (offset as usize)
+                .checked_add(length)
+                .and_then(|end| block.data.get(offset as usize..end));
+            if let Some(data) = data {
+                return Ok(data.to_vec());
+            }
+        }
+
+        let missing = std::iter::once(self.image)
+            .chain(self.dump.cores.iter())
+            .flat_map(|image| image.missing.iter())
+            .find(|missing| missing.overlaps(address, length));
+        if let Some(missing) = missing {
+            anyhow::bail!(
+                "Memory at {address:#010X} with {length} bytes is missing from the dump, \
+                 the {} region could not be read: {}",
+                missing.name,
+                missing.reason
+            );
+        }
+        anyhow::bail!("Memory at {address:#010X} with {length} bytes is not part of the dump")
+    }
+}
+
+impl<'a> RegisterAccess for CoreView<'a> {
+    fn read_register(&self, name: &str) -> anyhow::Result<u32> {
+        self.image
+            .registers
+            .get(name)
+            .copied()
+            .with_context(|| format!("Register {name} is not part of the dump"))
+    }
+}
+
+/// Serializes bytes as a hex string, which is more compact than a list of numbers
+#[cfg(feature = "serde")]
+mod hex_bytes {
+    use std::fmt::Write;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hex = String::with_capacity(data.len() * 2);
+        for byte in data {
+            let _ = write!(hex, "{byte:02x}");
+        }
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("hex string has an odd length"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| {
+                hex.get(index..index + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| D::Error::custom("invalid hex string"))
+            })
+            .collect()
+    }
+}
@@ -1,6 +1,21 @@
 use std::io::Write;
 
+use backtrace::walker::CsaRegion;
+use debug::DebugTarget;
+use dump::{CoreDump, DumpRequest};
+use reset::{CoreResetClasses, ResetConfig};
+use run::{HaltReason, RunControl};
+use watch::{WatchRequest, WatchSample};
+
+pub mod access;
 pub mod backtrace;
+pub mod debug;
+pub mod dump;
+pub mod reset;
+pub mod rtt;
+pub mod run;
+pub mod trap;
+pub mod watch;
 
 /// Implementors provide an interface to a chip, allowing to perform basic
 /// operations on it.
@@ -21,11 +36,63 @@ pub trait Chip: Sized {
     /// Reset the chip and pass the data found in the first channel of the specified
     /// RTT control block to the given decoder
     ///
-    /// The function will return when the device halts, which happens when a
-    /// breakpoint is hit, e.g. `asm!("debug")`
+    /// The cores are reset as specified by the reset configuration. The function
+    /// will return when the device halts, which happens when a breakpoint is hit,
+    /// e.g. `asm!("debug")`, or when the run control stops the session. In the
+    /// latter case, the remaining RTT data is passed to the decoder before the
+    /// function returns.
+    ///
+    /// The backtraces of the halted cores end at the first link outside of the
+    /// given CSA regions, see [backtrace::Stacktrace::capture].
     fn read_rtt<W: Write>(
         &self,
         rtt_control_block_address: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
+        decoder: W,
+    ) -> anyhow::Result<HaltReason>;
+
+    /// Like [Chip::read_rtt], but the memory given by the request is sampled
+    /// periodically while the device runs
+    ///
+    /// Each sample is passed to the given function, an error returned by it
+    /// ends the session.
+    #[allow(clippy::too_many_arguments)]
+    fn watch<W: Write>(
+        &self,
+        rtt_control_block_address: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
+        request: &WatchRequest,
         decoder: W,
-    ) -> anyhow::Result<backtrace::Stacktrace>;
+        samples: &mut dyn FnMut(WatchSample) -> anyhow::Result<()>,
+    ) -> anyhow::Result<HaltReason>;
+
+    /// Reset the chip as specified by the reset configuration and let it run
+    fn reset(&self, reset: &ResetConfig) -> anyhow::Result<()>;
+
+    /// Query the reset classes that are available for each core of the chip
+    fn list_reset_classes(&self) -> anyhow::Result<Vec<CoreResetClasses>>;
+
+    /// Capture a core dump of the halted device
+    ///
+    /// The registers of all cores are captured together with the memory given by
+    /// the request. Parts that cannot be read are left out of the dump.
+    fn capture_dump(&self, request: &DumpRequest) -> anyhow::Result<CoreDump>;
+
+    /// Open a debug session to control the execution of the cores
+    ///
+    /// The session is passed to the given function and is closed once the
+    /// function returns. The cores are left in the state the session left them in.
+    /// The backtraces end at the first link outside of the given CSA regions.
+    /// Commands that let a core run until it reaches an address are stopped by
+    /// the timeout and cancellation of the control.
+    fn debug<R, F: FnOnce(&mut dyn DebugTarget) -> anyhow::Result<R>>(
+        &self,
+        csa_regions: &[CsaRegion],
+        control: &RunControl,
+        session: F,
+    ) -> anyhow::Result<R>;
 }
@@ -0,0 +1,187 @@
+//! This module defines how the cores of a chip should be reset, see [ResetConfig]
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{bail, Context};
+
+/// Selects a reset class, either by its index or by its description
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ResetClassSelector {
+    /// The index of the reset class as reported by the debug controller
+    Index(u8),
+    /// The description of the reset class, compared case-insensitively
+    Name(String),
+}
+
+impl ResetClassSelector {
+    /// Check whether the given reset class is selected by this selector
+    pub fn matches(&self, class: &ResetClassInfo) -> bool {
+        match self {
+            ResetClassSelector::Index(index) => class.index == *index,
+            ResetClassSelector::Name(name) => class.description.eq_ignore_ascii_case(name.trim()),
+        }
+    }
+}
+
+impl Default for ResetClassSelector {
+    /// Reset class 0, which is assumed to be the simplest reset
+    fn default() -> Self {
+        ResetClassSelector::Index(0)
+    }
+}
+
+impl FromStr for ResetClassSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            bail!("Reset class must either be an index or a name");
+        }
+
+        Ok(match s.trim().parse::<u8>() {
+            Ok(index) => ResetClassSelector::Index(index),
+            Err(_) => ResetClassSelector::Name(s.to_owned()),
+        })
+    }
+}
+
+impl Display for ResetClassSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResetClassSelector::Index(index) => write!(f, "{index}"),
+            ResetClassSelector::Name(name) => write!(f, "{name:?}"),
+        }
+    }
+}
+
+/// A reset class as reported by the debug controller
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ResetClassInfo {
+    pub index: u8,
+    pub description: String,
+}
+
+/// The reset classes that are available for a single core
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CoreResetClasses {
+    pub core: usize,
+    pub classes: Vec<ResetClassInfo>,
+}
+
+impl CoreResetClasses {
+    /// Resolve the selector to one of the reset classes of this core
+    ///
+    /// Fails if the core does not offer the selected reset class.
+    pub fn resolve(&self, selector: &ResetClassSelector) -> anyhow::Result<&ResetClassInfo> {
+        self.classes
+            .iter()
+            .find(|class| selector.matches(class))
+            .with_context(|| {
+                format!(
+                    "Reset class {selector} is not available for core {}, available classes are {:?} (see 'reset --list')",
+                    self.core,
+                    self.classes
+                        .iter()
+                        .map(|class| format!("{}: {}", class.index, class.description))
+                        .collect::<Vec<_>>()
+                )
+            })
+    }
+}
+
+/// Defines what happens to a single core when the chip is reset
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum CoreResetPolicy {
+    /// Reset the core with the selected reset class
+    Reset(ResetClassSelector),
+    /// Do not reset the core at all
+    Skip,
+}
+
+impl FromStr for CoreResetPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("skip") {
+            return Ok(CoreResetPolicy::Skip);
+        }
+
+        Ok(CoreResetPolicy::Reset(s.parse()?))
+    }
+}
+
+/// Overrides the reset policy for a single core, given as `<core>=<class|skip>`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct CoreResetOverride {
+    pub core: usize,
+    pub policy: CoreResetPolicy,
+}
+
+impl FromStr for CoreResetOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (core, policy) = s
+            .split_once('=')
+            .with_context(|| format!("Expected <core>=<class|skip>, got {s:?}"))?;
+
+        Ok(CoreResetOverride {
+            core: core
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid core index {core:?}"))?,
+            policy: policy.parse()?,
+        })
+    }
+}
+
+/// Configures which reset classes are used when the chip is reset
+#[derive(clap::Args, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ResetConfig {
+    /// Reset class used to reset the cores, given by index or by name. Defaults
+    /// to reset class 0
+    #[arg(long)]
+    pub reset_class: Option<ResetClassSelector>,
+
+    /// Overrides the reset policy for a single core, e.g. '1=skip' to leave the
+    /// second core untouched or '2=0' to reset the third core with class 0. May
+    /// be given multiple times
+    #[arg(long = "core-reset", value_name = "CORE=CLASS|skip")]
+    pub core_reset: Vec<CoreResetOverride>,
+
+    /// Memory that is written after the cores were reset and before they run,
+    /// e.g. to pass parameters to the firmware
+    #[arg(skip)]
+    pub memory_writes: Vec<MemoryWrite>,
+}
+
+/// Data written to the memory of the chip, see [ResetConfig::memory_writes]
+///
+/// The firmware must place the target in memory that is not initialized by
+/// its startup code, otherwise the data is overwritten.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct MemoryWrite {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl ResetConfig {
+    /// The reset policy for the core with the given index
+    ///
+    /// If no override for the core is configured, the core is reset with the
+    /// globally configured reset class.
+    pub fn policy_for_core(&self, core: usize) -> CoreResetPolicy {
+        self.core_reset
+            .iter()
+            .rev()
+            .find(|core_override| core_override.core == core)
+            .map(|core_override| core_override.policy.clone())
+            .unwrap_or_else(|| CoreResetPolicy::Reset(self.reset_class.clone().unwrap_or_default()))
+    }
+}
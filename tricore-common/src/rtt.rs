@@ -0,0 +1,222 @@
+//! This module describes the layout of the SEGGER RTT control block that is used
+//! by defmt to transfer its data, see [RttControlBlock]
+//!
+//! Only the first up channel is read, the control block of defmt does not have
+//! any other channels.
+
+use anyhow::{bail, Context};
+
+use crate::access::MemoryAccess;
+
+/// The ID the firmware writes to the start of an initialized control block
+pub const RTT_ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+
+/// The value of the flags of the up channel that tells the firmware a host
+/// is connected
+pub const HOST_CONNECTED: u32 = 2;
+
+/// Helper structure to facilitate reading at the correct offsets within
+/// the control block
+///
+/// The offsets were inferred as of https://github.com/knurling-rs/defmt/blob/59c14b924815a7185fd0079a74b936dba90c867c/firmware/defmt-rtt/src/lib.rs#L124
+#[derive(Debug, Clone, Copy)]
+pub struct RttControlBlock {
+    base_address: u32,
+}
+
+impl RttControlBlock {
+    pub fn new(address: u32) -> Self {
+        RttControlBlock {
+            base_address: address,
+        }
+    }
+
+    pub fn id_addr(&self) -> u32 {
+        self.base_address
+    }
+
+    pub fn buffer_addr_and_size(&self) -> u32 {
+        self.base_address + 28
+    }
+
+    pub fn device_write_index_addr(&self) -> u32 {
+        self.base_address + 36
+    }
+
+    pub fn host_read_index_addr(&self) -> u32 {
+        self.base_address + 40
+    }
+
+    pub fn flags_addr(&self) -> u32 {
+        self.base_address + 44
+    }
+
+    /// Check whether the firmware initialized the control block
+    pub fn is_initialized(&self, memory: &dyn MemoryAccess) -> anyhow::Result<bool> {
+        Ok(memory.read_memory(self.id_addr(), RTT_ID.len())? == RTT_ID)
+    }
+
+    /// Read the address and size of the ring buffer of the up channel
+    pub fn read_buffer(&self, memory: &dyn MemoryAccess) -> anyhow::Result<BufferParameters> {
+        let address = read_u32(memory, self.buffer_addr_and_size())
+            .context("Cannot obtain buffer specification (address and size)")?;
+        let size = read_u32(memory, self.buffer_addr_and_size() + 4)
+            .context("Cannot obtain buffer specification (address and size)")?;
+        Ok(BufferParameters { address, size })
+    }
+
+    /// Read the index up to which the firmware wrote the ring buffer
+    pub fn read_write_index(&self, memory: &dyn MemoryAccess) -> anyhow::Result<u32> {
+        read_u32(memory, self.device_write_index_addr())
+            .context("Error while obtaining the device write index")
+    }
+
+    /// Read the index up to which the host read the ring buffer
+    pub fn read_read_index(&self, memory: &dyn MemoryAccess) -> anyhow::Result<u32> {
+        read_u32(memory, self.host_read_index_addr())
+            .context("Error while obtaining the host read index")
+    }
+}
+
+/// Address and size of the buffer that holds the actually rtt data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferParameters {
+    pub address: u32,
+    pub size: u32,
+}
+
+impl BufferParameters {
+    /// Read the data the firmware wrote between the two indices
+    ///
+    /// The data wraps around the end of the buffer if the write index is below
+    /// the read index.
+    pub fn read_data(
+        &self,
+        memory: &dyn MemoryAccess,
+        read_index: u32,
+        write_index: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        if write_index >= self.size || read_index >= self.size {
+            bail!(
+                "The RTT indices {read_index:#X} and {write_index:#X} exceed the buffer size {:#X}",
+                self.size
+            );
+        }
+
+        let data = if write_index >= read_index {
+            memory.read_memory(
+                self.address + read_index,
+                (write_index - read_index) as usize,
+            )
+        } else {
+            // The write wrapped, we might need to do two reads
+            memory
+                .read_memory(self.address + read_index, (self.size - read_index) as usize)
+                .and_then(|mut data| {
+                    if write_index != 0 {
+                        data.extend(memory.read_memory(self.address, write_index as usize)?);
+                    }
+                    Ok(data)
+                })
+        };
+        data.context("Error while reading buffer data")
+    }
+}
+
+fn read_u32(memory: &dyn MemoryAccess, address: u32) -> anyhow::Result<u32> {
+    let data = memory.read_memory(address, 4)?;
+    Ok(u32::from_le_bytes(data.as_slice().try_into()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    struct Memory(BTreeMap<u32, u8>);
+
+    impl MemoryAccess for Memory {
+        fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+            (address..address + length as u32)
+                .map(|address| {
+                    self.0
+                        .get(&address)
+                        .copied()
+                        .with_context(|| format!("{address:#X} is not mapped"))
+                })
+                .collect()
+        }
+    }
+
+    const BLOCK: u32 = 0x7000_0000;
+    const BUFFER: u32 = 0x7000_1000;
+
+    /// A control block with an up buffer of 8 bytes holding the bytes 0 to 7
+    fn memory(write_index: u32, read_index: u32) -> Memory {
+        let mut block = RTT_ID.to_vec();
+        block.resize(48, 0);
+        block[28..32].copy_from_slice(&BUFFER.to_le_bytes());
+        block[32..36].copy_from_slice(&8u32.to_le_bytes());
+        block[36..40].copy_from_slice(&write_index.to_le_bytes());
+        block[40..44].copy_from_slice(&read_index.to_le_bytes());
+
+        let block = (BLOCK..).zip(block);
+        let buffer = (BUFFER..).zip(0..8);
+        Memory(block.chain(buffer).collect())
+    }
+
+    #[test]
+    fn control_block_is_parsed() {
+        let memory = memory(5, 2);
+        let block = RttControlBlock::new(BLOCK);
+
+        assert!(block.is_initialized(&memory).unwrap());
+        assert_eq!(
+            block.read_buffer(&memory).unwrap(),
+            BufferParameters {
+                address: BUFFER,
+                size: 8
+            }
+        );
+        assert_eq!(block.read_write_index(&memory).unwrap(), 5);
+        assert_eq!(block.read_read_index(&memory).unwrap(), 2);
+    }
+
+    #[test]
+    fn uninitialized_control_block_is_detected() {
+        let mut memory = memory(0, 0);
+        memory.0.insert(BLOCK, 0);
+
+        assert!(!RttControlBlock::new(BLOCK).is_initialized(&memory).unwrap());
+    }
+
+    #[test]
+    fn data_is_read_between_the_indices() {
+        let memory = memory(0, 0);
+        let buffer = RttControlBlock::new(BLOCK).read_buffer(&memory).unwrap();
+
+        let cases: &[(u32, u32, &[u8])] = &[
+            (0, 0, &[]),
+            (2, 5, &[2, 3, 4]),
+            (6, 2, &[6, 7, 0, 1]),
+            (6, 0, &[6, 7]),
+        ];
+        for &(read_index, write_index, expected) in cases {
+            assert_eq!(
+                buffer.read_data(&memory, read_index, write_index).unwrap(),
+                expected,
+                "read {read_index}, write {write_index}"
+            );
+        }
+    }
+
+    #[test]
+    fn indices_outside_of_the_buffer_are_rejected() {
+        let memory = memory(0, 0);
+        let buffer = RttControlBlock::new(BLOCK).read_buffer(&memory).unwrap();
+
+        assert!(buffer.read_data(&memory, 0, 8).is_err());
+        assert!(buffer.read_data(&memory, 8, 0).is_err());
+    }
+}
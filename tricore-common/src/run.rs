@@ -0,0 +1,144 @@
+//! This module defines how a run session is limited and why it ended, see
+//! [RunControl] and [HaltReason]
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+
+use crate::backtrace::Stacktrace;
+
+/// Allows to stop a run session from another thread, e.g. a signal handler
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the run session to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the run session was requested to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Withdraw the request to stop, e.g. once a debug session handled it, such
+    /// that the next run is not stopped right away
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Configures when a run session is stopped before the device halts
+#[derive(clap::Args, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct RunControl {
+    /// Stop the run after the given number of seconds, even if the device did
+    /// not halt
+    #[arg(long, value_name = "SECONDS", value_parser = parse_timeout)]
+    pub timeout: Option<Duration>,
+
+    /// Halt the cores and print a backtrace when the run is stopped by the
+    /// timeout or Ctrl-C. Otherwise the cores are left running
+    #[arg(long, default_value_t = false)]
+    pub halt_on_stop: bool,
+
+    /// Stops the run when cancelled, e.g. on Ctrl-C
+    #[arg(skip)]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancellation: CancellationToken,
+}
+
+impl RunControl {
+    /// Check whether a run that started at the given instant must be stopped
+    pub fn stop_reason(&self, started: Instant) -> Option<StopReason> {
+        if self.cancellation.is_cancelled() {
+            return Some(StopReason::Cancelled);
+        }
+
+        match self.timeout {
+            Some(timeout) if started.elapsed() >= timeout => Some(StopReason::Timeout),
+            _ => None,
+        }
+    }
+}
+
+fn parse_timeout(value: &str) -> anyhow::Result<Duration> {
+    let seconds: f64 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid timeout {value:?}, expected a number of seconds"))?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        bail!("Timeout must be a positive number of seconds");
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Why a run session was stopped before the device halted
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum StopReason {
+    /// The timeout of the [RunControl] elapsed
+    Timeout,
+    /// The [CancellationToken] of the [RunControl] was cancelled
+    Cancelled,
+}
+
+/// The stacktraces of all cores after the device halted
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct HaltedCores {
+    /// The ID of the core whose halt ended the run session, the main core if
+    /// the session was stopped
+    pub trigger: u32,
+    /// The stacktrace of each core by the core ID reported by the debug
+    /// controller. Cores whose state could not be read are missing
+    pub stacktraces: BTreeMap<u32, Stacktrace>,
+}
+
+impl HaltedCores {
+    /// The stacktrace of the core that ended the run session
+    pub fn trigger_stacktrace(&self) -> Option<&Stacktrace> {
+        self.stacktraces.get(&self.trigger)
+    }
+}
+
+/// Why a run session ended
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum HaltReason {
+    /// A core hit a breakpoint, e.g. `asm!("debug")`, all other cores were
+    /// halted as well
+    DebugHit(HaltedCores),
+    /// The session was stopped before the device halted
+    ///
+    /// The stacktraces are only available if the cores were halted, see
+    /// [RunControl::halt_on_stop].
+    Stopped(StopReason, Option<HaltedCores>),
+}
+
+impl HaltReason {
+    /// The stacktraces of all cores, if the cores were halted
+    pub fn halted_cores(&self) -> Option<&HaltedCores> {
+        match self {
+            HaltReason::DebugHit(halted_cores) => Some(halted_cores),
+            HaltReason::Stopped(_, halted_cores) => halted_cores.as_ref(),
+        }
+    }
+
+    /// The stacktrace of the core that ended the run session, if any
+    pub fn stacktrace(&self) -> Option<&Stacktrace> {
+        self.halted_cores()
+            .and_then(HaltedCores::trigger_stacktrace)
+    }
+}
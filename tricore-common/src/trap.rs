@@ -0,0 +1,487 @@
+//! This module decodes traps of the tricore architecture, see [Trap] and
+//! [TrapRegisters]
+//!
+//! See also chapter 6 of https://www.infineon.com/dgdl/tc1_6__architecture_vol1.pdf?fileId=db3a3043372d5cc801373b0f374d5d67
+use std::fmt::Display;
+
+/// A trap, identified by its class and its trap identification number (TIN)
+///
+/// The class is the index of the trap handler in the trap vector table, the TIN
+/// is passed to the trap handler in D15.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Trap {
+    pub class: u8,
+    pub tin: u8,
+}
+
+/// Describes a trap of a specific class and TIN, see [Trap::cause]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TrapCause {
+    /// The abbreviation used by the architecture manual, e.g. `DSE`
+    pub mnemonic: &'static str,
+    pub name: &'static str,
+    /// What usually leads to this trap
+    pub explanation: &'static str,
+}
+
+const fn cause(mnemonic: &'static str, name: &'static str, explanation: &'static str) -> TrapCause {
+    TrapCause {
+        mnemonic,
+        name,
+        explanation,
+    }
+}
+
+/// The names of the trap classes, indexed by class
+const CLASS_NAMES: [&str; 8] = [
+    "MMU",
+    "Internal Protection",
+    "Instruction Error",
+    "Context Management",
+    "Bus Error",
+    "Assertion",
+    "System Call",
+    "NMI",
+];
+
+/// The known traps as (class, TIN, cause)
+#[rustfmt::skip]
+const CAUSES: &[(u8, u8, TrapCause)] = &[
+    (0, 0, cause("VAF", "Virtual Address Fill", "The MMU has no translation for the virtual address")),
+    (0, 1, cause("VAP", "Virtual Address Protection", "The MMU does not permit the access to the virtual address")),
+    (1, 1, cause("PRIV", "Privileged Instruction", "An instruction was executed that is not allowed in the current I/O privilege level, see PSW.IO")),
+    (1, 2, cause("MPR", "Memory Protection Read", "A load from an address that is not readable in the active protection set")),
+    (1, 3, cause("MPW", "Memory Protection Write", "A store to an address that is not writable in the active protection set")),
+    (1, 4, cause("MPX", "Memory Protection Execution", "An instruction was fetched from an address that is not executable in the active protection set")),
+    (1, 5, cause("MPP", "Memory Protection Peripheral Access", "A peripheral or the core special function registers were accessed in user mode")),
+    (1, 6, cause("MPN", "Memory Protection Null Address", "A memory access to address 0, e.g. by dereferencing a null pointer")),
+    (1, 7, cause("GRWP", "Global Register Write Protection", "A global address register (A0, A1, A8, A9) was written without permission, see PSW.GW")),
+    (2, 1, cause("IOPC", "Illegal Opcode", "The instruction is not defined, e.g. because code jumped into data")),
+    (2, 2, cause("UOPC", "Unimplemented Opcode", "The instruction is not implemented by this core, e.g. a missing coprocessor")),
+    (2, 3, cause("OPD", "Invalid Operand", "An instruction was called with an invalid operand, e.g. an odd register for a 64 bit operand")),
+    (2, 4, cause("ALN", "Data Address Alignment", "A load or store to an address that is not aligned as required by the instruction")),
+    (2, 5, cause("MEM", "Invalid Local Memory Address", "An address computation crossed a segment or accessed an invalid local memory address")),
+    (3, 1, cause("FCD", "Free Context List Depletion", "The last free context save area was used, the stack of contexts is almost exhausted")),
+    (3, 2, cause("CDO", "Call Depth Overflow", "The call depth counter overflowed, the call nesting is too deep")),
+    (3, 3, cause("CDU", "Call Depth Underflow", "A return was executed with a call depth counter of zero")),
+    (3, 4, cause("FCU", "Free Context List Underflow", "No free context save area is left, the context save areas are exhausted")),
+    (3, 5, cause("CSU", "Call Stack Underflow", "A return was executed without a saved context, PCXI is zero")),
+    (3, 6, cause("CTYP", "Context Type", "The restored context has the wrong type, e.g. a lower context was restored on return")),
+    (3, 7, cause("NEST", "Nesting Error", "An RFE was executed while an interrupt was still nested")),
+    (4, 1, cause("PSE", "Program Fetch Synchronous Error", "An instruction fetch failed on the bus, see PSTR")),
+    (4, 2, cause("DSE", "Data Access Synchronous Error", "A load failed, e.g. because the address is not backed by memory, see DSTR and DEADD")),
+    (4, 3, cause("DAE", "Data Access Asynchronous Error", "A store failed after the instruction completed, see DATR and DEADD")),
+    (4, 4, cause("CAE", "Coprocessor Trap Asynchronous Error", "A coprocessor reported an error asynchronously")),
+    (4, 5, cause("PIE", "Program Memory Integrity Error", "An ECC or parity error was detected in program memory, see PIETR and PIEAR")),
+    (4, 6, cause("DIE", "Data Memory Integrity Error", "An ECC or parity error was detected in data memory, see DIETR and DIEAR")),
+    (4, 7, cause("TAE", "Temporal Asynchronous Error", "The temporal protection system detected an exceeded time budget")),
+    (5, 1, cause("OVF", "Arithmetic Overflow", "A TRAPV instruction was executed while the overflow flag was set")),
+    (5, 2, cause("SOVF", "Sticky Arithmetic Overflow", "A TRAPSV instruction was executed while the sticky overflow flag was set")),
+    (6, 0, cause("SYS", "System Call", "A SYSCALL instruction was executed, the TIN is its argument")),
+    (7, 0, cause("NMI", "Non-Maskable Interrupt", "A non-maskable interrupt was requested, e.g. by the safety management unit")),
+];
+
+impl Trap {
+    /// The name of the trap class
+    pub fn class_name(&self) -> &'static str {
+        CLASS_NAMES
+            .get(self.class as usize)
+            .copied()
+            .unwrap_or("Unknown")
+    }
+
+    /// The cause of the trap, if the combination of class and TIN is known
+    ///
+    /// For system calls, any TIN is valid.
+    pub fn cause(&self) -> Option<TrapCause> {
+        let tin = match self.class {
+            6 => 0,
+            _ => self.tin,
+        };
+        CAUSES
+            .iter()
+            .find(|(class, cause_tin, _)| *class == self.class && *cause_tin == tin)
+            .map(|(_, _, cause)| *cause)
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} trap (class {}, TIN {})",
+            self.class_name(),
+            self.class,
+            self.tin
+        )?;
+        if let Some(cause) = self.cause() {
+            write!(f, ": {} - {}", cause.mnemonic, cause.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The trap status registers of a core, which hold details about the last trap
+///
+/// Registers that could not be read are [None].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct TrapRegisters {
+    /// Program Synchronous Trap Register
+    pub pstr: Option<u32>,
+    /// Data Synchronous Trap Register
+    pub dstr: Option<u32>,
+    /// Data Asynchronous Trap Register
+    pub datr: Option<u32>,
+    /// Data Error Address Register
+    pub deadd: Option<u32>,
+    /// Program Integrity Error Trap Register
+    pub pietr: Option<u32>,
+    /// Program Integrity Error Address Register
+    pub piear: Option<u32>,
+    /// Data Integrity Error Trap Register
+    pub dietr: Option<u32>,
+    /// Data Integrity Error Address Register
+    pub diear: Option<u32>,
+}
+
+/// A flag of a trap status register as (bit, name, description)
+type StatusFlag = (u8, &'static str, &'static str);
+
+const PSTR_FLAGS: &[StatusFlag] = &[
+    (0, "FRE", "fetch range error"),
+    (2, "FBE", "fetch bus error"),
+    (12, "FPE", "fetch peripheral error"),
+    (14, "FME", "fetch memory integrity error"),
+];
+
+const DSTR_FLAGS: &[StatusFlag] = &[
+    (0, "SRE", "scratchpad range error"),
+    (1, "GAE", "global address error"),
+    (2, "LBE", "load bus error"),
+    (6, "CRE", "cache refill error"),
+    (14, "DTME", "data tag memory integrity error"),
+    (15, "LOE", "load overlay error"),
+    (16, "SDE", "segment difference error"),
+    (17, "SCE", "segment crossing error"),
+    (18, "CAC", "core special function register access error"),
+    (19, "MPE", "memory protection error"),
+    (20, "CLE", "context location error"),
+    (24, "ALN", "alignment error"),
+];
+
+const DATR_FLAGS: &[StatusFlag] = &[
+    (3, "SBE", "store bus error"),
+    (9, "CWE", "cache writeback error"),
+    (10, "CFE", "cache flush error"),
+    (14, "SOE", "store overlay error"),
+];
+
+/// The flags of PIETR and DIETR
+const INTEGRITY_FLAGS: &[StatusFlag] = &[
+    (0, "IED", "integrity error detected"),
+    (1, "IE_T", "error in the tag memory"),
+    (2, "IE_C", "error in the cache memory"),
+    (3, "IE_S", "error in the scratchpad memory"),
+    (4, "IE_BI", "error on the bus interface"),
+];
+
+impl TrapRegisters {
+    /// The address of the memory access that caused the trap, if the trap
+    /// status registers capture it for this trap
+    pub fn faulting_address(&self, trap: &Trap) -> Option<u32> {
+        match (trap.class, trap.tin) {
+            (4, 2) | (4, 3) => self.deadd,
+            (4, 5) => self.piear,
+            (4, 6) => self.diear,
+            _ => None,
+        }
+    }
+
+    /// Describe the flags of the trap status register that is relevant for the
+    /// given trap, e.g. `DSTR.LBE: load bus error`
+    pub fn status_flags(&self, trap: &Trap) -> Vec<String> {
+        let (register, value, flags) = match (trap.class, trap.tin) {
+            (4, 1) => ("PSTR", self.pstr, PSTR_FLAGS),
+            (4, 2) => ("DSTR", self.dstr, DSTR_FLAGS),
+            (4, 3) => ("DATR", self.datr, DATR_FLAGS),
+            (4, 5) => ("PIETR", self.pietr, INTEGRITY_FLAGS),
+            (4, 6) => ("DIETR", self.dietr, INTEGRITY_FLAGS),
+            _ => return Vec::new(),
+        };
+        let Some(value) = value else {
+            return Vec::new();
+        };
+
+        flags
+            .iter()
+            .filter(|(bit, _, _)| value & (1 << bit) != 0)
+            .map(|(_, name, description)| format!("{register}.{name}: {description}"))
+            .collect()
+    }
+}
+
+/// The size of an entry in the trap vector table in bytes
+const TRAP_VECTOR_SIZE: u32 = 32;
+
+/// The base addresses of the vector tables a core is configured with
+///
+/// Each core has its own tables, so on multi-core devices the tables must be
+/// obtained from the core a stacktrace belongs to. Registers that could not be
+/// read are [None].
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct VectorTables {
+    /// Base Trap Vector table pointer
+    pub btv: Option<u32>,
+    /// Base Interrupt Vector table pointer, bit 0 selects the vector spacing
+    pub biv: Option<u32>,
+}
+
+impl VectorTables {
+    /// The trap class whose entry in the trap vector table contains the address
+    pub fn trap_class(&self, address: u32) -> Option<u8> {
+        // Bit 0 of BTV is reserved
+        let offset = address.checked_sub(self.btv? & !1)?;
+        let class = offset / TRAP_VECTOR_SIZE;
+
+        (class < CLASS_NAMES.len() as u32).then_some(class as u8)
+    }
+
+    /// The interrupt priority whose entry in the interrupt vector table contains
+    /// the address
+    ///
+    /// The entries are 32 bytes apart, or 8 bytes if the vector spacing select bit
+    /// BIV.VSS is set. Priority 0 never triggers an interrupt, so its entry is
+    /// not recognized.
+    pub fn interrupt_priority(&self, address: u32) -> Option<u8> {
+        let biv = self.biv?;
+        let spacing = if biv & 1 == 0 { 32 } else { 8 };
+        let offset = address.checked_sub(biv & !1)?;
+
+        match offset / spacing {
+            0 => None,
+            priority => u8::try_from(priority).ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class_names() {
+        let cases = [
+            (0, "MMU"),
+            (1, "Internal Protection"),
+            (2, "Instruction Error"),
+            (3, "Context Management"),
+            (4, "Bus Error"),
+            (5, "Assertion"),
+            (6, "System Call"),
+            (7, "NMI"),
+            (8, "Unknown"),
+            (255, "Unknown"),
+        ];
+        for (class, name) in cases {
+            assert_eq!(Trap { class, tin: 0 }.class_name(), name, "class {class}");
+        }
+    }
+
+    #[test]
+    fn causes() {
+        // At least one trap per class, and the first and last TIN of a class
+        let cases = [
+            (0, 0, Some("VAF")),
+            (0, 1, Some("VAP")),
+            (0, 2, None),
+            (1, 0, None),
+            (1, 1, Some("PRIV")),
+            (1, 6, Some("MPN")),
+            (1, 7, Some("GRWP")),
+            (2, 1, Some("IOPC")),
+            (2, 4, Some("ALN")),
+            (2, 5, Some("MEM")),
+            (2, 6, None),
+            (3, 1, Some("FCD")),
+            (3, 4, Some("FCU")),
+            (3, 7, Some("NEST")),
+            (4, 0, None),
+            (4, 1, Some("PSE")),
+            (4, 2, Some("DSE")),
+            (4, 7, Some("TAE")),
+            (5, 1, Some("OVF")),
+            (5, 2, Some("SOVF")),
+            (5, 3, None),
+            // Any TIN is the argument of a system call
+            (6, 0, Some("SYS")),
+            (6, 42, Some("SYS")),
+            (6, 255, Some("SYS")),
+            (7, 0, Some("NMI")),
+            (7, 1, None),
+            (8, 0, None),
+        ];
+        for (class, tin, mnemonic) in cases {
+            let cause = Trap { class, tin }.cause();
+            assert_eq!(
+                cause.map(|cause| cause.mnemonic),
+                mnemonic,
+                "class {class}, TIN {tin}"
+            );
+        }
+    }
+
+    #[test]
+    fn causes_are_unique_and_belong_to_a_known_class() {
+        for (index, (class, tin, cause)) in CAUSES.iter().enumerate() {
+            assert!((*class as usize) < CLASS_NAMES.len(), "{}", cause.mnemonic);
+            assert!(
+                !CAUSES[..index]
+                    .iter()
+                    .any(|(other_class, other_tin, _)| other_class == class && other_tin == tin),
+                "class {class}, TIN {tin} is listed twice"
+            );
+        }
+    }
+
+    #[test]
+    fn display() {
+        let cases = [
+            (
+                Trap { class: 4, tin: 2 },
+                "Bus Error trap (class 4, TIN 2): DSE - Data Access Synchronous Error",
+            ),
+            (
+                Trap { class: 6, tin: 3 },
+                "System Call trap (class 6, TIN 3): SYS - System Call",
+            ),
+            (
+                Trap { class: 2, tin: 7 },
+                "Instruction Error trap (class 2, TIN 7)",
+            ),
+            (Trap { class: 9, tin: 1 }, "Unknown trap (class 9, TIN 1)"),
+        ];
+        for (trap, text) in cases {
+            assert_eq!(trap.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn faulting_address_and_status_flags() {
+        let registers = TrapRegisters {
+            pstr: Some(1 << 2),
+            dstr: Some(1 << 2 | 1 << 24),
+            datr: Some(0),
+            deadd: Some(0xD000_0004),
+            pietr: None,
+            piear: Some(0x8000_0000),
+            dietr: Some(1 << 0 | 1 << 3),
+            diear: Some(0x7000_0000),
+        };
+        let cases: [(u8, u8, Option<u32>, &[&str]); 7] = [
+            (4, 1, None, &["PSTR.FBE: fetch bus error"]),
+            (
+                4,
+                2,
+                Some(0xD000_0004),
+                &["DSTR.LBE: load bus error", "DSTR.ALN: alignment error"],
+            ),
+            (4, 3, Some(0xD000_0004), &[]),
+            (4, 4, None, &[]),
+            // The trap register could not be read
+            (4, 5, Some(0x8000_0000), &[]),
+            (
+                4,
+                6,
+                Some(0x7000_0000),
+                &[
+                    "DIETR.IED: integrity error detected",
+                    "DIETR.IE_S: error in the scratchpad memory",
+                ],
+            ),
+            (2, 4, None, &[]),
+        ];
+        for (class, tin, address, flags) in cases {
+            let trap = Trap { class, tin };
+            assert_eq!(registers.faulting_address(&trap), address, "{trap}");
+            assert_eq!(registers.status_flags(&trap), flags, "{trap}");
+        }
+    }
+
+    #[test]
+    fn trap_class_of_handler_addresses() {
+        let tables = VectorTables {
+            btv: Some(0x8000_0100),
+            biv: None,
+        };
+        let cases = [
+            (0x8000_00FF, None),
+            (0x8000_0100, Some(0)),
+            (0x8000_011F, Some(0)),
+            (0x8000_0120, Some(1)),
+            // The handler of class 4 starts at BTV + 4 * 32
+            (0x8000_0100 + 4 * 32, Some(4)),
+            (0x8000_0100 + 4 * 32 + 8, Some(4)),
+            (0x8000_0100 + 7 * 32 + 31, Some(7)),
+            (0x8000_0100 + 8 * 32, None),
+            (0x0000_0000, None),
+            (0xFFFF_FFFF, None),
+        ];
+        for (address, class) in cases {
+            assert_eq!(tables.trap_class(address), class, "{address:#X}");
+        }
+
+        // The reserved bit 0 is not part of the base address
+        let tables = VectorTables {
+            btv: Some(0x8000_0101),
+            biv: None,
+        };
+        assert_eq!(tables.trap_class(0x8000_0100), Some(0));
+        assert_eq!(tables.trap_class(0x8000_0100 + 2 * 32), Some(2));
+
+        // Unknown without BTV
+        assert_eq!(VectorTables::default().trap_class(0x8000_0100), None);
+    }
+
+    #[test]
+    fn interrupt_priority_of_handler_addresses() {
+        // (BIV, address, priority)
+        let cases = [
+            // 32 byte spacing
+            (0x8000_2000, 0x8000_1FFF, None),
+            (0x8000_2000, 0x8000_2000, None),
+            (0x8000_2000, 0x8000_201F, None),
+            (0x8000_2000, 0x8000_2020, Some(1)),
+            (0x8000_2000, 0x8000_2000 + 10 * 32 + 4, Some(10)),
+            (0x8000_2000, 0x8000_2000 + 255 * 32 + 31, Some(255)),
+            (0x8000_2000, 0x8000_2000 + 256 * 32, None),
+            // 8 byte spacing selected by BIV.VSS
+            (0x8000_2001, 0x8000_2000, None),
+            (0x8000_2001, 0x8000_2008, Some(1)),
+            (0x8000_2001, 0x8000_2000 + 10 * 8 + 4, Some(10)),
+            (0x8000_2001, 0x8000_2000 + 32, Some(4)),
+            (0x8000_2001, 0x8000_2000 + 255 * 8 + 7, Some(255)),
+            (0x8000_2001, 0x8000_2000 + 256 * 8, None),
+        ];
+        for (biv, address, priority) in cases {
+            let tables = VectorTables {
+                btv: None,
+                biv: Some(biv),
+            };
+            assert_eq!(
+                tables.interrupt_priority(address),
+                priority,
+                "BIV {biv:#X}, address {address:#X}"
+            );
+        }
+
+        // Unknown without BIV
+        assert_eq!(
+            VectorTables::default().interrupt_priority(0x8000_2020),
+            None
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! This module defines how memory is sampled while the device runs, see
+//! [WatchRequest]
+//!
+//! The memory is read through the main core in between reading the RTT data,
+//! the samples hold the raw bytes. Interpreting them, e.g. according to the
+//! debug information, is left to the host.
+use std::time::Duration;
+
+/// The memory to sample and how often
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct WatchRequest {
+    pub regions: Vec<WatchedRegion>,
+    /// The time between two samples. Samples are taken at most as often as the
+    /// RTT data is polled
+    pub interval: Duration,
+}
+
+/// A memory region that is sampled, usually a variable
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct WatchedRegion {
+    pub address: u32,
+    pub length: u32,
+}
+
+/// The contents of the watched regions at a point in time
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct WatchSample {
+    /// The time since the device started running
+    pub elapsed: Duration,
+    /// The contents of each region in the order of [WatchRequest::regions],
+    /// [None] if the region could not be read
+    pub data: Vec<Option<Vec<u8>>>,
+}
@@ -0,0 +1,329 @@
+//! Framing of the messages between the host and the daemon, see [write_message]
+//! and [read_message]
+//!
+//! Each message is a CBOR encoded [Message], preceded by its length as a little
+//! endian `u32`. The length allows to skip messages that cannot be decoded, e.g.
+//! commands added in a newer version, without losing track of the stream.
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The version of the protocol, both sides must use the same version
+///
+/// Increment it whenever [super::Commands], [super::Response] or the framing
+/// change in an incompatible way.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// The size of the largest message that is accepted, e.g. a flashed hex file
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// The ID of messages that do not belong to a request, e.g. [super::Response::Log]
+pub const NO_REQUEST: u64 = u64::MAX;
+
+/// A command or response with the ID of its request
+///
+/// Responses carry the ID of the command they answer. The data sent during a
+/// defmt session carries the ID of the command that started the session.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Message<T> {
+    pub id: u64,
+    pub body: T,
+}
+
+/// Exchanged as the first command and response, such that incompatible
+/// versions of the host and the daemon are detected
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    /// The version of the crate that sends the handshake
+    pub crate_version: String,
+}
+
+impl Handshake {
+    /// The handshake of this protocol version, sent by the crate with the given version
+    pub fn new(crate_version: &str) -> Self {
+        Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            crate_version: crate_version.to_owned(),
+        }
+    }
+
+    /// Whether the other side speaks the same protocol
+    pub fn is_compatible(&self, other: &Handshake) -> bool {
+        self.protocol_version == other.protocol_version
+    }
+}
+
+/// Why a message could not be read, see [read_message]
+#[derive(Debug)]
+pub enum ReadError {
+    /// The stream ended or failed, no further messages can be read
+    Io(std::io::Error),
+    /// The message was skipped because it could not be decoded, further
+    /// messages can be read. The ID is known if only the body was invalid
+    Invalid { id: Option<u64>, reason: String },
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(error) => write!(f, "Cannot read message: {error}"),
+            ReadError::Invalid {
+                id: Some(id),
+                reason,
+            } => {
+                write!(f, "Invalid message with ID {id}: {reason}")
+            }
+            ReadError::Invalid { id: None, reason } => write!(f, "Invalid message: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<std::io::Error> for ReadError {
+    fn from(error: std::io::Error) -> Self {
+        ReadError::Io(error)
+    }
+}
+
+/// Write the message with its length as a single write
+pub fn write_message<T: Serialize, W: Write>(writer: W, message: &T) -> anyhow::Result<()> {
+    let mut frame = vec![0; 4];
+    ciborium::ser::into_writer(message, &mut frame)?;
+    let length = frame.len() - 4;
+    if length > MAX_MESSAGE_SIZE {
+        anyhow::bail!(
+            "Message of {length} bytes exceeds the maximum size of {MAX_MESSAGE_SIZE} bytes"
+        );
+    }
+    frame[..4].copy_from_slice(&(length as u32).to_le_bytes());
+
+    write_frame(writer, &frame)?;
+    Ok(())
+}
+
+/// Read the next frame without decoding it, the frame includes the length
+///
+/// Allows to forward messages as a whole, see [write_frame].
+pub fn read_frame<R: Read>(mut reader: R) -> std::io::Result<Vec<u8>> {
+    let mut frame = vec![0; 4];
+    reader.read_exact(&mut frame)?;
+    let length = u32::from_le_bytes(frame[..4].try_into().unwrap()) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        // The stream is out of sync, nothing after this can be trusted
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Message of {length} bytes exceeds the maximum size of {MAX_MESSAGE_SIZE} bytes"
+            ),
+        ));
+    }
+    frame.resize(4 + length, 0);
+    reader.read_exact(&mut frame[4..])?;
+    Ok(frame)
+}
+
+/// Write a frame obtained with [read_frame]
+pub fn write_frame<W: Write>(mut writer: W, frame: &[u8]) -> std::io::Result<()> {
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+/// Read the next message
+///
+/// The message is read completely before it is decoded, such that a message
+/// that cannot be decoded does not corrupt the following ones.
+pub fn read_message<T: DeserializeOwned, R: Read>(reader: R) -> Result<Message<T>, ReadError> {
+    let frame = read_frame(reader)?;
+
+    let message: Message<ciborium::value::Value> =
+        ciborium::de::from_reader(&frame[4..]).map_err(|error| ReadError::Invalid {
+            id: None,
+            reason: error.to_string(),
+        })?;
+    let body = message
+        .body
+        .deserialized()
+        .map_err(|error| ReadError::Invalid {
+            id: Some(message.id),
+            reason: error.to_string(),
+        })?;
+
+    Ok(Message {
+        id: message.id,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+
+    use super::*;
+    use crate::win_daemon::{Commands, ErrorKind as RemoteErrorKind, RemoteError, Response};
+
+    /// Commands of a newer protocol version, which this version does not know
+    #[derive(Serialize)]
+    enum FutureCommands {
+        Teleport { core: usize },
+    }
+
+    /// Append a message to the stream
+    fn write<T: Serialize>(stream: &mut Vec<u8>, id: u64, body: T) {
+        write_message(stream, &Message { id, body }).unwrap();
+    }
+
+    fn is_io_error(error: &ReadError, kind: ErrorKind) -> bool {
+        matches!(error, ReadError::Io(error) if error.kind() == kind)
+    }
+
+    #[test]
+    fn messages_are_read_as_written() {
+        let mut stream = Vec::new();
+        write(&mut stream, 1, Commands::ListResetClasses);
+        write(&mut stream, 2, Response::Memory(vec![1, 2, 3]));
+        let mut stream = Cursor::new(stream);
+
+        let command: Message<Commands> = read_message(&mut stream).unwrap();
+        assert_eq!(command.id, 1);
+        assert!(matches!(command.body, Commands::ListResetClasses));
+        let response: Message<Response> = read_message(&mut stream).unwrap();
+        assert_eq!(response.id, 2);
+        assert!(matches!(response.body, Response::Memory(data) if data == [1, 2, 3]));
+
+        let end = read_message::<Commands, _>(&mut stream).unwrap_err();
+        assert!(is_io_error(&end, ErrorKind::UnexpectedEof), "{end}");
+    }
+
+    #[test]
+    fn frames_are_forwarded_unchanged() {
+        let mut written = Vec::new();
+        write(&mut written, 7, Commands::StopDefmtData);
+
+        let frame = read_frame(Cursor::new(&written)).unwrap();
+        assert_eq!(frame, written);
+        assert_eq!(frame[..4], ((written.len() - 4) as u32).to_le_bytes());
+
+        let mut forwarded = Vec::new();
+        write_frame(&mut forwarded, &frame).unwrap();
+        let message: Message<Commands> = read_message(Cursor::new(forwarded)).unwrap();
+        assert_eq!(message.id, 7);
+    }
+
+    #[test]
+    fn frames_exceeding_the_maximum_size_are_rejected() {
+        let mut stream = ((MAX_MESSAGE_SIZE + 1) as u32).to_le_bytes().to_vec();
+        stream.extend([0; 16]);
+
+        let error = read_frame(Cursor::new(&stream)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        // The stream cannot be resynchronized, so no further messages are read
+        let error = read_message::<Commands, _>(Cursor::new(&stream)).unwrap_err();
+        assert!(is_io_error(&error, ErrorKind::InvalidData), "{error}");
+    }
+
+    #[test]
+    fn truncated_frames_end_the_stream() {
+        let mut written = Vec::new();
+        write(&mut written, 1, Commands::ListResetClasses);
+
+        // A truncated length prefix and a truncated body
+        for length in [2, written.len() - 1] {
+            let error = read_message::<Commands, _>(Cursor::new(&written[..length])).unwrap_err();
+            assert!(
+                is_io_error(&error, ErrorKind::UnexpectedEof),
+                "Truncated to {length} bytes: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_commands_are_skipped() {
+        let mut stream = Vec::new();
+        write(&mut stream, 3, FutureCommands::Teleport { core: 1 });
+        write(&mut stream, 4, Commands::ListResetClasses);
+        let mut stream = Cursor::new(stream);
+
+        let error = read_message::<Commands, _>(&mut stream).unwrap_err();
+        assert!(
+            matches!(error, ReadError::Invalid { id: Some(3), .. }),
+            "{error}"
+        );
+        let message: Message<Commands> = read_message(&mut stream).unwrap();
+        assert_eq!(message.id, 4);
+    }
+
+    #[test]
+    fn undecodable_frames_are_skipped_without_an_id() {
+        let mut stream = 3u32.to_le_bytes().to_vec();
+        stream.extend([0xFF; 3]);
+        write(&mut stream, 5, Commands::ListResetClasses);
+        let mut stream = Cursor::new(stream);
+
+        let error = read_message::<Commands, _>(&mut stream).unwrap_err();
+        assert!(
+            matches!(error, ReadError::Invalid { id: None, .. }),
+            "{error}"
+        );
+        let message: Message<Commands> = read_message(&mut stream).unwrap();
+        assert_eq!(message.id, 5);
+    }
+
+    #[test]
+    fn handshakes_detect_other_protocol_versions() {
+        let older = Handshake {
+            protocol_version: PROTOCOL_VERSION - 1,
+            crate_version: "0.1.0".to_owned(),
+        };
+        let mut written = Vec::new();
+        write(&mut written, 0, Commands::Handshake(older.clone()));
+
+        let received: Message<Commands> = read_message(Cursor::new(written)).unwrap();
+        let Commands::Handshake(received) = received.body else {
+            panic!("Expected a handshake, got {:?}", received.body);
+        };
+        assert_eq!(received, older);
+        let own = Handshake::new("0.1.0");
+        assert!(!own.is_compatible(&received));
+        assert!(own.is_compatible(&Handshake::new("0.2.0")));
+    }
+
+    #[test]
+    fn remote_errors_keep_their_causes() {
+        let cause = RemoteError::new(RemoteErrorKind::DeviceAccess, "Device is locked");
+        let error = anyhow::Error::new(cause)
+            .context("Cannot read memory")
+            .context("Cannot read backtrace");
+        let remote =
+            RemoteError::from_error(RemoteErrorKind::Failed, &error).with_code(Some(0x100));
+
+        let mut written = Vec::new();
+        write(&mut written, 9, Response::Error(remote));
+        let response: Message<Response> = read_message(Cursor::new(written)).unwrap();
+        let Response::Error(remote) = response.body else {
+            panic!("Expected an error, got {}", response.body.name());
+        };
+
+        let error = remote.into_anyhow();
+        assert_eq!(
+            format!("{error:#}"),
+            "Cannot read backtrace: Cannot read memory: Device is locked"
+        );
+        let chain: Vec<String> = error.chain().map(|cause| cause.to_string()).collect();
+        assert_eq!(
+            chain,
+            [
+                "Cannot read backtrace",
+                "Cannot read memory",
+                "Device is locked"
+            ]
+        );
+        let root = error.downcast_ref::<RemoteError>().unwrap();
+        assert_eq!(root.kind, RemoteErrorKind::Failed);
+        assert_eq!(root.code, Some(0x100));
+    }
+}
@@ -46,6 +46,26 @@ pub struct Record {
     pub line: Option<u32>,
 }
 
+impl Record {
+    /// Emit the record to the logger of this process, the target is prefixed
+    /// with the context the record originates from
+    pub fn forward(&self, context: &str) {
+        let level: log::Level = self.level.clone().into();
+        if level <= log::max_level() {
+            log::logger().log(
+                &log::Record::builder()
+                    .args(format_args!("{}", self.message))
+                    .level(level)
+                    .target(&format!("{} <- {}", self.target, context))
+                    .module_path(self.module_path.as_deref())
+                    .file(self.file.as_deref())
+                    .line(self.line)
+                    .build(),
+            );
+        }
+    }
+}
+
 impl<'a> From<&log::Record<'a>> for Record {
     fn from(value: &log::Record) -> Self {
         Record {
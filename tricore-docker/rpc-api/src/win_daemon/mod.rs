@@ -1,15 +1,98 @@
-use std::fmt::Debug;
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+};
 
+pub mod codec;
 pub mod log;
 
 use serde::{Deserialize, Serialize};
-use tricore_common::backtrace::Stacktrace;
+use tricore_common::{
+    backtrace::walker::CsaRegion,
+    debug::{Breakpoint, CoreStatus, DebugCommand},
+    dump::{CoreDump, DumpRequest},
+    reset::{CoreResetClasses, ResetConfig},
+    run::{HaltReason, RunControl},
+    watch::{WatchRequest, WatchSample},
+};
+
+use self::codec::Handshake;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Commands {
+    /// Must be the first command, answered with [Response::Handshake]
+    Handshake(Handshake),
     WriteHex(WriteHex),
-    Reset,
-    DefmtData { address: u64 },
+    Reset(ResetConfig),
+    ListResetClasses,
+    DefmtData {
+        address: u64,
+        reset: ResetConfig,
+        control: RunControl,
+        /// The backtraces of the halted cores end at the first link outside of
+        /// these regions
+        csa_regions: Vec<CsaRegion>,
+    },
+    /// Like [Commands::DefmtData], the memory given by the request is sampled
+    /// while the device runs
+    WatchData {
+        address: u64,
+        reset: ResetConfig,
+        control: RunControl,
+        csa_regions: Vec<CsaRegion>,
+        request: WatchRequest,
+    },
+    /// Ends a session started with [Commands::DefmtData] or [Commands::WatchData]
+    ///
+    /// Must be sent exactly once per session: if it arrives before the device
+    /// halted, the session is stopped as if it was cancelled.
+    StopDefmtData,
+    /// Starts a debug session, the backtraces end at the first link outside of
+    /// the given CSA regions
+    StartDebug {
+        csa_regions: Vec<CsaRegion>,
+        /// Bounds the commands that run a core to an address, only its timeout
+        /// applies as the cancellation is not transferred
+        control: RunControl,
+    },
+    Debug {
+        core: usize,
+        command: DebugCommand,
+    },
+    EndDebug,
+    /// Reads memory during a debug session
+    ReadMemory {
+        core: usize,
+        address: u32,
+        length: usize,
+    },
+    /// Writes memory during a debug session
+    WriteMemory {
+        core: usize,
+        address: u32,
+        data: Vec<u8>,
+    },
+    /// Reads all registers during a debug session
+    ReadRegisters {
+        core: usize,
+    },
+    /// Writes a register during a debug session
+    WriteRegister {
+        core: usize,
+        name: String,
+        value: u32,
+    },
+    /// Installs a breakpoint during a debug session
+    InsertBreakpoint {
+        core: usize,
+        breakpoint: Breakpoint,
+    },
+    /// Removes a breakpoint during a debug session
+    RemoveBreakpoint {
+        core: usize,
+        breakpoint: Breakpoint,
+    },
+    CaptureDump(DumpRequest),
 }
 
 #[derive(Deserialize, Serialize)]
@@ -29,23 +112,135 @@ impl Debug for WriteHex {
 
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Response {
+    Handshake(Handshake),
     Ok,
-    Error,
-    Log(String),
+    Error(RemoteError),
+    /// A log record of the daemon, sent at any time with
+    /// [codec::NO_REQUEST] as its ID
+    Log(self::log::Record),
     DefmtData(Vec<u8>),
-    StackFrame(Stacktrace),
+    Halted(Box<HaltReason>),
+    ResetClasses(Vec<CoreResetClasses>),
+    DebugSession {
+        core_count: usize,
+    },
+    CoreStatus(CoreStatus),
+    Dump(Box<CoreDump>),
+    Memory(Vec<u8>),
+    Registers(BTreeMap<String, u32>),
+    WatchSample(WatchSample),
+}
+
+/// Why a command failed in the daemon
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RemoteError {
+    pub kind: ErrorKind,
+    /// The error code reported by the MCD library, if the command failed there
+    pub code: Option<u32>,
+    /// The error message followed by its causes
+    pub chain: Vec<String>,
 }
 
-pub enum Error {
-    Internal,
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    /// The command was executed, but failed
+    Failed,
+    /// The command is not valid in the current state, e.g. a debug command
+    /// outside of a debug session
+    InvalidState,
+    /// The command could not be decoded
+    InvalidMessage,
+    /// The device cannot be accessed, e.g. because it is locked, powered down
+    /// or in reset
+    DeviceAccess,
+    /// The command was interrupted because the device was reset
+    DeviceReset,
+    /// The response does not fit the command, see [Response::as_result]
+    UnexpectedResponse,
 }
 
+impl RemoteError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RemoteError {
+            kind,
+            code: None,
+            chain: vec![message.into()],
+        }
+    }
+
+    /// Capture the message and the causes of the error
+    pub fn from_error(kind: ErrorKind, error: &anyhow::Error) -> Self {
+        RemoteError {
+            kind,
+            code: None,
+            chain: error.chain().map(|cause| cause.to_string()).collect(),
+        }
+    }
+
+    pub fn with_code(self, code: Option<u32>) -> Self {
+        RemoteError { code, ..self }
+    }
+
+    /// Convert into an error with the same causes as the error in the daemon
+    ///
+    /// The innermost cause remains a [RemoteError], such that the kind and the
+    /// code can be retrieved with [anyhow::Error::downcast_ref].
+    pub fn into_anyhow(self) -> anyhow::Error {
+        let RemoteError {
+            kind,
+            code,
+            mut chain,
+        } = self;
+        let root = chain.pop().unwrap_or_else(|| "Unknown error".to_owned());
+        let root = anyhow::Error::new(RemoteError {
+            kind,
+            code,
+            chain: vec![root],
+        });
+        chain
+            .into_iter()
+            .rev()
+            .fold(root, |error, context| error.context(context))
+    }
+}
+
+impl Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.chain.join(": "))
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
 impl Response {
-    pub fn as_result(&self) -> Result<(), Error> {
+    /// Interpret the response to a command that returns no data
+    pub fn as_result(&self) -> Result<(), RemoteError> {
         match self {
             Self::Ok => Ok(()),
-            Self::Error => Err(Error::Internal),
-            _ => panic!("Invalid cast"),
+            Self::Error(error) => Err(error.clone()),
+            response => Err(RemoteError::new(
+                ErrorKind::UnexpectedResponse,
+                format!("Expected no data, received {}", response.name()),
+            )),
+        }
+    }
+
+    /// The name of the variant, to describe unexpected responses without their data
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Handshake(_) => "Handshake",
+            Self::Ok => "Ok",
+            Self::Error(_) => "Error",
+            Self::Log(_) => "Log",
+            Self::DefmtData(_) => "DefmtData",
+            Self::Halted(_) => "Halted",
+            Self::ResetClasses(_) => "ResetClasses",
+            Self::DebugSession { .. } => "DebugSession",
+            Self::CoreStatus(_) => "CoreStatus",
+            Self::Dump(_) => "Dump",
+            Self::Memory(_) => "Memory",
+            Self::Registers(_) => "Registers",
+            Self::WatchSample(_) => "WatchSample",
         }
     }
 }
@@ -1,4 +1,7 @@
-use std::process::{Command, Stdio};
+use std::{
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+};
 
 use anyhow::Context;
 
@@ -53,6 +56,9 @@ impl<'a, 'pipe> DockerBuilder<'a, 'pipe> {
         let command = docker_command
             .stderr(Stdio::inherit())
             .stdout(Stdio::inherit())
+            // Signals like Ctrl-C must not reach the container directly, they
+            // are handled by ending the session gracefully
+            .process_group(0)
             .args(["run", "--init", "--rm", "--network=host"]);
 
         let mut daemon_command = "RUST_LOG=trace xvfb-run wine64 win-daemon.exe".to_owned();
@@ -105,7 +111,10 @@ pub struct Created<'a> {
     image_name: Option<&'a str>,
 }
 
-pub struct Spawned;
+/// A running docker instance, the container is removed when this is dropped
+pub struct Spawned {
+    name: Option<String>,
+}
 
 pub struct DockerInstance<S> {
     state: S,
@@ -115,6 +124,7 @@ impl<'a> DockerInstance<Created<'a>> {
     pub fn spawn(mut self) -> anyhow::Result<DockerInstance<Spawned>> {
         log::debug!("Spawning docker with command {:?}", self.state.command);
         if let Some(image_name) = self.state.image_name {
+            log::warn!("Removing/Killing docker container \"{}\"", image_name);
             kill_docker_with_name(image_name)
                 .with_context(|| "Could not remove previous docker instance")?;
         }
@@ -126,16 +136,32 @@ impl<'a> DockerInstance<Created<'a>> {
 
         std::thread::spawn(move || {
             let exit_status = child.wait().expect("Docker did not execute properly");
-            assert!(exit_status.success(), "docker command failed!");
+            if !exit_status.success() {
+                log::debug!("Docker exited with {exit_status}");
+            }
         });
 
-        Ok(DockerInstance { state: Spawned })
+        Ok(DockerInstance {
+            state: Spawned {
+                name: self.state.image_name.map(str::to_owned),
+            },
+        })
+    }
+}
+
+impl Drop for Spawned {
+    fn drop(&mut self) {
+        if let Some(name) = &self.name {
+            log::debug!("Stopping docker container \"{name}\"");
+            if let Err(error) = kill_docker_with_name(name) {
+                log::warn!("Could not stop docker container \"{name}\": {error:#}");
+            }
+        }
     }
 }
 
 fn kill_docker_with_name(name: &str) -> anyhow::Result<()> {
-    log::warn!("Removing/Killing docker container \"{}\"", name);
-    // Kill potentially existing container before spawning (ingore output because we don't care)
+    // Kill the container if it exists (ignore output because we don't care)
     Command::new("docker")
         .arg("container")
         .arg("kill")
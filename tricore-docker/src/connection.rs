@@ -0,0 +1,76 @@
+use std::{os::unix::net::UnixStream, sync::Arc};
+
+use rpc_api::win_daemon::{
+    codec::{read_message, write_message, Message, ReadError},
+    Commands, Response,
+};
+
+use super::{daemon::VirtualizedDaemon, ftdi::FTDIClient, pipe::DuplexPipeConnection};
+
+/// Name of the container started for a single invocation
+pub const CONTAINER_NAME: &str = "tricore-probe";
+
+/// A container with the daemon and the FTDI client it talks to
+pub struct Container {
+    pipes: Arc<DuplexPipeConnection>,
+    _docker: VirtualizedDaemon,
+    _ftdi: FTDIClient,
+}
+
+impl Container {
+    /// Start the container with the given name, a container of the same name
+    /// is replaced
+    pub fn spawn(name: &str, with_gui: Option<String>) -> anyhow::Result<Self> {
+        let rpc_channel_ftdi = Arc::new(DuplexPipeConnection::new());
+        let pipe_for_driver = rpc_channel_ftdi.clone();
+
+        let rpc_channel_commands = Arc::new(DuplexPipeConnection::new());
+
+        log::trace!("Spawning FTDI client for {:?}", pipe_for_driver);
+        let ftdi = FTDIClient::spawn(pipe_for_driver);
+
+        log::trace!("Spawning virtualized docker daemon");
+        let _docker = VirtualizedDaemon::spawn(
+            name,
+            with_gui,
+            rpc_channel_commands.clone(),
+            rpc_channel_ftdi,
+        )?;
+
+        Ok(Container {
+            pipes: rpc_channel_commands,
+            _docker,
+            _ftdi: ftdi,
+        })
+    }
+
+    /// The channel the commands are sent and the responses are received on
+    pub fn pipes(&self) -> &Arc<DuplexPipeConnection> {
+        &self.pipes
+    }
+}
+
+/// How the commands reach the daemon in the container
+pub enum Connection {
+    /// A container started for this process only
+    Owned(Container),
+    /// A session of the daemon that keeps its container running, see
+    /// [crate::persistent]
+    Persistent(UnixStream),
+}
+
+impl Connection {
+    pub fn send(&self, message: &Message<Commands>) -> anyhow::Result<()> {
+        match self {
+            Connection::Owned(container) => write_message(container.pipes.to().open(), message),
+            Connection::Persistent(stream) => write_message(stream, message),
+        }
+    }
+
+    pub fn receive(&self) -> Result<Message<Response>, ReadError> {
+        match self {
+            Connection::Owned(container) => read_message(container.pipes.from().open()),
+            Connection::Persistent(stream) => read_message(stream),
+        }
+    }
+}
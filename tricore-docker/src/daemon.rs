@@ -15,7 +15,10 @@ pub struct VirtualizedDaemon {
 }
 
 impl VirtualizedDaemon {
+    /// Start the container with the given name, a container of the same name
+    /// is replaced
     pub fn spawn(
+        name: &str,
         with_gui: Option<String>,
         rpc_channel_commands: Arc<DuplexPipeConnection>,
         rpc_channel_ftdi: Arc<DuplexPipeConnection>,
@@ -31,7 +34,7 @@ impl VirtualizedDaemon {
 
         let docker = builder
             .image_name("veecle/flash-tricore")
-            .named("tricore-probe")
+            .named(name)
             .add_pipe_as_argument("ftd2xx-log-file", &ftd2xx_log_file)
             .add_pipe_as_argument("log-file", &log_file)
             .add_pipe_as_argument("in-from-driver", rpc_channel_ftdi.from())
@@ -1,57 +1,85 @@
-use std::{io::Write, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::Args;
 
-use rpc_api::win_daemon::{Commands, Response, WriteHex};
-use tricore_common::{backtrace::Stacktrace, Chip};
+use rpc_api::win_daemon::{
+    codec::{Handshake, Message, ReadError},
+    Commands, Response, WriteHex,
+};
+use tricore_common::{
+    backtrace::walker::CsaRegion,
+    debug::{Breakpoint, CoreStatus, DebugCommand, DebugTarget},
+    dump::{CoreDump, DumpRequest},
+    reset::{CoreResetClasses, ResetConfig},
+    run::{HaltReason, RunControl},
+    watch::{WatchRequest, WatchSample},
+    Chip,
+};
 
-use self::{daemon::VirtualizedDaemon, ftdi::FTDIClient, pipe::DuplexPipeConnection};
+use self::connection::{Connection, Container, CONTAINER_NAME};
 
 pub type Config = DockerConfig;
 
 mod builder;
+mod connection;
 mod daemon;
 mod ftdi;
 mod logger;
+pub mod persistent;
 mod pipe;
 
 #[derive(Args, Debug)]
 pub struct DockerConfig {
     #[arg(long)]
     with_gui: Option<String>,
+
+    /// Start a container for this invocation, even if a daemon started with
+    /// `tricore-probe daemon start` is running
+    #[arg(long, default_value_t = false)]
+    no_daemon: bool,
 }
 
 pub struct ChipInterface {
-    server: Arc<DuplexPipeConnection>,
-    _docker: VirtualizedDaemon,
-    _ftdi: FTDIClient,
+    connection: Connection,
+    /// The ID of the next request sent to the daemon
+    next_id: AtomicU64,
 }
 
 impl Chip for ChipInterface {
     type Config = DockerConfig;
 
-    fn new(enable_gui: Config) -> anyhow::Result<Self> {
-        let rpc_channel_ftdi = Arc::new(DuplexPipeConnection::new());
-        let pipe_for_driver = rpc_channel_ftdi.clone();
-
-        let rpc_channel_commands = Arc::new(DuplexPipeConnection::new());
-
-        log::trace!("Spawning FTDI client for {:?}", pipe_for_driver);
-        let ftdi = FTDIClient::spawn(pipe_for_driver);
-
-        log::trace!("Spawning virtualized docker daemon");
-        let _docker = VirtualizedDaemon::spawn(
-            enable_gui.with_gui,
-            rpc_channel_commands.clone(),
-            rpc_channel_ftdi,
-        )?;
+    fn new(config: Config) -> anyhow::Result<Self> {
+        let session = if config.no_daemon {
+            None
+        } else {
+            persistent::connect()?
+        };
+        let (connection, first_id) = match session {
+            Some((stream, first_id)) => {
+                log::info!("Using the running daemon");
+                if config.with_gui.is_some() {
+                    log::warn!("Ignoring --with-gui, the container of the daemon is used");
+                }
+                (Connection::Persistent(stream), first_id)
+            }
+            None => (
+                Connection::Owned(Container::spawn(CONTAINER_NAME, config.with_gui)?),
+                0,
+            ),
+        };
 
-        Ok(ChipInterface {
-            server: rpc_channel_commands,
-            _ftdi: ftdi,
-            _docker,
-        })
+        let interface = ChipInterface {
+            connection,
+            next_id: AtomicU64::new(first_id),
+        };
+        interface.handshake()?;
+        Ok(interface)
     }
 
     fn flash_hex(&self, ihex: String, halt_memtool: bool) -> anyhow::Result<()> {
@@ -61,9 +89,8 @@ impl Chip for ChipInterface {
             halt_memtool,
         });
 
-        let response = self.send_request(request)?;
-
-        assert!(response.as_result().is_ok());
+        self.execute(request)
+            .with_context(|| "Could not flash the device")?;
         log::trace!("Flash completed");
         Ok(())
     }
@@ -71,46 +98,375 @@ impl Chip for ChipInterface {
     fn read_rtt<W: Write>(
         &self,
         rtt_control_block: u64,
-        mut decoder: W,
-    ) -> anyhow::Result<Stacktrace> {
-        let result = self.send_request(Commands::DefmtData {
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
+        decoder: W,
+    ) -> anyhow::Result<HaltReason> {
+        let command = Commands::DefmtData {
             address: rtt_control_block,
-        })?;
-        result
-            .as_result()
-            .map_err(|_| anyhow::Error::msg("Could not start decoding defmt data"))?;
+            reset: reset.clone(),
+            control: control.clone(),
+            csa_regions: csa_regions.to_vec(),
+        };
+        self.rtt_session(command, control, decoder, None)
+    }
 
-        loop {
-            let response = self.next_response()?;
-            match response {
-                Response::DefmtData(data) => {
-                    decoder.write_all(data.as_slice())?;
-                }
-                Response::StackFrame(frame) => return Ok(frame),
-                Response::Ok => todo!(),
-                Response::Error => todo!(),
-                Response::Log(_) => todo!(),
+    fn watch<W: Write>(
+        &self,
+        rtt_control_block: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
+        request: &WatchRequest,
+        decoder: W,
+        samples: &mut dyn FnMut(WatchSample) -> anyhow::Result<()>,
+    ) -> anyhow::Result<HaltReason> {
+        let command = Commands::WatchData {
+            address: rtt_control_block,
+            reset: reset.clone(),
+            control: control.clone(),
+            csa_regions: csa_regions.to_vec(),
+            request: request.clone(),
+        };
+        self.rtt_session(command, control, decoder, Some(samples))
+    }
+
+    fn reset(&self, reset: &ResetConfig) -> anyhow::Result<()> {
+        log::trace!("Sending reset command to daemon");
+        self.execute(Commands::Reset(reset.clone()))
+            .with_context(|| "Could not reset the device")
+    }
+
+    fn list_reset_classes(&self) -> anyhow::Result<Vec<CoreResetClasses>> {
+        match self
+            .send_request(Commands::ListResetClasses)
+            .with_context(|| "Could not query reset classes")?
+        {
+            Response::ResetClasses(reset_classes) => Ok(reset_classes),
+            response => Err(anyhow::Error::msg(format!(
+                "Could not query reset classes, daemon responded with {}",
+                response.name()
+            ))),
+        }
+    }
+
+    fn capture_dump(&self, request: &DumpRequest) -> anyhow::Result<CoreDump> {
+        log::trace!("Sending dump command to daemon");
+        match self
+            .send_request(Commands::CaptureDump(request.clone()))
+            .with_context(|| "Could not capture core dump")?
+        {
+            Response::Dump(dump) => Ok(*dump),
+            response => Err(anyhow::Error::msg(format!(
+                "Could not capture core dump, daemon responded with {}",
+                response.name()
+            ))),
+        }
+    }
+
+    fn debug<R, F: FnOnce(&mut dyn DebugTarget) -> anyhow::Result<R>>(
+        &self,
+        csa_regions: &[CsaRegion],
+        control: &RunControl,
+        session: F,
+    ) -> anyhow::Result<R> {
+        log::trace!("Starting debug session in daemon");
+        // The daemon executes one command at a time, a run-to cannot be cancelled
+        // while it waits for the core and is only stopped by the timeout
+        let core_count = match self
+            .send_request(Commands::StartDebug {
+                csa_regions: csa_regions.to_vec(),
+                control: control.clone(),
+            })
+            .with_context(|| "Could not start debug session")?
+        {
+            Response::DebugSession { core_count } => core_count,
+            response => {
+                return Err(anyhow::Error::msg(format!(
+                    "Could not start debug session, daemon responded with {}",
+                    response.name()
+                )))
             }
+        };
+
+        let result = session(&mut RemoteDebugTarget {
+            interface: self,
+            core_count,
+        });
+
+        self.execute(Commands::EndDebug)
+            .with_context(|| "Could not end debug session")?;
+        result
+    }
+}
+
+/// Forwards debug commands to the daemon during a debug session
+struct RemoteDebugTarget<'a> {
+    interface: &'a ChipInterface,
+    core_count: usize,
+}
+
+impl<'a> DebugTarget for RemoteDebugTarget<'a> {
+    fn core_count(&self) -> usize {
+        self.core_count
+    }
+
+    fn execute(&mut self, core: usize, command: DebugCommand) -> anyhow::Result<CoreStatus> {
+        match self
+            .interface
+            .send_request(Commands::Debug { core, command })
+            .with_context(|| format!("Could not execute {command:?} on core {core}"))?
+        {
+            Response::CoreStatus(status) => Ok(status),
+            response => Err(anyhow::Error::msg(format!(
+                "Could not execute {command:?} on core {core}, daemon responded with {}",
+                response.name()
+            ))),
         }
     }
+
+    fn read_memory(&self, core: usize, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        match self
+            .interface
+            .send_request(Commands::ReadMemory {
+                core,
+                address,
+                length,
+            })
+            .with_context(|| format!("Could not read memory at {address:#010X}"))?
+        {
+            Response::Memory(data) => Ok(data),
+            response => Err(anyhow::Error::msg(format!(
+                "Could not read memory at {address:#010X}, daemon responded with {}",
+                response.name()
+            ))),
+        }
+    }
+
+    fn write_memory(&mut self, core: usize, address: u32, data: &[u8]) -> anyhow::Result<()> {
+        self.interface
+            .execute(Commands::WriteMemory {
+                core,
+                address,
+                data: data.to_vec(),
+            })
+            .with_context(|| format!("Could not write memory at {address:#010X}"))
+    }
+
+    fn read_registers(&self, core: usize) -> anyhow::Result<BTreeMap<String, u32>> {
+        match self
+            .interface
+            .send_request(Commands::ReadRegisters { core })
+            .with_context(|| format!("Could not read registers of core {core}"))?
+        {
+            Response::Registers(registers) => Ok(registers),
+            response => Err(anyhow::Error::msg(format!(
+                "Could not read registers of core {core}, daemon responded with {}",
+                response.name()
+            ))),
+        }
+    }
+
+    fn write_register(&mut self, core: usize, name: &str, value: u32) -> anyhow::Result<()> {
+        self.interface
+            .execute(Commands::WriteRegister {
+                core,
+                name: name.to_owned(),
+                value,
+            })
+            .with_context(|| format!("Could not write {name} register"))
+    }
+
+    fn insert_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()> {
+        self.interface
+            .execute(Commands::InsertBreakpoint { core, breakpoint })
+            .with_context(|| format!("Could not install {breakpoint:?}"))
+    }
+
+    fn remove_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()> {
+        self.interface
+            .execute(Commands::RemoveBreakpoint { core, breakpoint })
+            .with_context(|| format!("Could not remove {breakpoint:?}"))
+    }
 }
 
 impl ChipInterface {
+    /// Start a session with [Commands::DefmtData] or [Commands::WatchData] and
+    /// pass the data sent by the daemon to the decoder and the sample sink
+    /// until the device halts
+    fn rtt_session<W: Write>(
+        &self,
+        command: Commands,
+        control: &RunControl,
+        mut decoder: W,
+        mut samples: Option<&mut dyn FnMut(WatchSample) -> anyhow::Result<()>>,
+    ) -> anyhow::Result<HaltReason> {
+        let id = self.send_command(command)?;
+        self.response_to(id)
+            .and_then(expect_ok)
+            .with_context(|| "Could not start decoding defmt data")?;
+
+        // The daemon expects exactly one stop command per session, either to
+        // cancel it or to acknowledge its end
+        let connection = &self.connection;
+        let next_id = &self.next_id;
+        let stop_sent = AtomicBool::new(false);
+        let send_stop = || {
+            if !stop_sent.swap(true, Ordering::SeqCst) {
+                log::trace!("Ending defmt session in daemon");
+                let id = next_id.fetch_add(1, Ordering::SeqCst);
+                if let Err(error) = send_command(connection, id, Commands::StopDefmtData) {
+                    log::error!("Cannot end defmt session: {error:#}");
+                }
+            }
+        };
+        let finished = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while !finished.load(Ordering::SeqCst) {
+                    if control.cancellation.is_cancelled() {
+                        send_stop();
+                        return;
+                    }
+                    std::thread::sleep(CANCELLATION_POLL_INTERVAL);
+                }
+            });
+
+            let result = loop {
+                let response = match self.response_to(id) {
+                    Ok(response) => response,
+                    Err(error) => break Err(error),
+                };
+                match response {
+                    Response::DefmtData(data) => {
+                        if let Err(error) = decoder.write_all(data.as_slice()) {
+                            break Err(error.into());
+                        }
+                    }
+                    Response::Halted(halt_reason) => break Ok(*halt_reason),
+                    Response::WatchSample(sample) => match samples.as_mut() {
+                        Some(samples) => {
+                            if let Err(error) = samples(sample) {
+                                break Err(error);
+                            }
+                        }
+                        None => log::warn!("Received a sample outside of a watch session"),
+                    },
+                    response => {
+                        break Err(anyhow::Error::msg(format!(
+                            "Unexpected {} response during a defmt session",
+                            response.name()
+                        )))
+                    }
+                }
+            };
+
+            finished.store(true, Ordering::SeqCst);
+            if result.is_ok() {
+                send_stop();
+            }
+            result
+        })
+    }
+
+    /// Check that the daemon speaks the same protocol
+    fn handshake(&self) -> anyhow::Result<()> {
+        let handshake = Handshake::new(env!("CARGO_PKG_VERSION"));
+        match self.send_request(Commands::Handshake(handshake.clone()))? {
+            Response::Handshake(daemon) if handshake.is_compatible(&daemon) => {
+                log::debug!("Connected to daemon version {}", daemon.crate_version);
+                Ok(())
+            }
+            Response::Handshake(daemon) => bail!(
+                "The daemon version {} speaks protocol version {}, but version {} is required. \
+                 Rebuild the docker image",
+                daemon.crate_version,
+                daemon.protocol_version,
+                handshake.protocol_version
+            ),
+            response => bail!(
+                "Expected a handshake, daemon responded with {}",
+                response.name()
+            ),
+        }
+    }
+
+    /// Send a command that returns no data and wait for its response
+    fn execute(&self, request: Commands) -> anyhow::Result<()> {
+        self.send_request(request).and_then(expect_ok)
+    }
+
+    /// Send the command and wait for its response, see [Self::response_to]
     fn send_request(&self, request: Commands) -> anyhow::Result<Response> {
-        self.send_command(request)?;
-        self.next_response()
+        let id = self.send_command(request)?;
+        self.response_to(id)
     }
 
-    fn send_command(&self, request: Commands) -> anyhow::Result<()> {
-        log::trace!("Sending request {:?}", request);
-        ciborium::ser::into_writer(&request, self.server.to().open()).unwrap();
-        Ok(())
+    /// Send the command, returns the ID of the request
+    fn send_command(&self, request: Commands) -> anyhow::Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        send_command(&self.connection, id, request)?;
+        Ok(id)
     }
 
-    fn next_response(&self) -> anyhow::Result<Response> {
-        let r = ciborium::de::from_reader(self.server.from().open())
-            .with_context(|| "Failed to obtain response from docker")?;
+    /// Wait for the next response to the request with the given ID
+    ///
+    /// Responses to other requests and responses that cannot be decoded are
+    /// skipped, log records of the daemon are forwarded to the logger. An error
+    /// reported by the daemon is returned with its causes, see
+    /// [rpc_api::win_daemon::RemoteError::into_anyhow].
+    fn response_to(&self, id: u64) -> anyhow::Result<Response> {
+        loop {
+            let response: Message<Response> = match self.connection.receive() {
+                Ok(response) => response,
+                Err(ReadError::Invalid {
+                    id: Some(response_id),
+                    reason,
+                }) if response_id == id => {
+                    bail!("Cannot decode the response of the daemon: {reason}")
+                }
+                Err(error @ ReadError::Invalid { .. }) => {
+                    log::warn!("Skipping response: {error}");
+                    continue;
+                }
+                Err(error) => {
+                    return Err(error).with_context(|| "Failed to obtain response from docker")
+                }
+            };
 
-        Ok(r)
+            if let Response::Log(record) = &response.body {
+                record.forward("docker");
+                continue;
+            }
+            if response.id != id {
+                log::warn!(
+                    "Skipping {} response to request {}, expected a response to request {id}",
+                    response.body.name(),
+                    response.id
+                );
+                continue;
+            }
+            return match response.body {
+                Response::Error(error) => Err(error.into_anyhow()),
+                body => Ok(body),
+            };
+        }
     }
 }
+
+/// Check that the response does not contain data, see [Response::as_result]
+fn expect_ok(response: Response) -> anyhow::Result<()> {
+    Ok(response.as_result()?)
+}
+
+/// Interval at which a running defmt session checks whether it was cancelled
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn send_command(connection: &Connection, id: u64, request: Commands) -> anyhow::Result<()> {
+    log::trace!("Sending request {id}: {request:?}");
+    connection
+        .send(&Message { id, body: request })
+        .with_context(|| "Failed to send request to docker")
+}
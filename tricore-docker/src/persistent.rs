@@ -0,0 +1,614 @@
+//! A daemon that keeps the container running between invocations
+//!
+//! `tricore-probe daemon start` spawns a detached process that owns the
+//! container and the FTDI client, see [run]. Later invocations connect to its
+//! socket in the [runtime_directory] and their commands are relayed to the
+//! container, one session at a time.
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    os::unix::{
+        net::{UnixListener, UnixStream},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use clap::{Args, Subcommand};
+use rpc_api::win_daemon::{
+    codec::{read_frame, read_message, write_frame, write_message, Handshake, Message, NO_REQUEST},
+    Commands,
+};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use crate::{connection::Container, pipe::DuplexPipeConnection};
+
+/// Name of the socket clients connect to
+const SOCKET: &str = "daemon.sock";
+
+/// Name of the file that contains the process ID of the running daemon
+const LOCK: &str = "daemon.lock";
+
+/// Name of the file the output of a started daemon is written to
+const LOG: &str = "daemon.log";
+
+/// Name of the container of the daemon, invocations with `--no-daemon` start
+/// their own container and must not replace it
+const CONTAINER_NAME: &str = "tricore-probe-daemon";
+
+/// Time the daemon gets to start the container and accept connections
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Interval at which a starting daemon is checked
+const START_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time the container gets to end a session the client left open
+const SESSION_END_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Manages the daemon, expected as the `daemon` subcommand of tricore-probe
+#[derive(Subcommand, Debug)]
+pub enum DaemonCommand {
+    /// Start the container in the background, later invocations use it instead
+    /// of starting their own
+    Start(StartArgs),
+    /// Stop the container once the active session ended
+    Stop,
+    /// Print whether the daemon is running
+    Status,
+    /// Run the daemon in the foreground, used by the start command
+    #[command(hide = true)]
+    Run(StartArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct StartArgs {
+    #[arg(long)]
+    with_gui: Option<String>,
+}
+
+/// What the daemon reports with `tricore-probe daemon status`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub uptime: Duration,
+    /// The number of sessions started since the daemon started
+    pub sessions: u64,
+    /// Whether a session is active, further sessions wait for it to end
+    pub active: bool,
+}
+
+impl Display for DaemonStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Daemon is running with pid {} since {}s, {} session(s) started",
+            self.pid,
+            self.uptime.as_secs(),
+            self.sessions
+        )?;
+        if self.active {
+            write!(f, ", a session is active")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sent by a client as the first message
+#[derive(Serialize, Deserialize, Debug)]
+enum Request {
+    /// Relay the following messages to the container, see [Reply::Connected]
+    Connect,
+    Status,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Reply {
+    /// Another session is active, the request is answered once it ended
+    Queued,
+    /// Messages are relayed to the container from now on, the IDs of the
+    /// requests of this session start at the given ID
+    Connected {
+        first_id: u64,
+    },
+    Status(DaemonStatus),
+    /// The daemon stops the container and exits
+    Stopping,
+}
+
+/// The directory of the socket and the lock of the daemon
+///
+/// Defaults to a folder in the temporary directory, can be changed with the
+/// `TRICORE_PROBE_RUNTIME_DIR` environment variable.
+pub fn runtime_directory() -> PathBuf {
+    std::env::var_os("TRICORE_PROBE_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("tricore-probe"))
+}
+
+/// Start the daemon in the background and wait until it accepts connections
+pub fn start(args: StartArgs) -> anyhow::Result<DaemonStatus> {
+    if let Some(status) = status()? {
+        bail!("Daemon is already running with pid {}", status.pid);
+    }
+    let directory = runtime_directory();
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Could not create {}", directory.display()))?;
+    let log_path = directory.join(LOG);
+    let log = File::create(&log_path)
+        .with_context(|| format!("Could not create {}", log_path.display()))?;
+
+    let mut command = Command::new(
+        std::env::current_exe().with_context(|| "Cannot locate tricore-probe executable")?,
+    );
+    command.args(["daemon", "run", "--log-level"]);
+    command.arg(log::max_level().to_string().to_lowercase());
+    if let Some(with_gui) = &args.with_gui {
+        command.arg("--with-gui").arg(with_gui);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // The daemon must outlive signals sent to the terminal
+        .process_group(0)
+        .spawn()
+        .with_context(|| "Could not spawn daemon")?;
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = status()? {
+            return Ok(status);
+        }
+        if let Some(exit_status) = child.try_wait()? {
+            bail!(
+                "Daemon exited with {exit_status}, see {} for its output",
+                log_path.display()
+            );
+        }
+        if started.elapsed() > START_TIMEOUT {
+            bail!(
+                "Daemon did not start within {START_TIMEOUT:?}, see {} for its output",
+                log_path.display()
+            );
+        }
+        std::thread::sleep(START_POLL_INTERVAL);
+    }
+}
+
+/// Stop the daemon once the active session ended, returns whether it was running
+pub fn stop() -> anyhow::Result<bool> {
+    match send_request(Request::Stop)? {
+        Some((_, Reply::Stopping)) => Ok(true),
+        Some((_, reply)) => bail!("Expected the daemon to stop, it replied with {reply:?}"),
+        None => Ok(false),
+    }
+}
+
+/// The status of the daemon, if it is running
+pub fn status() -> anyhow::Result<Option<DaemonStatus>> {
+    match send_request(Request::Status)? {
+        Some((_, Reply::Status(status))) => Ok(Some(status)),
+        Some((_, reply)) => bail!("Expected the status of the daemon, it replied with {reply:?}"),
+        None => Ok(None),
+    }
+}
+
+/// Start a session with the daemon, if it is running
+///
+/// Returns the connection to relay messages over and the ID of the first request.
+pub(crate) fn connect() -> anyhow::Result<Option<(UnixStream, u64)>> {
+    match send_request(Request::Connect)? {
+        Some((stream, Reply::Connected { first_id })) => Ok(Some((stream, first_id))),
+        Some((_, reply)) => bail!("Expected a session, the daemon replied with {reply:?}"),
+        None => Ok(None),
+    }
+}
+
+/// Send the request and wait for its reply, [None] if the daemon is not running
+fn send_request(request: Request) -> anyhow::Result<Option<(UnixStream, Reply)>> {
+    let socket = runtime_directory().join(SOCKET);
+    let stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(error)
+            if matches!(
+                error.kind(),
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("Could not connect to {}", socket.display()))
+        }
+    };
+
+    send_control(&stream, request)?;
+    loop {
+        let reply: Message<Reply> =
+            read_message(&stream).with_context(|| "Daemon did not reply")?;
+        match reply.body {
+            Reply::Queued => log::warn!("Waiting for the active session of the daemon to end"),
+            reply => return Ok(Some((stream, reply))),
+        }
+    }
+}
+
+/// Send a request of a client or a reply of the daemon, before a session starts
+fn send_control<T: Serialize>(stream: &UnixStream, body: T) -> anyhow::Result<()> {
+    write_message(
+        stream,
+        &Message {
+            id: NO_REQUEST,
+            body,
+        },
+    )
+}
+
+/// Run the daemon until it is stopped, see [stop]
+pub fn run(args: StartArgs) -> anyhow::Result<()> {
+    let directory = runtime_directory();
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Could not create {}", directory.display()))?;
+    let _lock = Lock::acquire(directory.join(LOCK))?;
+
+    let container = Container::spawn(CONTAINER_NAME, args.with_gui)?;
+
+    let socket = directory.join(SOCKET);
+    // A socket left behind by a daemon that did not exit cleanly, we own the lock
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Could not listen on {}", socket.display()))?;
+
+    let server = Arc::new(Server {
+        pipes: container.pipes().clone(),
+        client: Mutex::new(None),
+        session_end: Mutex::new(None),
+        session: Mutex::new(()),
+        sessions: AtomicU64::new(0),
+        started: Instant::now(),
+    });
+
+    let responses = server.clone();
+    std::thread::spawn(move || responses.relay_responses());
+
+    let (stop_sender, stop_receiver) = mpsc::channel();
+    let clients = server.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    log::warn!("Cannot accept client: {error}");
+                    continue;
+                }
+            };
+            let server = clients.clone();
+            let stop_sender = stop_sender.clone();
+            std::thread::spawn(move || {
+                if let Err(error) = server.serve(stream, &stop_sender) {
+                    log::warn!("Client failed: {error:#}");
+                }
+            });
+        }
+    });
+
+    log::info!("Daemon is ready, listening on {}", socket.display());
+    let _ = stop_receiver.recv();
+    log::info!("Stopping daemon");
+    let _ = fs::remove_file(&socket);
+    drop(container);
+    Ok(())
+}
+
+/// Relays the messages of the client of the active session to the container
+struct Server {
+    pipes: Arc<DuplexPipeConnection>,
+    /// The client of the active session, receives the responses of the container
+    client: Mutex<Option<UnixStream>>,
+    /// Notified once the container answered the request with the given ID,
+    /// see [Server::end_session]
+    session_end: Mutex<Option<(u64, Sender<()>)>>,
+    /// Held while a session is active, such that sessions are serialised
+    session: Mutex<()>,
+    sessions: AtomicU64,
+    started: Instant,
+}
+
+impl Server {
+    fn serve(&self, stream: UnixStream, stop: &Sender<()>) -> anyhow::Result<()> {
+        let request: Message<Request> = read_message(&stream)?;
+        log::debug!("Received {:?}", request.body);
+        match request.body {
+            Request::Status => send_control(&stream, Reply::Status(self.status())),
+            Request::Connect => {
+                let _session = self.lock_session(&stream)?;
+                let session = self.sessions.fetch_add(1, Ordering::SeqCst);
+                // IDs must not repeat, otherwise a late response of the previous
+                // session would be taken as the response of this session
+                send_control(
+                    &stream,
+                    Reply::Connected {
+                        first_id: session << 32,
+                    },
+                )?;
+                *self.client() = Some(stream.try_clone()?);
+                let mut open = None;
+                let relayed = self.relay_commands(&stream, &mut open);
+                *self.client() = None;
+                // The next session must not be started within the session of
+                // this client, e.g. its handshake would end a defmt session
+                if let Some(open) = open {
+                    log::info!("Client of session {session} left a {open:?} session open");
+                    if let Err(error) = self.end_session(session, open) {
+                        log::error!("Cannot end {open:?} session: {error:#}");
+                    }
+                }
+                log::info!("Session {session} ended");
+                relayed
+            }
+            Request::Stop => {
+                let _session = self.lock_session(&stream)?;
+                send_control(&stream, Reply::Stopping)?;
+                let _ = stop.send(());
+                Ok(())
+            }
+        }
+    }
+
+    /// Wait for the active session to end, the client is told that it is queued
+    fn lock_session(&self, stream: &UnixStream) -> anyhow::Result<MutexGuard<'_, ()>> {
+        match self.session.try_lock() {
+            Ok(session) => Ok(session),
+            Err(TryLockError::WouldBlock) => {
+                send_control(stream, Reply::Queued)?;
+                Ok(self.session.lock().unwrap_or_else(PoisonError::into_inner))
+            }
+            Err(TryLockError::Poisoned(session)) => Ok(session.into_inner()),
+        }
+    }
+
+    fn client(&self) -> MutexGuard<'_, Option<UnixStream>> {
+        self.client.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn status(&self) -> DaemonStatus {
+        DaemonStatus {
+            pid: std::process::id(),
+            uptime: self.started.elapsed(),
+            sessions: self.sessions.load(Ordering::SeqCst),
+            active: matches!(self.session.try_lock(), Err(TryLockError::WouldBlock)),
+        }
+    }
+
+    /// Forward complete messages until the client disconnects, a client that
+    /// disconnects within a message does not corrupt the next session
+    ///
+    /// The session the client opened in the container is tracked, such that it
+    /// can be ended if the client does not end it, see [Server::end_session].
+    fn relay_commands(
+        &self,
+        stream: &UnixStream,
+        open: &mut Option<ContainerSession>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let frame = match read_frame(stream) {
+                Ok(frame) => frame,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error).with_context(|| "Cannot read from client"),
+            };
+            // Commands that cannot be decoded are rejected by the container
+            if let Ok(command) = read_message::<Commands, _>(frame.as_slice()) {
+                ContainerSession::track(open, &command.body);
+            }
+            write_frame(self.pipes.to().open(), &frame)
+                .with_context(|| "Cannot relay command to container")?;
+        }
+    }
+
+    /// End the session the client left open and wait until the container
+    /// processed it
+    ///
+    /// The session is ended with the command the client would have sent. It is
+    /// followed by a handshake, which the container answers once it finished
+    /// the session. The commands use the last IDs of the session of the client,
+    /// their responses are not relayed.
+    fn end_session(&self, session: u64, open: ContainerSession) -> anyhow::Result<()> {
+        let last_id = ((session + 1) << 32) - 1;
+        let (sender, receiver) = mpsc::channel();
+        *self.session_end() = Some((last_id, sender));
+
+        let sent = self
+            .send_to_container(last_id - 1, open.end_command())
+            .and_then(|()| {
+                let handshake = Handshake::new(env!("CARGO_PKG_VERSION"));
+                self.send_to_container(last_id, Commands::Handshake(handshake))
+            });
+        let ended = sent.and_then(|()| {
+            receiver.recv_timeout(SESSION_END_TIMEOUT).with_context(|| {
+                format!("Container did not respond within {SESSION_END_TIMEOUT:?}")
+            })
+        });
+        *self.session_end() = None;
+        ended
+    }
+
+    fn send_to_container(&self, id: u64, body: Commands) -> anyhow::Result<()> {
+        write_message(self.pipes.to().open(), &Message { id, body })
+            .with_context(|| "Cannot send command to container")
+    }
+
+    fn session_end(&self) -> MutexGuard<'_, Option<(u64, Sender<()>)>> {
+        self.session_end
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forward the responses of the container to the client of the active session
+    fn relay_responses(&self) {
+        loop {
+            let frame = match read_frame(self.pipes.from().open()) {
+                Ok(frame) => frame,
+                Err(error) => {
+                    log::error!("Cannot read from container: {error}");
+                    return;
+                }
+            };
+            if let Some((id, ended)) = &*self.session_end() {
+                if response_id(&frame) == Some(*id) {
+                    let _ = ended.send(());
+                    continue;
+                }
+            }
+            match &*self.client() {
+                Some(client) => {
+                    if let Err(error) = write_frame(client, &frame) {
+                        log::debug!("Cannot relay response to client: {error}");
+                    }
+                }
+                None => log::debug!("Dropping a response outside of a session"),
+            }
+        }
+    }
+}
+
+/// The ID of the request a frame of the container answers, without decoding the response
+fn response_id(frame: &[u8]) -> Option<u64> {
+    read_message::<IgnoredAny, _>(frame)
+        .ok()
+        .map(|response| response.id)
+}
+
+/// A session in the container that the client must end, see [Server::end_session]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ContainerSession {
+    /// Started with [Commands::DefmtData] or [Commands::WatchData]
+    Defmt,
+    /// Started with [Commands::StartDebug]
+    Debug,
+}
+
+impl ContainerSession {
+    /// Update the open session with a command sent to the container
+    ///
+    /// A debug session that fails to start is assumed to be open, the
+    /// container rejects the command that ends it then.
+    fn track(open: &mut Option<Self>, command: &Commands) {
+        match command {
+            Commands::DefmtData { .. } | Commands::WatchData { .. } => {
+                *open = Some(ContainerSession::Defmt)
+            }
+            Commands::StartDebug { .. } => *open = Some(ContainerSession::Debug),
+            Commands::StopDefmtData | Commands::EndDebug => *open = None,
+            _ => {}
+        }
+    }
+
+    /// The command the client ends the session with
+    fn end_command(self) -> Commands {
+        match self {
+            ContainerSession::Defmt => Commands::StopDefmtData,
+            ContainerSession::Debug => Commands::EndDebug,
+        }
+    }
+}
+
+/// Ensures that a single daemon runs, the lock is released when dropped
+struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    fn acquire(path: PathBuf) -> anyhow::Result<Self> {
+        if let Some(pid) = Self::owner(&path) {
+            // The process ID may have been reused, but then the lock is
+            // removed by hand at worst
+            if Path::new("/proc").join(pid.to_string()).exists() {
+                bail!("Daemon is already running with pid {pid}");
+            }
+            log::warn!("Removing stale lock of pid {pid}");
+            fs::remove_file(&path)?;
+        }
+
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Could not create lock {}", path.display()))?;
+        write!(file, "{}", std::process::id())?;
+        Ok(Lock { path })
+    }
+
+    /// The process ID stored in the lock, if it exists
+    fn owner(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_file(&self.path) {
+            log::warn!("Could not remove lock {}: {error}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rpc_api::win_daemon::Response;
+    use tricore_common::{reset::ResetConfig, run::RunControl};
+
+    use super::*;
+
+    #[test]
+    fn sessions_are_tracked_until_they_are_ended() {
+        let mut open = None;
+        ContainerSession::track(&mut open, &Commands::ListResetClasses);
+        assert_eq!(open, None);
+
+        let defmt = Commands::DefmtData {
+            address: 0x7000_0000,
+            reset: ResetConfig::default(),
+            control: RunControl::default(),
+            csa_regions: Vec::new(),
+        };
+        ContainerSession::track(&mut open, &defmt);
+        assert_eq!(open, Some(ContainerSession::Defmt));
+        assert!(matches!(
+            open.unwrap().end_command(),
+            Commands::StopDefmtData
+        ));
+        ContainerSession::track(&mut open, &Commands::StopDefmtData);
+        assert_eq!(open, None);
+
+        let debug = Commands::StartDebug {
+            csa_regions: Vec::new(),
+            control: RunControl::default(),
+        };
+        ContainerSession::track(&mut open, &debug);
+        ContainerSession::track(&mut open, &Commands::ReadRegisters { core: 0 });
+        assert_eq!(open, Some(ContainerSession::Debug));
+        assert!(matches!(open.unwrap().end_command(), Commands::EndDebug));
+        ContainerSession::track(&mut open, &Commands::EndDebug);
+        assert_eq!(open, None);
+    }
+
+    #[test]
+    fn responses_are_identified_without_decoding_them() {
+        let mut frame = Vec::new();
+        let body = Response::Memory(vec![0; 16]);
+        write_message(&mut frame, &Message { id: 42 << 32, body }).unwrap();
+
+        assert_eq!(response_id(&frame), Some(42 << 32));
+        assert_eq!(response_id(&frame[..4]), None);
+    }
+}
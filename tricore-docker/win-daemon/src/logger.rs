@@ -0,0 +1,64 @@
+use std::{
+    fs::File,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use log::Log;
+use rpc_api::win_daemon::{
+    codec::{write_message, Message, NO_REQUEST},
+    Response,
+};
+
+/// Forwards log records to the host as [Response::Log] once the response
+/// channel is open, before that they are passed to the fallback logger
+pub struct DaemonLogger {
+    fallback: Box<dyn Log>,
+    responses: OnceLock<Arc<Mutex<File>>>,
+}
+
+impl DaemonLogger {
+    /// Install the logger for this process, all records are forwarded since the
+    /// host filters them itself
+    pub fn install(fallback: Box<dyn Log>) -> &'static Self {
+        let logger = Box::leak(Box::new(DaemonLogger {
+            fallback,
+            responses: OnceLock::new(),
+        }));
+        log::set_logger(logger).expect("Logger was already installed");
+        log::set_max_level(log::LevelFilter::Trace);
+        logger
+    }
+
+    /// Send all further records over the given response channel
+    pub fn forward_to(&self, responses: Arc<Mutex<File>>) {
+        if self.responses.set(responses).is_err() {
+            log::warn!("Log records are already forwarded to the host");
+        }
+    }
+}
+
+impl Log for DaemonLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Some(responses) = self.responses.get() {
+            let message = Message {
+                id: NO_REQUEST,
+                body: Response::Log(record.into()),
+            };
+            let Ok(responses) = responses.lock() else {
+                return;
+            };
+            if write_message(&*responses, &message).is_ok() {
+                return;
+            }
+        }
+        self.fallback.log(record)
+    }
+
+    fn flush(&self) {
+        self.fallback.flush()
+    }
+}
@@ -1,18 +1,32 @@
 use std::{
+    cell::Cell,
     fs::File,
     io::Write,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
 use anyhow::Context;
-use rpc_api::win_daemon::{log::PipeLogger, Commands, Response};
+use rpc_api::win_daemon::{
+    codec::{read_message, write_message, Handshake, Message, ReadError},
+    log::PipeLogger,
+    Commands, ErrorKind, RemoteError, Response,
+};
 
 use clap::Parser;
-use tricore_common::Chip;
+use rust_mcd::error::McdError;
+use tricore_common::{
+    backtrace::walker::CsaRegion, debug::DebugTarget, reset::ResetConfig, run::RunControl,
+    watch::WatchRequest, Chip,
+};
 use tricore_windows::{ChipInterface, Config};
 
+use crate::logger::DaemonLogger;
+
+mod logger;
+
 /// Program that manages the udas server and manages its connection with the Infineon
 /// memtool and the internal MCD connection
 #[derive(Parser, Debug)]
@@ -52,13 +66,10 @@ fn existing_path(input_path: &str) -> Result<PathBuf, anyhow::Error> {
 fn main() -> Result<(), anyhow::Error> {
     let args = Args::parse();
 
-    if let Some(log_file) = args.log_file {
-        let logger = PipeLogger::new(log_file.as_path());
-        log::set_boxed_logger(Box::new(logger)).unwrap();
-        log::set_max_level(log::LevelFilter::Trace);
-    } else {
-        env_logger::init();
-    }
+    let logger = match args.log_file {
+        Some(log_file) => DaemonLogger::install(Box::new(PipeLogger::new(log_file.as_path()))),
+        None => DaemonLogger::install(Box::new(env_logger::Builder::from_default_env().build())),
+    };
 
     let to_driver = args.out_to_driver;
     let from_driver = args.in_from_driver;
@@ -70,41 +81,316 @@ fn main() -> Result<(), anyhow::Error> {
         std::env::set_var("FTD2XX_LOGS", &path);
     }
 
-    let interface = ChipInterface::new(Config)?;
+    let interface = ChipInterface::new(Config::default())?;
 
     const WAIT_TIME: Duration = Duration::from_secs(2);
     log::info!("Waiting {:?} for UDAS to start", WAIT_TIME);
     std::thread::sleep(WAIT_TIME);
 
-    let mut command_connection =
+    let command_connection =
         CommandServer::new(args.out_commands.as_path(), args.in_commands.as_path());
+    logger.forward_to(command_connection.out.clone());
 
-    while let Ok(command) = command_connection.next_command() {
-        match command {
-            Commands::WriteHex(hex) => {
-                interface.flash_hex(hex.elf_data, hex.halt_memtool)?;
-                command_connection.send_answer(Response::Ok);
-            }
-            Commands::Reset => {
-                log::debug!("Resetting core");
-                interface.reset()?;
-                command_connection.send_answer(Response::Ok);
+    loop {
+        let Message { id, body: command } = match command_connection.next_command() {
+            Ok(message) => message,
+            Err(ReadError::Io(error)) => {
+                log::debug!("Command channel closed: {error}");
+                break;
             }
-            Commands::DefmtData { address } => {
-                log::debug!("Initializing defmt data transmission");
-                command_connection.send_answer(Response::Ok);
-                let f = interface.read_rtt(address, command_connection.defmt_sink())?;
-                log::trace!("Device hit debug");
-                command_connection.send_answer(Response::StackFrame(f));
+            Err(error) => {
+                command_connection.reject(error);
+                continue;
             }
+        };
+        if let Err(error) = execute(&interface, &command_connection, id, command) {
+            command_connection.send_answer(id, failure(&error));
         }
     }
     log::trace!("Docker application finished, goodbye!");
     Ok(())
 }
 
+/// Execute the command and send its responses, an error is not answered yet
+fn execute(
+    interface: &ChipInterface,
+    command_connection: &CommandServer<'_>,
+    id: u64,
+    command: Commands,
+) -> anyhow::Result<()> {
+    match command {
+        Commands::Handshake(host) => {
+            let handshake = Handshake::new(env!("CARGO_PKG_VERSION"));
+            if !handshake.is_compatible(&host) {
+                log::error!(
+                    "Host version {} speaks protocol version {}, but this daemon speaks version {}",
+                    host.crate_version,
+                    host.protocol_version,
+                    handshake.protocol_version
+                );
+            }
+            command_connection.send_answer(id, Response::Handshake(handshake));
+        }
+        Commands::WriteHex(hex) => {
+            interface.flash_hex(hex.elf_data, hex.halt_memtool)?;
+            command_connection.send_answer(id, Response::Ok);
+        }
+        Commands::Reset(reset) => {
+            log::debug!("Resetting core");
+            interface.reset(&reset)?;
+            command_connection.send_answer(id, Response::Ok);
+        }
+        Commands::ListResetClasses => {
+            log::debug!("Querying reset classes");
+            let reset_classes = interface.list_reset_classes()?;
+            command_connection.send_answer(id, Response::ResetClasses(reset_classes));
+        }
+        Commands::DefmtData {
+            address,
+            reset,
+            control,
+            csa_regions,
+        } => {
+            log::debug!("Initializing defmt data transmission");
+            rtt_session(
+                interface,
+                command_connection,
+                id,
+                address,
+                &reset,
+                control,
+                &csa_regions,
+                None,
+            );
+        }
+        Commands::WatchData {
+            address,
+            reset,
+            control,
+            csa_regions,
+            request,
+        } => {
+            log::debug!("Initializing defmt data transmission with sampling");
+            rtt_session(
+                interface,
+                command_connection,
+                id,
+                address,
+                &reset,
+                control,
+                &csa_regions,
+                Some(&request),
+            );
+        }
+        Commands::StopDefmtData => {
+            log::warn!("Received stop command outside of a defmt session");
+            command_connection.send_answer(id, invalid_state("There is no defmt session to stop"));
+        }
+        Commands::StartDebug {
+            csa_regions,
+            control,
+        } => {
+            log::debug!("Starting debug session");
+            // Failures are answered to the last command of the session
+            let last_id = Cell::new(id);
+            let response = match interface.debug(&csa_regions, &control, |target| {
+                debug_session(target, command_connection, id, &last_id)
+            }) {
+                Ok(()) => {
+                    log::debug!("Debug session finished");
+                    Response::Ok
+                }
+                Err(error) => failure(&error),
+            };
+            command_connection.send_answer(last_id.get(), response);
+        }
+        Commands::CaptureDump(request) => {
+            log::debug!("Capturing core dump");
+            let dump = interface.capture_dump(&request)?;
+            command_connection.send_answer(id, Response::Dump(Box::new(dump)));
+        }
+        Commands::Debug { .. }
+        | Commands::ReadMemory { .. }
+        | Commands::WriteMemory { .. }
+        | Commands::ReadRegisters { .. }
+        | Commands::WriteRegister { .. }
+        | Commands::InsertBreakpoint { .. }
+        | Commands::RemoveBreakpoint { .. }
+        | Commands::EndDebug => {
+            log::warn!("Received debug command outside of a debug session");
+            command_connection.send_answer(id, invalid_state("There is no debug session"));
+        }
+    }
+    Ok(())
+}
+
+/// Answer the commands of a debug session until it is ended by the host
+///
+/// Failing commands are answered with an error, only a broken command channel
+/// ends the session early.
+fn debug_session(
+    target: &mut dyn DebugTarget,
+    command_connection: &CommandServer<'_>,
+    id: u64,
+    last_id: &Cell<u64>,
+) -> anyhow::Result<()> {
+    command_connection.send_answer(
+        id,
+        Response::DebugSession {
+            core_count: target.core_count(),
+        },
+    );
+    loop {
+        let Message { id, body: command } = match command_connection.next_command() {
+            Ok(message) => message,
+            Err(ReadError::Io(_)) => anyhow::bail!("Debug session was interrupted"),
+            Err(error) => {
+                command_connection.reject(error);
+                continue;
+            }
+        };
+        last_id.set(id);
+        let response = match command {
+            Commands::Debug { core, command } => {
+                respond(target.execute(core, command), Response::CoreStatus)
+            }
+            Commands::ReadMemory {
+                core,
+                address,
+                length,
+            } => respond(target.read_memory(core, address, length), Response::Memory),
+            Commands::WriteMemory {
+                core,
+                address,
+                data,
+            } => answer(target.write_memory(core, address, &data)),
+            Commands::ReadRegisters { core } => {
+                respond(target.read_registers(core), Response::Registers)
+            }
+            Commands::WriteRegister { core, name, value } => {
+                answer(target.write_register(core, &name, value))
+            }
+            Commands::InsertBreakpoint { core, breakpoint } => {
+                answer(target.insert_breakpoint(core, breakpoint))
+            }
+            Commands::RemoveBreakpoint { core, breakpoint } => {
+                answer(target.remove_breakpoint(core, breakpoint))
+            }
+            Commands::EndDebug => return Ok(()),
+            command => {
+                log::warn!("Received {command:?} during a debug session");
+                invalid_state("Command is invalid during a debug session")
+            }
+        };
+        command_connection.send_answer(id, response);
+    }
+}
+
+/// The answer to a command that does not return data, see [respond]
+fn answer(result: anyhow::Result<()>) -> Response {
+    respond(result, |()| Response::Ok)
+}
+
+/// The answer to a command, failures are logged
+fn respond<T>(result: anyhow::Result<T>, response: impl FnOnce(T) -> Response) -> Response {
+    match result {
+        Ok(value) => response(value),
+        Err(error) => failure(&error),
+    }
+}
+
+/// Describe the failure of a command to the host
+///
+/// Errors of the MCD library are classified and carry their error code.
+fn failure(error: &anyhow::Error) -> Response {
+    log::debug!("Command failed: {error:#}");
+    let mcd_error = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<McdError>());
+    let kind = match mcd_error {
+        Some(mcd_error) if mcd_error.is_reset() => ErrorKind::DeviceReset,
+        Some(mcd_error) if mcd_error.is_access_denied() => ErrorKind::DeviceAccess,
+        _ => ErrorKind::Failed,
+    };
+    let code = mcd_error
+        .and_then(McdError::error_code)
+        .map(|code| code.code());
+    Response::Error(RemoteError::from_error(kind, error).with_code(code))
+}
+
+fn invalid_state(message: &str) -> Response {
+    Response::Error(RemoteError::new(ErrorKind::InvalidState, message))
+}
+
+/// Forward the defmt data and, if requested, the samples of the watched memory
+/// to the host until the device halts
+///
+/// The host ends every session with a stop command, if it arrives while the
+/// device is still running the session is cancelled.
+#[allow(clippy::too_many_arguments)]
+fn rtt_session(
+    interface: &ChipInterface,
+    command_connection: &CommandServer<'_>,
+    id: u64,
+    address: u64,
+    reset: &ResetConfig,
+    control: RunControl,
+    csa_regions: &[CsaRegion],
+    request: Option<&WatchRequest>,
+) {
+    command_connection.send_answer(id, Response::Ok);
+
+    let cancellation = control.cancellation.clone();
+    let input = command_connection.input.to_path_buf();
+    let stop_listener = std::thread::spawn(move || {
+        let command = receive_command(&input);
+        cancellation.cancel();
+        command
+    });
+
+    let halt_reason = match request {
+        Some(request) => interface.watch(
+            address,
+            reset,
+            &control,
+            csa_regions,
+            request,
+            command_connection.defmt_sink(id),
+            &mut |sample| {
+                command_connection.send_answer(id, Response::WatchSample(sample));
+                Ok(())
+            },
+        ),
+        None => interface.read_rtt(
+            address,
+            reset,
+            &control,
+            csa_regions,
+            command_connection.defmt_sink(id),
+        ),
+    };
+    log::trace!("Defmt data transmission finished: {halt_reason:?}");
+    // The host sends the stop command after a failure as well
+    let response = respond(halt_reason, |halt_reason| {
+        Response::Halted(Box::new(halt_reason))
+    });
+    command_connection.send_answer(id, response);
+
+    match stop_listener.join() {
+        Ok(Ok(Message {
+            body: Commands::StopDefmtData,
+            ..
+        })) => {}
+        Ok(Ok(command)) => {
+            log::warn!("Expected the end of the defmt session, received {command:?}")
+        }
+        _ => log::warn!("Defmt session was not ended by the host"),
+    }
+}
+
 struct CommandServer<'a> {
-    out: File,
+    /// Shared with the logger, which forwards log records to the host
+    out: Arc<Mutex<File>>,
     input: &'a Path,
 }
 
@@ -115,35 +401,63 @@ impl<'a> CommandServer<'a> {
             .write(true)
             .open(output)
             .expect("Could not open response channel");
-        CommandServer { out, input }
+        CommandServer {
+            out: Arc::new(Mutex::new(out)),
+            input,
+        }
+    }
+
+    /// A sink that sends the written data as the response to the request with the given ID
+    fn defmt_sink<'b>(&'b self, id: u64) -> DefmtSink<'a, 'b> {
+        DefmtSink { server: self, id }
     }
 
-    fn defmt_sink<'b>(&'b mut self) -> DefmtSink<'a, 'b> {
-        DefmtSink { server: self }
+    fn next_command(&self) -> Result<Message<Commands>, ReadError> {
+        receive_command(self.input)
     }
 
-    fn next_command(&self) -> Result<Commands, ()> {
-        let mut command_receive = File::options();
-        let command_receive = command_receive.read(true);
-        let command_receive = command_receive
-            .open(self.input)
-            .expect("Could not open receive channel");
-        ciborium::de::from_reader(&command_receive).map_err(|_| ())
+    fn send_answer(&self, id: u64, response: Response) {
+        let out = self.out.lock().expect("Response channel is poisoned");
+        write_message(&*out, &Message { id, body: response })
+            .expect("Could not write to response channel");
     }
 
-    fn send_answer(&self, response: Response) {
-        ciborium::ser::into_writer(&response, &self.out).unwrap();
-        (&mut &self.out).flush().unwrap();
+    /// Answer a command that could not be decoded, if its ID is known
+    fn reject(&self, error: ReadError) {
+        log::warn!("Rejecting command: {error}");
+        if let ReadError::Invalid {
+            id: Some(id),
+            reason,
+        } = error
+        {
+            let error = RemoteError::new(
+                ErrorKind::InvalidMessage,
+                format!("Cannot decode command: {reason}"),
+            );
+            self.send_answer(id, Response::Error(error));
+        }
     }
 }
 
+/// Wait for the next command on the receive channel at the given path
+fn receive_command(input: &Path) -> Result<Message<Commands>, ReadError> {
+    let mut command_receive = File::options();
+    let command_receive = command_receive.read(true);
+    let command_receive = command_receive
+        .open(input)
+        .expect("Could not open receive channel");
+    read_message(&command_receive)
+}
+
 struct DefmtSink<'a, 'b> {
-    server: &'b mut CommandServer<'a>,
+    server: &'b CommandServer<'a>,
+    id: u64,
 }
 
 impl<'a, 'b> Write for DefmtSink<'a, 'b> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.server.send_answer(Response::DefmtData(buf.to_vec()));
+        self.server
+            .send_answer(self.id, Response::DefmtData(buf.to_vec()));
         Ok(buf.len())
     }
 
@@ -1,64 +1,55 @@
 //! This module defines structures to create a backtrace using the [rust_mcd] library
 
-use anyhow::{Context, Ok};
-use rust_mcd::core::Core;
-use tricore_common::backtrace::{csa::UpperContext, Stacktrace};
-
-use crate::backtrace::pcxi::PCXIExt;
-
-mod csa;
-mod pcxi;
+use anyhow::Context;
+use rust_mcd::{core::Core, registers::RegisterGroup};
+use tricore_common::{
+    access::{MemoryAccess, RegisterAccess},
+    backtrace::{walker::CsaRegion, Stacktrace},
+};
 
 /// Extension trait to obtain a stacktrace
 pub trait StacktraceExt: Sized {
     /// Read the stacktrace for the given core
     ///
-    /// This function is available on the [Core] type.
-    fn read_current(&self) -> anyhow::Result<Stacktrace>;
+    /// This function is available on the [Core] type. The walk of the CSA link
+    /// chain stops at the first link outside of the given CSA regions.
+    fn read_current(&self, csa_regions: &[CsaRegion]) -> anyhow::Result<Stacktrace>;
 }
 
 impl<'a> StacktraceExt for &'a Core<'a> {
-    fn read_current(&self) -> anyhow::Result<Stacktrace> {
+    fn read_current(&self, csa_regions: &[CsaRegion]) -> anyhow::Result<Stacktrace> {
         let groups = self.register_groups()?;
         let group = groups.get_group(0)?;
 
-        let register = |name: &str| {
-            Ok(group
-                .register(name)
-                .ok_or_else(|| {
-                    anyhow::Error::msg(format!("Could not find {name} register for core"))
-                })?
-                .read()
-                .with_context(|| format!("Cannot read {name} register"))?)
-        };
-
-        let current_upper = UpperContext {
-            pcxi: register("PCXI")?.into(),
-            psw: register("PSW")?,
-            a10: register("A10")?,
-            a11: register("A11")?,
-            d8: register("D8")?,
-            d9: register("D9")?,
-            d10: register("D10")?,
-            d11: register("D11")?,
-            a12: register("A12")?,
-            a13: register("A13")?,
-            a14: register("A14")?,
-            a15: register("A15")?,
-            d12: register("D12")?,
-            d13: register("D13")?,
-            d14: register("D14")?,
-            d15: register("D15")?,
-        };
+        Stacktrace::capture(
+            &McdAccess {
+                core: self,
+                group: &group,
+            },
+            csa_regions,
+        )
+    }
+}
 
-        let current_pc = register("PC")?;
+/// Access to a core through the [rust_mcd] library
+pub struct McdAccess<'a> {
+    pub core: &'a Core<'a>,
+    /// The register group the registers are looked up in
+    pub group: &'a RegisterGroup<'a>,
+}
 
-        let stack_frames = current_upper.pcxi.walk_context(self).collect();
+impl<'a> MemoryAccess for McdAccess<'a> {
+    fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        Ok(self.core.read_bytes(address as u64, length)?)
+    }
+}
 
-        Ok(Stacktrace {
-            stack_frames,
-            current_pc,
-            current_upper,
-        })
+impl<'a> RegisterAccess for McdAccess<'a> {
+    fn read_register(&self, name: &str) -> anyhow::Result<u32> {
+        self.group
+            .register(name)
+            .with_context(|| format!("Could not find {name} register for core"))?
+            .read()
+            .with_context(|| format!("Cannot read {name} register"))
     }
 }
@@ -0,0 +1,378 @@
+//! Implements a [DebugTarget] on top of the cores of a [rust_mcd::system::System]
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use rust_mcd::{
+    breakpoint::TriggerType,
+    core::{Core, CoreState, Trigger},
+    watcher::StateWatcher,
+};
+use tricore_common::{
+    backtrace::{walker::CsaRegion, Stacktrace},
+    debug::{Breakpoint, BreakpointKind, CoreStatus, DebugCommand, DebugTarget},
+    run::RunControl,
+};
+
+use crate::{backtrace::StacktraceExt, dump::read_registers};
+
+/// Controls the execution of the given cores
+pub struct McdDebugTarget<'a> {
+    cores: &'a [Core<'a>],
+    poll_interval: Duration,
+    /// The regions that hold the context save areas, see [StacktraceExt::read_current]
+    csa_regions: &'a [CsaRegion],
+    /// Bounds the commands that let a core run until it reaches an address
+    control: &'a RunControl,
+    /// The installed breakpoints with the index of their core
+    breakpoints: Vec<(usize, Breakpoint, Trigger<'a>)>,
+}
+
+impl<'a> McdDebugTarget<'a> {
+    /// Control the given cores, their state is queried at the poll interval
+    /// while waiting for them to halt
+    ///
+    /// A run-to, e.g. stepping over a call, is stopped when the control's timeout
+    /// elapses or it is cancelled.
+    pub fn new(
+        cores: &'a [Core<'a>],
+        poll_interval: Duration,
+        csa_regions: &'a [CsaRegion],
+        control: &'a RunControl,
+    ) -> Self {
+        McdDebugTarget {
+            cores,
+            poll_interval,
+            csa_regions,
+            control,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    fn core(&self, core: usize) -> anyhow::Result<&'a Core<'a>> {
+        let cores: &'a [Core<'a>] = self.cores;
+        cores.get(core).with_context(|| {
+            format!(
+                "Core {core} does not exist, there are {} cores",
+                cores.len()
+            )
+        })
+    }
+}
+
+impl<'a> DebugTarget for McdDebugTarget<'a> {
+    fn core_count(&self) -> usize {
+        self.cores.len()
+    }
+
+    fn execute(&mut self, core_index: usize, command: DebugCommand) -> anyhow::Result<CoreStatus> {
+        let core = self.core(core_index)?;
+        log::debug!("Executing {command:?} on core {core_index}");
+
+        let (poll_interval, csa_regions, control) =
+            (self.poll_interval, self.csa_regions, self.control);
+        let stacktrace = match command {
+            DebugCommand::Halt => {
+                core.stop()
+                    .with_context(|| format!("Cannot halt core {core_index}"))?;
+                wait_for_halt(core, poll_interval, csa_regions, Some(HALT_TIMEOUT))?
+            }
+            DebugCommand::Continue => {
+                core.run()
+                    .with_context(|| format!("Cannot resume core {core_index}"))?;
+                return Ok(CoreStatus::Running);
+            }
+            DebugCommand::StepInstruction => {
+                core.step()
+                    .with_context(|| format!("Cannot step core {core_index}"))?;
+                wait_for_halt(core, poll_interval, csa_regions, Some(HALT_TIMEOUT))?
+            }
+            DebugCommand::StepOver => step_over(core, poll_interval, csa_regions, control)?,
+            DebugCommand::RunTo(address) => {
+                run_to(core, poll_interval, csa_regions, control, address)?
+            }
+            DebugCommand::Status => {
+                let state = core.query_state()?;
+                if state.state == CoreState::Running {
+                    return Ok(CoreStatus::Running);
+                }
+                core.read_current(csa_regions)?
+            }
+        };
+
+        Ok(CoreStatus::Halted(Box::new(stacktrace)))
+    }
+
+    fn read_memory(&self, core: usize, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        let data = self.core(core)?.read_bytes(address as u64, length)?;
+        Ok(data)
+    }
+
+    fn write_memory(&mut self, core: usize, address: u32, data: &[u8]) -> anyhow::Result<()> {
+        self.core(core)?.write(address as u64, data.to_vec())?;
+        Ok(())
+    }
+
+    fn read_registers(&self, core: usize) -> anyhow::Result<BTreeMap<String, u32>> {
+        read_registers(self.core(core)?)
+    }
+
+    fn write_register(&mut self, core: usize, name: &str, value: u32) -> anyhow::Result<()> {
+        let groups = self.core(core)?.register_groups()?;
+        let group = groups.get_group(0)?;
+        group
+            .register(name)
+            .with_context(|| format!("Could not find {name} register for core"))?
+            .write(value)
+            .with_context(|| format!("Cannot write {name} register"))
+    }
+
+    fn insert_breakpoint(
+        &mut self,
+        core_index: usize,
+        breakpoint: Breakpoint,
+    ) -> anyhow::Result<()> {
+        let core = self.core(core_index)?;
+        let trigger_type = match breakpoint.kind {
+            BreakpointKind::Execution => TriggerType::IP,
+            BreakpointKind::Access => TriggerType::RW,
+        };
+        let trigger = core.create_breakpoint(
+            trigger_type,
+            breakpoint.address as u64,
+            breakpoint.length as u64,
+        )?;
+        core.download_triggers()?;
+
+        self.breakpoints.push((core_index, breakpoint, trigger));
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, core: usize, breakpoint: Breakpoint) -> anyhow::Result<()> {
+        let index = self
+            .breakpoints
+            .iter()
+            .position(|(index, installed, _)| *index == core && *installed == breakpoint)
+            .with_context(|| format!("No such breakpoint installed on core {core}"))?;
+
+        let (_, _, trigger) = self.breakpoints.remove(index);
+        trigger.remove()?;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for McdDebugTarget<'a> {
+    /// Remove the breakpoints that are still installed when the session ends
+    fn drop(&mut self) {
+        for (core, breakpoint, trigger) in self.breakpoints.drain(..) {
+            if let Err(error) = trigger.remove() {
+                log::warn!("Cannot remove {breakpoint:?} from core {core}: {error}");
+            }
+        }
+    }
+}
+
+/// Time a core is given to halt after it was stopped or stepped
+const HALT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Block until the core halts and read its stacktrace
+///
+/// If no timeout is given, this function waits indefinitely.
+pub(crate) fn wait_for_halt<'a>(
+    core: &'a Core<'a>,
+    poll_interval: Duration,
+    csa_regions: &[CsaRegion],
+    timeout: Option<Duration>,
+) -> anyhow::Result<Stacktrace> {
+    let mut watcher = StateWatcher::new().with_poll_interval(poll_interval);
+    watcher.watch(core);
+    if watcher.wait_for_halt(timeout)?.is_none() {
+        bail!("Core did not halt within {:?}", timeout.unwrap_or_default());
+    }
+
+    core.read_current(csa_regions)
+        .with_context(|| "Cannot read backtrace from device")
+}
+
+/// Let the core run until it hits the given address
+///
+/// The run-to is stopped when the timeout of the control elapses or it is
+/// cancelled, such that the debug session does not block on a core that never
+/// returns. The core is then halted if [RunControl::halt_on_stop] is set and left
+/// running otherwise.
+fn run_to<'a>(
+    core: &'a Core<'a>,
+    poll_interval: Duration,
+    csa_regions: &[CsaRegion],
+    control: &RunControl,
+    address: u32,
+) -> anyhow::Result<Stacktrace> {
+    let breakpoint = core.create_breakpoint(TriggerType::IP, address as u64, 4)?;
+    core.download_triggers()?;
+    // A cancellation while no run-to was in progress, e.g. at the prompt of the
+    // debugger, does not apply to this one
+    control.cancellation.reset();
+    let started = Instant::now();
+    core.run()?;
+
+    let mut watcher = StateWatcher::new().with_poll_interval(poll_interval);
+    watcher.watch(core);
+    let stopped = loop {
+        match watcher.wait_for_halt(Some(poll_interval)) {
+            Ok(Some(_)) => break Ok(None),
+            Ok(None) => {}
+            Err(error) => break Err(anyhow::Error::from(error)),
+        }
+        if let Some(reason) = control.stop_reason(started) {
+            break Ok(Some(reason));
+        }
+    };
+
+    let halt = stopped.is_err() || control.halt_on_stop;
+    if !matches!(stopped, Ok(None)) && halt {
+        let halted = core
+            .stop()
+            .map_err(anyhow::Error::from)
+            .and_then(|()| wait_for_halt(core, poll_interval, csa_regions, Some(HALT_TIMEOUT)));
+        if let Err(error) = halted {
+            log::warn!("Cannot halt core after run-to was stopped: {error:#}");
+        }
+    }
+    breakpoint.remove()?;
+
+    let state = if halt { "halted" } else { "left running" };
+    match stopped.with_context(|| format!("Core did not reach {address:#X}, it was {state}"))? {
+        None => core
+            .read_current(csa_regions)
+            .with_context(|| "Cannot read backtrace from device"),
+        Some(reason) => {
+            control.cancellation.reset();
+            bail!("Core did not reach {address:#X}, run-to was stopped ({reason:?}) and {state}")
+        }
+    }
+}
+
+/// Execute a single instruction, but run until the function returns if the
+/// instruction was a call
+///
+/// A call is detected by checking whether the step saved the previous upper
+/// context in the CSA link chain. Execution then continues until the return
+/// address stored in A11 is reached.
+fn step_over<'a>(
+    core: &'a Core<'a>,
+    poll_interval: Duration,
+    csa_regions: &[CsaRegion],
+    control: &RunControl,
+) -> anyhow::Result<Stacktrace> {
+    let before = core.read_current(csa_regions)?;
+    core.step()?;
+    let after = wait_for_halt(core, poll_interval, csa_regions, Some(HALT_TIMEOUT))?;
+
+    let previous_pcxi = u32::from(before.current_upper.pcxi);
+    let entered_call = after
+        .stack_frames
+        .first()
+        .is_some_and(|saved| u32::from(saved.context.pcxi()) == previous_pcxi)
+        && u32::from(after.current_upper.pcxi) != previous_pcxi;
+
+    if !entered_call {
+        return Ok(after);
+    }
+
+    log::trace!(
+        "Step entered a call, running to return address {:#X}",
+        after.current_upper.a11
+    );
+    run_to(
+        core,
+        poll_interval,
+        csa_regions,
+        control,
+        after.current_upper.a11,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_mcd::{core::CoreState, system::System};
+
+    use super::*;
+    use crate::simulation::simulate_core;
+
+    const TARGET: u32 = 0x8000_2000;
+
+    /// Execute a run-to the target on the simulated core
+    fn run_to_target(control: &RunControl) -> anyhow::Result<CoreStatus> {
+        let system = System::connect().unwrap();
+        let cores = [system.get_core(0).unwrap()];
+        let mut target = McdDebugTarget::new(&cores, Duration::from_millis(1), &[], control);
+        target.execute(0, DebugCommand::RunTo(TARGET))
+    }
+
+    #[test]
+    fn run_to_halts_at_the_target() {
+        let simulation = simulate_core(0x8000_1000);
+        let mut executions = 0;
+        simulation.set_program(0, move |context| {
+            executions += 1;
+            if executions == 5 {
+                context.execute_at(TARGET as u64);
+            }
+        });
+
+        let status = run_to_target(&RunControl::default()).unwrap();
+        assert!(matches!(status, CoreStatus::Halted(_)));
+        assert_eq!(simulation.state(0), CoreState::Debug);
+        assert!(simulation.trigger_addresses(0).is_empty());
+    }
+
+    #[test]
+    fn run_to_is_stopped_by_the_timeout() {
+        let simulation = simulate_core(0x8000_1000);
+        simulation.set_program(0, |_| {});
+
+        let mut control = RunControl {
+            timeout: Some(Duration::from_millis(20)),
+            halt_on_stop: true,
+            ..Default::default()
+        };
+        let error = run_to_target(&control).unwrap_err();
+        assert!(format!("{error:#}").contains("stopped (Timeout) and halted"));
+        assert_eq!(simulation.state(0), CoreState::Halted);
+        assert!(simulation.trigger_addresses(0).is_empty());
+
+        control.halt_on_stop = false;
+        let error = run_to_target(&control).unwrap_err();
+        assert!(format!("{error:#}").contains("stopped (Timeout) and left running"));
+        assert_eq!(simulation.state(0), CoreState::Running);
+        assert!(simulation.trigger_addresses(0).is_empty());
+    }
+
+    #[test]
+    fn run_to_is_stopped_by_a_cancellation_while_it_runs() {
+        let simulation = simulate_core(0x8000_1000);
+        let control = RunControl {
+            halt_on_stop: true,
+            ..Default::default()
+        };
+        let cancellation = control.cancellation.clone();
+        let mut executions = 0;
+        simulation.set_program(0, move |_| {
+            executions += 1;
+            if executions == 5 {
+                cancellation.cancel();
+            }
+        });
+
+        // Cancelled before the run-to started, e.g. at the prompt of the debugger
+        control.cancellation.cancel();
+        let error = run_to_target(&control).unwrap_err();
+        assert!(format!("{error:#}").contains("stopped (Cancelled) and halted"));
+        assert!(!control.cancellation.is_cancelled());
+        assert_eq!(simulation.state(0), CoreState::Halted);
+        assert!(simulation.trigger_addresses(0).is_empty());
+    }
+}
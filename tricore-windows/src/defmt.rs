@@ -1,40 +1,65 @@
-use crate::backtrace::StacktraceExt;
+use crate::{
+    debug::wait_for_halt,
+    reset::{reset_core, write_memory},
+    watch::Sampler,
+};
 use anyhow::{bail, Context};
-use byteorder::ReadBytesExt;
-use rust_mcd::{
-    breakpoint::TriggerType,
-    core::{Core, CoreState},
-    error::EventError,
-    reset::ResetClass,
+use rust_mcd::{breakpoint::TriggerType, core::Core, watcher::StateWatcher};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    time::{Duration, Instant},
+};
+use tricore_common::{
+    access::MemoryAccess,
+    backtrace::walker::CsaRegion,
+    dump::RttSnapshot,
+    reset::ResetConfig,
+    rtt::{BufferParameters, RttControlBlock, HOST_CONNECTED},
+    run::{HaltReason, HaltedCores, RunControl, StopReason},
 };
-use std::{io::Write, time::Duration};
-use tricore_common::backtrace::Stacktrace;
 
 /// Decode the rtt data from the first channel of the specified rtt block and
 /// write it to the supplied data sink.
 ///
 /// A main core must be provided through which the RTT data is read from the chip.
+/// All cores are reset as specified by the reset configuration before the data
+/// is acquired, the memory writes of the configuration are applied before the
+/// main core runs.
 ///
 /// The function will return when the device halts, e.g. when any core (including the
-/// secondary ones) hits a breakpoint. The backtrace returned is obtained by
-/// traversing the CSA link list.
+/// secondary ones) hits a breakpoint. All other cores are halted then as well and
+/// the backtrace of each core is obtained by traversing its CSA link list, which
+/// ends at the first link outside of the given CSA regions. The state of the
+/// cores is queried at the given poll interval.
+///
+/// The function also returns when the run control stops the session, after the
+/// remaining rtt data was written to the data sink.
+///
+/// If a sampler is given, it samples memory through the main core in between
+/// reading the rtt data, starting once the rtt control block was found.
+#[allow(clippy::too_many_arguments)]
 pub fn decode_rtt<W: Write>(
-    core: &mut Core<'_>,
-    secondary_cores: &mut [Core<'_>],
+    core: &Core<'_>,
+    secondary_cores: &[Core<'_>],
     rtt_block_address: u64,
+    reset: &ResetConfig,
+    control: &RunControl,
+    poll_interval: Duration,
+    csa_regions: &[CsaRegion],
     mut data_sink: W,
+    mut sampler: Option<Sampler<'_>>,
 ) -> anyhow::Result<HaltReason> {
-    let rtt_block = RttControlBlock::new(rtt_block_address);
-    // Construct reset class 0 which is assumed to be the simplest reset
-    // Can we assume that this reset class exists and it fits the usecase here?
-    let system_reset = ResetClass::construct_reset_class(core, 0);
+    let started = Instant::now();
+    let rtt_block = RttControlBlock::new(rtt_block_address as u32);
 
-    // Do we also need to reset the other cores?
-    core.reset(system_reset, true)?;
+    reset_core(core, 0, reset, true)?;
+    write_memory(core, reset)?;
 
-    for secondary_core in secondary_cores.iter_mut() {
-        secondary_core.reset(system_reset, true)?;
-        secondary_core.download_triggers();
+    for (secondary_index, secondary_core) in secondary_cores.iter().enumerate() {
+        if reset_core(secondary_core, secondary_index + 1, reset, true)? {
+            secondary_core.download_triggers()?;
+        }
     }
 
     log::info!(
@@ -44,52 +69,71 @@ pub fn decode_rtt<W: Write>(
 
     // We create a breakpoint that puts the chip into debug mode when the write index
     // is changed and then wait for the chip to hit the breakpoint.
-    let breakpoint_on_write_change =
-        core.create_breakpoint(TriggerType::RW, rtt_block.device_write_index_addr(), 4)?;
-    core.download_triggers();
+    let breakpoint_on_write_change = core.create_breakpoint(
+        TriggerType::RW,
+        rtt_block.device_write_index_addr() as u64,
+        4,
+    )?;
+    core.download_triggers()?;
     core.run()?;
 
-    loop {
-        let state = core.query_state()?;
-        if state.state != CoreState::Running {
-            log::trace!("Breakpoint hit, checking validity of structure");
-            break;
+    let mut watcher = StateWatcher::new().with_poll_interval(poll_interval);
+    watcher.watch(core);
+    while watcher.wait_for_halt(Some(poll_interval))?.is_none() {
+        if let Some(reason) = control.stop_reason(started) {
+            log::info!("Run stopped ({reason:?}) before the RTT control block was written");
+            breakpoint_on_write_change.remove()?;
+            return stop_session(
+                core,
+                secondary_cores,
+                reason,
+                control,
+                poll_interval,
+                csa_regions,
+            );
         }
     }
+    log::trace!("Breakpoint hit, checking validity of structure");
 
     // Best effort to make sure that the address is correct: We check the first
     // bytes of the rtt control block, they must contain the given data
-    let data = core.read_bytes(rtt_block.id_addr(), 16)?;
-    if &data[..16] == b"SEGGER RTT\0\0\0\0\0\0" {
+    if rtt_block.is_initialized(&CoreMemory(core))? {
         log::info!("Detected RTT control block");
     } else {
         bail!("The device halted, but the rtt control is malformatted");
     }
 
-    core.write(rtt_block.flags_addr(), u32::to_le_bytes(2).to_vec())?; // Set the flag that the host is connected
+    // Set the flag that the host is connected
+    core.write(
+        rtt_block.flags_addr() as u64,
+        HOST_CONNECTED.to_le_bytes().to_vec(),
+    )?;
 
     // Read the buffer parameters at startup, they should not change at runtime
-    let ring_buffer = {
-        let data = core
-            .read_bytes(rtt_block.buffer_addr_and_size(), 8)
-            .with_context(|| "Cannot obtain buffer specification (address and size)")?;
-
-        let mut data = data.as_slice();
-        let address = data.read_u32::<byteorder::LE>()?;
-        let size = data.read_u32::<byteorder::LE>()?;
-
-        log::trace!("Found buffer at {:#X} with size {}", address, size);
-
-        BufferParameters { address, size }
-    };
+    let ring_buffer = rtt_block.read_buffer(&CoreMemory(core))?;
+    log::trace!(
+        "Found buffer at {:#X} with size {}",
+        ring_buffer.address,
+        ring_buffer.size
+    );
 
     // Remove the breakpoint, we do busy looping to acquire the rtt data
     breakpoint_on_write_change.remove()?;
 
     core.run()?;
+    if let Some(sampler) = sampler.as_mut() {
+        sampler.start();
+    }
 
     let mut local_read_index = 0;
 
+    let mut watcher = StateWatcher::new().with_poll_interval(poll_interval);
+    watcher.watch(core);
+    for secondary_core in secondary_cores.iter() {
+        // Secondary cores are started by the main core, which may report resets
+        watcher.watch_ignoring_resets(secondary_core);
+    }
+
     loop {
         read_from_core(
             core,
@@ -98,181 +142,170 @@ pub fn decode_rtt<W: Write>(
             &mut local_read_index,
             &ring_buffer,
         )?;
+        if let Some(sampler) = sampler.as_mut() {
+            sampler.poll(core)?;
+        }
+
+        if let Some(reason) = control.stop_reason(started) {
+            log::info!("Run stopped ({reason:?}), collecting remaining RTT data");
+            let halt_reason = stop_session(
+                core,
+                secondary_cores,
+                reason,
+                control,
+                poll_interval,
+                csa_regions,
+            )?;
+            read_from_core(
+                core,
+                &mut data_sink,
+                &rtt_block,
+                &mut local_read_index,
+                &ring_buffer,
+            )?;
+            return Ok(halt_reason);
+        }
 
-        /// Check if the core is still running, if it is not we assume a
-        /// breakpoint was hit
-        ///
-        /// This function is a bit of a hack to work around lifetime issues
-        /// when borrowing the cores in multiple iterations of the loop
-        fn should_exit_for_core(
-            core: &mut Core,
-            accept_reset_event: bool,
-        ) -> Option<anyhow::Result<HaltReason>> {
-            let core_state = core.query_state_gracefully(|e| {
-                accept_reset_event && e.event_error_code() == EventError::Reset
-            });
-
-            let core_state = match core_state {
-                Ok(core_state) => core_state,
-                Err(error) => return Some(Err(error).context("Failed to query core state")),
+        let Some(halted) = watcher
+            .wait_for_halt(Some(Duration::ZERO))
+            .context("Failed to query core state")?
+        else {
+            let idle = match sampler.as_ref() {
+                Some(sampler) => watcher.poll_interval().min(sampler.time_to_next_sample()),
+                None => watcher.poll_interval(),
             };
+            std::thread::sleep(idle);
+            continue;
+        };
 
-            if core_state.state != CoreState::Running {
-                log::trace!("Device halted, attempting to acquire backtrace");
-                return Some(
-                    (&*core)
-                        .read_current()
-                        .with_context(|| "Cannot read backtrace from device")
-                        .map(|backtrace| HaltReason::DebugHit(backtrace)),
-                );
-            }
+        const RTT_WAIT_DURATION: Duration = Duration::from_millis(300);
 
-            None
-        }
+        // The watcher indices follow the order in which the cores were watched
+        let halted_core = match halted.core {
+            0 => core,
+            index => &secondary_cores[index - 1],
+        };
+        log::info!(
+            "Core {} ({}) halted, collecting RTT data for {}ms",
+            halted_core.id(),
+            halted_core.name(),
+            RTT_WAIT_DURATION.as_millis()
+        );
 
-        const RTT_WAIT_DURATION: Duration = Duration::from_millis(300);
+        // The main core may still be running and write the data that explains
+        // the halt, so it is collected before the other cores are halted
+        std::thread::sleep(RTT_WAIT_DURATION);
+        read_from_core(
+            core,
+            &mut data_sink,
+            &rtt_block,
+            &mut local_read_index,
+            &ring_buffer,
+        )?;
 
-        if let Some(exit_reason) = should_exit_for_core(core, false) {
-            if exit_reason.is_ok() {
-                log::info!(
-                    "Main core halted, collecting RTT data for {}ms",
-                    RTT_WAIT_DURATION.as_millis()
-                );
-                std::thread::sleep(RTT_WAIT_DURATION);
-                read_from_core(
-                    core,
-                    &mut data_sink,
-                    &rtt_block,
-                    &mut local_read_index,
-                    &ring_buffer,
-                )?;
-            }
-            return exit_reason.context("Cannot query state of the main core");
-        }
+        log::trace!("Device halted, attempting to acquire backtraces");
+        let halted_cores = halt_all_cores(
+            core,
+            secondary_cores,
+            halted_core,
+            poll_interval,
+            csa_regions,
+        )?;
 
-        for (secondary_index, core) in secondary_cores.iter_mut().enumerate() {
-            if let Some(exit_reason) = should_exit_for_core(core, true) {
-                if exit_reason.is_ok() {
-                    // FIXME: The core index we give here might be misleading, we can probably obtain
-                    // that information from the core itself
-                    log::info!(
-                        "Secondary core {} halted, collecting RTT data for {}ms",
-                        secondary_index + 1,
-                        RTT_WAIT_DURATION.as_millis()
-                    );
-                    std::thread::sleep(RTT_WAIT_DURATION);
-                    read_from_core(
-                        core,
-                        &mut data_sink,
-                        &rtt_block,
-                        &mut local_read_index,
-                        &ring_buffer,
-                    )?;
-                }
-                return exit_reason.with_context(|| {
-                    format!("Cannot query state of core {}", secondary_index + 1)
-                });
-            }
-        }
+        return Ok(HaltReason::DebugHit(halted_cores));
     }
 }
 
-/// Reason why decoding rtt data failed
-#[derive(Debug)]
-pub enum HaltReason {
-    DebugHit(Stacktrace),
-}
+/// Time the cores are given to halt when the session is stopped
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
 
-/// Helper structure to facilitate reading at the correct offsets within
-/// the control block
+/// End the session before the device halted by itself
 ///
-/// The offsets were inferred as of https://github.com/knurling-rs/defmt/blob/59c14b924815a7185fd0079a74b936dba90c867c/firmware/defmt-rtt/src/lib.rs#L124
-struct RttControlBlock {
-    base_address: u64,
+/// If configured, all cores are halted and their backtraces are collected,
+/// otherwise the cores are left running.
+fn stop_session(
+    core: &Core<'_>,
+    secondary_cores: &[Core<'_>],
+    reason: StopReason,
+    control: &RunControl,
+    poll_interval: Duration,
+    csa_regions: &[CsaRegion],
+) -> anyhow::Result<HaltReason> {
+    if !control.halt_on_stop {
+        return Ok(HaltReason::Stopped(reason, None));
+    }
+
+    let halted_cores = halt_all_cores(core, secondary_cores, core, poll_interval, csa_regions)?;
+    Ok(HaltReason::Stopped(reason, Some(halted_cores)))
 }
 
-impl RttControlBlock {
-    fn new(address: u64) -> Self {
-        RttControlBlock {
-            base_address: address,
+/// Halt all cores and read the backtrace of each of them
+///
+/// The backtraces are labelled with the core IDs reported by the debug controller.
+/// The backtrace of the trigger core must be available, for the other cores a
+/// failure is only logged, e.g. if a secondary core was never started.
+fn halt_all_cores(
+    core: &Core<'_>,
+    secondary_cores: &[Core<'_>],
+    trigger: &Core<'_>,
+    poll_interval: Duration,
+    csa_regions: &[CsaRegion],
+) -> anyhow::Result<HaltedCores> {
+    log::debug!("Halting all cores");
+    let cores: Vec<&Core<'_>> = std::iter::once(core).chain(secondary_cores).collect();
+    for core in cores.iter() {
+        if let Err(error) = core.stop() {
+            log::warn!("Cannot halt core {}: {error}", core.id());
         }
     }
 
-    fn id_addr(&self) -> u64 {
-        self.base_address
-    }
-
-    fn device_write_index_addr(&self) -> u64 {
-        self.base_address + 36
+    let mut stacktraces = BTreeMap::new();
+    for core in cores {
+        let is_trigger = std::ptr::eq(core, trigger);
+        match wait_for_halt(core, poll_interval, csa_regions, Some(STOP_TIMEOUT)) {
+            Ok(stacktrace) => {
+                stacktraces.insert(core.id(), stacktrace);
+            }
+            Err(error) if is_trigger => {
+                return Err(error.context(format!("Cannot read backtrace of core {}", core.id())))
+            }
+            Err(error) => log::warn!("Cannot read backtrace of core {}: {error:#}", core.id()),
+        }
     }
 
-    fn buffer_addr_and_size(&self) -> u64 {
-        self.base_address + 28
-    }
+    Ok(HaltedCores {
+        trigger: trigger.id(),
+        stacktraces,
+    })
+}
 
-    fn host_read_index_addr(&self) -> u64 {
-        self.base_address + 40
-    }
+/// The memory of a core through the [rust_mcd] library
+struct CoreMemory<'a>(&'a Core<'a>);
 
-    fn flags_addr(&self) -> u64 {
-        self.base_address + 44
+impl<'a> MemoryAccess for CoreMemory<'a> {
+    fn read_memory(&self, address: u32, length: usize) -> anyhow::Result<Vec<u8>> {
+        Ok(self.0.read_bytes(address as u64, length)?)
     }
 }
 
-/// Address and size of the buffer that holds the actually rtt data
-struct BufferParameters {
-    address: u32,
-    size: u32,
-}
-
 fn read_from_core<W: Write>(
-    core: &mut Core,
+    core: &Core,
     data_sink: &mut W,
     rtt_block: &RttControlBlock,
     local_read_index: &mut u32,
     ring_buffer: &BufferParameters,
 ) -> anyhow::Result<()> {
-    let device_write_index = core
-        .read_bytes(rtt_block.device_write_index_addr(), 4)
-        .with_context(|| "Error while obtaining the device write index")?
-        .as_slice()
-        .read_u32::<byteorder::LE>()?;
+    let device_write_index = rtt_block.read_write_index(&CoreMemory(core))?;
     if device_write_index == *local_read_index {
         return Ok(());
     }
-    if device_write_index > ring_buffer.size {
-        bail!(
-            "The RTT write index on the device is {:#X} which exceeds the given size of {:#X}",
-            device_write_index,
-            ring_buffer.size
-        );
-    }
-    let new_data = if device_write_index < *local_read_index {
-        // The write wrapped, we might need to do two reads
-        let mut chunk_at_end = core
-            .read_bytes(
-                (ring_buffer.address + *local_read_index) as u64,
-                (ring_buffer.size - *local_read_index) as usize,
-            )
-            .with_context(|| "Error while reading buffer data")?;
-        if device_write_index != 0 {
-            chunk_at_end.extend(
-                core.read_bytes(ring_buffer.address as u64, device_write_index as usize)
-                    .with_context(|| "Error while reading buffer data")?,
-            );
-        }
-        chunk_at_end
-    } else {
-        let read_address = ring_buffer.address + *local_read_index;
-        let read_length = device_write_index - *local_read_index;
-        core.read_bytes(read_address as u64, read_length as usize)
-            .with_context(|| "Error while reading buffer data")?
-    };
+    let new_data =
+        ring_buffer.read_data(&CoreMemory(core), *local_read_index, device_write_index)?;
     log::trace!("Read {} bytes from the device", new_data.len());
     *local_read_index = device_write_index;
 
     core.write(
-        rtt_block.host_read_index_addr(),
+        rtt_block.host_read_index_addr() as u64,
         u32::to_le_bytes(*local_read_index).into(),
     )?;
     data_sink.write_all(&new_data)?;
@@ -280,3 +313,138 @@ fn read_from_core<W: Write>(
 
     Ok(())
 }
+
+/// Read the up buffer of the RTT control block at the given address
+pub(crate) fn read_rtt_snapshot(core: &Core, address: u64) -> anyhow::Result<RttSnapshot> {
+    let rtt_block = RttControlBlock::new(address as u32);
+    let memory = CoreMemory(core);
+
+    let ring_buffer = rtt_block.read_buffer(&memory)?;
+    let write_offset = rtt_block.read_write_index(&memory)?;
+    let read_offset = rtt_block.read_read_index(&memory)?;
+
+    let buffer = memory
+        .read_memory(ring_buffer.address, ring_buffer.size as usize)
+        .context("Error while reading buffer data")?;
+
+    Ok(RttSnapshot {
+        buffer_address: ring_buffer.address,
+        write_offset,
+        read_offset,
+        buffer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_mcd::{core::CoreState, simulation::ExclusiveSimulation, system::System};
+
+    use super::*;
+    use crate::simulation::simulate_core;
+
+    const RTT_BLOCK: u64 = 0x7000_0000;
+    const RTT_BUFFER: u32 = 0x7000_1000;
+
+    /// Set up a core with the registers of a backtrace and an RTT control block
+    /// with an up buffer of 64 bytes
+    fn simulate_rtt_core() -> ExclusiveSimulation {
+        let simulation = simulate_core(0x8000_1234);
+        let core = 0;
+
+        let mut block = b"SEGGER RTT\0\0\0\0\0\0".to_vec();
+        block.resize(48, 0);
+        block[28..32].copy_from_slice(&RTT_BUFFER.to_le_bytes());
+        block[32..36].copy_from_slice(&64u32.to_le_bytes());
+        simulation.write_memory(core, RTT_BLOCK, &block);
+
+        simulation
+    }
+
+    /// Let the program of the core append the message to the up buffer
+    fn write_rtt(context: &mut rust_mcd::simulation::ProgramContext, message: &[u8]) {
+        let index = context.read_memory(RTT_BLOCK + 36, 4);
+        let index = u32::from_le_bytes(index.try_into().unwrap());
+        context.write_memory((RTT_BUFFER + index) as u64, message);
+        context.write_memory(
+            RTT_BLOCK + 36,
+            &(index + message.len() as u32).to_le_bytes(),
+        );
+    }
+
+    #[test]
+    fn rtt_data_is_collected_until_the_core_halts() {
+        let simulation = simulate_rtt_core();
+        let mut executions = 0;
+        simulation.set_program(0, move |context| {
+            executions += 1;
+            match executions {
+                1..=4 => write_rtt(context, b"hi!"),
+                8 => context.halt(),
+                _ => {}
+            }
+        });
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+        let mut data = Vec::new();
+        let halt_reason = decode_rtt(
+            &core,
+            &[],
+            RTT_BLOCK,
+            &ResetConfig::default(),
+            &RunControl::default(),
+            Duration::from_millis(1),
+            &[],
+            &mut data,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(data, b"hi!hi!hi!hi!");
+        let HaltReason::DebugHit(halted) = halt_reason else {
+            panic!("Expected the core to halt, got {halt_reason:?}");
+        };
+        assert_eq!(halted.trigger, 0);
+        assert!(halted.stacktraces.contains_key(&0));
+        assert!(simulation.trigger_addresses(0).is_empty());
+    }
+
+    #[test]
+    fn rtt_session_is_stopped_by_the_timeout() {
+        let simulation = simulate_rtt_core();
+        simulation.set_program(0, |context| {
+            let index = context.read_memory(RTT_BLOCK + 36, 4);
+            if index == [0; 4] {
+                write_rtt(context, b"started");
+            }
+        });
+
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+        let control = RunControl {
+            timeout: Some(Duration::from_millis(50)),
+            halt_on_stop: true,
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        let halt_reason = decode_rtt(
+            &core,
+            &[],
+            RTT_BLOCK,
+            &ResetConfig::default(),
+            &control,
+            Duration::from_millis(1),
+            &[],
+            &mut data,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(data, b"started");
+        assert!(matches!(
+            halt_reason,
+            HaltReason::Stopped(StopReason::Timeout, Some(_))
+        ));
+        assert_eq!(simulation.state(0), CoreState::Halted);
+    }
+}
@@ -0,0 +1,234 @@
+//! Captures core dumps of a halted device, see [capture_dump]
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use rust_mcd::core::Core;
+use tricore_common::dump::{
+    CoreDump, CoreImage, DumpRequest, MemoryBlock, MissingBlock, DUMP_FORMAT_VERSION,
+};
+
+use crate::defmt::read_rtt_snapshot;
+
+/// Capture the registers of all cores and the memory given by the request
+///
+/// Registers that cannot be read, e.g. because a core is not halted, are left
+/// out of the dump. Memory that cannot be read is recorded as missing.
+pub fn capture_dump(cores: &[Core<'_>], request: &DumpRequest) -> anyhow::Result<CoreDump> {
+    let mut images = Vec::new();
+    for (core_index, core) in cores.iter().enumerate() {
+        let mut image = CoreImage {
+            core: core_index,
+            ..CoreImage::default()
+        };
+
+        match read_registers(core) {
+            Ok(registers) => image.registers = registers,
+            Err(error) => log::warn!("Cannot read registers of core {core_index}: {error:#}"),
+        }
+
+        let stack = image
+            .registers
+            .get("A10")
+            .filter(|_| request.stack_size > 0)
+            .map(|stack_pointer| ("stack", *stack_pointer, request.stack_size));
+        let regions = request
+            .regions
+            .iter()
+            .filter(|region| region.core == core_index)
+            .map(|region| (region.name.as_str(), region.address, region.length))
+            .chain(stack);
+
+        for (name, address, length) in regions {
+            read_region(core, &mut image, name, address, length);
+        }
+
+        images.push(image);
+    }
+
+    let rtt = request.rtt_control_block.and_then(|address| {
+        let core = cores.first()?;
+        read_rtt_snapshot(core, address as u64)
+            .map_err(|error| log::warn!("Cannot read RTT buffer: {error:#}"))
+            .ok()
+    });
+
+    Ok(CoreDump {
+        version: DUMP_FORMAT_VERSION,
+        build_id: None,
+        cores: images,
+        rtt,
+    })
+}
+
+/// Read a memory region into the image in transactions of the maximum payload
+///
+/// The parts of the region that cannot be read are recorded as missing, such
+/// that the readable parts are still captured.
+fn read_region(core: &Core<'_>, image: &mut CoreImage, name: &str, address: u32, length: u32) {
+    let chunk_size = core.query_payload_size().unwrap_or(length).max(1);
+
+    let mut offset = 0;
+    while offset < length {
+        let chunk_address = address.wrapping_add(offset);
+        let chunk_length = chunk_size.min(length - offset);
+        offset += chunk_length;
+
+        match core.read_bytes(chunk_address as u64, chunk_length as usize) {
+            Ok(data) => match image.memory.last_mut() {
+                Some(block)
+                    if block.name == name
+                        && block.address.wrapping_add(block.data.len() as u32) == chunk_address =>
+                {
+                    block.data.extend(data)
+                }
+                _ => image.memory.push(MemoryBlock {
+                    name: name.to_owned(),
+                    address: chunk_address,
+                    data,
+                }),
+            },
+            Err(error) => {
+                // Include the error the library reported, which is the source
+                let reason = format!("{:#}", anyhow::Error::from(error));
+                match image.missing.last_mut() {
+                    Some(missing)
+                        if missing.name == name
+                            && missing.address.wrapping_add(missing.length) == chunk_address =>
+                    {
+                        missing.length += chunk_length
+                    }
+                    _ => {
+                        log::warn!(
+                            "Cannot read {name} region at {chunk_address:#010X} of core {}: {reason}",
+                            image.core
+                        );
+                        image.missing.push(MissingBlock {
+                            name: name.to_owned(),
+                            address: chunk_address,
+                            length: chunk_length,
+                            reason,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Read all registers of the core that can be read
+pub(crate) fn read_registers(core: &Core<'_>) -> anyhow::Result<BTreeMap<String, u32>> {
+    let groups = core.register_groups()?;
+    let group = groups
+        .get_group(0)
+        .with_context(|| "Cannot query registers of core")?;
+
+    let mut registers = BTreeMap::new();
+    for register in group.registers() {
+        match register.read() {
+            Ok(value) => {
+                registers.insert(register.name(), value);
+            }
+            Err(error) => log::debug!("Cannot read register {}: {error}", register.name()),
+        }
+    }
+    Ok(registers)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_mcd::{
+        error::McdErrorCode,
+        simulation::{Operation, SimulatedError, SimulatedMcd},
+        system::System,
+    };
+    use tricore_common::dump::DumpRegion;
+
+    use super::*;
+    use crate::simulation::simulate_core;
+
+    fn region(name: &str, address: u32, length: u32) -> DumpRegion {
+        DumpRegion {
+            name: name.to_owned(),
+            core: 0,
+            address,
+            length,
+        }
+    }
+
+    #[test]
+    fn regions_are_read_in_transactions_of_the_payload() {
+        let simulation = simulate_core(0x8000_0000);
+        simulation.set_payload_size(0, 8);
+        simulation.set_register(0, "A10", 0x7000_0F00);
+        let data: Vec<u8> = (0..=255).collect();
+        simulation.write_memory(0, 0x7000_0000, &data);
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+
+        let request = DumpRequest {
+            regions: vec![
+                region("csa", 0x7000_0000, 0x100),
+                region("lmu", 0x9000_0000, 3),
+            ],
+            stack_size: 0x14,
+            rtt_control_block: None,
+        };
+        let dump = capture_dump(&[core], &request).unwrap();
+
+        let image = &dump.cores[0];
+        assert_eq!(image.registers.get("A10"), Some(&0x7000_0F00));
+        let blocks: Vec<_> = image
+            .memory
+            .iter()
+            .map(|block| (block.name.as_str(), block.address, block.data.len()))
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                ("csa", 0x7000_0000, 0x100),
+                ("lmu", 0x9000_0000, 3),
+                ("stack", 0x7000_0F00, 0x14)
+            ]
+        );
+        assert_eq!(image.memory[0].data, data);
+        assert!(image.missing.is_empty());
+    }
+
+    #[test]
+    fn unreadable_parts_of_regions_are_recorded_as_missing() {
+        // Without registers, the first transactions read the region
+        let simulation = SimulatedMcd::exclusive().unwrap();
+        simulation.add_core("core0");
+        simulation.set_payload_size(0, 8);
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+
+        // The first two transactions fail
+        for _ in 0..2 {
+            simulation.inject_error(
+                Operation::ExecuteTransactions,
+                SimulatedError::new(McdErrorCode::McdErrTxlistTx, "Bus error"),
+            );
+        }
+        let request = DumpRequest {
+            regions: vec![region("ram", 0x7000_0000, 0x1C)],
+            ..DumpRequest::default()
+        };
+        let dump = capture_dump(&[core], &request).unwrap();
+
+        let image = &dump.cores[0];
+        let missing: Vec<_> = image
+            .missing
+            .iter()
+            .map(|missing| (missing.name.as_str(), missing.address, missing.length))
+            .collect();
+        assert_eq!(missing, [("ram", 0x7000_0000, 0x10)]);
+        assert!(image.missing[0].reason.contains("Bus error"));
+        let blocks: Vec<_> = image
+            .memory
+            .iter()
+            .map(|block| (block.address, block.data.len()))
+            .collect();
+        assert_eq!(blocks, [(0x7000_0010, 0xC)]);
+    }
+}
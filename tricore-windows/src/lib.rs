@@ -1,30 +1,63 @@
 #![feature(type_alias_impl_trait)]
 
-use std::io::Write;
+use std::{io::Write, time::Duration};
 
 use das::run_console;
-use defmt::{decode_rtt, HaltReason};
+use debug::McdDebugTarget;
+use defmt::decode_rtt;
+use dump::capture_dump;
 use flash::MemtoolUpload;
-use rust_mcd::{reset::ResetClass, system::System};
-use tricore_common::{backtrace::Stacktrace, Chip};
+use reset::{available_reset_classes, reset_core, write_memory};
+use rust_mcd::{system::System, watcher::DEFAULT_POLL_INTERVAL};
+use tricore_common::{
+    backtrace::walker::CsaRegion,
+    debug::DebugTarget,
+    dump::{CoreDump, DumpRequest},
+    reset::{CoreResetClasses, ResetConfig},
+    run::{HaltReason, RunControl},
+    watch::{WatchRequest, WatchSample},
+    Chip,
+};
+use watch::Sampler;
 
 mod backtrace;
 pub mod das;
+pub mod debug;
 pub mod defmt;
+pub mod dump;
 pub mod flash;
+pub mod reset;
+#[cfg(test)]
+mod simulation;
+pub mod watch;
 
 #[derive(clap::Args, Debug)]
-pub struct Config;
+pub struct Config {
+    /// Interval in milliseconds at which the state of the cores is queried
+    /// while waiting for them to halt
+    #[arg(long, default_value_t = DEFAULT_POLL_INTERVAL.as_millis() as u64)]
+    pub poll_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            poll_interval: DEFAULT_POLL_INTERVAL.as_millis() as u64,
+        }
+    }
+}
 
 #[derive(Default)]
-pub struct ChipInterface {}
+pub struct ChipInterface {
+    config: Config,
+}
 
 impl Chip for ChipInterface {
     type Config = Config;
 
-    fn new(_config: Self::Config) -> anyhow::Result<Self> {
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
         std::thread::spawn(run_console);
-        Ok(ChipInterface {})
+        Ok(ChipInterface { config })
     }
 
     fn flash_hex(&self, ihex: String, halt_memtool: bool) -> anyhow::Result<()> {
@@ -37,36 +70,131 @@ impl Chip for ChipInterface {
     fn read_rtt<W: Write>(
         &self,
         rtt_control_block_address: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
         decoder: W,
-    ) -> anyhow::Result<Stacktrace> {
-        rust_mcd::library::init();
+    ) -> anyhow::Result<HaltReason> {
+        rust_mcd::library::init()?;
         let system = System::connect()?;
         let core_count = system.core_count();
-        let mut core = system.get_core(0)?;
+        let core = system.get_core(0)?;
         let secondary_cores: Result<Vec<_>, _> = (1..(core_count))
             .map(|core_index| system.get_core(core_index))
             .collect();
-        let mut secondary_cores = secondary_cores?;
-        let HaltReason::DebugHit(halt_reason) = decode_rtt(
-            &mut core,
-            &mut secondary_cores,
+        let secondary_cores = secondary_cores?;
+        decode_rtt(
+            &core,
+            &secondary_cores,
             rtt_control_block_address,
+            reset,
+            control,
+            Duration::from_millis(self.config.poll_interval),
+            csa_regions,
             decoder,
-        )?;
-        Ok(halt_reason)
+            None,
+        )
     }
-}
 
-impl ChipInterface {
-    pub fn reset(&self) -> anyhow::Result<()> {
-        rust_mcd::library::init();
+    fn watch<W: Write>(
+        &self,
+        rtt_control_block_address: u64,
+        reset: &ResetConfig,
+        control: &RunControl,
+        csa_regions: &[CsaRegion],
+        request: &WatchRequest,
+        decoder: W,
+        samples: &mut dyn FnMut(WatchSample) -> anyhow::Result<()>,
+    ) -> anyhow::Result<HaltReason> {
+        rust_mcd::library::init()?;
         let system = System::connect()?;
+        let core_count = system.core_count();
         let core = system.get_core(0)?;
-        let system_reset = ResetClass::construct_reset_class(&core, 0);
-        // Do we also need to reset the other cores?
-        core.reset(system_reset, true)?;
-        core.run()?;
+        let secondary_cores: Result<Vec<_>, _> = (1..(core_count))
+            .map(|core_index| system.get_core(core_index))
+            .collect();
+        let secondary_cores = secondary_cores?;
+        decode_rtt(
+            &core,
+            &secondary_cores,
+            rtt_control_block_address,
+            reset,
+            control,
+            Duration::from_millis(self.config.poll_interval),
+            csa_regions,
+            decoder,
+            Some(Sampler::new(request, samples)),
+        )
+    }
+
+    fn reset(&self, reset: &ResetConfig) -> anyhow::Result<()> {
+        rust_mcd::library::init()?;
+        let system = System::connect()?;
+        let cores: Result<Vec<_>, _> = (0..system.core_count())
+            .map(|core_index| system.get_core(core_index))
+            .collect();
+        let cores = cores?;
+
+        for (core_index, core) in cores.iter().enumerate() {
+            if !reset_core(core, core_index, reset, true)? {
+                continue;
+            }
+            if core_index == 0 {
+                write_memory(core, reset)?;
+            }
+            core.run()?;
+        }
+        drop(cores);
         drop(system);
         Ok(())
     }
+
+    fn list_reset_classes(&self) -> anyhow::Result<Vec<CoreResetClasses>> {
+        rust_mcd::library::init()?;
+        let system = System::connect()?;
+        let reset_classes = (0..system.core_count())
+            .map(|core_index| available_reset_classes(&system.get_core(core_index)?, core_index))
+            .collect();
+        drop(system);
+        reset_classes
+    }
+
+    fn capture_dump(&self, request: &DumpRequest) -> anyhow::Result<CoreDump> {
+        rust_mcd::library::init()?;
+        let system = System::connect()?;
+        let cores: Result<Vec<_>, _> = (0..system.core_count())
+            .map(|core_index| system.get_core(core_index))
+            .collect();
+        let cores = cores?;
+
+        let dump = capture_dump(&cores, request);
+        drop(cores);
+        drop(system);
+        dump
+    }
+
+    fn debug<R, F: FnOnce(&mut dyn DebugTarget) -> anyhow::Result<R>>(
+        &self,
+        csa_regions: &[CsaRegion],
+        control: &RunControl,
+        session: F,
+    ) -> anyhow::Result<R> {
+        rust_mcd::library::init()?;
+        let system = System::connect()?;
+        let cores: Result<Vec<_>, _> = (0..system.core_count())
+            .map(|core_index| system.get_core(core_index))
+            .collect();
+        let cores = cores?;
+
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+        let result = session(&mut McdDebugTarget::new(
+            &cores,
+            poll_interval,
+            csa_regions,
+            control,
+        ));
+        drop(cores);
+        drop(system);
+        result
+    }
 }
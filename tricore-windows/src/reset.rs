@@ -0,0 +1,116 @@
+//! Applies a [ResetConfig] to the cores of a system
+
+use anyhow::Context;
+use rust_mcd::core::Core;
+use tricore_common::reset::{CoreResetClasses, CoreResetPolicy, ResetClassInfo, ResetConfig};
+
+/// Query the reset classes the given core offers, including their descriptions
+pub fn available_reset_classes(core: &Core, core_index: usize) -> anyhow::Result<CoreResetClasses> {
+    let classes = core
+        .get_reset_classes()
+        .with_context(|| format!("Cannot query reset classes of core {core_index}"))?
+        .map(|reset_class| {
+            Ok(ResetClassInfo {
+                index: reset_class.class(),
+                description: reset_class.get_info()?.description().into_owned(),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .with_context(|| format!("Cannot query reset class information of core {core_index}"))?;
+
+    Ok(CoreResetClasses {
+        core: core_index,
+        classes,
+    })
+}
+
+/// Reset the given core according to the reset configuration
+///
+/// The configured reset class is validated against the classes the core reports
+/// before the reset is issued. Returns false if the configuration instructs to
+/// skip the reset of this core.
+pub fn reset_core(
+    core: &Core,
+    core_index: usize,
+    config: &ResetConfig,
+    halt_after_reset: bool,
+) -> anyhow::Result<bool> {
+    let selector = match config.policy_for_core(core_index) {
+        CoreResetPolicy::Reset(selector) => selector,
+        CoreResetPolicy::Skip => {
+            log::debug!("Skipping reset of core {core_index}");
+            return Ok(false);
+        }
+    };
+
+    let available = available_reset_classes(core, core_index)?;
+    let class = available.resolve(&selector)?;
+    log::debug!(
+        "Resetting core {core_index} with reset class {} ({})",
+        class.index,
+        class.description
+    );
+
+    let reset_class = core.get_reset_class(class.index)?;
+    core.reset(reset_class, halt_after_reset)
+        .with_context(|| format!("Cannot reset core {core_index}"))?;
+
+    Ok(true)
+}
+
+/// Apply the memory writes of the reset configuration through the given core
+///
+/// The core should be halted, otherwise the firmware may read the memory before
+/// it is written.
+pub fn write_memory(core: &Core, config: &ResetConfig) -> anyhow::Result<()> {
+    for memory_write in config.memory_writes.iter() {
+        log::debug!(
+            "Writing {} bytes to {:#X}",
+            memory_write.data.len(),
+            memory_write.address
+        );
+        core.write(memory_write.address as u64, memory_write.data.clone())
+            .with_context(|| format!("Cannot write memory at {:#X}", memory_write.address))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_mcd::system::System;
+    use tricore_common::reset::MemoryWrite;
+
+    use super::*;
+    use crate::simulation::simulate_core;
+
+    #[test]
+    fn memory_writes_exceeding_the_payload_are_split() {
+        let simulation = simulate_core(0x8000_0000);
+        simulation.set_payload_size(0, 8);
+        let system = System::connect().unwrap();
+        let core = system.get_core(0).unwrap();
+
+        let data: Vec<u8> = (0..100).collect();
+        let config = ResetConfig {
+            memory_writes: vec![
+                MemoryWrite {
+                    address: 0x7000_0002,
+                    data: data.clone(),
+                },
+                MemoryWrite {
+                    address: 0x7000_1000,
+                    data: vec![0xA5; 8],
+                },
+            ],
+            ..ResetConfig::default()
+        };
+        write_memory(&core, &config).unwrap();
+
+        assert_eq!(simulation.read_memory(0, 0x7000_0002, 100), data);
+        assert_eq!(
+            simulation.read_memory(0, 0x7000_1000, 9),
+            [0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+//! Simulated cores for the tests of this crate, see [rust_mcd::simulation]
+
+use rust_mcd::simulation::{ExclusiveSimulation, SimulatedMcd};
+
+/// The registers of a core that are read for a backtrace
+const REGISTERS: [&str; 35] = [
+    "PCXI", "PSW", "PC", "A0", "A1", "A2", "A3", "A4", "A5", "A6", "A7", "A10", "A11", "A12",
+    "A13", "A8", "A9", "A14", "A15", "D0", "D1", "D2", "D3", "D4", "D5", "D6", "D7", "D8", "D9",
+    "D10", "D11", "D12", "D13", "D14", "D15",
+];
+
+/// Set up a simulation with a single core that has the registers of a
+/// backtrace and a reset class
+///
+/// The core is halted at the given program counter and has no saved contexts.
+pub(crate) fn simulate_core(pc: u32) -> ExclusiveSimulation {
+    let simulation = SimulatedMcd::exclusive().unwrap();
+    let core = simulation.add_core("core0");
+    simulation.add_reset_class(core, 0, "System reset");
+
+    for (index, register) in REGISTERS.into_iter().enumerate() {
+        simulation.add_register(core, register, 0xF881_0000 + 4 * index as u64);
+    }
+    simulation.set_register(core, "PC", pc);
+
+    simulation
+}
@@ -0,0 +1,79 @@
+//! Samples memory while the device runs, see [Sampler]
+
+use std::time::{Duration, Instant};
+
+use rust_mcd::core::Core;
+use tricore_common::watch::{WatchRequest, WatchSample};
+
+/// Samples the watched memory through a core at the interval of the request,
+/// see [crate::defmt::decode_rtt]
+pub struct Sampler<'a> {
+    request: &'a WatchRequest,
+    sink: &'a mut dyn FnMut(WatchSample) -> anyhow::Result<()>,
+    started: Instant,
+    next_sample: Instant,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(
+        request: &'a WatchRequest,
+        sink: &'a mut dyn FnMut(WatchSample) -> anyhow::Result<()>,
+    ) -> Self {
+        let now = Instant::now();
+        Sampler {
+            request,
+            sink,
+            started: now,
+            next_sample: now,
+        }
+    }
+
+    /// Restart the time of the samples, e.g. once the device runs
+    pub fn start(&mut self) {
+        self.started = Instant::now();
+        self.next_sample = self.started;
+    }
+
+    /// The time until the next sample is due
+    pub fn time_to_next_sample(&self) -> Duration {
+        self.next_sample.saturating_duration_since(Instant::now())
+    }
+
+    /// Take a sample if it is due
+    ///
+    /// A region that cannot be read does not end the session, it is missing
+    /// from the sample instead.
+    pub fn poll(&mut self, core: &Core<'_>) -> anyhow::Result<()> {
+        let now = Instant::now();
+        if now < self.next_sample {
+            return Ok(());
+        }
+        // Samples that were missed, e.g. because reading RTT data took long, are
+        // skipped instead of taken in a burst
+        self.next_sample += self.request.interval;
+        if self.next_sample < now {
+            self.next_sample = now + self.request.interval;
+        }
+
+        let data = self
+            .request
+            .regions
+            .iter()
+            .map(|region| {
+                core.read_bytes(region.address as u64, region.length as usize)
+                    .map_err(|error| {
+                        log::debug!(
+                            "Cannot sample memory at {:#010X}: {error:#}",
+                            region.address
+                        )
+                    })
+                    .ok()
+            })
+            .collect();
+
+        (self.sink)(WatchSample {
+            elapsed: now - self.started,
+            data,
+        })
+    }
+}